@@ -1,8 +1,9 @@
 use cel::context::{Context, VariableResolver};
 use cel::parser::Parser;
-use cel::{Program, Value};
+use cel::{Backend, Env, Program, Value};
 use criterion::{black_box, criterion_group, BenchmarkId, Criterion};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 const EXPRESSIONS: [(&str, &str); 34] = [
     ("ternary_1", "(false || true) ? 1 : 2"),
@@ -74,6 +75,29 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     }
 }
 
+pub fn planned_benchmark(c: &mut Criterion) {
+    for (group, backend) in [
+        ("execute planned", Backend::Tree),
+        ("execute bytecode", Backend::Bytecode),
+    ] {
+        let mut planned_group = c.benchmark_group(group);
+        for (name, expr) in black_box(&EXPRESSIONS) {
+            planned_group.bench_function(BenchmarkId::from_parameter(name), |b| {
+                let env = Arc::new(Env::stdlib());
+                let program = Program::compile(expr)
+                    .expect("Parsing failed")
+                    .plan_with(&env, backend);
+                let mut ctx = Context::with_env(env);
+                ctx.add_variable_from_value("foo", HashMap::from([("bar", 1)]));
+                ctx.add_variable_from_value("apple", true);
+                ctx.add_variable_from_value("a", 1);
+                ctx.set_variable_resolver(&Resolver);
+                b.iter(|| program.execute(&ctx).expect("Eval failed!"))
+            });
+        }
+    }
+}
+
 pub fn criterion_benchmark_parsing(c: &mut Criterion) {
     let mut parsing_group = c.benchmark_group("parse");
     for (name, expr) in black_box(&EXPRESSIONS) {
@@ -103,7 +127,7 @@ pub fn map_macro_benchmark(c: &mut Criterion) {
 criterion_group! {
     name = benches;
     config = Criterion::default();
-    targets = criterion_benchmark, criterion_benchmark_parsing, map_macro_benchmark
+    targets = criterion_benchmark, planned_benchmark, criterion_benchmark_parsing, map_macro_benchmark
 }
 
 #[cfg(feature = "dhat-heap")]
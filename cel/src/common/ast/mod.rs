@@ -4,12 +4,15 @@ use std::borrow::Cow;
 use std::collections::BTreeMap;
 
 pub mod operators;
+#[cfg(feature = "expr_proto")]
+pub mod proto;
 
 pub struct Ast {
     pub expr: IdedExpr,
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expr {
     #[default]
     /// UnspecifiedExprKind represents an unset expression with no specified properties.
@@ -41,6 +44,7 @@ pub enum Expr {
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LiteralValue {
     Boolean(CelBool),
     Bytes(CelBytes),
@@ -53,13 +57,12 @@ pub enum LiteralValue {
 
 impl LiteralValue {
     pub fn to_val<'a>(&'a self) -> Cow<'a, dyn Val> {
-        // todo refactor to return Cow::Borrowed
         match &self {
             LiteralValue::Boolean(b) => Cow::Borrowed(b),
             LiteralValue::Bytes(b) => Cow::Borrowed(b),
             LiteralValue::Double(f) => Cow::Borrowed(f),
             LiteralValue::Int(i) => Cow::Borrowed(i),
-            LiteralValue::Null => Cow::<dyn Val>::Owned(Box::new(CelNull)),
+            LiteralValue::Null => Cow::Borrowed(&CelNull),
             LiteralValue::String(s) => Cow::Borrowed(s),
             LiteralValue::UInt(ui) => Cow::Borrowed(ui),
         }
@@ -67,24 +70,158 @@ impl LiteralValue {
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EntryExpr {
     StructField(StructFieldExpr),
     MapEntry(MapEntryExpr),
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct IdedExpr {
     pub id: u64,
     pub expr: Expr,
 }
 
+/// How deeply nested the expression of a program loaded from bytes or from a protobuf message
+/// may be. Loading recurses into every level of the expression, so a program nested deeper fails
+/// to load rather than overflowing the stack of the thread, even in an unoptimized build.
+#[cfg(any(feature = "serde", feature = "expr_proto"))]
+pub(crate) const MAX_LOAD_DEPTH: usize = 200;
+
+impl IdedExpr {
+    /// Iterates over the expression and every expression within it, along with how deeply each
+    /// is nested, starting from 1 for this one. Expressions come before those within them, and
+    /// after those to their left, without recursing, so that any depth can be walked.
+    pub(crate) fn walk(&self) -> Walk<'_> {
+        Walk {
+            stack: vec![(self, 1)],
+        }
+    }
+
+    /// How deeply nested the expression is, 1 for one with no expression within it, along with
+    /// the id of the first expression nested that deep.
+    pub(crate) fn deepest(&self) -> (usize, u64) {
+        self.walk().fold((0, self.id), |deepest, (expr, depth)| {
+            if depth > deepest.0 {
+                (depth, expr.id)
+            } else {
+                deepest
+            }
+        })
+    }
+
+    /// Calls `f` with each of the expressions directly within this one, left to right.
+    fn for_each_child<'a>(&'a self, mut f: impl FnMut(&'a IdedExpr)) {
+        let mut entries = |entries: &'a [IdedEntryExpr]| {
+            for entry in entries {
+                match &entry.expr {
+                    EntryExpr::StructField(field) => f(&field.value),
+                    EntryExpr::MapEntry(entry) => {
+                        f(&entry.key);
+                        f(&entry.value);
+                    }
+                }
+            }
+        };
+        match &self.expr {
+            Expr::Unspecified | Expr::Ident(_) | Expr::Literal(_) => {}
+            Expr::Call(call) => {
+                call.target.as_deref().into_iter().for_each(&mut f);
+                call.args.iter().for_each(f);
+            }
+            Expr::Comprehension(comprehension) => [
+                &comprehension.iter_range,
+                &comprehension.accu_init,
+                &comprehension.loop_cond,
+                &comprehension.loop_step,
+                &comprehension.result,
+            ]
+            .into_iter()
+            .for_each(f),
+            Expr::List(list) => list.elements.iter().for_each(f),
+            Expr::Map(map) => entries(&map.entries),
+            Expr::Select(select) => f(&select.operand),
+            Expr::Struct(strct) => entries(&strct.entries),
+        }
+    }
+}
+
+/// The iterator of [`IdedExpr::walk`].
+pub(crate) struct Walk<'a> {
+    stack: Vec<(&'a IdedExpr, usize)>,
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = (&'a IdedExpr, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (expr, depth) = self.stack.pop()?;
+        let start = self.stack.len();
+        expr.for_each_child(|child| self.stack.push((child, depth + 1)));
+        self.stack[start..].reverse();
+        Some((expr, depth))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for IdedExpr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(remote = "IdedExpr", rename = "IdedExpr")]
+        struct Fields {
+            id: u64,
+            expr: Expr,
+        }
+
+        let _nested = Nested::enter().ok_or_else(|| {
+            <D::Error as serde::de::Error>::custom(format!(
+                "Expression nested deeper than {MAX_LOAD_DEPTH}"
+            ))
+        })?;
+        Fields::deserialize(deserializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+thread_local! {
+    /// How deeply nested the expression being deserialized on this thread is.
+    static DESERIALIZING: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// The deserialization of an expression nested in those being deserialized on this thread,
+/// which leaves their depth as it was once dropped, even if deserializing fails or panics.
+#[cfg(feature = "serde")]
+struct Nested(usize);
+
+#[cfg(feature = "serde")]
+impl Nested {
+    fn enter() -> Option<Nested> {
+        let depth = DESERIALIZING.get();
+        if depth >= MAX_LOAD_DEPTH {
+            return None;
+        }
+        DESERIALIZING.set(depth + 1);
+        Some(Nested(depth))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Drop for Nested {
+    fn drop(&mut self) {
+        DESERIALIZING.set(self.0);
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IdedEntryExpr {
     pub id: u64,
     pub expr: EntryExpr,
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CallExpr {
     pub func_name: String,
     pub target: Option<Box<IdedExpr>>,
@@ -92,6 +229,7 @@ pub struct CallExpr {
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SelectExpr {
     pub operand: Box<IdedExpr>,
     pub field: String,
@@ -99,17 +237,20 @@ pub struct SelectExpr {
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StructExpr {
     pub type_name: String,
     pub entries: Vec<IdedEntryExpr>,
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MapExpr {
     pub entries: Vec<IdedEntryExpr>,
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ListExpr {
     pub elements: Vec<IdedExpr>,
     pub optional_indices: Vec<usize>,
@@ -129,6 +270,7 @@ impl ListExpr {
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StructFieldExpr {
     pub field: String,
     pub value: IdedExpr,
@@ -136,6 +278,7 @@ pub struct StructFieldExpr {
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MapEntryExpr {
     pub key: IdedExpr,
     pub value: IdedExpr,
@@ -143,6 +286,7 @@ pub struct MapEntryExpr {
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ComprehensionExpr {
     pub iter_range: IdedExpr,
     pub iter_var: String,
@@ -154,13 +298,101 @@ pub struct ComprehensionExpr {
     pub result: IdedExpr,
 }
 
+impl ComprehensionExpr {
+    /// Returns the condition, if any, and the element each step appends to the accumulator,
+    /// for comprehensions accumulating a list from a list literal with steps of the form
+    /// `accu + [element]` or `condition ? accu + [element] : accu`, which `map` and `filter`
+    /// expand to.
+    pub(crate) fn appends(&self) -> Option<(Option<&IdedExpr>, &IdedExpr)> {
+        fn is_ident(expr: &IdedExpr, name: &str) -> bool {
+            matches!(&expr.expr, Expr::Ident(ident) if ident == name)
+        }
+        fn call<'e>(expr: &'e IdedExpr, name: &str, arity: usize) -> Option<&'e [IdedExpr]> {
+            match &expr.expr {
+                Expr::Call(call)
+                    if call.func_name == name
+                        && call.target.is_none()
+                        && call.args.len() == arity =>
+                {
+                    Some(&call.args)
+                }
+                _ => None,
+            }
+        }
+        let append = |expr| {
+            let args = call(expr, operators::ADD, 2)?;
+            match &args[1].expr {
+                Expr::List(list)
+                    if is_ident(&args[0], &self.accu_var)
+                        && list.elements.len() == 1
+                        && list.optional_indices.is_empty() =>
+                {
+                    Some(&list.elements[0])
+                }
+                _ => None,
+            }
+        };
+
+        match &self.accu_init.expr {
+            Expr::List(list)
+                if list.optional_indices.is_empty() && self.iter_var != self.accu_var => {}
+            _ => return None,
+        }
+        if let Some(element) = append(&self.loop_step) {
+            return Some((None, element));
+        }
+        let args = call(&self.loop_step, operators::CONDITIONAL, 3)?;
+        if !is_ident(&args[2], &self.accu_var) {
+            return None;
+        }
+        append(&args[1]).map(|element| (Some(&args[0]), element))
+    }
+}
+
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SourceInfo {
     offsets: BTreeMap<u64, OffsetRange>,
     pub source: String,
+    name: Option<String>,
+    line_offset: u32,
+    column_offset: u32,
+    macro_calls: BTreeMap<u64, IdedExpr>,
 }
 
 impl SourceInfo {
+    /// The name of the source errors are reported against, `<input>` unless one was given with
+    /// [`Parser::source_name`](crate::parser::Parser::source_name).
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("<input>")
+    }
+
+    pub(crate) fn set_name(&mut self, name: String) {
+        self.name = Some(name);
+    }
+
+    /// The number of lines, and columns of the first line, preceding the source in the document
+    /// it is embedded in, see [`Parser::source_offset`](crate::parser::Parser::source_offset).
+    pub fn location_offset(&self) -> (u32, u32) {
+        (self.line_offset, self.column_offset)
+    }
+
+    pub(crate) fn set_location_offset(&mut self, lines: u32, columns: u32) {
+        self.line_offset = lines;
+        self.column_offset = columns;
+    }
+
+    /// Maps a line and column within the source to the line and column within the document the
+    /// source is embedded in. Only the first line is shifted by the column offset.
+    pub fn location(&self, (line, column): (isize, isize)) -> (isize, isize) {
+        let column = if line == 1 {
+            column + self.column_offset as isize
+        } else {
+            column
+        };
+        (line + self.line_offset as isize, column)
+    }
+
     pub fn add_offset(&mut self, id: u64, start: u32, stop: u32) {
         self.offsets.insert(id, OffsetRange { start, stop });
     }
@@ -169,6 +401,24 @@ impl SourceInfo {
         self.offsets.get(&id).map(|range| (range.start, range.stop))
     }
 
+    pub fn add_macro_call(&mut self, id: u64, call: IdedExpr) {
+        self.macro_calls.insert(id, call);
+    }
+
+    /// The call to a macro which expanded to expression `id`, if the parser recorded it, see
+    /// [`Parser::populate_macro_calls`](crate::parser::Parser::populate_macro_calls). Arguments
+    /// which are macro calls themselves are left as unspecified expressions with the id of
+    /// their expansion.
+    pub fn macro_call(&self, id: u64) -> Option<&IdedExpr> {
+        self.macro_calls.get(&id)
+    }
+
+    /// The calls to macros the parser recorded, keyed by the id of the expression each expanded
+    /// to.
+    pub fn macro_calls(&self) -> impl Iterator<Item = (u64, &IdedExpr)> {
+        self.macro_calls.iter().map(|(id, call)| (*id, call))
+    }
+
     pub(crate) fn pos_for(&self, id: u64) -> Option<(isize, isize)> {
         match self.offset_for(id) {
             Some((start, _)) => {
@@ -194,6 +444,7 @@ impl SourceInfo {
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OffsetRange {
     pub start: u32,
     pub stop: u32,
@@ -0,0 +1,845 @@
+//! Conversion of the AST to and from the `cel.expr` protobuf messages, `ParsedExpr` and
+//! `CheckedExpr`, in which other CEL implementations such as cel-go and cel-cpp exchange
+//! expressions. Expression ids are kept as they are, and the messages here encode to, and decode
+//! from, the same bytes as the ones generated from `cel/expr/syntax.proto` and
+//! `cel/expr/checked.proto`.
+//!
+//! Positions in the messages are offsets in code points, rather than bytes, and are converted
+//! using the source when it is at hand. The messages don't carry the source itself.
+
+use crate::common::ast::{
+    self, CallExpr, ComprehensionExpr, EntryExpr, IdedEntryExpr, IdedExpr, ListExpr, LiteralValue,
+    MapEntryExpr, MapExpr, SelectExpr, StructExpr, StructFieldExpr, MAX_LOAD_DEPTH,
+};
+use crate::common::types::{CelBool, CelBytes, CelDouble, CelInt, CelString, CelUInt};
+use crate::{ParseErrors, Program};
+use std::collections::HashMap;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// An expression as parsed, `cel.expr.ParsedExpr`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ParsedExpr {
+    #[prost(message, optional, tag = "2")]
+    pub expr: Option<Expr>,
+    #[prost(message, optional, tag = "3")]
+    pub source_info: Option<SourceInfo>,
+}
+
+/// A type-checked expression, `cel.expr.CheckedExpr`. Its `type_map` isn't modelled, as this
+/// crate doesn't type-check expressions, and is skipped when decoding.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CheckedExpr {
+    #[prost(map = "int64, message", tag = "2")]
+    pub reference_map: HashMap<i64, Reference>,
+    #[prost(message, optional, tag = "5")]
+    pub source_info: Option<SourceInfo>,
+    #[prost(string, tag = "6")]
+    pub expr_version: String,
+    #[prost(message, optional, tag = "4")]
+    pub expr: Option<Expr>,
+}
+
+/// What an identifier or function call in a [`CheckedExpr`] refers to, `cel.expr.Reference`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Reference {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, repeated, tag = "3")]
+    pub overload_id: Vec<String>,
+    #[prost(message, optional, tag = "4")]
+    pub value: Option<Constant>,
+}
+
+/// `cel.expr.Expr`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Expr {
+    #[prost(int64, tag = "2")]
+    pub id: i64,
+    #[prost(oneof = "expr::ExprKind", tags = "3, 4, 5, 6, 7, 8, 9")]
+    pub expr_kind: Option<expr::ExprKind>,
+}
+
+pub mod expr {
+    use super::{Constant, Expr};
+
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum ExprKind {
+        #[prost(message, tag = "3")]
+        ConstExpr(Constant),
+        #[prost(message, tag = "4")]
+        IdentExpr(Ident),
+        #[prost(message, tag = "5")]
+        SelectExpr(Select),
+        #[prost(message, tag = "6")]
+        CallExpr(Call),
+        #[prost(message, tag = "7")]
+        ListExpr(CreateList),
+        #[prost(message, tag = "8")]
+        StructExpr(CreateStruct),
+        #[prost(message, tag = "9")]
+        ComprehensionExpr(Comprehension),
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Ident {
+        #[prost(string, tag = "1")]
+        pub name: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Select {
+        #[prost(message, optional, boxed, tag = "1")]
+        pub operand: Option<Box<Expr>>,
+        #[prost(string, tag = "2")]
+        pub field: String,
+        #[prost(bool, tag = "3")]
+        pub test_only: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Call {
+        #[prost(message, optional, boxed, tag = "1")]
+        pub target: Option<Box<Expr>>,
+        #[prost(string, tag = "2")]
+        pub function: String,
+        #[prost(message, repeated, tag = "3")]
+        pub args: Vec<Expr>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CreateList {
+        #[prost(message, repeated, tag = "1")]
+        pub elements: Vec<Expr>,
+        #[prost(int32, repeated, tag = "2")]
+        pub optional_indices: Vec<i32>,
+    }
+
+    /// A map when it has no `message_name`, a message otherwise.
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CreateStruct {
+        #[prost(string, tag = "1")]
+        pub message_name: String,
+        #[prost(message, repeated, tag = "2")]
+        pub entries: Vec<create_struct::Entry>,
+    }
+
+    pub mod create_struct {
+        use super::super::Expr;
+
+        #[derive(Clone, PartialEq, prost::Message)]
+        pub struct Entry {
+            #[prost(int64, tag = "1")]
+            pub id: i64,
+            #[prost(oneof = "entry::KeyKind", tags = "2, 3")]
+            pub key_kind: Option<entry::KeyKind>,
+            #[prost(message, optional, tag = "4")]
+            pub value: Option<Expr>,
+            #[prost(bool, tag = "5")]
+            pub optional_entry: bool,
+        }
+
+        pub mod entry {
+            use super::super::super::Expr;
+
+            #[derive(Clone, PartialEq, prost::Oneof)]
+            pub enum KeyKind {
+                #[prost(string, tag = "2")]
+                FieldKey(String),
+                #[prost(message, tag = "3")]
+                MapKey(Expr),
+            }
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Comprehension {
+        #[prost(string, tag = "1")]
+        pub iter_var: String,
+        #[prost(string, tag = "8")]
+        pub iter_var2: String,
+        #[prost(message, optional, boxed, tag = "2")]
+        pub iter_range: Option<Box<Expr>>,
+        #[prost(string, tag = "3")]
+        pub accu_var: String,
+        #[prost(message, optional, boxed, tag = "4")]
+        pub accu_init: Option<Box<Expr>>,
+        #[prost(message, optional, boxed, tag = "5")]
+        pub loop_condition: Option<Box<Expr>>,
+        #[prost(message, optional, boxed, tag = "6")]
+        pub loop_step: Option<Box<Expr>>,
+        #[prost(message, optional, boxed, tag = "7")]
+        pub result: Option<Box<Expr>>,
+    }
+}
+
+/// `cel.expr.Constant`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Constant {
+    #[prost(oneof = "constant::ConstantKind", tags = "1, 2, 3, 4, 5, 6, 7, 8, 9")]
+    pub constant_kind: Option<constant::ConstantKind>,
+}
+
+pub mod constant {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum ConstantKind {
+        #[prost(enumeration = "prost_types::NullValue", tag = "1")]
+        NullValue(i32),
+        #[prost(bool, tag = "2")]
+        BoolValue(bool),
+        #[prost(int64, tag = "3")]
+        Int64Value(i64),
+        #[prost(uint64, tag = "4")]
+        Uint64Value(u64),
+        #[prost(double, tag = "5")]
+        DoubleValue(f64),
+        #[prost(string, tag = "6")]
+        StringValue(String),
+        #[prost(bytes = "vec", tag = "7")]
+        BytesValue(Vec<u8>),
+        /// Deprecated in `cel.expr`, and not supported by this crate.
+        #[prost(message, tag = "8")]
+        DurationValue(prost_types::Duration),
+        /// Deprecated in `cel.expr`, and not supported by this crate.
+        #[prost(message, tag = "9")]
+        TimestampValue(prost_types::Timestamp),
+    }
+}
+
+/// `cel.expr.SourceInfo`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SourceInfo {
+    #[prost(string, tag = "1")]
+    pub syntax_version: String,
+    #[prost(string, tag = "2")]
+    pub location: String,
+    /// The offset, in code points, of the start of each line after the first, followed by the
+    /// length of the source plus one.
+    #[prost(int32, repeated, tag = "3")]
+    pub line_offsets: Vec<i32>,
+    /// The offset, in code points, of each expression.
+    #[prost(map = "int64, int32", tag = "4")]
+    pub positions: HashMap<i64, i32>,
+    #[prost(map = "int64, message", tag = "5")]
+    pub macro_calls: HashMap<i64, Expr>,
+}
+
+/// An error converting from the `cel.expr` messages.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum FromProtoError {
+    #[error("Expression {id} is missing its {field}")]
+    MissingField { id: i64, field: &'static str },
+    #[error("Invalid expression id {0}")]
+    InvalidId(i64),
+    /// Indicates a deprecated duration or timestamp constant.
+    #[error("Unsupported constant in expression {id}")]
+    UnsupportedConstant { id: i64 },
+    #[error("Invalid program: {0}")]
+    InvalidProgram(String),
+    /// Indicates an expression nested deeper than loading a program allows, which is 200.
+    #[error("Expression {id} is nested deeper than {max}")]
+    TooDeep { id: i64, max: usize },
+}
+
+impl From<ParseErrors> for FromProtoError {
+    fn from(errors: ParseErrors) -> Self {
+        FromProtoError::InvalidProgram(errors.to_string())
+    }
+}
+
+impl IdedExpr {
+    /// Converts the expression to a `cel.expr.Expr`.
+    pub fn to_proto(&self) -> Expr {
+        let boxed = |expr: &IdedExpr| Some(Box::new(expr.to_proto()));
+        let kind = match &self.expr {
+            ast::Expr::Unspecified => None,
+            ast::Expr::Literal(literal) => Some(expr::ExprKind::ConstExpr(literal.to_proto())),
+            ast::Expr::Ident(name) => Some(expr::ExprKind::IdentExpr(expr::Ident {
+                name: name.clone(),
+            })),
+            ast::Expr::Select(select) => Some(expr::ExprKind::SelectExpr(expr::Select {
+                operand: boxed(&select.operand),
+                field: select.field.clone(),
+                test_only: select.test,
+            })),
+            ast::Expr::Call(call) => Some(expr::ExprKind::CallExpr(expr::Call {
+                target: call.target.as_deref().and_then(boxed),
+                function: call.func_name.clone(),
+                args: call.args.iter().map(IdedExpr::to_proto).collect(),
+            })),
+            ast::Expr::List(list) => Some(expr::ExprKind::ListExpr(expr::CreateList {
+                elements: list.elements.iter().map(IdedExpr::to_proto).collect(),
+                optional_indices: list.optional_indices.iter().map(|i| *i as i32).collect(),
+            })),
+            ast::Expr::Map(map) => Some(expr::ExprKind::StructExpr(expr::CreateStruct {
+                message_name: String::new(),
+                entries: map.entries.iter().map(entry_to_proto).collect(),
+            })),
+            ast::Expr::Struct(s) => Some(expr::ExprKind::StructExpr(expr::CreateStruct {
+                message_name: s.type_name.clone(),
+                entries: s.entries.iter().map(entry_to_proto).collect(),
+            })),
+            ast::Expr::Comprehension(comprehension) => {
+                Some(expr::ExprKind::ComprehensionExpr(expr::Comprehension {
+                    iter_var: comprehension.iter_var.clone(),
+                    iter_var2: comprehension.iter_var2.clone().unwrap_or_default(),
+                    iter_range: boxed(&comprehension.iter_range),
+                    accu_var: comprehension.accu_var.clone(),
+                    accu_init: boxed(&comprehension.accu_init),
+                    loop_condition: boxed(&comprehension.loop_cond),
+                    loop_step: boxed(&comprehension.loop_step),
+                    result: boxed(&comprehension.result),
+                }))
+            }
+        };
+        Expr {
+            id: self.id as i64,
+            expr_kind: kind,
+        }
+    }
+
+    /// Converts a `cel.expr.Expr` to an expression. An `Expr` without a kind, as stands for a
+    /// nested macro call in [`SourceInfo::macro_calls`], converts to
+    /// [`ast::Expr::Unspecified`].
+    pub fn from_proto(proto: &Expr) -> Result<IdedExpr, FromProtoError> {
+        IdedExpr::from_proto_nested(proto, 1)
+    }
+
+    /// Converts `proto`, nested `depth` deep in the expression being converted.
+    fn from_proto_nested(proto: &Expr, depth: usize) -> Result<IdedExpr, FromProtoError> {
+        let id = proto.id;
+        if depth > MAX_LOAD_DEPTH {
+            return Err(FromProtoError::TooDeep {
+                id,
+                max: MAX_LOAD_DEPTH,
+            });
+        }
+        let nested = |expr: &Expr| IdedExpr::from_proto_nested(expr, depth + 1);
+        let required = |expr: &Option<Box<Expr>>, field| match expr {
+            Some(expr) => nested(expr),
+            None => Err(FromProtoError::MissingField { id, field }),
+        };
+        let all = |exprs: &[Expr]| -> Result<Vec<_>, _> { exprs.iter().map(nested).collect() };
+        let expr = match &proto.expr_kind {
+            None => ast::Expr::Unspecified,
+            Some(expr::ExprKind::ConstExpr(constant)) => {
+                ast::Expr::Literal(LiteralValue::from_proto(constant, id)?)
+            }
+            Some(expr::ExprKind::IdentExpr(ident)) => ast::Expr::Ident(ident.name.clone()),
+            Some(expr::ExprKind::SelectExpr(select)) => ast::Expr::Select(SelectExpr {
+                operand: Box::new(required(&select.operand, "operand")?),
+                field: select.field.clone(),
+                test: select.test_only,
+            }),
+            Some(expr::ExprKind::CallExpr(call)) => ast::Expr::Call(CallExpr {
+                func_name: call.function.clone(),
+                target: match &call.target {
+                    Some(target) => Some(Box::new(nested(target)?)),
+                    None => None,
+                },
+                args: all(&call.args)?,
+            }),
+            Some(expr::ExprKind::ListExpr(list)) => {
+                let optional_indices = list.optional_indices.iter();
+                ast::Expr::List(ListExpr::new_with_optionals(
+                    all(&list.elements)?,
+                    optional_indices.map(|i| *i as usize).collect(),
+                ))
+            }
+            Some(expr::ExprKind::StructExpr(s)) if s.message_name.is_empty() => {
+                ast::Expr::Map(MapExpr {
+                    entries: entries_from_proto(&s.entries, id, nested)?,
+                })
+            }
+            Some(expr::ExprKind::StructExpr(s)) => ast::Expr::Struct(StructExpr {
+                type_name: s.message_name.clone(),
+                entries: entries_from_proto(&s.entries, id, nested)?,
+            }),
+            Some(expr::ExprKind::ComprehensionExpr(comprehension)) => {
+                ast::Expr::Comprehension(Box::new(ComprehensionExpr {
+                    iter_range: required(&comprehension.iter_range, "iter_range")?,
+                    iter_var: comprehension.iter_var.clone(),
+                    iter_var2: Some(comprehension.iter_var2.clone()).filter(|v| !v.is_empty()),
+                    accu_var: comprehension.accu_var.clone(),
+                    accu_init: required(&comprehension.accu_init, "accu_init")?,
+                    loop_cond: required(&comprehension.loop_condition, "loop_condition")?,
+                    loop_step: required(&comprehension.loop_step, "loop_step")?,
+                    result: required(&comprehension.result, "result")?,
+                }))
+            }
+        };
+        Ok(IdedExpr {
+            id: expr_id(id)?,
+            expr,
+        })
+    }
+}
+
+fn expr_id(id: i64) -> Result<u64, FromProtoError> {
+    u64::try_from(id).map_err(|_| FromProtoError::InvalidId(id))
+}
+
+fn entry_to_proto(entry: &IdedEntryExpr) -> expr::create_struct::Entry {
+    use expr::create_struct::entry::KeyKind;
+    let (key, value, optional) = match &entry.expr {
+        EntryExpr::StructField(field) => (
+            KeyKind::FieldKey(field.field.clone()),
+            &field.value,
+            field.optional,
+        ),
+        EntryExpr::MapEntry(entry) => (
+            KeyKind::MapKey(entry.key.to_proto()),
+            &entry.value,
+            entry.optional,
+        ),
+    };
+    expr::create_struct::Entry {
+        id: entry.id as i64,
+        key_kind: Some(key),
+        value: Some(value.to_proto()),
+        optional_entry: optional,
+    }
+}
+
+/// Converts the `entries` of the struct or map expression `id`, their keys and values with
+/// `nested`.
+fn entries_from_proto(
+    entries: &[expr::create_struct::Entry],
+    id: i64,
+    nested: impl Fn(&Expr) -> Result<IdedExpr, FromProtoError>,
+) -> Result<Vec<IdedEntryExpr>, FromProtoError> {
+    use expr::create_struct::entry::KeyKind;
+    entries
+        .iter()
+        .map(|entry| {
+            let value = match &entry.value {
+                Some(value) => nested(value)?,
+                None => return Err(FromProtoError::MissingField { id, field: "value" }),
+            };
+            let expr = match &entry.key_kind {
+                Some(KeyKind::FieldKey(field)) => EntryExpr::StructField(StructFieldExpr {
+                    field: field.clone(),
+                    value,
+                    optional: entry.optional_entry,
+                }),
+                Some(KeyKind::MapKey(key)) => EntryExpr::MapEntry(MapEntryExpr {
+                    key: nested(key)?,
+                    value,
+                    optional: entry.optional_entry,
+                }),
+                None => return Err(FromProtoError::MissingField { id, field: "key" }),
+            };
+            Ok(IdedEntryExpr {
+                id: expr_id(entry.id)?,
+                expr,
+            })
+        })
+        .collect()
+}
+
+impl LiteralValue {
+    /// Converts the literal to a `cel.expr.Constant`.
+    pub fn to_proto(&self) -> Constant {
+        use constant::ConstantKind;
+        let kind = match self {
+            LiteralValue::Null => ConstantKind::NullValue(prost_types::NullValue::NullValue as i32),
+            LiteralValue::Boolean(b) => ConstantKind::BoolValue(*b.inner()),
+            LiteralValue::Int(i) => ConstantKind::Int64Value(*i.inner()),
+            LiteralValue::UInt(u) => ConstantKind::Uint64Value(*u.inner()),
+            LiteralValue::Double(d) => ConstantKind::DoubleValue(*d.inner()),
+            LiteralValue::String(s) => ConstantKind::StringValue(s.inner().to_string()),
+            LiteralValue::Bytes(b) => ConstantKind::BytesValue(b.inner().to_vec()),
+        };
+        Constant {
+            constant_kind: Some(kind),
+        }
+    }
+
+    /// Converts the `cel.expr.Constant` of expression `id` to a literal.
+    pub fn from_proto(constant: &Constant, id: i64) -> Result<LiteralValue, FromProtoError> {
+        use constant::ConstantKind;
+        Ok(match &constant.constant_kind {
+            Some(ConstantKind::NullValue(_)) => LiteralValue::Null,
+            Some(ConstantKind::BoolValue(b)) => LiteralValue::Boolean(CelBool::from(*b)),
+            Some(ConstantKind::Int64Value(i)) => LiteralValue::Int(CelInt::from(*i)),
+            Some(ConstantKind::Uint64Value(u)) => LiteralValue::UInt(CelUInt::from(*u)),
+            Some(ConstantKind::DoubleValue(d)) => LiteralValue::Double(CelDouble::from(*d)),
+            Some(ConstantKind::StringValue(s)) => LiteralValue::String(CelString::from(s.as_str())),
+            Some(ConstantKind::BytesValue(b)) => LiteralValue::Bytes(CelBytes::from(b.clone())),
+            Some(ConstantKind::DurationValue(_) | ConstantKind::TimestampValue(_)) => {
+                return Err(FromProtoError::UnsupportedConstant { id });
+            }
+            None => {
+                return Err(FromProtoError::MissingField {
+                    id,
+                    field: "constant",
+                })
+            }
+        })
+    }
+}
+
+/// Converts between byte offsets into the source and the code point offsets of the messages.
+/// Without a source, offsets are taken to be the same.
+struct Offsets<'a> {
+    source: &'a str,
+    chars: Vec<usize>,
+}
+
+impl<'a> Offsets<'a> {
+    fn new(source: &'a str) -> Self {
+        Offsets {
+            source,
+            chars: source.char_indices().map(|(i, _)| i).collect(),
+        }
+    }
+
+    fn to_code_points(&self, byte: u32) -> i32 {
+        if self.source.is_empty() {
+            return byte as i32;
+        }
+        let (Ok(i) | Err(i)) = self.chars.binary_search(&(byte as usize));
+        i as i32
+    }
+
+    fn to_bytes(&self, code_point: i32) -> u32 {
+        if self.source.is_empty() {
+            return code_point as u32;
+        }
+        let code_point = code_point.max(0) as usize;
+        self.chars
+            .get(code_point)
+            .copied()
+            .unwrap_or(self.source.len()) as u32
+    }
+}
+
+impl ast::SourceInfo {
+    /// Converts the source info to a `cel.expr.SourceInfo`. Only the start of each expression
+    /// is kept, and macro calls are only there if the parser
+    /// [populated them](crate::parser::Parser::populate_macro_calls).
+    pub fn to_proto(&self) -> SourceInfo {
+        let offsets = Offsets::new(&self.source);
+        let mut line_offsets = Vec::new();
+        let mut offset = 0;
+        for line in self.source.split('\n') {
+            offset += line.chars().count() as i32 + 1;
+            line_offsets.push(offset);
+        }
+        SourceInfo {
+            syntax_version: String::new(),
+            location: self.name().to_string(),
+            line_offsets,
+            positions: self
+                .offsets
+                .iter()
+                .map(|(id, range)| (*id as i64, offsets.to_code_points(range.start)))
+                .collect(),
+            macro_calls: self
+                .macro_calls
+                .iter()
+                .map(|(id, call)| (*id as i64, call.to_proto()))
+                .collect(),
+        }
+    }
+
+    /// Converts a `cel.expr.SourceInfo` for the `source` of the expression, if at hand, to
+    /// source info. Positions can only be reported with the source.
+    pub fn from_proto(
+        proto: &SourceInfo,
+        source: Option<&str>,
+    ) -> Result<ast::SourceInfo, FromProtoError> {
+        let mut info = ast::SourceInfo {
+            source: source.unwrap_or_default().to_string(),
+            ..Default::default()
+        };
+        if !proto.location.is_empty() {
+            info.set_name(proto.location.clone());
+        }
+        let offsets = Offsets::new(&info.source);
+        let positions: Vec<_> = proto
+            .positions
+            .iter()
+            .map(|(id, position)| Ok((expr_id(*id)?, offsets.to_bytes(*position))))
+            .collect::<Result<_, FromProtoError>>()?;
+        for (id, start) in positions {
+            info.add_offset(id, start, start);
+        }
+        for (id, call) in &proto.macro_calls {
+            info.add_macro_call(expr_id(*id)?, IdedExpr::from_proto(call)?);
+        }
+        Ok(info)
+    }
+}
+
+impl Program {
+    /// Loads a program from a `cel.expr.ParsedExpr`, for instance one parsed by cel-go, given
+    /// the source it was parsed from, if at hand, to report positions in.
+    ///
+    /// # Example
+    /// ```
+    /// # use cel::{Context, Program};
+    /// # use cel::parser::Parser;
+    /// # use cel::common::ast::proto::ParsedExpr;
+    /// # use prost::Message;
+    /// let parser = Parser::new().populate_macro_calls(true);
+    /// let exported = Program::compile_with(parser, "[1, 2].all(x, x > 0)")
+    ///     .unwrap()
+    ///     .to_parsed_expr()
+    ///     .encode_to_vec();
+    ///
+    /// let parsed = ParsedExpr::decode(exported.as_slice()).unwrap();
+    /// let program = Program::from_parsed_expr(&parsed, None).unwrap();
+    /// assert_eq!(program.execute(&Context::default()), Ok(true.into()));
+    /// ```
+    pub fn from_parsed_expr(
+        parsed: &ParsedExpr,
+        source: Option<&str>,
+    ) -> Result<Program, FromProtoError> {
+        Program::from_proto(parsed.expr.as_ref(), parsed.source_info.as_ref(), source)
+    }
+
+    /// Loads a program from a `cel.expr.CheckedExpr`. Its references and types are not used:
+    /// the program runs as it would from the expression as parsed.
+    pub fn from_checked_expr(
+        checked: &CheckedExpr,
+        source: Option<&str>,
+    ) -> Result<Program, FromProtoError> {
+        Program::from_proto(checked.expr.as_ref(), checked.source_info.as_ref(), source)
+    }
+
+    fn from_proto(
+        expr: Option<&Expr>,
+        source_info: Option<&SourceInfo>,
+        source: Option<&str>,
+    ) -> Result<Program, FromProtoError> {
+        let expression = match expr {
+            Some(expr) => IdedExpr::from_proto(expr)?,
+            None => {
+                return Err(FromProtoError::MissingField {
+                    id: 0,
+                    field: "expr",
+                })
+            }
+        };
+        let source_info = match source_info {
+            Some(info) => ast::SourceInfo::from_proto(info, source)?,
+            None => ast::SourceInfo {
+                source: source.unwrap_or_default().to_string(),
+                ..Default::default()
+            },
+        };
+        Ok(Program::from_parsed(
+            expression,
+            Arc::new(source_info),
+            true,
+        )?)
+    }
+
+    /// Converts the program to a `cel.expr.ParsedExpr`, which cel-go or cel-cpp can run with
+    /// the same expression ids. Compile with a parser which
+    /// [populates macro calls](crate::parser::Parser::populate_macro_calls) to include them.
+    pub fn to_parsed_expr(&self) -> ParsedExpr {
+        ParsedExpr {
+            expr: Some(self.expression.to_proto()),
+            source_info: Some(self.source_info.to_proto()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::{Context, Value};
+    use prost::Message;
+
+    fn compile(source: &str) -> Program {
+        let parser = Parser::new()
+            .populate_macro_calls(true)
+            .enable_optional_syntax(true);
+        Program::compile_with(parser, source).unwrap()
+    }
+
+    #[test]
+    fn wire_format() {
+        let parsed = ParsedExpr {
+            expr: Some(
+                IdedExpr {
+                    id: 1,
+                    expr: ast::Expr::Literal(LiteralValue::Int(CelInt::from(42))),
+                }
+                .to_proto(),
+            ),
+            source_info: None,
+        };
+        let bytes = parsed.encode_to_vec();
+        assert_eq!(bytes, [0x12, 0x06, 0x10, 0x01, 0x1a, 0x02, 0x18, 0x2a]);
+        assert_eq!(ParsedExpr::decode(bytes.as_slice()).unwrap(), parsed);
+    }
+
+    #[test]
+    fn round_trip() {
+        for source in [
+            "null == null && true != false",
+            "-1 + 2u * 3.5 / b'\\x00'.size() % 4 > 'x'.size()",
+            "a.b.c[0] in [1, ?x.y, 3] ? {'k': v, ?'o': w}.k : has(m.f)",
+            "[1, 2].map(x, [x].exists(y, y > 1)) == [[1]].filter(l, l.all(e, e != 2))",
+            "{1: 'a'}.transformList(k, v, v) == [] || Msg{field: 1, ?opt: x}.field > 0",
+        ] {
+            let program = compile(source);
+            let bytes = program.to_parsed_expr().encode_to_vec();
+            let parsed = ParsedExpr::decode(bytes.as_slice()).unwrap();
+            let loaded = Program::from_parsed_expr(&parsed, Some(source)).unwrap();
+            assert_eq!(loaded.expression(), program.expression(), "{source}");
+            assert_eq!(
+                loaded.source_info.macro_calls().collect::<Vec<_>>(),
+                program.source_info.macro_calls().collect::<Vec<_>>(),
+                "{source}"
+            );
+            for id in 1..=program.expression().id + 20 {
+                assert_eq!(
+                    loaded.source_info.pos_for(id),
+                    program.source_info.pos_for(id),
+                    "{source}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn macro_calls() {
+        let program = compile("[[1]].all(l, l.exists(e, e > 0))");
+        let info = program.to_parsed_expr().source_info.unwrap();
+        assert_eq!(info.macro_calls.len(), 2);
+        let all = &info.macro_calls[&(program.expression().id as i64)];
+        let Some(expr::ExprKind::CallExpr(call)) = &all.expr_kind else {
+            panic!("not a call: {all:?}");
+        };
+        assert_eq!(call.function, "all");
+        // The nested exists() is left as the id of its expansion.
+        let exists = &call.args[1];
+        assert_eq!(exists.expr_kind, None);
+        assert!(info.macro_calls.contains_key(&exists.id));
+    }
+
+    #[test]
+    fn positions_in_code_points() {
+        let source = "'é' + x";
+        let program = compile(source);
+        let info = program.to_parsed_expr().source_info.unwrap();
+        let x = program.expression().id as i64 + 1;
+        assert_eq!(info.positions[&x], 6);
+        assert_eq!(info.line_offsets, [8]);
+
+        let parsed = program.to_parsed_expr();
+        let err = Program::from_parsed_expr(&parsed, Some(source))
+            .unwrap()
+            .execute_with_location(&Context::default())
+            .unwrap_err();
+        assert_eq!(err.position(), Some((1, 8)));
+    }
+
+    #[test]
+    fn from_go() {
+        // `x.exists(i, i == 2)` as cel-go parses it.
+        let ident = |id, name: &str| Expr {
+            id,
+            expr_kind: Some(expr::ExprKind::IdentExpr(expr::Ident { name: name.into() })),
+        };
+        let call = |id, function: &str, args| Expr {
+            id,
+            expr_kind: Some(expr::ExprKind::CallExpr(expr::Call {
+                target: None,
+                function: function.into(),
+                args,
+            })),
+        };
+        let constant = |id, kind| Expr {
+            id,
+            expr_kind: Some(expr::ExprKind::ConstExpr(Constant {
+                constant_kind: Some(kind),
+            })),
+        };
+        let parsed = ParsedExpr {
+            expr: Some(Expr {
+                id: 14,
+                expr_kind: Some(expr::ExprKind::ComprehensionExpr(expr::Comprehension {
+                    iter_var: "i".into(),
+                    iter_var2: String::new(),
+                    iter_range: Some(Box::new(ident(1, "x"))),
+                    accu_var: "@result".into(),
+                    accu_init: Some(Box::new(constant(
+                        7,
+                        constant::ConstantKind::BoolValue(false),
+                    ))),
+                    loop_condition: Some(Box::new(call(
+                        10,
+                        "@not_strictly_false",
+                        vec![call(9, "!_", vec![ident(8, "@result")])],
+                    ))),
+                    loop_step: Some(Box::new(call(
+                        12,
+                        "_||_",
+                        vec![
+                            ident(11, "@result"),
+                            call(
+                                5,
+                                "_==_",
+                                vec![
+                                    ident(4, "i"),
+                                    constant(6, constant::ConstantKind::Int64Value(2)),
+                                ],
+                            ),
+                        ],
+                    ))),
+                    result: Some(Box::new(ident(13, "@result"))),
+                })),
+            }),
+            source_info: Some(SourceInfo {
+                location: "<input>".into(),
+                line_offsets: vec![20],
+                positions: HashMap::from([(1, 0), (4, 12), (5, 14), (6, 17), (14, 8)]),
+                ..Default::default()
+            }),
+        };
+        let program = Program::from_parsed_expr(&parsed, None).unwrap();
+        assert_eq!(program.expression().id, 14);
+
+        let mut context = Context::default();
+        context.add_variable_from_value("x", vec![1, 2, 3]);
+        assert_eq!(program.execute(&context), Ok(Value::Bool(true)));
+        context.add_variable_from_value("x", vec![1, 3]);
+        assert_eq!(program.execute(&context), Ok(Value::Bool(false)));
+    }
+
+    #[test]
+    fn invalid() {
+        let parsed = ParsedExpr {
+            expr: Some(Expr {
+                id: 1,
+                expr_kind: Some(expr::ExprKind::SelectExpr(expr::Select {
+                    operand: None,
+                    field: "f".into(),
+                    test_only: false,
+                })),
+            }),
+            source_info: None,
+        };
+        assert_eq!(
+            Program::from_parsed_expr(&parsed, None).unwrap_err(),
+            FromProtoError::MissingField {
+                id: 1,
+                field: "operand"
+            }
+        );
+        assert_eq!(
+            Program::from_parsed_expr(&ParsedExpr::default(), None).unwrap_err(),
+            FromProtoError::MissingField {
+                id: 0,
+                field: "expr"
+            }
+        );
+    }
+}
@@ -1,8 +1,13 @@
 use std::borrow::Cow;
 
 use crate::common::functions::Function;
+#[cfg(feature = "regex")]
+use crate::common::regex::{RegexLimits, RegexOp};
 use crate::common::types::Type;
 use crate::common::value::Val;
+use crate::ExecutionError;
+#[cfg(feature = "regex")]
+use regex::Regex;
 
 pub struct FunctionDecl {
     pub name: String,
@@ -17,20 +22,35 @@ impl FunctionDecl {
         }
     }
 
+    /// The first overload which can be called with `args`, as a plain [`Function`]. `None` if
+    /// it compiles patterns within limits other than the default ones, which a plain
+    /// [`Function`] can't be bound to.
     pub fn find_overload(&self, member_function: bool, args: &[Cow<dyn Val>]) -> Option<Function> {
-        for overload in &self.overloads {
-            if overload.member_function == member_function
-                && args.len() == overload.arg_types.len()
-                && overload
-                    .arg_types
-                    .iter()
-                    .enumerate()
-                    .all(|(i, t)| t.is_assignable(args[i].as_ref()))
-            {
-                return Some(overload.op);
-            }
-        }
-        None
+        self.find(member_function, args)
+            .and_then(OverloadDecl::function)
+    }
+
+    /// The first overload which can be called with `args`.
+    pub(crate) fn find(
+        &self,
+        member_function: bool,
+        args: &[Cow<dyn Val>],
+    ) -> Option<&OverloadDecl> {
+        self.overloads
+            .iter()
+            .find(|overload| overload.member_function == member_function && overload.accepts(args))
+    }
+
+    /// The overloads which could be called with `arity` arguments, counting the target of a
+    /// member function as one.
+    pub(crate) fn candidates(
+        &self,
+        member_function: bool,
+        arity: usize,
+    ) -> impl Iterator<Item = &OverloadDecl> {
+        self.overloads.iter().filter(move |overload| {
+            overload.member_function == member_function && overload.arg_types.len() == arity
+        })
     }
 
     pub(crate) fn add_overload(
@@ -38,7 +58,7 @@ impl FunctionDecl {
         id: String,
         member_function: bool,
         arg_types: Vec<Type>,
-        op: Function,
+        op: Op,
     ) -> Result<(), ()> {
         if self.is_present(&id, member_function, &arg_types) {
             return Err(());
@@ -52,6 +72,16 @@ impl FunctionDecl {
         Ok(())
     }
 
+    /// Makes the overloads compiling a pattern compile it within `limits`.
+    #[cfg(feature = "regex")]
+    pub(crate) fn set_regex_limits(&mut self, limits: &RegexLimits) {
+        for overload in &mut self.overloads {
+            if let Op::Regex(op) = &mut overload.op {
+                op.set_limits(limits.clone());
+            }
+        }
+    }
+
     fn is_present(&self, name: &str, member_function: bool, arg_types: &[Type]) -> bool {
         for overload in &self.overloads {
             if overload.id == name
@@ -70,7 +100,97 @@ pub struct OverloadDecl {
     //result_type: &'a Type<'a>,
     member_function: bool,
     //operand_traits: TraitSet,
-    op: Function,
+    op: Op,
+}
+
+/// The implementation of an overload.
+#[derive(Clone)]
+pub(crate) enum Op {
+    Function(Function),
+    /// An overload compiling a pattern, within the limits of the [`Env`](crate::Env) it was
+    /// added to.
+    #[cfg(feature = "regex")]
+    Regex(RegexOp),
+}
+
+impl OverloadDecl {
+    /// Whether this overload can be called with `args`.
+    pub(crate) fn accepts(&self, args: &[Cow<dyn Val>]) -> bool {
+        args.len() == self.arg_types.len()
+            && self
+                .arg_types
+                .iter()
+                .zip(args)
+                .all(|(t, arg)| t.is_assignable(arg.as_ref()))
+    }
+
+    pub(crate) fn call<'a>(
+        &self,
+        args: Vec<Cow<'a, dyn Val>>,
+    ) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+        match &self.op {
+            Op::Function(op) => op(args),
+            #[cfg(feature = "regex")]
+            Op::Regex(op) => op.call(args),
+        }
+    }
+
+    /// Calls the overload, matching against `re`, the pattern of the call compiled ahead of
+    /// execution within the default limits, if it takes a pattern as its last argument and
+    /// compiles within those limits.
+    #[cfg(feature = "regex")]
+    pub(crate) fn call_with_pattern<'a>(
+        &self,
+        re: &Regex,
+        args: Vec<Cow<'a, dyn Val>>,
+    ) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+        match &self.op {
+            Op::Regex(RegexOp {
+                precompiled: Some(precompiled),
+                limits,
+                ..
+            }) if *limits == RegexLimits::default() => precompiled(re, &args),
+            _ => self.call(args),
+        }
+    }
+
+    /// Binds `pattern`, the literal last argument of a call, ahead of execution: if the
+    /// overload takes a pattern as its last argument, the overload returned matches against
+    /// `pattern` compiled within its limits whenever called. Patterns which don't compile
+    /// within them are left to fail when called.
+    #[cfg(feature = "regex")]
+    pub(crate) fn with_pattern(&self, pattern: &str) -> OverloadDecl {
+        let mut overload = self.clone();
+        if let Op::Regex(op) = &mut overload.op {
+            if op.precompiled.is_some() {
+                op.pattern = crate::common::regex::compile(&self.id, pattern, &op.limits)
+                    .ok()
+                    .map(|re| Regex::clone(&re));
+            }
+        }
+        overload
+    }
+
+    /// The overload as a plain [`Function`], unless it compiles patterns within limits other
+    /// than the default ones, which a plain [`Function`] can't be bound to.
+    fn function(&self) -> Option<Function> {
+        match &self.op {
+            Op::Function(op) => Some(*op),
+            #[cfg(feature = "regex")]
+            Op::Regex(op) => (op.limits == RegexLimits::default()).then(|| op.function()),
+        }
+    }
+}
+
+impl Clone for OverloadDecl {
+    fn clone(&self) -> Self {
+        OverloadDecl {
+            id: self.id.clone(),
+            arg_types: self.arg_types.iter().map(Type::to_owned).collect(),
+            member_function: self.member_function,
+            op: self.op.clone(),
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -1,6 +1,8 @@
 pub mod ast;
 pub mod decls;
 pub mod functions;
+#[cfg(feature = "regex")]
+pub(crate) mod regex;
 pub mod traits;
 pub mod types;
 pub mod value;
@@ -0,0 +1,710 @@
+use crate::common::ast::{Expr, IdedExpr, LiteralValue, SourceInfo};
+use crate::common::functions::Function;
+use crate::common::types::{self, CelInt, CelList, CelOptional, CelString};
+use crate::common::value::Val;
+use crate::{ExecutionError, ParseError, ParseErrorKind};
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, LazyLock, Mutex};
+
+/// Maximum number of compiled patterns kept in the shared [`RegexCache`].
+const CACHE_CAPACITY: usize = 256;
+
+static CACHE: LazyLock<RegexCache> = LazyLock::new(|| RegexCache::new(CACHE_CAPACITY));
+
+/// Limits enforced when compiling the regular expressions used by `matches` and the `regex.*`
+/// functions, configured per [`Env`](crate::Env) with
+/// [`Env::set_regex_limits`](crate::Env::set_regex_limits).
+///
+/// Patterns are usually as untrusted as the input they are matched against; these bound the
+/// memory a single pattern can make the regex engine use. Patterns exceeding them fail with
+/// [`ExecutionError::RegexLimitExceeded`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RegexLimits {
+    size_limit: usize,
+    dfa_size_limit: usize,
+    max_pattern_length: Option<usize>,
+}
+
+impl Default for RegexLimits {
+    /// The defaults of [`regex::RegexBuilder`], with no maximum pattern length.
+    fn default() -> Self {
+        Self {
+            size_limit: 10 * (1 << 20),
+            dfa_size_limit: 2 * (1 << 20),
+            max_pattern_length: None,
+        }
+    }
+}
+
+impl RegexLimits {
+    /// Sets the approximate size, in bytes, a compiled pattern may use.
+    /// See [`regex::RegexBuilder::size_limit`].
+    pub fn size_limit(mut self, bytes: usize) -> Self {
+        self.size_limit = bytes;
+        self
+    }
+
+    /// Sets the approximate size, in bytes, of the cache used by the lazy DFA of a pattern.
+    /// See [`regex::RegexBuilder::dfa_size_limit`].
+    pub fn dfa_size_limit(mut self, bytes: usize) -> Self {
+        self.dfa_size_limit = bytes;
+        self
+    }
+
+    /// Sets the maximum length, in bytes, of a pattern.
+    pub fn max_pattern_length(mut self, bytes: usize) -> Self {
+        self.max_pattern_length = Some(bytes);
+        self
+    }
+
+    /// Compiles `pattern` within these limits, reporting failures as errors of `function`.
+    pub(crate) fn build(&self, function: &str, pattern: &str) -> Result<Regex, ExecutionError> {
+        if let Some(max) = self.max_pattern_length {
+            if pattern.len() > max {
+                return Err(ExecutionError::RegexLimitExceeded(format!(
+                    "pattern is {} bytes long, the maximum is {max}",
+                    pattern.len()
+                )));
+            }
+        }
+        RegexBuilder::new(pattern)
+            .size_limit(self.size_limit)
+            .dfa_size_limit(self.dfa_size_limit)
+            .build()
+            .map_err(|err| match err {
+                regex::Error::CompiledTooBig(limit) => ExecutionError::RegexLimitExceeded(format!(
+                    "compiled pattern exceeds the size limit of {limit} bytes"
+                )),
+                err => ExecutionError::function_error(function, invalid_regex(pattern, &err)),
+            })
+    }
+}
+
+/// A bounded cache of compiled regular expressions, shared by `matches` and the `regex.*`
+/// extension functions so that a pattern is only compiled once across evaluations. A pattern
+/// is cached once per [`RegexLimits`] it is compiled within, so that environments with
+/// different limits don't evict each other's.
+///
+/// Once `capacity` patterns are cached, the least recently inserted one is evicted.
+pub(crate) struct RegexCache {
+    capacity: usize,
+    inner: Mutex<CacheInner>,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    /// The compiled patterns, with the limits each was compiled within.
+    entries: HashMap<String, Vec<(RegexLimits, Arc<Regex>)>>,
+    /// The pattern and limits of every cached entry, least recently inserted first.
+    order: VecDeque<(String, RegexLimits)>,
+}
+
+impl CacheInner {
+    fn get(&self, pattern: &str, limits: &RegexLimits) -> Option<&Arc<Regex>> {
+        self.entries
+            .get(pattern)?
+            .iter()
+            .find(|(cached, _)| cached == limits)
+            .map(|(_, re)| re)
+    }
+
+    fn evict_oldest(&mut self) {
+        let Some((pattern, limits)) = self.order.pop_front() else {
+            return;
+        };
+        if let Some(compiled) = self.entries.get_mut(&pattern) {
+            compiled.retain(|(cached, _)| *cached != limits);
+            if compiled.is_empty() {
+                self.entries.remove(&pattern);
+            }
+        }
+    }
+}
+
+impl RegexCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(CacheInner::default()),
+        }
+    }
+
+    /// Returns `pattern` compiled within `limits`, compiling and caching it first if needed.
+    pub(crate) fn get_or_compile(
+        &self,
+        function: &str,
+        pattern: &str,
+        limits: &RegexLimits,
+    ) -> Result<Arc<Regex>, ExecutionError> {
+        if let Some(re) = self.lock().get(pattern, limits) {
+            return Ok(re.clone());
+        }
+        // Compile outside the lock, so that a slow pattern doesn't block other evaluations
+        let re = Arc::new(limits.build(function, pattern)?);
+        if self.capacity > 0 {
+            let mut inner = self.lock();
+            // Unless another evaluation cached it meanwhile
+            if inner.get(pattern, limits).is_none() {
+                while inner.order.len() >= self.capacity {
+                    inner.evict_oldest();
+                }
+                inner.order.push_back((pattern.to_owned(), limits.clone()));
+                inner
+                    .entries
+                    .entry(pattern.to_owned())
+                    .or_default()
+                    .push((limits.clone(), re.clone()));
+            }
+        }
+        Ok(re)
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.lock().order.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheInner> {
+        // The cache holds no invariant a panicking thread could break, recover from poisoning
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Compiles `pattern` within `limits` through the shared cache, reporting failures as errors of
+/// `function`.
+pub(crate) fn compile(
+    function: &str,
+    pattern: &str,
+    limits: &RegexLimits,
+) -> Result<Arc<Regex>, ExecutionError> {
+    CACHE.get_or_compile(function, pattern, limits)
+}
+
+fn invalid_regex(pattern: &str, err: &regex::Error) -> String {
+    format!("'{pattern}' not a valid regex:\n{err}")
+}
+
+/// Compiles the pattern of every `matches` call whose pattern is a string literal, keyed by the
+/// id of the call expression. Invalid patterns are reported as [`ParseError`]s pointing at the
+/// literal.
+///
+/// Patterns are compiled within the default [`RegexLimits`], and are only used by overloads
+/// which have those. Whether a pattern is within the limits depends on the
+/// [`Env`](crate::Env) executing it, so patterns that aren't within the default ones are left
+/// to be compiled, and rejected or not, when planned or executed.
+pub(crate) fn precompile_literals(
+    expr: &IdedExpr,
+    source_info: &Arc<SourceInfo>,
+    regexes: &mut HashMap<u64, Regex>,
+    errors: &mut Vec<ParseError>,
+) {
+    for (expr, _) in expr.walk() {
+        let Expr::Call(call) = &expr.expr else {
+            continue;
+        };
+        let pattern = match (&call.target, call.args.as_slice()) {
+            (Some(_), [pattern]) | (None, [_, pattern]) if call.func_name == "matches" => pattern,
+            _ => continue,
+        };
+        let IdedExpr {
+            id,
+            expr: Expr::Literal(LiteralValue::String(pattern)),
+        } = pattern
+        else {
+            continue;
+        };
+        match RegexLimits::default().build("matches", pattern.inner()) {
+            Ok(re) => {
+                regexes.insert(expr.id, re);
+            }
+            Err(ExecutionError::RegexLimitExceeded(_)) => {}
+            Err(err) => errors.push(ParseError {
+                msg: match err {
+                    ExecutionError::FunctionError { message, .. } => message,
+                    err => err.to_string(),
+                },
+                pos: source_info.pos_for(*id).unwrap_or_default(),
+                source: None,
+                expr_id: *id,
+                source_info: Some(source_info.clone()),
+                kind: ParseErrorKind::InvalidExpression,
+            }),
+        }
+    }
+}
+
+fn string_arg<'v>(
+    function: &str,
+    args: &'v [Cow<dyn Val>],
+    idx: usize,
+) -> Result<&'v str, ExecutionError> {
+    args[idx]
+        .downcast_ref::<CelString>()
+        .map(|s| s.inner())
+        .ok_or_else(|| {
+            ExecutionError::function_error(
+                function,
+                format!("expected string, got {}", args[idx].get_type().name()),
+            )
+        })
+}
+
+/// Rejects patterns with more than one capture group, which `extract` and `extractAll` can't
+/// unambiguously map to a single string.
+fn single_group(function: &str, re: &Regex) -> Result<bool, ExecutionError> {
+    match re.captures_len() {
+        1 => Ok(false),
+        2 => Ok(true),
+        _ => Err(ExecutionError::function_error(
+            function,
+            format!(
+                "regular expression has more than one capturing group: '{}'",
+                re.as_str()
+            ),
+        )),
+    }
+}
+
+fn extract<'a>(
+    limits: &RegexLimits,
+    args: Vec<Cow<'a, dyn Val>>,
+) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+    let target = string_arg("regex.extract", &args, 0)?;
+    let re = compile(
+        "regex.extract",
+        string_arg("regex.extract", &args, 1)?,
+        limits,
+    )?;
+    let group = single_group("regex.extract", &re)?;
+    let found = re
+        .captures(target)
+        .and_then(|caps| caps.get(if group { 1 } else { 0 }))
+        .map(|m| Box::new(CelString::from(m.as_str())) as Box<dyn Val>);
+    Ok(Cow::<dyn Val>::Owned(Box::new(CelOptional::from(found))))
+}
+
+fn extract_all<'a>(
+    limits: &RegexLimits,
+    args: Vec<Cow<'a, dyn Val>>,
+) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+    let target = string_arg("regex.extractAll", &args, 0)?;
+    let re = compile(
+        "regex.extractAll",
+        string_arg("regex.extractAll", &args, 1)?,
+        limits,
+    )?;
+    let group = single_group("regex.extractAll", &re)?;
+    let found: Vec<Box<dyn Val>> = re
+        .captures_iter(target)
+        .filter_map(|caps| caps.get(if group { 1 } else { 0 }))
+        .filter(|m| !group || !m.is_empty())
+        .map(|m| Box::new(CelString::from(m.as_str())) as Box<dyn Val>)
+        .collect();
+    Ok(Cow::<dyn Val>::Owned(Box::new(CelList::from(found))))
+}
+
+/// A piece of a parsed replacement string: either literal text or a capture group reference.
+enum Replacement {
+    Literal(String),
+    Group(usize),
+}
+
+/// Parses a replacement string, where `\1` to `\9` refer to capture groups and `\\` is a
+/// literal backslash. All other characters, including `$`, are taken literally.
+fn parse_replacement(re: &Regex, repl: &str) -> Result<Vec<Replacement>, ExecutionError> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = repl.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            literal.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => literal.push('\\'),
+            Some(d) if d.is_ascii_digit() => {
+                let group = d.to_digit(10).expect("is a digit") as usize;
+                if group >= re.captures_len() {
+                    return Err(ExecutionError::function_error(
+                        "regex.replace",
+                        format!("replacement string references group {group} which does not exist in '{}'", re.as_str()),
+                    ));
+                }
+                if !literal.is_empty() {
+                    parts.push(Replacement::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Replacement::Group(group));
+            }
+            _ => {
+                return Err(ExecutionError::function_error(
+                    "regex.replace",
+                    format!("invalid replacement string: '{repl}'"),
+                ))
+            }
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(Replacement::Literal(literal));
+    }
+    Ok(parts)
+}
+
+fn replace_n<'a>(
+    limits: &RegexLimits,
+    args: &[Cow<'a, dyn Val>],
+    count: Option<i64>,
+) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+    let target = string_arg("regex.replace", args, 0)?;
+    let re = compile(
+        "regex.replace",
+        string_arg("regex.replace", args, 1)?,
+        limits,
+    )?;
+    let parts = parse_replacement(&re, string_arg("regex.replace", args, 2)?)?;
+    // A negative count replaces all matches
+    let limit = match count {
+        Some(n) if n >= 0 => n as usize,
+        _ => usize::MAX,
+    };
+
+    let mut result = String::with_capacity(target.len());
+    let mut last = 0;
+    for caps in re.captures_iter(target).take(limit) {
+        let m = caps.get(0).expect("group 0 is always present");
+        result.push_str(&target[last..m.start()]);
+        for part in &parts {
+            match part {
+                Replacement::Literal(s) => result.push_str(s),
+                Replacement::Group(g) => {
+                    result.push_str(caps.get(*g).map(|m| m.as_str()).unwrap_or_default())
+                }
+            }
+        }
+        last = m.end();
+    }
+    result.push_str(&target[last..]);
+    Ok(Cow::<dyn Val>::Owned(Box::new(CelString::from(result))))
+}
+
+fn replace<'a>(
+    limits: &RegexLimits,
+    args: Vec<Cow<'a, dyn Val>>,
+) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+    replace_n(limits, &args, None)
+}
+
+fn replace_count<'a>(
+    limits: &RegexLimits,
+    args: Vec<Cow<'a, dyn Val>>,
+) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+    let count = args[3]
+        .downcast_ref::<CelInt>()
+        .map(|i| *i.inner())
+        .ok_or_else(|| ExecutionError::function_error("regex.replace", "count must be an int"))?;
+    replace_n(limits, &args, Some(count))
+}
+
+/// An overload compiling a pattern, within the [`RegexLimits`] it is given.
+pub(crate) type LimitedFunction =
+    for<'a> fn(&RegexLimits, Vec<Cow<'a, dyn Val>>) -> Result<Cow<'a, dyn Val>, ExecutionError>;
+
+/// An overload matching against a pattern compiled ahead of execution, the last of its
+/// arguments.
+pub(crate) type PrecompiledFunction =
+    for<'a> fn(&Regex, &[Cow<'a, dyn Val>]) -> Result<Cow<'a, dyn Val>, ExecutionError>;
+
+/// The implementation of an overload compiling a pattern, within the [`RegexLimits`] of the
+/// [`Env`](crate::Env) it was added to.
+#[derive(Clone)]
+pub(crate) struct RegexOp {
+    pub(crate) op: LimitedFunction,
+    /// `op` within the default limits, for when it must be a plain [`Function`].
+    pub(crate) function: Function,
+    /// `op` given its pattern compiled, if the pattern is its last argument.
+    pub(crate) precompiled: Option<PrecompiledFunction>,
+    pub(crate) limits: RegexLimits,
+    /// The pattern of the call, compiled ahead of execution within `limits`, which
+    /// `precompiled` matches against rather than compiling it on every call, see
+    /// [`OverloadDecl::with_pattern`](crate::common::decls::OverloadDecl::with_pattern).
+    pub(crate) pattern: Option<Regex>,
+}
+
+impl RegexOp {
+    pub(crate) fn call<'a>(
+        &self,
+        args: Vec<Cow<'a, dyn Val>>,
+    ) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+        match (&self.pattern, self.precompiled) {
+            (Some(re), Some(precompiled)) => precompiled(re, &args),
+            _ => (self.op)(&self.limits, args),
+        }
+    }
+
+    pub(crate) fn function(&self) -> Function {
+        self.function
+    }
+
+    pub(crate) fn set_limits(&mut self, limits: RegexLimits) {
+        self.limits = limits;
+    }
+}
+
+/// The [`Op`](crate::common::decls::Op) of the [`LimitedFunction`] `op`, within the default
+/// limits until the [`Env`](crate::Env) sets others, and of the [`PrecompiledFunction`]
+/// `precompiled` if the pattern is its last argument.
+macro_rules! regex_op {
+    ($op:path) => {
+        $crate::common::regex::regex_op!($op, None)
+    };
+    ($op:path, $precompiled:expr) => {
+        $crate::common::decls::Op::Regex($crate::common::regex::RegexOp {
+            op: $op,
+            function: |args| $op(&$crate::common::regex::RegexLimits::default(), args),
+            precompiled: $precompiled,
+            limits: $crate::common::regex::RegexLimits::default(),
+            pattern: None,
+        })
+    };
+}
+
+pub(crate) use regex_op;
+
+pub(crate) fn stdlib(env: &mut crate::Env) {
+    env.add_op(
+        "regex.extract",
+        "regex_extract_string_string",
+        false,
+        vec![types::STRING_TYPE, types::STRING_TYPE],
+        regex_op!(extract),
+    )
+    .expect("Must be unique id");
+    env.add_op(
+        "regex.extractAll",
+        "regex_extractAll_string_string",
+        false,
+        vec![types::STRING_TYPE, types::STRING_TYPE],
+        regex_op!(extract_all),
+    )
+    .expect("Must be unique id");
+    env.add_op(
+        "regex.replace",
+        "regex_replace_string_string_string",
+        false,
+        vec![types::STRING_TYPE, types::STRING_TYPE, types::STRING_TYPE],
+        regex_op!(replace),
+    )
+    .expect("Must be unique id");
+    env.add_op(
+        "regex.replace",
+        "regex_replace_string_string_string_int",
+        false,
+        vec![
+            types::STRING_TYPE,
+            types::STRING_TYPE,
+            types::STRING_TYPE,
+            types::INT_TYPE,
+        ],
+        regex_op!(replace_count),
+    )
+    .expect("Must be unique id");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RegexCache, RegexLimits};
+    use crate::tests::test_script;
+    use crate::{Context, Env, Program};
+    use std::sync::Arc;
+
+    #[test]
+    fn cache_reuses_compiled_pattern() {
+        let cache = RegexCache::new(2);
+        let limits = RegexLimits::default();
+        let a = cache.get_or_compile("test", "^a+$", &limits).unwrap();
+        let b = cache.get_or_compile("test", "^a+$", &limits).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(cache.len(), 1);
+        let c = cache
+            .get_or_compile("test", "^a+$", &limits.clone().size_limit(1 << 16))
+            .unwrap();
+        assert!(!Arc::ptr_eq(&a, &c));
+        assert_eq!(cache.len(), 2);
+        // Compiling within other limits doesn't replace the pattern within the first ones
+        let d = cache.get_or_compile("test", "^a+$", &limits).unwrap();
+        assert!(Arc::ptr_eq(&a, &d));
+    }
+
+    #[test]
+    fn cache_is_bounded() {
+        let cache = RegexCache::new(2);
+        let limits = RegexLimits::default();
+        let first = cache.get_or_compile("test", "a", &limits).unwrap();
+        cache.get_or_compile("test", "b", &limits).unwrap();
+        cache.get_or_compile("test", "c", &limits).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(!Arc::ptr_eq(
+            &first,
+            &cache.get_or_compile("test", "a", &limits).unwrap()
+        ));
+        assert!(cache.get_or_compile("test", "(", &limits).is_err());
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_regex_limits() {
+        let mut env = Env::stdlib();
+        env.set_regex_limits(
+            RegexLimits::default()
+                .max_pattern_length(8)
+                .size_limit(1 << 12),
+        );
+        let env = Arc::new(env);
+        let mut ctx = Context::with_env(env.clone());
+        ctx.add_variable_from_value("long", "a".repeat(9));
+        ctx.add_variable_from_value("big", "\\w{100}");
+
+        [
+            (
+                "matches",
+                "'a'.matches(long)",
+                "Regex limit exceeded: pattern is 9 bytes long, the maximum is 8",
+            ),
+            (
+                "precompiled literal",
+                "'a'.matches('aaaaaaaaa')",
+                "Regex limit exceeded: pattern is 9 bytes long, the maximum is 8",
+            ),
+            (
+                "extract",
+                "regex.extract('a', big)",
+                "Regex limit exceeded: compiled pattern exceeds the size limit of 4096 bytes",
+            ),
+            (
+                "replace",
+                "regex.replace('a', long, 'b')",
+                "Regex limit exceeded: pattern is 9 bytes long, the maximum is 8",
+            ),
+        ]
+        .iter()
+        .for_each(|(name, script, error)| {
+            let program = Program::compile(script).unwrap();
+            assert_eq!(
+                program.execute(&ctx).map_err(|e| e.to_string()),
+                Err(error.to_string()),
+                "{name}"
+            );
+        });
+
+        let program =
+            Program::compile("'ab'.matches('^a') && regex.extractAll('ab', 'b') == ['b']").unwrap();
+        assert_eq!(program.execute(&ctx), Ok(true.into()));
+    }
+
+    #[test]
+    fn literal_patterns_are_checked_against_env_limits() {
+        // Too big for the default limits, which only the executing environment may raise
+        let program = Program::compile("'a'.matches('a{1000}{1000}')").unwrap();
+        assert!(program.regexes.is_empty());
+        assert!(matches!(
+            program.execute(&Context::default()),
+            Err(crate::ExecutionError::RegexLimitExceeded(_))
+        ));
+
+        let mut env = Env::stdlib();
+        env.set_regex_limits(RegexLimits::default().size_limit(1 << 28));
+        let ctx = Context::with_env(Arc::new(env));
+        assert_eq!(program.execute(&ctx), Ok(false.into()));
+    }
+
+    #[test]
+    fn test_regex_ext() {
+        [
+            (
+                "extract",
+                "regex.extract('hello world', 'w[a-z]+').value() == 'world'",
+            ),
+            (
+                "extract group",
+                "regex.extract('item-42', 'item-(\\\\d+)').value() == '42'",
+            ),
+            (
+                "extract none",
+                "!regex.extract('hello', '\\\\d+').hasValue()",
+            ),
+            (
+                "extractAll",
+                "regex.extractAll('a1 b22 c333', '\\\\d+') == ['1', '22', '333']",
+            ),
+            (
+                "extractAll group",
+                "regex.extractAll('k=v x=y', '(\\\\w)=') == ['k', 'x']",
+            ),
+            ("extractAll empty", "regex.extractAll('abc', '\\\\d') == []"),
+            ("replace", "regex.replace('a-b-c', '-', '+') == 'a+b+c'"),
+            (
+                "replace count",
+                "regex.replace('a-b-c', '-', '+', 1) == 'a+b-c'",
+            ),
+            (
+                "replace zero",
+                "regex.replace('a-b-c', '-', '+', 0) == 'a-b-c'",
+            ),
+            (
+                "replace negative",
+                "regex.replace('a-b-c', '-', '+', -1) == 'a+b+c'",
+            ),
+            (
+                "replace groups",
+                "regex.replace('john smith', '(\\\\w+) (\\\\w+)', '\\\\2 \\\\1') == 'smith john'",
+            ),
+            (
+                "replace literal $",
+                "regex.replace('cost', 'cost', '$1') == '$1'",
+            ),
+            (
+                "replace backslash",
+                "regex.replace('a', 'a', '\\\\\\\\') == '\\\\'",
+            ),
+        ]
+        .iter()
+        .for_each(|(name, script)| {
+            assert_eq!(test_script(script, None), Ok(true.into()), "{name}");
+        });
+    }
+
+    #[test]
+    fn test_regex_ext_errors() {
+        [
+            (
+                "extract many groups",
+                "regex.extract('ab', '(a)(b)')",
+                "Error executing function 'regex.extract': regular expression has more than one capturing group: '(a)(b)'",
+            ),
+            (
+                "invalid pattern",
+                "regex.extractAll('ab', '(a')",
+                "Error executing function 'regex.extractAll': '(a' not a valid regex:\nregex parse error:\n    (a\n    ^\nerror: unclosed group",
+            ),
+            (
+                "missing group",
+                "regex.replace('ab', 'a', '\\\\1')",
+                "Error executing function 'regex.replace': replacement string references group 1 which does not exist in 'a'",
+            ),
+            (
+                "bad escape",
+                "regex.replace('ab', 'a', '\\\\z')",
+                "Error executing function 'regex.replace': invalid replacement string: '\\z'",
+            ),
+        ]
+        .iter()
+        .for_each(|(name, script, error)| {
+            assert_eq!(
+                test_script(script, None).map_err(|e| e.to_string()),
+                Err(error.to_string()),
+                "{name}"
+            );
+        });
+    }
+}
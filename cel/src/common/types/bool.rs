@@ -5,6 +5,8 @@ use crate::ExecutionError;
 use std::ops::Deref;
 
 #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct Bool(bool);
 
 impl Bool {
@@ -19,6 +21,15 @@ impl Bool {
     pub fn inner(&self) -> &bool {
         &self.0
     }
+
+    /// Borrows the static `true` or `false`, for results that needn't be allocated.
+    pub(crate) fn borrowed(value: bool) -> &'static Bool {
+        if value {
+            &Bool(true)
+        } else {
+            &Bool(false)
+        }
+    }
 }
 
 impl Deref for Bool {
@@ -5,9 +5,12 @@ use crate::Value;
 use crate::{common::traits, ExecutionError};
 use std::borrow::Cow;
 use std::ops::Deref;
+use std::sync::Arc;
 use traits::{Adder, Comparer};
 
 #[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct Bytes(Vec<u8>);
 
 impl Bytes {
@@ -104,6 +107,12 @@ impl From<Vec<u8>> for Bytes {
     }
 }
 
+impl From<Arc<Vec<u8>>> for Bytes {
+    fn from(v: Arc<Vec<u8>>) -> Self {
+        Self(Arc::unwrap_or_clone(v))
+    }
+}
+
 impl From<Bytes> for Vec<u8> {
     fn from(value: Bytes) -> Self {
         value.0
@@ -1,5 +1,5 @@
 use crate::common::traits::{Adder, Comparer, Divider, Multiplier, Negator, Subtractor, Zeroer};
-use crate::common::types::{CelInt, CelString, CelUInt, Kind, Type};
+use crate::common::types::{numeric, CelInt, CelString, CelUInt, Kind, Type};
 use crate::common::value::{Downcast, Val};
 use crate::{ExecutionError, Value};
 use std::borrow::Cow;
@@ -7,6 +7,8 @@ use std::cmp::Ordering;
 use std::ops::Deref;
 
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct Double(f64);
 
 impl Double {
@@ -87,24 +89,17 @@ impl Adder for Double {
 
 impl Comparer for Double {
     fn compare(&self, rhs: &dyn Val) -> Result<Ordering, ExecutionError> {
-        if let Some(rhs) = rhs.downcast_ref::<Self>() {
-            Ok(self
-                .0
-                .partial_cmp(&rhs.0)
-                .ok_or(ExecutionError::NoSuchOverload)?)
+        let ordering = if let Some(rhs) = rhs.downcast_ref::<Self>() {
+            self.0.partial_cmp(&rhs.0)
         } else if let Some(rhs) = rhs.downcast_ref::<CelInt>() {
-            Ok(self
-                .0
-                .partial_cmp(&(*rhs.inner() as f64))
-                .ok_or(ExecutionError::NoSuchOverload)?)
+            numeric::cmp_int_double(*rhs.inner(), self.0).map(Ordering::reverse)
         } else if let Some(rhs) = rhs.downcast_ref::<CelUInt>() {
-            Ok(self
-                .0
-                .partial_cmp(&(*rhs.inner() as f64))
-                .ok_or(ExecutionError::NoSuchOverload)?)
+            numeric::cmp_uint_double(*rhs.inner(), self.0).map(Ordering::reverse)
         } else {
-            Err(ExecutionError::NoSuchOverload)
-        }
+            None
+        };
+        // NaN can't be ordered.
+        ordering.ok_or(ExecutionError::NoSuchOverload)
     }
 }
 
@@ -1,6 +1,6 @@
 use crate::common::traits::Negator;
 use crate::common::traits::{self, Comparer};
-use crate::common::types::{CelDouble, CelString, CelUInt, Kind, Type};
+use crate::common::types::{numeric, CelDouble, CelString, CelUInt, Kind, Type};
 use crate::common::value::{Downcast, Val};
 use crate::ExecutionError;
 use std::borrow::Cow;
@@ -8,6 +8,8 @@ use std::cmp::Ordering;
 use std::ops::{Deref, Neg};
 
 #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct Int(i64);
 
 impl Int {
@@ -97,15 +99,9 @@ impl traits::Comparer for Int {
         if let Some(i) = rhs.downcast_ref::<Self>() {
             Ok(self.0.cmp(&i.0))
         } else if let Some(u) = rhs.downcast_ref::<CelUInt>() {
-            Ok((*self.inner())
-                .try_into()
-                .map(|a: u64| a.cmp(u.inner()))
-                // If the i64 doesn't fit into a u64 it must be less than 0.
-                .unwrap_or(Ordering::Less))
+            Ok(numeric::cmp_int_uint(self.0, *u.inner()))
         } else if let Some(d) = rhs.downcast_ref::<CelDouble>() {
-            Ok((*self.inner() as f64)
-                .partial_cmp(d.inner())
-                .ok_or(ExecutionError::NoSuchOverload)?)
+            numeric::cmp_int_double(self.0, *d.inner()).ok_or(ExecutionError::NoSuchOverload)
         } else {
             Err(ExecutionError::NoSuchOverload)
         }
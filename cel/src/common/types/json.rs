@@ -0,0 +1,391 @@
+use crate::common::traits::{self, Adder, Container, Indexer, Iterable, Sizer, Zeroer};
+use crate::common::types::{
+    self, CelBool, CelDouble, CelInt, CelList, CelMapKey, CelNull, CelString, CelUInt, Kind, Type,
+};
+use crate::common::value::Val;
+use crate::{ExecutionError, Value};
+use std::borrow::Cow;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, OnceLock};
+
+/// A JSON array or object, read straight from the document it is part of.
+///
+/// Nothing is converted up front: selecting a field or indexing an element only converts that
+/// one node, scalars to their CEL counterpart and arrays and objects to another [`Json`]
+/// sharing the same document. This keeps evaluating `request.body.items[0].id` proportional
+/// to the path it reads, not to the size of `request`.
+pub struct Json {
+    doc: Arc<serde_json::Value>,
+    /// The way from the root of `doc` to the node this stands for, which is the root itself
+    /// when there is none.
+    path: Option<Arc<Path>>,
+    /// The elements of an array or the keys of an object, once they've been iterated over.
+    items: OnceLock<Vec<Box<dyn Val>>>,
+}
+
+/// The last step of the way to a node, shared with the node it is taken from.
+struct Path {
+    parent: Option<Arc<Path>>,
+    step: Step,
+}
+
+enum Step {
+    Field(String),
+    Index(usize),
+}
+
+impl Json {
+    /// Wraps `doc` as a CEL value. Arrays and objects are read lazily, scalars are converted.
+    pub fn from_document(doc: Arc<serde_json::Value>) -> Box<dyn Val> {
+        match &*doc {
+            serde_json::Value::Array(_) | serde_json::Value::Object(_) => Box::new(Json {
+                doc,
+                path: None,
+                items: OnceLock::new(),
+            }),
+            scalar => scalar_to_val(scalar),
+        }
+    }
+
+    /// The JSON value this stands for.
+    pub fn inner(&self) -> &serde_json::Value {
+        resolve(&self.doc, self.path.as_deref())
+    }
+
+    /// Converts the whole of this node to a [`Value`].
+    pub fn to_value(&self) -> Value {
+        Value::from(self.inner())
+    }
+
+    /// Wraps `node`, which `step` leads to from this one.
+    fn child(&self, step: Step, node: &serde_json::Value) -> Box<dyn Val> {
+        match node {
+            serde_json::Value::Array(_) | serde_json::Value::Object(_) => Box::new(Json {
+                doc: self.doc.clone(),
+                path: Some(Arc::new(Path {
+                    parent: self.path.clone(),
+                    step,
+                })),
+                items: OnceLock::new(),
+            }),
+            scalar => scalar_to_val(scalar),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self.inner() {
+            serde_json::Value::Array(array) => array.len(),
+            serde_json::Value::Object(object) => object.len(),
+            _ => 0,
+        }
+    }
+
+    fn items(&self) -> &[Box<dyn Val>] {
+        self.items.get_or_init(|| match self.inner() {
+            serde_json::Value::Array(array) => array
+                .iter()
+                .enumerate()
+                .map(|(i, item)| self.child(Step::Index(i), item))
+                .collect(),
+            serde_json::Value::Object(object) => object
+                .keys()
+                .map(|k| Box::new(CelString::from(k.as_str())) as Box<dyn Val>)
+                .collect(),
+            _ => Vec::new(),
+        })
+    }
+}
+
+/// Follows `path` from the root of `doc`.
+fn resolve<'a>(doc: &'a serde_json::Value, path: Option<&Path>) -> &'a serde_json::Value {
+    let Some(path) = path else {
+        return doc;
+    };
+    let node = resolve(doc, path.parent.as_deref());
+    match &path.step {
+        Step::Field(name) => &node[name.as_str()],
+        Step::Index(idx) => &node[*idx],
+    }
+}
+
+fn scalar_to_val(json: &serde_json::Value) -> Box<dyn Val> {
+    match json {
+        serde_json::Value::Bool(b) => Box::new(CelBool::from(*b)),
+        serde_json::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Box::new(CelInt::from(i))
+            } else if let Some(u) = n.as_u64() {
+                Box::new(CelUInt::from(u))
+            } else {
+                Box::new(CelDouble::from(n.as_f64().unwrap_or(f64::NAN)))
+            }
+        }
+        serde_json::Value::String(s) => Box::new(CelString::from(s.as_str())),
+        _ => Box::new(CelNull),
+    }
+}
+
+impl Debug for Json {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Json").field(self.inner()).finish()
+    }
+}
+
+impl Val for Json {
+    fn get_type(&self) -> &Type {
+        match self.inner() {
+            serde_json::Value::Object(_) => &types::MAP_TYPE,
+            _ => &types::LIST_TYPE,
+        }
+    }
+
+    fn as_adder(&self) -> Option<&dyn Adder> {
+        match self.inner() {
+            serde_json::Value::Array(_) => Some(self),
+            _ => None,
+        }
+    }
+
+    fn as_container(&self) -> Option<&dyn Container> {
+        Some(self)
+    }
+
+    fn as_indexer(&self) -> Option<&dyn Indexer> {
+        Some(self)
+    }
+
+    fn into_indexer(self: Box<Self>) -> Option<Box<dyn Indexer>> {
+        Some(self)
+    }
+
+    fn as_iterable(&self) -> Option<&dyn Iterable> {
+        Some(self)
+    }
+
+    fn as_sizer(&self) -> Option<&dyn Sizer> {
+        Some(self)
+    }
+
+    fn as_zeroer(&self) -> Option<&dyn Zeroer> {
+        Some(self)
+    }
+
+    fn equals(&self, other: &dyn Val) -> bool {
+        match other.downcast_ref::<Json>() {
+            Some(other) => self.inner() == other.inner(),
+            None => Value::try_from(other).is_ok_and(|other| self.to_value() == other),
+        }
+    }
+
+    fn clone_as_boxed(&self) -> Box<dyn Val> {
+        Box::new(Json {
+            doc: self.doc.clone(),
+            path: self.path.clone(),
+            items: OnceLock::new(),
+        })
+    }
+}
+
+impl Adder for Json {
+    fn add<'a>(&'a self, rhs: &dyn Val) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+        let list: Box<dyn Val> = Box::new(CelList::from(
+            self.items()
+                .iter()
+                .map(|item| item.clone_as_boxed())
+                .collect::<Vec<_>>(),
+        ));
+        Ok(Cow::Owned(list.as_adder().unwrap().add(rhs)?.into_owned()))
+    }
+}
+
+impl Container for Json {
+    fn contains(&self, value: &dyn Val) -> Result<bool, ExecutionError> {
+        match self.inner() {
+            serde_json::Value::Object(object) => Ok(value
+                .downcast_ref::<CelString>()
+                .is_some_and(|key| object.contains_key(key.inner()))),
+            _ => Ok(self.items().iter().any(|item| item.equals(value))),
+        }
+    }
+}
+
+impl Indexer for Json {
+    fn get<'a>(&'a self, idx: &dyn Val) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+        match self.inner() {
+            serde_json::Value::Object(object) => {
+                let Some(key) = idx.downcast_ref::<CelString>() else {
+                    // Objects only have string keys.
+                    return Err(match CelMapKey::try_from(idx.clone_as_boxed())? {
+                        CelMapKey::Bool(b) => ExecutionError::NoSuchKey(Arc::new(b.to_string())),
+                        CelMapKey::Int(i) => ExecutionError::NoSuchKey(Arc::new(i.to_string())),
+                        CelMapKey::UInt(u) => ExecutionError::NoSuchKey(Arc::new(u.to_string())),
+                        CelMapKey::String(s) => ExecutionError::NoSuchKey(Arc::new(s.into_inner())),
+                    });
+                };
+                match object.get(key.inner()) {
+                    Some(field) => Ok(Cow::Owned(
+                        self.child(Step::Field(key.inner().to_owned()), field),
+                    )),
+                    None => Err(ExecutionError::NoSuchKey(Arc::new(key.inner().to_owned()))),
+                }
+            }
+            node => {
+                let array = node.as_array().map_or(&[][..], Vec::as_slice);
+                let len = array.len();
+                let idx = match idx.get_type().kind() {
+                    Kind::Int => {
+                        let idx = *idx.downcast_ref::<CelInt>().unwrap().inner();
+                        usize::try_from(idx)
+                            .ok()
+                            .filter(|i| *i < len)
+                            .ok_or_else(|| ExecutionError::IndexOutOfBounds(idx.into()))?
+                    }
+                    Kind::UInt => {
+                        let idx = *idx.downcast_ref::<CelUInt>().unwrap().inner();
+                        usize::try_from(idx)
+                            .ok()
+                            .filter(|i| *i < len)
+                            .ok_or_else(|| ExecutionError::IndexOutOfBounds(idx.into()))?
+                    }
+                    _ => {
+                        return Err(ExecutionError::UnexpectedType {
+                            got: idx.get_type().runtime_type_name.to_string(),
+                            want: format!(
+                                "{}|{}",
+                                types::INT_TYPE.runtime_type_name,
+                                types::UINT_TYPE.runtime_type_name
+                            ),
+                        })
+                    }
+                };
+                Ok(Cow::Owned(self.child(Step::Index(idx), &array[idx])))
+            }
+        }
+    }
+
+    fn steal(self: Box<Self>, idx: &dyn Val) -> Result<Box<dyn Val>, ExecutionError> {
+        self.get(idx).map(Cow::into_owned)
+    }
+}
+
+impl Iterable for Json {
+    fn iter<'a>(&'a self) -> Box<dyn traits::Iterator<'a> + 'a> {
+        Box::new(ItemIterator(self.items().iter()))
+    }
+}
+
+struct ItemIterator<'a>(std::slice::Iter<'a, Box<dyn Val>>);
+
+impl<'a> traits::Iterator<'a> for ItemIterator<'a> {
+    fn next(&mut self) -> Option<&'a dyn Val> {
+        self.0.next().map(Box::as_ref)
+    }
+}
+
+impl Sizer for Json {
+    fn size(&self) -> CelInt {
+        (self.len() as i64).into()
+    }
+}
+
+impl Zeroer for Json {
+    fn is_zero_value(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Context, Program, Value};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn eval(script: &str, doc: &Arc<serde_json::Value>) -> Value {
+        let mut context = Context::default();
+        context.add_json_variable("request", doc.clone());
+        Program::compile(script).unwrap().execute(&context).unwrap()
+    }
+
+    #[test]
+    fn test_selection() {
+        let doc = Arc::new(json!({
+            "body": {
+                "items": [{"id": 7, "tags": ["a", "b"]}, {"id": 18446744073709551615u64}],
+                "price": 1.5,
+                "paid": false,
+                "note": null,
+            },
+        }));
+        assert_eq!(eval("request.body.items[0].id", &doc), Value::Int(7));
+        assert_eq!(
+            eval("request.body.items[1u].id", &doc),
+            Value::UInt(u64::MAX)
+        );
+        assert_eq!(eval("request.body.price", &doc), Value::Float(1.5));
+        assert_eq!(eval("request.body.paid", &doc), Value::Bool(false));
+        assert_eq!(eval("request.body.note", &doc), Value::Null);
+        assert_eq!(eval("request['body'].items[0].tags[1]", &doc), "b".into());
+        assert_eq!(
+            eval("request.body.items[0].tags", &doc),
+            Value::List(Arc::new(vec!["a".into(), "b".into()]))
+        );
+        assert_eq!(eval("has(request.body.note)", &doc), Value::Bool(true));
+        assert_eq!(eval("has(request.body.missing)", &doc), Value::Bool(false));
+        assert_eq!(eval("'paid' in request.body", &doc), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_lists() {
+        let doc = Arc::new(json!({"items": [{"id": 1}, {"id": 2}, {"id": 3}], "empty": []}));
+        assert_eq!(eval("size(request.items)", &doc), Value::Int(3));
+        assert_eq!(eval("request.empty.size()", &doc), Value::Int(0));
+        assert_eq!(
+            eval("request.items.exists(i, i.id == 2)", &doc),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            eval("request.items.map(i, i.id * 2)", &doc),
+            Value::List(Arc::new(vec![2.into(), 4.into(), 6.into()]))
+        );
+        assert_eq!(
+            eval("request.items + [1]", &doc),
+            eval("[{'id': 1}, {'id': 2}, {'id': 3}, 1]", &doc)
+        );
+        assert_eq!(eval("{'id': 2} in request.items", &doc), Value::Bool(true));
+        assert_eq!(
+            eval("request.all(k, k in ['items', 'empty'])", &doc),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            eval("request.items[0] == {'id': 1}", &doc),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            eval("{'id': 1} == request.items[0]", &doc),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            eval("request.items == request.items", &doc),
+            Value::Bool(true)
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let doc = Arc::new(json!({"items": [1]}));
+        let mut context = Context::default();
+        context.add_json_variable("request", doc);
+        for (script, error) in [
+            ("request.missing", "No such key: missing"),
+            ("request.items[1]", "Index out of bounds: Int(1)"),
+            ("request.items[-1]", "Index out of bounds: Int(-1)"),
+            ("request[1]", "No such key: 1"),
+        ] {
+            let err = Program::compile(script)
+                .unwrap()
+                .execute(&context)
+                .unwrap_err();
+            assert_eq!(err.to_string(), error, "{script}");
+        }
+    }
+}
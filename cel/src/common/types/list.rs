@@ -1,4 +1,6 @@
 use crate::common::traits::{Adder, Container, Indexer, Iterable, Sizer, Zeroer};
+#[cfg(feature = "json")]
+use crate::common::types::CelJson;
 use crate::common::types::{CelInt, CelUInt, Kind, Type};
 use crate::common::value::Val;
 use crate::common::{traits, types};
@@ -19,6 +21,10 @@ impl DefaultList {
         &self.0
     }
 
+    pub(crate) fn push(&mut self, val: Box<dyn Val>) {
+        self.0.push(val);
+    }
+
     fn clone(&self) -> Self {
         let mut vec = Vec::with_capacity(self.0.len());
         for i in self.0.iter().map(|i| i.clone_as_boxed()) {
@@ -70,9 +76,16 @@ impl Val for DefaultList {
     }
 
     fn equals(&self, other: &dyn Val) -> bool {
-        other
-            .downcast_ref::<Self>()
-            .is_some_and(|other| self.0 == other.0)
+        match other.downcast_ref::<Self>() {
+            Some(other) => self.0 == other.0,
+            #[cfg(feature = "json")]
+            None => match other.downcast_ref::<CelJson>() {
+                Some(json) => json.equals(self),
+                None => false,
+            },
+            #[cfg(not(feature = "json"))]
+            None => false,
+        }
     }
 
     fn clone_as_boxed(&self) -> Box<dyn Val> {
@@ -1,32 +1,71 @@
 use crate::common::traits::{Container, Indexer, Iterable, Sizer, Zeroer};
-use crate::common::types::{CelBool, CelInt, CelString, CelUInt, Kind, Type};
+#[cfg(feature = "json")]
+use crate::common::types::CelJson;
+use crate::common::types::{numeric, CelBool, CelDouble, CelInt, CelString, CelUInt, Kind, Type};
 use crate::common::value::Val;
 use crate::common::{traits, types};
 use crate::ExecutionError;
 use crate::ExecutionError::NoSuchOverload;
 use std::borrow::{Borrow, Cow};
 use std::cmp::Ordering;
-use std::collections::hash_map::Keys;
-use std::collections::HashMap;
 use std::hash::Hash;
 use std::ops::Deref;
 use std::sync::Arc;
 
+use super::map_storage::Keys;
+pub use super::map_storage::MapStorage;
+
 #[derive(Debug, Default)]
-pub struct DefaultMap(HashMap<Key, Box<dyn Val>>);
+pub struct DefaultMap(MapStorage<Key, Box<dyn Val>>);
 
 impl DefaultMap {
-    pub fn into_inner(self) -> HashMap<Key, Box<dyn Val>> {
+    pub fn into_inner(self) -> MapStorage<Key, Box<dyn Val>> {
         self.0
     }
 
-    pub fn inner(&self) -> &HashMap<Key, Box<dyn Val>> {
+    pub fn inner(&self) -> &MapStorage<Key, Box<dyn Val>> {
         &self.0
     }
+
+    /// Finds the entry for `key`, matching numeric keys by value across int, uint and double the
+    /// way `==` does, so `1`, `1u` and `1.0` all find the entry for `1`. The outer `None` means
+    /// `key` isn't of a type maps can be keyed by.
+    fn entry(&self, key: &dyn Val) -> Option<Option<(&Key, &Box<dyn Val>)>> {
+        let key = if let Some(s) = key.downcast_ref::<CelString>() {
+            KeyRef::String(s.inner())
+        } else if let Some(i) = key.downcast_ref::<CelInt>() {
+            KeyRef::Int(*i.inner())
+        } else if let Some(u) = key.downcast_ref::<CelUInt>() {
+            KeyRef::Uint(*u.inner())
+        } else if let Some(b) = key.downcast_ref::<CelBool>() {
+            KeyRef::Bool(*b.inner())
+        } else if let Some(d) = key.downcast_ref::<CelDouble>() {
+            match numeric::double_to_int(*d.inner()) {
+                Some(i) => KeyRef::Int(i),
+                None => match numeric::double_to_uint(*d.inner()) {
+                    Some(u) => KeyRef::Uint(u),
+                    // A double that isn't a whole number can't be equal to any key.
+                    None => return Some(None),
+                },
+            }
+        } else {
+            return None;
+        };
+        let other = match key {
+            KeyRef::Int(i) => u64::try_from(i).ok().map(KeyRef::Uint),
+            KeyRef::Uint(u) => i64::try_from(u).ok().map(KeyRef::Int),
+            _ => None,
+        };
+        Some(
+            self.0
+                .get_key_value(&key as &dyn AsKeyRef)
+                .or_else(|| self.0.get_key_value(&other? as &dyn AsKeyRef)),
+        )
+    }
 }
 
 impl Deref for DefaultMap {
-    type Target = HashMap<Key, Box<dyn Val>>;
+    type Target = MapStorage<Key, Box<dyn Val>>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -63,13 +102,28 @@ impl Val for DefaultMap {
     }
 
     fn equals(&self, other: &dyn Val) -> bool {
-        other
-            .downcast_ref::<Self>()
-            .is_some_and(|other| self.0 == other.0)
+        match other.downcast_ref::<Self>() {
+            Some(other) => {
+                self.0.len() == other.0.len()
+                    && self.0.iter().all(|(k, v)| {
+                        other
+                            .entry(k.inner())
+                            .flatten()
+                            .is_some_and(|(_, o)| v.equals(o.as_ref()))
+                    })
+            }
+            #[cfg(feature = "json")]
+            None => match other.downcast_ref::<CelJson>() {
+                Some(json) => json.equals(self),
+                None => false,
+            },
+            #[cfg(not(feature = "json"))]
+            None => false,
+        }
     }
 
     fn clone_as_boxed(&self) -> Box<dyn Val> {
-        let mut map = HashMap::with_capacity(self.0.len());
+        let mut map = MapStorage::with_capacity(self.0.len());
         for (k, v) in self.0.iter() {
             map.insert(k.clone(), v.clone_as_boxed());
         }
@@ -79,64 +133,55 @@ impl Val for DefaultMap {
 
 impl Container for DefaultMap {
     fn contains(&self, key: &dyn Val) -> Result<bool, ExecutionError> {
-        if let Some(s) = key.downcast_ref::<CelString>() {
-            Ok(self.0.contains_key(s as &dyn AsKeyRef))
-        } else if let Some(i) = key.downcast_ref::<CelInt>() {
-            Ok(self.0.contains_key(i as &dyn AsKeyRef))
-        } else if let Some(u) = key.downcast_ref::<CelUInt>() {
-            Ok(self.0.contains_key(u as &dyn AsKeyRef))
-        } else if let Some(b) = key.downcast_ref::<CelBool>() {
-            Ok(self.0.contains_key(b as &dyn AsKeyRef))
-        } else {
-            let key: Key = key.clone_as_boxed().try_into()?;
-            Ok(self.0.contains_key(&key))
+        match self.entry(key) {
+            Some(entry) => Ok(entry.is_some()),
+            None => Err(unsupported_key(key)),
         }
     }
 }
 
 impl Indexer for DefaultMap {
     fn get<'a>(&'a self, key: &dyn Val) -> Result<Cow<'a, dyn Val>, ExecutionError> {
-        let k = if let Some(s) = key.downcast_ref::<CelString>() {
-            s as &dyn AsKeyRef
-        } else if let Some(i) = key.downcast_ref::<CelInt>() {
-            i as &dyn AsKeyRef
-        } else if let Some(u) = key.downcast_ref::<CelUInt>() {
-            u as &dyn AsKeyRef
-        } else if let Some(b) = key.downcast_ref::<CelBool>() {
-            b as &dyn AsKeyRef
-        } else {
-            return Err(NoSuchOverload);
-        };
-
-        self.0
-            .get(k)
-            .map(|v| Cow::Borrowed(v.as_ref()))
-            .ok_or_else(|| {
-                let key = match key.clone_as_boxed().try_into().unwrap() {
-                    Key::Bool(b) => b.into_inner().to_string(),
-                    Key::Int(i) => i.into_inner().to_string(),
-                    Key::String(s) => s.into_inner(),
-                    Key::UInt(u) => u.into_inner().to_string(),
-                };
-                ExecutionError::NoSuchKey(Arc::new(key))
-            })
+        match self.entry(key) {
+            Some(Some((_, v))) => Ok(Cow::Borrowed(v.as_ref())),
+            Some(None) => Err(no_such_key(key)),
+            None => Err(NoSuchOverload),
+        }
     }
 
     fn steal(self: Box<Self>, key: &dyn Val) -> Result<Box<dyn Val>, ExecutionError> {
         let mut map = self;
-        let key: Key = key.clone_as_boxed().try_into()?;
-        map.0.remove(&key).ok_or_else(|| {
-            let key = match key {
-                Key::Bool(b) => b.into_inner().to_string(),
-                Key::Int(i) => i.into_inner().to_string(),
-                Key::String(s) => s.into_inner(),
-                Key::UInt(u) => u.into_inner().to_string(),
-            };
-            ExecutionError::NoSuchKey(Arc::new(key))
-        })
+        let key = match map.entry(key) {
+            Some(Some((k, _))) => k.clone(),
+            Some(None) => return Err(no_such_key(key)),
+            None => return Err(unsupported_key(key)),
+        };
+        let value = map.0.remove(&key);
+        Ok(value.expect("entry was just found"))
     }
 }
 
+fn no_such_key(key: &dyn Val) -> ExecutionError {
+    let key = if let Some(s) = key.downcast_ref::<CelString>() {
+        s.inner().to_string()
+    } else if let Some(i) = key.downcast_ref::<CelInt>() {
+        i.inner().to_string()
+    } else if let Some(u) = key.downcast_ref::<CelUInt>() {
+        u.inner().to_string()
+    } else if let Some(b) = key.downcast_ref::<CelBool>() {
+        b.inner().to_string()
+    } else if let Some(d) = key.downcast_ref::<CelDouble>() {
+        d.inner().to_string()
+    } else {
+        key.get_type().name().to_owned()
+    };
+    ExecutionError::NoSuchKey(Arc::new(key))
+}
+
+fn unsupported_key(key: &dyn Val) -> ExecutionError {
+    ExecutionError::UnsupportedKeyType(key.try_into().unwrap_or(crate::Value::Null))
+}
+
 impl Iterable for DefaultMap {
     fn iter<'a>(&'a self) -> Box<dyn super::traits::Iterator<'a> + 'a> {
         Box::new(MapKeyIterator::new(self.0.keys()))
@@ -155,8 +200,8 @@ impl Zeroer for DefaultMap {
     }
 }
 
-impl From<HashMap<Key, Box<dyn Val>>> for DefaultMap {
-    fn from(value: HashMap<Key, Box<dyn Val>>) -> Self {
+impl From<MapStorage<Key, Box<dyn Val>>> for DefaultMap {
+    fn from(value: MapStorage<Key, Box<dyn Val>>) -> Self {
         Self(value)
     }
 }
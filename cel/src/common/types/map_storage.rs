@@ -0,0 +1,203 @@
+//! The storage of CEL maps, which keeps the same API whether or not the `preserve_order` feature
+//! is enabled, so that enabling the feature doesn't change any public type.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::hash::Hash;
+
+#[cfg(not(feature = "preserve_order"))]
+use std::collections::hash_map as inner;
+#[cfg(not(feature = "preserve_order"))]
+type Inner<K, V> = HashMap<K, V>;
+
+#[cfg(feature = "preserve_order")]
+use indexmap::map as inner;
+#[cfg(feature = "preserve_order")]
+type Inner<K, V> = indexmap::IndexMap<K, V>;
+
+/// The map CEL maps are stored in. It is backed by a [`HashMap`], unless the `preserve_order`
+/// feature is enabled, in which case maps iterate in the order their entries were inserted in,
+/// e.g. the order of a map literal or of the input a map was converted from.
+pub struct MapStorage<K, V>(Inner<K, V>);
+
+impl<K, V> MapStorage<K, V> {
+    pub fn new() -> Self {
+        Self(Inner::default())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter(self.0.iter())
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys(self.0.keys())
+    }
+
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values(self.0.values())
+    }
+}
+
+impl<K: Hash + Eq, V> MapStorage<K, V> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self(Inner::with_capacity_and_hasher(
+            capacity,
+            Default::default(),
+        ))
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.0.get(key)
+    }
+
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.0.get_key_value(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.0.contains_key(key)
+    }
+
+    /// Inserts an entry, replacing the value of the key if it is in the map already, which then
+    /// keeps its position.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.0.insert(key, value)
+    }
+
+    /// Removes the entry of the key, keeping the order of the others.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        #[cfg(not(feature = "preserve_order"))]
+        return self.0.remove(key);
+        #[cfg(feature = "preserve_order")]
+        return self.0.shift_remove(key);
+    }
+}
+
+impl<K, V> Default for MapStorage<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Clone, V: Clone> Clone for MapStorage<K, V> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for MapStorage<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<K: Hash + Eq, V: PartialEq> PartialEq for MapStorage<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(k, v)| other.get(k).is_some_and(|o| v == o))
+    }
+}
+
+impl<K: Hash + Eq, V> FromIterator<(K, V)> for MapStorage<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self(Inner::from_iter(iter))
+    }
+}
+
+impl<K: Hash + Eq, V> Extend<(K, V)> for MapStorage<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.0.extend(iter)
+    }
+}
+
+impl<K: Hash + Eq, V> From<HashMap<K, V>> for MapStorage<K, V> {
+    fn from(map: HashMap<K, V>) -> Self {
+        #[cfg(not(feature = "preserve_order"))]
+        return Self(map);
+        #[cfg(feature = "preserve_order")]
+        return map.into_iter().collect();
+    }
+}
+
+impl<K, V> IntoIterator for MapStorage<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter(self.0.into_iter())
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a MapStorage<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+macro_rules! iterator {
+    ($(#[$doc:meta])* $name:ident<$($lt:lifetime,)? $k:ident, $v:ident> => $item:ty) => {
+        $(#[$doc])*
+        pub struct $name<$($lt,)? $k, $v>(inner::$name<$($lt,)? $k, $v>);
+
+        impl<$($lt,)? $k, $v> Iterator for $name<$($lt,)? $k, $v> {
+            type Item = $item;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                self.0.next()
+            }
+
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                self.0.size_hint()
+            }
+        }
+
+        impl<$($lt,)? $k, $v> ExactSizeIterator for $name<$($lt,)? $k, $v> {}
+    };
+}
+
+iterator!(
+    /// An iterator over the entries of a [`MapStorage`].
+    Iter<'a, K, V> => (&'a K, &'a V)
+);
+iterator!(
+    /// An iterator over the keys of a [`MapStorage`].
+    Keys<'a, K, V> => &'a K
+);
+iterator!(
+    /// An iterator over the values of a [`MapStorage`].
+    Values<'a, K, V> => &'a V
+);
+iterator!(
+    /// An iterator moving the entries out of a [`MapStorage`].
+    IntoIter<K, V> => (K, V)
+);
@@ -0,0 +1,707 @@
+use std::{borrow::Cow, collections::HashMap, sync::Arc};
+
+use prost_reflect::{
+    bytes::Bytes as ProtoBytes, DynamicMessage, FieldDescriptor, Kind as FieldKind, MapKey,
+    ReflectMessage, Value as ProtoValue,
+};
+
+use crate::{
+    common::{
+        traits::{Indexer, Zeroer},
+        types::wkt,
+        types::{
+            CelBool, CelBytes, CelDouble, CelInt, CelList, CelMap, CelMapKey, CelNull, CelString,
+            CelUInt, MapStorage, Type,
+        },
+        value::Val,
+    },
+    ExecutionError,
+};
+
+/// A protobuf message, described at runtime by a [`prost_reflect::MessageDescriptor`].
+#[derive(Debug, PartialEq)]
+pub struct Message {
+    r#type: Type,
+    msg: Arc<DynamicMessage>,
+}
+
+impl Message {
+    pub fn name(&self) -> &str {
+        self.r#type.name()
+    }
+
+    pub fn inner(&self) -> &Arc<DynamicMessage> {
+        &self.msg
+    }
+
+    /// Returns the value of the field `name`, or its default value if it isn't set.
+    pub fn field_value(&self, name: &str) -> Result<Box<dyn Val>, ExecutionError> {
+        let field = self.field(name)?;
+        if let FieldKind::Message(desc) = field.kind() {
+            if !field.is_list() && !field.is_map() && !self.msg.has_field(&field) {
+                if let Some(val) = wkt::unset(&desc) {
+                    return Ok(val);
+                }
+            }
+        }
+        to_val(&self.msg.get_field(&field))
+    }
+
+    /// Tests the presence of the field `name`, as `has()` does: fields of messages and
+    /// fields with explicit presence must be set, repeated and map fields must be non-empty
+    /// and other scalar fields must not hold their default value.
+    pub fn has_field(&self, name: &str) -> Result<bool, ExecutionError> {
+        let field = self.field(name)?;
+        Ok(self.msg.has_field(&field))
+    }
+
+    fn field(&self, name: &str) -> Result<FieldDescriptor, ExecutionError> {
+        self.msg
+            .descriptor()
+            .get_field_by_name(name)
+            .ok_or_else(|| {
+                ExecutionError::NoSuchKey(Arc::new(format!(
+                    "field `{name}` on message `{}`",
+                    self.name()
+                )))
+            })
+    }
+
+    /// Builds a new message of type `desc` out of the `fields` of a struct expression.
+    pub(crate) fn new_message(
+        desc: prost_reflect::MessageDescriptor,
+        fields: Vec<(String, Cow<dyn Val>)>,
+    ) -> Result<Self, ExecutionError> {
+        let mut msg = DynamicMessage::new(desc);
+        for (name, value) in fields {
+            let field = msg.descriptor().get_field_by_name(&name).ok_or_else(|| {
+                ExecutionError::NoSuchKey(Arc::new(format!(
+                    "field `{name}` on message `{}`",
+                    msg.descriptor().full_name()
+                )))
+            })?;
+            // Assigning `null` leaves a message field unset, unless it's a `google.protobuf.Value`
+            if value.downcast_ref::<CelNull>().is_some()
+                && !field.is_list()
+                && matches!(field.kind(), FieldKind::Message(desc) if desc.full_name() != "google.protobuf.Value")
+            {
+                continue;
+            }
+            let value = from_val(&field, value.as_ref())?;
+            msg.set_field(&field, value);
+        }
+        Ok(Message::from(msg))
+    }
+}
+
+impl From<DynamicMessage> for Message {
+    fn from(msg: DynamicMessage) -> Self {
+        Arc::new(msg).into()
+    }
+}
+
+impl From<Arc<DynamicMessage>> for Message {
+    fn from(msg: Arc<DynamicMessage>) -> Self {
+        Self {
+            r#type: Type::new_struct(msg.descriptor().full_name().to_owned()),
+            msg,
+        }
+    }
+}
+
+impl Val for Message {
+    fn get_type(&self) -> &Type {
+        &self.r#type
+    }
+
+    fn clone_as_boxed(&self) -> Box<dyn Val> {
+        Box::new(Message::from(self.msg.clone()))
+    }
+
+    fn as_indexer(&self) -> Option<&dyn Indexer> {
+        Some(self)
+    }
+
+    fn into_indexer(self: Box<Self>) -> Option<Box<dyn Indexer>> {
+        Some(self)
+    }
+
+    fn as_zeroer(&self) -> Option<&dyn Zeroer> {
+        Some(self)
+    }
+
+    fn equals(&self, other: &dyn Val) -> bool {
+        other
+            .downcast_ref::<Message>()
+            .is_some_and(|other| self.msg == other.msg)
+    }
+}
+
+impl Indexer for Message {
+    fn get<'a>(&'a self, idx: &dyn Val) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+        if let Some(field) = idx.downcast_ref::<CelString>() {
+            self.field_value(field.inner()).map(Cow::Owned)
+        } else {
+            Err(ExecutionError::UnsupportedIndex(
+                idx.try_into()?,
+                (self as &dyn Val).try_into()?,
+            ))
+        }
+    }
+
+    fn steal(self: Box<Self>, idx: &dyn Val) -> Result<Box<dyn Val>, ExecutionError> {
+        self.get(idx).map(Cow::into_owned)
+    }
+}
+
+impl Zeroer for Message {
+    fn is_zero_value(&self) -> bool {
+        self.msg.fields().next().is_none()
+    }
+}
+
+/// Converts a message to its CEL counterpart, which is a [`Message`] unless it's of one of
+/// the well-known types.
+pub(crate) fn message_to_val(msg: Arc<DynamicMessage>) -> Result<Box<dyn Val>, ExecutionError> {
+    Ok(match wkt::unwrap(&msg)? {
+        Some(val) => val,
+        None => Box::new(Message::from(msg)),
+    })
+}
+
+/// Converts the value of a protobuf field to its CEL counterpart. Enum values are `int`s.
+pub(crate) fn to_val(value: &ProtoValue) -> Result<Box<dyn Val>, ExecutionError> {
+    Ok(match value {
+        ProtoValue::Bool(b) => Box::new(CelBool::from(*b)),
+        ProtoValue::I32(i) => Box::new(CelInt::from(*i as i64)),
+        ProtoValue::I64(i) => Box::new(CelInt::from(*i)),
+        ProtoValue::U32(u) => Box::new(CelUInt::from(*u as u64)),
+        ProtoValue::U64(u) => Box::new(CelUInt::from(*u)),
+        ProtoValue::F32(f) => Box::new(CelDouble::from(*f as f64)),
+        ProtoValue::F64(f) => Box::new(CelDouble::from(*f)),
+        ProtoValue::String(s) => Box::new(CelString::from(s.as_str())),
+        ProtoValue::Bytes(b) => Box::new(CelBytes::from(b.to_vec())),
+        ProtoValue::EnumNumber(n) => Box::new(CelInt::from(*n as i64)),
+        ProtoValue::Message(m) => match wkt::unwrap(m)? {
+            Some(val) => val,
+            None => Box::new(Message::from(m.clone())),
+        },
+        ProtoValue::List(l) => Box::new(CelList::from(
+            l.iter().map(to_val).collect::<Result<Vec<_>, _>>()?,
+        )),
+        ProtoValue::Map(m) => Box::new(CelMap::from(
+            m.iter()
+                .map(|(k, v)| Ok((map_key_to_val(k), to_val(v)?)))
+                .collect::<Result<MapStorage<_, _>, ExecutionError>>()?,
+        )),
+    })
+}
+
+fn map_key_to_val(key: &MapKey) -> CelMapKey {
+    match key {
+        MapKey::Bool(b) => (*b).into(),
+        MapKey::I32(i) => (*i as i64).into(),
+        MapKey::I64(i) => (*i).into(),
+        MapKey::U32(u) => (*u as u64).into(),
+        MapKey::U64(u) => (*u).into(),
+        MapKey::String(s) => s.as_str().into(),
+    }
+}
+
+/// Converts `val` to the value of the protobuf `field`, failing if its type doesn't match.
+pub(crate) fn from_val(
+    field: &FieldDescriptor,
+    val: &dyn Val,
+) -> Result<ProtoValue, ExecutionError> {
+    let unexpected = || ExecutionError::UnexpectedType {
+        got: val.get_type().name().to_owned(),
+        want: format!(
+            "{} for field {} in {}",
+            kind_name(&field.kind()),
+            field.name(),
+            field.parent_message().full_name()
+        ),
+    };
+    if field.is_map() {
+        let FieldKind::Message(entry) = field.kind() else {
+            unreachable!("map fields are messages")
+        };
+        let (key_field, value_field) = (entry.map_entry_key_field(), entry.map_entry_value_field());
+        let map = val.downcast_ref::<CelMap>().ok_or_else(unexpected)?;
+        let mut entries = HashMap::with_capacity(map.inner().len());
+        for (k, v) in map.inner() {
+            let key = match from_kind(&key_field.kind(), k.inner())? {
+                ProtoValue::Bool(b) => MapKey::Bool(b),
+                ProtoValue::I32(i) => MapKey::I32(i),
+                ProtoValue::I64(i) => MapKey::I64(i),
+                ProtoValue::U32(u) => MapKey::U32(u),
+                ProtoValue::U64(u) => MapKey::U64(u),
+                ProtoValue::String(s) => MapKey::String(s),
+                _ => return Err(unexpected()),
+            };
+            entries.insert(key, from_kind(&value_field.kind(), v.as_ref())?);
+        }
+        Ok(ProtoValue::Map(entries))
+    } else if field.is_list() {
+        let list = val.downcast_ref::<CelList>().ok_or_else(unexpected)?;
+        let kind = field.kind();
+        Ok(ProtoValue::List(
+            list.inner()
+                .iter()
+                .map(|v| from_kind(&kind, v.as_ref()))
+                .collect::<Result<_, _>>()?,
+        ))
+    } else {
+        from_kind(&field.kind(), val).map_err(|_| unexpected())
+    }
+}
+
+pub(crate) fn from_kind(kind: &FieldKind, val: &dyn Val) -> Result<ProtoValue, ExecutionError> {
+    let unexpected = || ExecutionError::UnexpectedType {
+        got: val.get_type().name().to_owned(),
+        want: kind_name(kind).into_owned(),
+    };
+    let out_of_range = || ExecutionError::UnexpectedType {
+        got: format!("{val:?}"),
+        want: format!("value in the range of {}", kind_name(kind)),
+    };
+    let int = || val.downcast_ref::<CelInt>().map(|i| *i.inner());
+    let uint = || val.downcast_ref::<CelUInt>().map(|u| *u.inner());
+    let value = match kind {
+        FieldKind::Double => val
+            .downcast_ref::<CelDouble>()
+            .map(|d| ProtoValue::F64(*d.inner())),
+        FieldKind::Float => val
+            .downcast_ref::<CelDouble>()
+            .map(|d| ProtoValue::F32(*d.inner() as f32)),
+        FieldKind::Int32 | FieldKind::Sint32 | FieldKind::Sfixed32 => match int() {
+            Some(i) => Some(ProtoValue::I32(
+                i32::try_from(i).map_err(|_| out_of_range())?,
+            )),
+            None => None,
+        },
+        FieldKind::Int64 | FieldKind::Sint64 | FieldKind::Sfixed64 => int().map(ProtoValue::I64),
+        FieldKind::Uint32 | FieldKind::Fixed32 => match uint() {
+            Some(u) => Some(ProtoValue::U32(
+                u32::try_from(u).map_err(|_| out_of_range())?,
+            )),
+            None => None,
+        },
+        FieldKind::Uint64 | FieldKind::Fixed64 => uint().map(ProtoValue::U64),
+        FieldKind::Bool => val
+            .downcast_ref::<CelBool>()
+            .map(|b| ProtoValue::Bool(*b.inner())),
+        FieldKind::String => val
+            .downcast_ref::<CelString>()
+            .map(|s| ProtoValue::String(s.inner().to_owned())),
+        FieldKind::Bytes => val
+            .downcast_ref::<CelBytes>()
+            .map(|b| ProtoValue::Bytes(ProtoBytes::copy_from_slice(b.inner()))),
+        FieldKind::Message(desc) => match wkt::wrap(desc, val)? {
+            Some(msg) => Some(ProtoValue::Message(msg)),
+            None => val
+                .downcast_ref::<Message>()
+                .filter(|m| m.msg.descriptor() == *desc)
+                .map(|m| ProtoValue::Message(m.msg.as_ref().clone())),
+        },
+        FieldKind::Enum(_) => match int() {
+            Some(i) => Some(ProtoValue::EnumNumber(
+                i32::try_from(i).map_err(|_| out_of_range())?,
+            )),
+            None => None,
+        },
+    };
+    value.ok_or_else(unexpected)
+}
+
+fn kind_name(kind: &FieldKind) -> Cow<'static, str> {
+    match kind {
+        FieldKind::Double => "double".into(),
+        FieldKind::Float => "float".into(),
+        FieldKind::Int32 => "int32".into(),
+        FieldKind::Int64 => "int64".into(),
+        FieldKind::Uint32 => "uint32".into(),
+        FieldKind::Uint64 => "uint64".into(),
+        FieldKind::Sint32 => "sint32".into(),
+        FieldKind::Sint64 => "sint64".into(),
+        FieldKind::Fixed32 => "fixed32".into(),
+        FieldKind::Fixed64 => "fixed64".into(),
+        FieldKind::Sfixed32 => "sfixed32".into(),
+        FieldKind::Sfixed64 => "sfixed64".into(),
+        FieldKind::Bool => "bool".into(),
+        FieldKind::String => "string".into(),
+        FieldKind::Bytes => "bytes".into(),
+        FieldKind::Message(desc) => desc.full_name().to_owned().into(),
+        FieldKind::Enum(desc) => desc.full_name().to_owned().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use prost_reflect::{
+        prost::Message as _,
+        prost_types::{
+            field_descriptor_proto::{Label, Type as FieldType},
+            DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+            FileDescriptorProto, FileDescriptorSet, MessageOptions,
+        },
+        DynamicMessage, MapKey, Value as ProtoValue,
+    };
+
+    use crate::{Context, Env, ExecutionError, Program, Value};
+
+    fn field(
+        name: &str,
+        number: i32,
+        r#type: FieldType,
+        type_name: Option<&str>,
+    ) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_owned()),
+            number: Some(number),
+            label: Some(Label::Optional as i32),
+            r#type: Some(r#type as i32),
+            type_name: type_name.map(str::to_owned),
+            json_name: None,
+            ..Default::default()
+        }
+    }
+
+    fn repeated(field: FieldDescriptorProto) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            label: Some(Label::Repeated as i32),
+            ..field
+        }
+    }
+
+    /// The descriptors of:
+    /// ```proto
+    /// syntax = "proto3";
+    /// package cel.test;
+    /// enum Color { RED = 0; GREEN = 1; }
+    /// message Nested { int64 value = 1; }
+    /// message Person {
+    ///   string name = 1;
+    ///   int32 age = 2;
+    ///   uint64 id = 3;
+    ///   Nested nested = 4;
+    ///   repeated string tags = 5;
+    ///   map<string, int64> scores = 6;
+    ///   Color color = 7;
+    ///   bytes data = 8;
+    /// }
+    /// message Event {
+    ///   google.protobuf.Timestamp at = 1;
+    ///   google.protobuf.Duration ttl = 2;
+    ///   google.protobuf.Int64Value count = 3;
+    ///   google.protobuf.StringValue label = 4;
+    ///   google.protobuf.Struct attrs = 5;
+    ///   google.protobuf.Value extra = 6;
+    ///   google.protobuf.ListValue items = 7;
+    ///   google.protobuf.Any payload = 8;
+    /// }
+    /// ```
+    fn env() -> Env {
+        let file = FileDescriptorProto {
+            name: Some("cel/test.proto".to_owned()),
+            package: Some("cel.test".to_owned()),
+            syntax: Some("proto3".to_owned()),
+            dependency: vec![
+                "google/protobuf/any.proto".to_owned(),
+                "google/protobuf/duration.proto".to_owned(),
+                "google/protobuf/struct.proto".to_owned(),
+                "google/protobuf/timestamp.proto".to_owned(),
+                "google/protobuf/wrappers.proto".to_owned(),
+            ],
+            enum_type: vec![EnumDescriptorProto {
+                name: Some("Color".to_owned()),
+                value: vec![
+                    EnumValueDescriptorProto {
+                        name: Some("RED".to_owned()),
+                        number: Some(0),
+                        options: None,
+                    },
+                    EnumValueDescriptorProto {
+                        name: Some("GREEN".to_owned()),
+                        number: Some(1),
+                        options: None,
+                    },
+                ],
+                ..Default::default()
+            }],
+            message_type: vec![
+                DescriptorProto {
+                    name: Some("Nested".to_owned()),
+                    field: vec![field("value", 1, FieldType::Int64, None)],
+                    ..Default::default()
+                },
+                DescriptorProto {
+                    name: Some("Person".to_owned()),
+                    field: vec![
+                        field("name", 1, FieldType::String, None),
+                        field("age", 2, FieldType::Int32, None),
+                        field("id", 3, FieldType::Uint64, None),
+                        field("nested", 4, FieldType::Message, Some(".cel.test.Nested")),
+                        repeated(field("tags", 5, FieldType::String, None)),
+                        repeated(field(
+                            "scores",
+                            6,
+                            FieldType::Message,
+                            Some(".cel.test.Person.ScoresEntry"),
+                        )),
+                        field("color", 7, FieldType::Enum, Some(".cel.test.Color")),
+                        field("data", 8, FieldType::Bytes, None),
+                    ],
+                    nested_type: vec![DescriptorProto {
+                        name: Some("ScoresEntry".to_owned()),
+                        field: vec![
+                            field("key", 1, FieldType::String, None),
+                            field("value", 2, FieldType::Int64, None),
+                        ],
+                        options: Some(MessageOptions {
+                            map_entry: Some(true),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+                DescriptorProto {
+                    name: Some("Event".to_owned()),
+                    field: [
+                        ("at", "Timestamp"),
+                        ("ttl", "Duration"),
+                        ("count", "Int64Value"),
+                        ("label", "StringValue"),
+                        ("attrs", "Struct"),
+                        ("extra", "Value"),
+                        ("items", "ListValue"),
+                        ("payload", "Any"),
+                    ]
+                    .iter()
+                    .zip(1..)
+                    .map(|((name, wkt), number)| {
+                        let type_name = format!(".google.protobuf.{wkt}");
+                        field(name, number, FieldType::Message, Some(&type_name))
+                    })
+                    .collect(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let set = FileDescriptorSet { file: vec![file] };
+        let mut env = Env::stdlib();
+        env.add_file_descriptor_set(set.encode_to_vec().as_slice())
+            .unwrap();
+        env
+    }
+
+    fn person(env: &Env) -> DynamicMessage {
+        let desc = env
+            .descriptor_pool()
+            .get_message_by_name("cel.test.Person")
+            .unwrap();
+        let mut msg = DynamicMessage::new(desc);
+        msg.set_field_by_name("name", ProtoValue::String("Ada".to_owned()));
+        msg.set_field_by_name("id", ProtoValue::U64(7));
+        msg.set_field_by_name(
+            "tags",
+            ProtoValue::List(vec![ProtoValue::String("admin".to_owned())]),
+        );
+        msg.set_field_by_name(
+            "scores",
+            ProtoValue::Map([(MapKey::String("math".to_owned()), ProtoValue::I64(90))].into()),
+        );
+        msg.set_field_by_name("color", ProtoValue::EnumNumber(1));
+        msg
+    }
+
+    fn eval(script: &str) -> Result<Value, ExecutionError> {
+        let env = env();
+        let msg = person(&env);
+        let mut ctx = Context::with_env(Arc::new(env));
+        ctx.add_variable_from_value("person", msg);
+        Program::compile(script).unwrap().execute(&ctx)
+    }
+
+    #[test]
+    fn test_field_selection() {
+        [
+            ("string", "person.name == 'Ada'"),
+            ("uint64", "person.id == 7u"),
+            ("unset int32", "person.age == 0"),
+            ("unset message", "person.nested.value == 0"),
+            ("repeated", "person.tags == ['admin']"),
+            ("map", "person.scores['math'] == 90"),
+            ("enum", "person.color == 1"),
+            ("enum constant", "person.color == cel.test.Color.GREEN"),
+            ("unset bytes", "person.data == b''"),
+        ]
+        .iter()
+        .for_each(|(name, script)| assert_eq!(eval(script), Ok(true.into()), "{name}"));
+    }
+
+    #[test]
+    fn test_has() {
+        [
+            ("set", "has(person.name)"),
+            ("default scalar", "!has(person.age)"),
+            ("unset message", "!has(person.nested)"),
+            ("non-empty list", "has(person.tags)"),
+            ("non-empty map", "has(person.scores)"),
+            (
+                "constructed",
+                "has(cel.test.Person{nested: cel.test.Nested{}}.nested)",
+            ),
+        ]
+        .iter()
+        .for_each(|(name, script)| assert_eq!(eval(script), Ok(true.into()), "{name}"));
+    }
+
+    #[test]
+    fn test_construction() {
+        let result = eval(
+            "cel.test.Person{name: 'Bob', age: 42, tags: ['a', 'b'], scores: {'x': 1}, \
+             color: cel.test.Color.GREEN, nested: cel.test.Nested{value: 3}}",
+        )
+        .unwrap();
+        let Value::Message(msg) = result else {
+            panic!("not a message: {result:?}")
+        };
+        assert_eq!(
+            msg.get_field_by_name("age").unwrap().as_ref(),
+            &ProtoValue::I32(42)
+        );
+        assert_eq!(
+            msg.get_field_by_name("color").unwrap().as_ref(),
+            &ProtoValue::EnumNumber(1)
+        );
+        assert_eq!(
+            eval("cel.test.Person{name: 'Ada'} == cel.test.Person{name: 'Ada'}"),
+            Ok(true.into())
+        );
+        assert_eq!(
+            eval("cel.test.Person{nested: null} == cel.test.Person{}"),
+            Ok(true.into())
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            eval("person.missing"),
+            Err(ExecutionError::no_such_key(
+                "field `missing` on message `cel.test.Person`"
+            ))
+        );
+        assert_eq!(
+            eval("cel.test.Person{age: 'old'}"),
+            Err(ExecutionError::UnexpectedType {
+                got: "string".to_owned(),
+                want: "int32 for field age in cel.test.Person".to_owned(),
+            })
+        );
+        assert!(matches!(
+            eval("cel.test.Person{age: 3000000000}"),
+            Err(ExecutionError::UnexpectedType { .. })
+        ));
+        assert_eq!(
+            eval("cel.test.Color.BLUE"),
+            Err(ExecutionError::undeclared_reference("cel"))
+        );
+    }
+
+    #[test]
+    fn test_value_round_trip() {
+        let env = env();
+        let msg = person(&env);
+        let mut ctx = Context::with_env(Arc::new(env));
+        ctx.add_variable_from_value("person", msg.clone());
+        let result = Program::compile("person").unwrap().execute(&ctx);
+        assert_eq!(result, Ok(Value::Message(Arc::new(msg))));
+    }
+
+    #[test]
+    fn test_well_known_types() {
+        let mut ctx = Context::with_env(Arc::new(env()));
+        let nested = Program::compile("cel.test.Nested{value: 5}")
+            .unwrap()
+            .execute(&ctx)
+            .unwrap();
+        ctx.add_variable_from_value("nested", nested);
+        let script =
+            "cel.test.Event{count: 3, attrs: {'region': 'eu', 'replicas': 3}, payload: nested}";
+        let event = Program::compile(script).unwrap().execute(&ctx).unwrap();
+        ctx.add_variable_from_value("event", event);
+        [
+            ("wrapper", "event.count == 3"),
+            ("unset wrapper", "event.label == null && !has(event.label)"),
+            (
+                "struct",
+                "event.attrs.region == 'eu' && event.attrs.replicas == 3.0",
+            ),
+            ("unset value", "event.extra == null"),
+            ("unset list value", "event.items == []"),
+            ("any", "event.payload.value == 5"),
+            (
+                "packed primitive",
+                "cel.test.Event{payload: 'x'}.payload == 'x'",
+            ),
+            ("null wrapper", "!has(cel.test.Event{count: null}.count)"),
+            ("null value", "has(cel.test.Event{extra: null}.extra)"),
+            (
+                "list value",
+                "cel.test.Event{items: [1, 'a', [true]]}.items[2][0]",
+            ),
+        ]
+        .iter()
+        .for_each(|(name, script)| {
+            let result = Program::compile(script).unwrap().execute(&ctx);
+            assert_eq!(result, Ok(true.into()), "{name}");
+        });
+        assert!(matches!(
+            Program::compile("cel.test.Event{count: 'three'}")
+                .unwrap()
+                .execute(&ctx),
+            Err(ExecutionError::UnexpectedType { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "chrono")]
+    fn test_well_known_time_types() {
+        let env = env();
+        let desc = env
+            .descriptor_pool()
+            .get_message_by_name("google.protobuf.Timestamp")
+            .unwrap();
+        let mut ts = DynamicMessage::new(desc);
+        ts.set_field_by_name("seconds", ProtoValue::I64(1_704_067_200));
+        let mut ctx = Context::with_env(Arc::new(env));
+        ctx.add_variable_from_value("ts", ts);
+        [
+            ("timestamp", "ts == timestamp('2024-01-01T00:00:00Z')"),
+            (
+                "timestamp field",
+                "cel.test.Event{at: ts}.at == timestamp('2024-01-01T00:00:00Z')",
+            ),
+            (
+                "duration field",
+                "cel.test.Event{ttl: duration('1.5s')}.ttl == duration('1500ms')",
+            ),
+            (
+                "unset timestamp",
+                "cel.test.Event{}.at == timestamp('1970-01-01T00:00:00Z')",
+            ),
+            ("unset duration", "cel.test.Event{}.ttl == duration('0s')"),
+        ]
+        .iter()
+        .for_each(|(name, script)| {
+            let result = Program::compile(script).unwrap().execute(&ctx);
+            assert_eq!(result, Ok(true.into()), "{name}");
+        });
+    }
+}
@@ -9,9 +9,15 @@ pub(crate) mod double;
 #[cfg(feature = "chrono")]
 pub(crate) mod duration;
 pub(crate) mod int;
+#[cfg(feature = "json")]
+pub(crate) mod json;
 pub(crate) mod list;
 pub(crate) mod map;
+pub(crate) mod map_storage;
+#[cfg(feature = "protobuf")]
+pub(crate) mod message;
 mod null;
+pub(crate) mod numeric;
 pub(crate) mod optional;
 pub(crate) mod string;
 #[cfg(feature = "structs")]
@@ -19,6 +25,8 @@ pub(crate) mod r#struct;
 #[cfg(feature = "chrono")]
 pub(crate) mod timestamp;
 pub(crate) mod uint;
+#[cfg(feature = "protobuf")]
+mod wkt;
 
 use crate::common::traits::TraitSet;
 use crate::common::value::Val;
@@ -28,9 +36,14 @@ pub use double::Double as CelDouble;
 #[cfg(feature = "chrono")]
 pub use duration::Duration as CelDuration;
 pub use int::Int as CelInt;
+#[cfg(feature = "json")]
+pub use json::Json as CelJson;
 pub use list::DefaultList as CelList;
 pub use map::DefaultMap as CelMap;
 pub use map::Key as CelMapKey;
+pub use map::MapStorage;
+#[cfg(feature = "protobuf")]
+pub use message::Message as CelMessage;
 pub use null::Null as CelNull;
 pub use optional::Optional as CelOptional;
 #[cfg(feature = "structs")]
@@ -0,0 +1,202 @@
+//! Comparisons and promotions across CEL's numeric types. The spec orders ints, uints and
+//! doubles on a single number line, so `1 == 1u`, `1 == 1.0` and `-1 < 1u` all hold. Comparisons
+//! are exact: a double is never rounded to an integer, nor an integer to a double, so
+//! `9223372036854775807` is less than `9223372036854775808.0` even though both convert to the
+//! same `f64`. NaN is unordered with respect to every number, itself included.
+
+use crate::common::types::{CelDouble, CelInt, CelUInt};
+use crate::common::value::Val;
+use crate::{ExecutionError, Value};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+/// 2^63 as a double, the smallest double above `i64::MAX`.
+const TWO_POW_63: f64 = 9_223_372_036_854_775_808.0;
+/// 2^64 as a double, the smallest double above `u64::MAX`.
+const TWO_POW_64: f64 = 18_446_744_073_709_551_616.0;
+
+pub(crate) fn cmp_int_uint(i: i64, u: u64) -> Ordering {
+    // If the i64 doesn't fit into a u64 it must be less than 0.
+    u64::try_from(i).map_or(Ordering::Less, |i| i.cmp(&u))
+}
+
+pub(crate) fn cmp_int_double(i: i64, d: f64) -> Option<Ordering> {
+    if d.is_nan() {
+        None
+    } else if d >= TWO_POW_63 {
+        Some(Ordering::Less)
+    } else if d < -TWO_POW_63 {
+        Some(Ordering::Greater)
+    } else {
+        // In range, so the integral part of `d` is exactly an i64.
+        let whole = d.trunc();
+        Some(i.cmp(&(whole as i64)).then(cmp_fraction(d - whole)))
+    }
+}
+
+pub(crate) fn cmp_uint_double(u: u64, d: f64) -> Option<Ordering> {
+    if d.is_nan() {
+        None
+    } else if d >= TWO_POW_64 {
+        Some(Ordering::Less)
+    } else if d < 0.0 {
+        Some(Ordering::Greater)
+    } else {
+        let whole = d.trunc();
+        Some(u.cmp(&(whole as u64)).then(cmp_fraction(d - whole)))
+    }
+}
+
+/// Orders an integer equal to the integral part of a double against the double itself, given
+/// the double's fractional part.
+fn cmp_fraction(fraction: f64) -> Ordering {
+    0.0.partial_cmp(&fraction).unwrap_or(Ordering::Equal)
+}
+
+/// The int equal to `d`, if there is one.
+pub(crate) fn double_to_int(d: f64) -> Option<i64> {
+    (d.fract() == 0.0 && (-TWO_POW_63..TWO_POW_63).contains(&d)).then_some(d as i64)
+}
+
+/// The uint equal to `d`, if there is one.
+pub(crate) fn double_to_uint(d: f64) -> Option<u64> {
+    (d.fract() == 0.0 && (0.0..TWO_POW_64).contains(&d)).then_some(d as u64)
+}
+
+/// The left and right operands of a binary operator.
+pub(crate) type Operands<'a> = (Cow<'a, dyn Val>, Cow<'a, dyn Val>);
+
+/// Converts mixed numeric operands of the arithmetic `operator` to a common type, for
+/// environments with [numeric promotion](crate::Env::set_numeric_promotion) enabled: an int or
+/// uint paired with a double becomes a double, and a uint paired with an int becomes an int.
+/// Operands of the same type, or which aren't both numbers, are returned untouched. A value the
+/// common type can't represent exactly is an error, rather than silently rounded.
+pub(crate) fn promote<'a>(
+    operator: &'static str,
+    lhs: Cow<'a, dyn Val>,
+    rhs: Cow<'a, dyn Val>,
+) -> Result<Operands<'a>, ExecutionError> {
+    let (Some(l), Some(r)) = (Number::of(lhs.as_ref()), Number::of(rhs.as_ref())) else {
+        return Ok((lhs, rhs));
+    };
+    let (l, r) = match (l, r) {
+        // There's no remainder of doubles to promote to.
+        (Number::Double(_), Number::Int(_) | Number::UInt(_))
+        | (Number::Int(_) | Number::UInt(_), Number::Double(_))
+            if operator != "rem" =>
+        {
+            (l.to_double(operator)?, r.to_double(operator)?)
+        }
+        (Number::Int(_), Number::UInt(_)) | (Number::UInt(_), Number::Int(_)) => {
+            (l.to_int(operator)?, r.to_int(operator)?)
+        }
+        _ => return Ok((lhs, rhs)),
+    };
+    Ok((Cow::Owned(l), Cow::Owned(r)))
+}
+
+#[derive(Clone, Copy)]
+enum Number {
+    Int(i64),
+    UInt(u64),
+    Double(f64),
+}
+
+impl Number {
+    fn of(val: &dyn Val) -> Option<Self> {
+        if let Some(i) = val.downcast_ref::<CelInt>() {
+            Some(Number::Int(*i.inner()))
+        } else if let Some(u) = val.downcast_ref::<CelUInt>() {
+            Some(Number::UInt(*u.inner()))
+        } else {
+            val.downcast_ref::<CelDouble>()
+                .map(|d| Number::Double(*d.inner()))
+        }
+    }
+
+    fn to_double(self, operator: &'static str) -> Result<Box<dyn Val>, ExecutionError> {
+        let d = match self {
+            Number::Int(i) if cmp_int_double(i, i as f64) == Some(Ordering::Equal) => i as f64,
+            Number::UInt(u) if cmp_uint_double(u, u as f64) == Some(Ordering::Equal) => u as f64,
+            Number::Double(d) => d,
+            _ => return Err(self.lossy(operator, "double")),
+        };
+        Ok(Box::new(CelDouble::from(d)))
+    }
+
+    fn to_int(self, operator: &'static str) -> Result<Box<dyn Val>, ExecutionError> {
+        let i = match self {
+            Number::Int(i) => i,
+            Number::UInt(u) => i64::try_from(u).map_err(|_| self.lossy(operator, "int"))?,
+            Number::Double(_) => unreachable!("doubles are never promoted to ints"),
+        };
+        Ok(Box::new(CelInt::from(i)))
+    }
+
+    fn lossy(self, operator: &'static str, to: &'static str) -> ExecutionError {
+        let value = match self {
+            Number::Int(i) => Value::Int(i),
+            Number::UInt(u) => Value::UInt(u),
+            Number::Double(d) => Value::Float(d),
+        };
+        ExecutionError::LossyPromotion {
+            operator,
+            value,
+            to,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cmp::Ordering::{Equal, Greater, Less};
+
+    #[test]
+    fn int_double() {
+        assert_eq!(cmp_int_double(1, 1.0), Some(Equal));
+        assert_eq!(cmp_int_double(1, 1.5), Some(Less));
+        assert_eq!(cmp_int_double(-1, -1.5), Some(Greater));
+        assert_eq!(cmp_int_double(-2, -1.5), Some(Less));
+        assert_eq!(cmp_int_double(0, -0.0), Some(Equal));
+        assert_eq!(cmp_int_double(i64::MAX, TWO_POW_63), Some(Less));
+        assert_eq!(cmp_int_double(i64::MIN, -TWO_POW_63), Some(Equal));
+        assert_eq!(cmp_int_double(i64::MIN, f64::NEG_INFINITY), Some(Greater));
+        assert_eq!(
+            cmp_int_double(1 << 53, (1u64 << 53) as f64 + 1.0),
+            Some(Equal)
+        );
+        assert_eq!(
+            cmp_int_double((1 << 53) + 1, (1u64 << 53) as f64),
+            Some(Greater)
+        );
+        assert_eq!(cmp_int_double(1, f64::NAN), None);
+    }
+
+    #[test]
+    fn uint_double() {
+        assert_eq!(cmp_uint_double(1, 1.0), Some(Equal));
+        assert_eq!(cmp_uint_double(0, -0.5), Some(Greater));
+        assert_eq!(cmp_uint_double(u64::MAX, TWO_POW_64), Some(Less));
+        assert_eq!(cmp_uint_double(u64::MAX, f64::INFINITY), Some(Less));
+        assert_eq!(cmp_uint_double(0, f64::NAN), None);
+    }
+
+    #[test]
+    fn int_uint() {
+        assert_eq!(cmp_int_uint(1, 1), Equal);
+        assert_eq!(cmp_int_uint(-1, 0), Less);
+        assert_eq!(cmp_int_uint(i64::MAX, u64::MAX), Less);
+    }
+
+    #[test]
+    fn exact_conversions() {
+        assert_eq!(double_to_int(-3.0), Some(-3));
+        assert_eq!(double_to_int(3.5), None);
+        assert_eq!(double_to_int(TWO_POW_63), None);
+        assert_eq!(double_to_int(f64::NAN), None);
+        assert_eq!(double_to_uint(TWO_POW_63), Some(1 << 63));
+        assert_eq!(double_to_uint(-1.0), None);
+        assert_eq!(double_to_uint(f64::INFINITY), None);
+    }
+}
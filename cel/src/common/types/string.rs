@@ -1,3 +1,5 @@
+#[cfg(feature = "regex")]
+use crate::common::regex::{self, RegexLimits};
 use crate::common::traits::{self, Adder, Comparer, Sizer, Zeroer};
 use crate::common::types::{CelBool, CelBytes, CelDouble, CelInt, CelUInt, Kind, Type};
 #[cfg(feature = "chrono")]
@@ -8,8 +10,11 @@ use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::ops::Deref;
 use std::string::String as StdString;
+use std::sync::Arc;
 
 #[derive(Clone, Debug, Default, Eq, Hash, PartialEq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct String(StdString);
 
 impl String {
@@ -113,6 +118,12 @@ impl From<String> for StdString {
     }
 }
 
+impl From<Arc<StdString>> for String {
+    fn from(v: Arc<StdString>) -> Self {
+        Self(Arc::unwrap_or_clone(v))
+    }
+}
+
 impl From<&str> for String {
     fn from(value: &str) -> Self {
         Self(StdString::from(value))
@@ -150,9 +161,7 @@ fn string_contains<'a>(args: Vec<Cow<'a, dyn Val>>) -> Result<Cow<'a, dyn Val>,
                 got: arg.get_type().name().to_string(),
                 want: super::STRING_TYPE.name().to_string(),
             }),
-            Some(needle) => Ok(Cow::<dyn Val>::Owned(Box::new(CelBool::from(
-                s.contains(needle.inner()),
-            )))),
+            Some(needle) => Ok(Cow::Borrowed(CelBool::borrowed(s.contains(needle.inner())))),
         },
     }
 }
@@ -180,19 +189,45 @@ fn starts_with_string<'a>(args: Vec<Cow<'a, dyn Val>>) -> Result<Cow<'a, dyn Val
 }
 
 #[cfg(feature = "regex")]
-fn matches<'a>(args: Vec<Cow<'a, dyn Val>>) -> Result<Cow<'a, dyn Val>, ExecutionError> {
-    super::binary_fn(
-        args,
-        super::STRING_TYPE,
-        super::STRING_TYPE,
-        |this: &String, regex: &String| match regex::Regex::new(regex.inner()) {
-            Ok(re) => Ok(Box::new(CelBool::from(re.is_match(this.inner())))),
-            Err(err) => Err(ExecutionError::FunctionError {
-                function: "matches".to_string(),
-                message: format!("'{}' not a valid regex:\n{err}", regex.inner()),
-            }),
-        },
-    )
+pub(crate) fn matches<'a>(
+    limits: &RegexLimits,
+    args: Vec<Cow<'a, dyn Val>>,
+) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+    let this = &args[0];
+    let regex = &args[1];
+    match (
+        this.downcast_ref::<String>(),
+        regex.downcast_ref::<String>(),
+    ) {
+        (Some(this), Some(regex)) => {
+            let re = crate::common::regex::compile("matches", regex.inner(), limits)?;
+            Ok(Cow::Borrowed(CelBool::borrowed(re.is_match(this.inner()))))
+        }
+        (None, _) => Err(ExecutionError::UnexpectedType {
+            got: this.get_type().name().to_string(),
+            want: super::STRING_TYPE.name().to_string(),
+        }),
+        (_, None) => Err(ExecutionError::UnexpectedType {
+            got: regex.get_type().name().to_string(),
+            want: super::STRING_TYPE.name().to_string(),
+        }),
+    }
+}
+
+/// `matches` with its pattern compiled ahead of execution, into `re`.
+#[cfg(feature = "regex")]
+pub(crate) fn matches_precompiled<'a>(
+    re: &::regex::Regex,
+    args: &[Cow<'a, dyn Val>],
+) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+    let this = &args[0];
+    match this.downcast_ref::<String>() {
+        Some(this) => Ok(Cow::Borrowed(CelBool::borrowed(re.is_match(this.inner())))),
+        None => Err(ExecutionError::UnexpectedType {
+            got: this.get_type().name().to_string(),
+            want: super::STRING_TYPE.name().to_string(),
+        }),
+    }
 }
 
 fn string<'a>(args: Vec<Cow<'a, dyn Val>>) -> Result<Cow<'a, dyn Val>, ExecutionError> {
@@ -313,14 +348,24 @@ pub(crate) fn stdlib(env: &mut crate::Env) {
     )
     .expect("Must be unique id");
     #[cfg(feature = "regex")]
-    env.add_member_overload(
-        "matches",
-        "matches",
-        super::STRING_TYPE,
-        vec![super::STRING_TYPE],
-        matches,
-    )
-    .expect("Must be unique id");
+    {
+        env.add_op(
+            "matches",
+            "matches_string",
+            false,
+            vec![super::STRING_TYPE, super::STRING_TYPE],
+            regex::regex_op!(matches, Some(matches_precompiled)),
+        )
+        .expect("Must be unique id");
+        env.add_op(
+            "matches",
+            "matches",
+            true,
+            vec![super::STRING_TYPE, super::STRING_TYPE],
+            regex::regex_op!(matches, Some(matches_precompiled)),
+        )
+        .expect("Must be unique id");
+    }
 }
 
 #[cfg(test)]
@@ -1,5 +1,5 @@
 use crate::common::traits::{Adder, Comparer, Divider, Modder, Multiplier, Subtractor, Zeroer};
-use crate::common::types::{CelDouble, CelInt, CelString, Kind, Type};
+use crate::common::types::{numeric, CelDouble, CelInt, CelString, Kind, Type};
 use crate::common::value::{Downcast, Val};
 use crate::{ExecutionError, Value};
 use std::borrow::Cow;
@@ -7,6 +7,8 @@ use std::cmp::Ordering;
 use std::ops::Deref;
 
 #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct UInt(u64);
 
 impl UInt {
@@ -98,16 +100,9 @@ impl Comparer for UInt {
         if let Some(rhs) = rhs.downcast_ref::<Self>() {
             Ok(self.0.cmp(&rhs.0))
         } else if let Some(rhs) = rhs.downcast_ref::<CelInt>() {
-            Ok(self
-                .0
-                .try_into()
-                .map(|a: i64| a.cmp(rhs.inner()))
-                // If the u64 doesn't fit into a i64 it must be greater than i64::MAX.
-                .unwrap_or(Ordering::Greater))
+            Ok(numeric::cmp_int_uint(*rhs.inner(), self.0).reverse())
         } else if let Some(rhs) = rhs.downcast_ref::<CelDouble>() {
-            Ok((*self.inner() as f64)
-                .partial_cmp(rhs.inner())
-                .ok_or(ExecutionError::NoSuchOverload)?)
+            numeric::cmp_uint_double(self.0, *rhs.inner()).ok_or(ExecutionError::NoSuchOverload)
         } else {
             Err(ExecutionError::NoSuchOverload)
         }
@@ -0,0 +1,346 @@
+//! The protobuf well-known types, as CEL sees them: `google.protobuf.Timestamp` and
+//! `google.protobuf.Duration` are timestamps and durations, wrappers are nullable primitives,
+//! `google.protobuf.Struct`, `Value` and `ListValue` are JSON-like maps and lists, and
+//! `google.protobuf.Any` is unpacked to the message it holds.
+
+use std::collections::HashMap;
+
+use prost_reflect::{
+    prost::Message as _, DynamicMessage, MapKey, MessageDescriptor, ReflectMessage,
+    Value as ProtoValue,
+};
+
+use crate::{
+    common::{
+        types::{
+            message::{from_kind, to_val},
+            CelBool, CelDouble, CelInt, CelList, CelMap, CelMessage, CelNull, CelString, CelUInt,
+            Kind, MapStorage,
+        },
+        value::Val,
+    },
+    ExecutionError,
+};
+
+const ANY: &str = "google.protobuf.Any";
+#[cfg(feature = "chrono")]
+const DURATION: &str = "google.protobuf.Duration";
+const LIST_VALUE: &str = "google.protobuf.ListValue";
+const STRUCT: &str = "google.protobuf.Struct";
+#[cfg(feature = "chrono")]
+const TIMESTAMP: &str = "google.protobuf.Timestamp";
+const VALUE: &str = "google.protobuf.Value";
+
+const WRAPPERS: [&str; 9] = [
+    "google.protobuf.BoolValue",
+    "google.protobuf.BytesValue",
+    "google.protobuf.DoubleValue",
+    "google.protobuf.FloatValue",
+    "google.protobuf.Int32Value",
+    "google.protobuf.Int64Value",
+    "google.protobuf.StringValue",
+    "google.protobuf.UInt32Value",
+    "google.protobuf.UInt64Value",
+];
+
+const TYPE_URL_PREFIX: &str = "type.googleapis.com/";
+
+/// Converts a message of a well-known type to its CEL counterpart, returning `None` for
+/// any other message.
+pub(crate) fn unwrap(msg: &DynamicMessage) -> Result<Option<Box<dyn Val>>, ExecutionError> {
+    let desc = msg.descriptor();
+    let val: Box<dyn Val> = match desc.full_name() {
+        name if WRAPPERS.contains(&name) => to_val(&field(msg, "value"))?,
+        #[cfg(feature = "chrono")]
+        TIMESTAMP => {
+            let (seconds, nanos) = (field(msg, "seconds"), field(msg, "nanos"));
+            let ts = chrono::DateTime::from_timestamp(
+                seconds.as_i64().unwrap_or_default(),
+                nanos.as_i32().unwrap_or_default() as u32,
+            )
+            .ok_or_else(|| invalid(TIMESTAMP, "out of range"))?;
+            Box::new(crate::common::types::CelTimestamp::from(ts.fixed_offset()))
+        }
+        #[cfg(feature = "chrono")]
+        DURATION => {
+            let (seconds, nanos) = (field(msg, "seconds"), field(msg, "nanos"));
+            let duration = chrono::Duration::try_seconds(seconds.as_i64().unwrap_or_default())
+                .ok_or_else(|| invalid(DURATION, "out of range"))?
+                + chrono::Duration::nanoseconds(nanos.as_i32().unwrap_or_default() as i64);
+            Box::new(crate::common::types::CelDuration::from(duration))
+        }
+        STRUCT => {
+            let fields = field(msg, "fields");
+            let mut map = MapStorage::new();
+            for (k, v) in fields.as_map().into_iter().flatten() {
+                if let (MapKey::String(k), ProtoValue::Message(v)) = (k, v) {
+                    map.insert(k.as_str().into(), json_value(v)?);
+                }
+            }
+            Box::new(CelMap::from(map))
+        }
+        VALUE => json_value(msg)?,
+        LIST_VALUE => {
+            let values = field(msg, "values");
+            let list = values
+                .as_list()
+                .into_iter()
+                .flatten()
+                .filter_map(ProtoValue::as_message)
+                .map(json_value)
+                .collect::<Result<Vec<_>, _>>()?;
+            Box::new(CelList::from(list))
+        }
+        ANY => {
+            let msg = unpack(msg)?;
+            match unwrap(&msg)? {
+                Some(val) => val,
+                None => Box::new(CelMessage::from(msg)),
+            }
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(val))
+}
+
+/// Returns the value of an unset field of the well-known type `desc`, when it isn't the
+/// conversion of the type's default message: wrappers, `google.protobuf.Value` and
+/// `google.protobuf.Any` are `null` when they aren't set.
+pub(crate) fn unset(desc: &MessageDescriptor) -> Option<Box<dyn Val>> {
+    match desc.full_name() {
+        name if WRAPPERS.contains(&name) || name == VALUE || name == ANY => Some(Box::new(CelNull)),
+        _ => None,
+    }
+}
+
+/// Converts `val` to a message of the well-known type `desc`, returning `None` if `desc`
+/// isn't a well-known type or `val` already is a message of that type.
+pub(crate) fn wrap(
+    desc: &MessageDescriptor,
+    val: &dyn Val,
+) -> Result<Option<DynamicMessage>, ExecutionError> {
+    if val
+        .downcast_ref::<CelMessage>()
+        .is_some_and(|msg| msg.inner().descriptor() == *desc)
+    {
+        return Ok(None);
+    }
+    let mut msg = DynamicMessage::new(desc.clone());
+    match desc.full_name() {
+        name if WRAPPERS.contains(&name) => {
+            let value = desc
+                .get_field_by_name("value")
+                .expect("wrappers have a value");
+            msg.set_field(&value, from_kind(&value.kind(), val)?);
+        }
+        #[cfg(feature = "chrono")]
+        TIMESTAMP => {
+            let ts = val
+                .downcast_ref::<crate::common::types::CelTimestamp>()
+                .ok_or_else(|| unexpected(val, TIMESTAMP))?
+                .inner();
+            msg.set_field_by_name("seconds", ProtoValue::I64(ts.timestamp()));
+            msg.set_field_by_name("nanos", ProtoValue::I32(ts.timestamp_subsec_nanos() as i32));
+        }
+        #[cfg(feature = "chrono")]
+        DURATION => {
+            let duration = val
+                .downcast_ref::<crate::common::types::CelDuration>()
+                .ok_or_else(|| unexpected(val, DURATION))?
+                .inner();
+            let seconds = duration.num_seconds();
+            let nanos = (*duration - chrono::Duration::seconds(seconds))
+                .num_nanoseconds()
+                .unwrap_or_default();
+            msg.set_field_by_name("seconds", ProtoValue::I64(seconds));
+            msg.set_field_by_name("nanos", ProtoValue::I32(nanos as i32));
+        }
+        STRUCT => {
+            let map = val
+                .downcast_ref::<CelMap>()
+                .ok_or_else(|| unexpected(val, STRUCT))?;
+            msg.set_field_by_name("fields", ProtoValue::Map(struct_fields(desc, map)?));
+        }
+        VALUE => return to_json_value(desc, val).map(Some),
+        LIST_VALUE => {
+            let list = val
+                .downcast_ref::<CelList>()
+                .ok_or_else(|| unexpected(val, LIST_VALUE))?;
+            msg.set_field_by_name("values", list_values(desc, list)?);
+        }
+        ANY => {
+            let packed = match val.downcast_ref::<CelMessage>() {
+                Some(packed) => packed.inner().as_ref().clone(),
+                None => {
+                    let name = wrapper_for(val).ok_or_else(|| unexpected(val, ANY))?;
+                    let wrapper_desc = message_desc(desc, name)?;
+                    wrap(&wrapper_desc, val)?.expect("a well-known type")
+                }
+            };
+            msg.set_field_by_name(
+                "type_url",
+                ProtoValue::String(format!(
+                    "{TYPE_URL_PREFIX}{}",
+                    packed.descriptor().full_name()
+                )),
+            );
+            msg.set_field_by_name("value", ProtoValue::Bytes(packed.encode_to_vec().into()));
+        }
+        _ => return Ok(None),
+    }
+    Ok(Some(msg))
+}
+
+/// Decodes the message held by a `google.protobuf.Any`, whose type must be known to the
+/// descriptor pool of the `Any` itself.
+fn unpack(any: &DynamicMessage) -> Result<DynamicMessage, ExecutionError> {
+    let type_url = field(any, "type_url");
+    let type_url = type_url.as_str().unwrap_or_default();
+    let name = type_url.rsplit_once('/').map_or(type_url, |(_, name)| name);
+    let desc = any
+        .descriptor()
+        .parent_pool()
+        .get_message_by_name(name)
+        .ok_or_else(|| invalid(ANY, format!("unknown type '{type_url}'")))?;
+    let value = field(any, "value");
+    DynamicMessage::decode(desc, value.as_bytes().cloned().unwrap_or_default())
+        .map_err(|err| invalid(ANY, err))
+}
+
+fn json_value(msg: &DynamicMessage) -> Result<Box<dyn Val>, ExecutionError> {
+    let Some((field, value)) = msg.fields().next() else {
+        return Ok(Box::new(CelNull));
+    };
+    Ok(match (field.name(), value) {
+        ("null_value", _) => Box::new(CelNull),
+        ("struct_value" | "list_value", ProtoValue::Message(msg)) => {
+            unwrap(msg)?.expect("a well-known type")
+        }
+        (_, value) => to_val(value)?,
+    })
+}
+
+fn to_json_value(
+    desc: &MessageDescriptor,
+    val: &dyn Val,
+) -> Result<DynamicMessage, ExecutionError> {
+    let mut msg = DynamicMessage::new(desc.clone());
+    let (field, value) = match val.get_type().kind() {
+        Kind::NullType => ("null_value", ProtoValue::EnumNumber(0)),
+        Kind::Boolean => (
+            "bool_value",
+            ProtoValue::Bool(*val.downcast_ref::<CelBool>().unwrap().inner()),
+        ),
+        Kind::Int => (
+            "number_value",
+            ProtoValue::F64(*val.downcast_ref::<CelInt>().unwrap().inner() as f64),
+        ),
+        Kind::UInt => (
+            "number_value",
+            ProtoValue::F64(*val.downcast_ref::<CelUInt>().unwrap().inner() as f64),
+        ),
+        Kind::Double => (
+            "number_value",
+            ProtoValue::F64(*val.downcast_ref::<CelDouble>().unwrap().inner()),
+        ),
+        Kind::String => (
+            "string_value",
+            ProtoValue::String(val.downcast_ref::<CelString>().unwrap().inner().to_owned()),
+        ),
+        Kind::Map => {
+            let map = val
+                .downcast_ref::<CelMap>()
+                .ok_or_else(|| unexpected(val, VALUE))?;
+            let mut strct = DynamicMessage::new(message_desc(desc, STRUCT)?);
+            strct.set_field_by_name("fields", ProtoValue::Map(struct_fields(desc, map)?));
+            ("struct_value", ProtoValue::Message(strct))
+        }
+        Kind::List => {
+            let list = val
+                .downcast_ref::<CelList>()
+                .ok_or_else(|| unexpected(val, VALUE))?;
+            let mut values = DynamicMessage::new(message_desc(desc, LIST_VALUE)?);
+            values.set_field_by_name("values", list_values(desc, list)?);
+            ("list_value", ProtoValue::Message(values))
+        }
+        _ => return Err(unexpected(val, VALUE)),
+    };
+    msg.set_field_by_name(field, value);
+    Ok(msg)
+}
+
+fn struct_fields(
+    desc: &MessageDescriptor,
+    map: &CelMap,
+) -> Result<HashMap<MapKey, ProtoValue>, ExecutionError> {
+    let value_desc = message_desc(desc, VALUE)?;
+    map.inner()
+        .iter()
+        .map(|(k, v)| {
+            let key = k
+                .inner()
+                .downcast_ref::<CelString>()
+                .ok_or_else(|| unexpected(k.inner(), "string key"))?;
+            Ok((
+                MapKey::String(key.inner().to_owned()),
+                ProtoValue::Message(to_json_value(&value_desc, v.as_ref())?),
+            ))
+        })
+        .collect()
+}
+
+fn list_values(desc: &MessageDescriptor, list: &CelList) -> Result<ProtoValue, ExecutionError> {
+    let value_desc = message_desc(desc, VALUE)?;
+    Ok(ProtoValue::List(
+        list.inner()
+            .iter()
+            .map(|v| to_json_value(&value_desc, v.as_ref()).map(ProtoValue::Message))
+            .collect::<Result<_, _>>()?,
+    ))
+}
+
+/// The well-known type a value of a primitive CEL type is packed as in a `google.protobuf.Any`.
+fn wrapper_for(val: &dyn Val) -> Option<&'static str> {
+    Some(match val.get_type().kind() {
+        Kind::Boolean => "google.protobuf.BoolValue",
+        Kind::Bytes => "google.protobuf.BytesValue",
+        Kind::Double => "google.protobuf.DoubleValue",
+        Kind::Int => "google.protobuf.Int64Value",
+        Kind::String => "google.protobuf.StringValue",
+        Kind::UInt => "google.protobuf.UInt64Value",
+        #[cfg(feature = "chrono")]
+        Kind::Duration => DURATION,
+        #[cfg(feature = "chrono")]
+        Kind::Timestamp => TIMESTAMP,
+        Kind::Map => STRUCT,
+        Kind::List => LIST_VALUE,
+        Kind::NullType => VALUE,
+        _ => return None,
+    })
+}
+
+/// Looks up the well-known type `name` in the pool `desc` comes from.
+fn message_desc(desc: &MessageDescriptor, name: &str) -> Result<MessageDescriptor, ExecutionError> {
+    desc.parent_pool()
+        .get_message_by_name(name)
+        .ok_or_else(|| ExecutionError::UnexpectedType {
+            got: name.to_owned(),
+            want: "message type known to the environment".to_owned(),
+        })
+}
+
+fn field<'a>(msg: &'a DynamicMessage, name: &str) -> std::borrow::Cow<'a, ProtoValue> {
+    msg.get_field_by_name(name)
+        .expect("well-known types have the field")
+}
+
+fn invalid<E: ToString>(name: &str, err: E) -> ExecutionError {
+    ExecutionError::function_error(name, err)
+}
+
+fn unexpected(val: &dyn Val, want: &str) -> ExecutionError {
+    ExecutionError::UnexpectedType {
+        got: val.get_type().name().to_owned(),
+        want: want.to_owned(),
+    }
+}
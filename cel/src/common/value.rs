@@ -72,6 +72,10 @@ impl dyn Val {
     pub fn downcast_ref<T: Val>(&self) -> Option<&T> {
         <dyn Any>::downcast_ref::<T>(self)
     }
+
+    pub fn downcast_mut<T: Val>(&mut self) -> Option<&mut T> {
+        <dyn Any>::downcast_mut::<T>(self)
+    }
 }
 
 pub trait Downcast {
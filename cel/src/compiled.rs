@@ -0,0 +1,255 @@
+//! Serialization of compiled [`Program`]s, so that they can be cached rather than parsed again.
+//! Programs serialize as their AST along with its [`SourceInfo`], anything else a program
+//! compiles ahead of execution is compiled again when it is deserialized. A
+//! [planned](Program::plan) program deserializes unplanned, as planning depends on the
+//! [`Env`](crate::Env).
+
+use crate::common::ast::{SourceInfo, MAX_LOAD_DEPTH};
+use crate::parser::Expression;
+use crate::Program;
+use bincode::Options;
+use serde::de::Error as _;
+use serde::ser;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Marks bytes as a program serialized by [`Program::to_bytes`].
+const MAGIC: &[u8; 4] = b"CELP";
+
+/// The version of the format of [`Program::to_bytes`]. It has to change along with anything
+/// that changes how a program serializes, such as the AST types.
+const FORMAT_VERSION: u16 = 3;
+
+/// An error loading a program with [`Program::from_bytes`].
+#[derive(Error, Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ProgramDecodeError {
+    #[error("Not a compiled CEL program")]
+    NotAProgram,
+    /// Indicates that the program was serialized by a version of this crate with a different
+    /// format, and has to be compiled again.
+    #[error("Unsupported compiled program format version {found}, expected {expected}")]
+    UnsupportedVersion { found: u16, expected: u16 },
+    #[error("Invalid compiled program: {0}")]
+    Invalid(String),
+}
+
+/// An error serializing a program with [`Program::to_bytes`].
+#[derive(Error, Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ProgramEncodeError {
+    /// Indicates a program nested deeper than [`Program::from_bytes`] loads, which is 200.
+    #[error("Expression nested deeper than {max}")]
+    TooDeep { max: usize },
+}
+
+/// The encoding of the program after the header.
+fn encoding() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+#[derive(Serialize)]
+#[serde(rename = "Program")]
+struct ProgramRef<'a> {
+    expression: &'a Expression,
+    source_info: &'a SourceInfo,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Program")]
+struct ProgramData {
+    expression: Expression,
+    source_info: SourceInfo,
+}
+
+impl Serialize for Program {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Written, it would fail to load.
+        if self.deepest.0 > MAX_LOAD_DEPTH {
+            return Err(ser::Error::custom(ProgramEncodeError::TooDeep {
+                max: MAX_LOAD_DEPTH,
+            }));
+        }
+        ProgramRef {
+            expression: &self.expression,
+            source_info: &self.source_info,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Program {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let ProgramData {
+            expression,
+            source_info,
+        } = ProgramData::deserialize(deserializer)?;
+        // Whichever parser it was compiled with, the program compiled
+        Program::from_parsed(expression, Arc::new(source_info), false).map_err(D::Error::custom)
+    }
+}
+
+impl Program {
+    /// Serializes the compiled program to bytes, which [`Program::from_bytes`] loads without
+    /// parsing the source again. The bytes start with a header identifying the format, and are
+    /// only meant to be loaded by the same version of this crate. Programs nested deeper than
+    /// 200, which wouldn't load, fail to serialize.
+    ///
+    /// # Example
+    /// ```
+    /// # use cel::{Context, Program};
+    /// let bytes = Program::compile("size(name) > 3").unwrap().to_bytes().unwrap();
+    /// let program = Program::from_bytes(&bytes).unwrap();
+    ///
+    /// let mut context = Context::default();
+    /// context.add_variable_from_value("name", "Alice");
+    /// assert_eq!(program.execute(&context), Ok(true.into()));
+    /// ```
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ProgramEncodeError> {
+        if self.deepest.0 > MAX_LOAD_DEPTH {
+            return Err(ProgramEncodeError::TooDeep {
+                max: MAX_LOAD_DEPTH,
+            });
+        }
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(FORMAT_VERSION.to_le_bytes());
+        encoding()
+            .serialize_into(&mut bytes, self)
+            .expect("programs loading back always serialize");
+        Ok(bytes)
+    }
+
+    /// Loads a program serialized by [`Program::to_bytes`]. The bytes may come from anywhere:
+    /// those that aren't such a program fail to load, as do programs nested deeper than 200.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Program, ProgramDecodeError> {
+        let bytes = bytes
+            .strip_prefix(MAGIC)
+            .ok_or(ProgramDecodeError::NotAProgram)?;
+        let (version, bytes) = bytes
+            .split_first_chunk()
+            .ok_or(ProgramDecodeError::NotAProgram)?;
+        let version = u16::from_le_bytes(*version);
+        if version != FORMAT_VERSION {
+            return Err(ProgramDecodeError::UnsupportedVersion {
+                found: version,
+                expected: FORMAT_VERSION,
+            });
+        }
+        // Lengths the bytes claim beyond their end fail before anything is allocated for them.
+        encoding()
+            .with_limit(bytes.len() as u64)
+            .deserialize(bytes)
+            .map_err(|err| ProgramDecodeError::Invalid(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::{Context, Value};
+
+    fn round_trip(program: &Program) -> Program {
+        Program::from_bytes(&program.to_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn program_round_trip() {
+        let program = Program::compile(
+            "[1, 2u, 3.5, b'4', null].exists(x, x == 3.5) && {'a': true}.a ? 'yes' : 'no'",
+        )
+        .unwrap();
+        let loaded = round_trip(&program);
+        assert_eq!(loaded.expression(), program.expression());
+        assert_eq!(
+            loaded.execute(&Context::default()),
+            Ok(Value::String(Arc::new("yes".into())))
+        );
+    }
+
+    #[test]
+    fn keeps_source_info() {
+        let parser = Parser::new().source_name("policy.yaml").source_offset(2, 4);
+        let program = Program::compile_with(parser, "1 +\n  {}.missing").unwrap();
+        let err = round_trip(&program)
+            .execute_with_location(&Context::default())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERROR: policy.yaml:4:5: No such key: missing\n|   {}.missing\n| ....^"
+        );
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn precompiles_regexes() {
+        let program = round_trip(&Program::compile("'abc'.matches('^a.c$')").unwrap());
+        assert_eq!(program.regexes.len(), 1);
+        assert_eq!(program.execute(&Context::default()), Ok(true.into()));
+    }
+
+    #[test]
+    fn round_trips_up_to_the_max_depth() {
+        let sum = |n: usize| Program::compile(&format!("1{}", " + 1".repeat(n - 1))).unwrap();
+        let program = round_trip(&sum(MAX_LOAD_DEPTH));
+        assert_eq!(program.execute(&Context::default()), Ok(200.into()));
+
+        let deeper = sum(MAX_LOAD_DEPTH + 1);
+        let too_deep = ProgramEncodeError::TooDeep { max: 200 };
+        assert_eq!(deeper.to_bytes(), Err(too_deep.clone()));
+        assert_eq!(
+            serde_json::to_string(&deeper).unwrap_err().to_string(),
+            too_deep.to_string()
+        );
+
+        // Bytes claiming to be a program any deeper, written some other way, fail to load.
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(FORMAT_VERSION.to_le_bytes());
+        let program = ProgramRef {
+            expression: &deeper.expression,
+            source_info: &deeper.source_info,
+        };
+        encoding().serialize_into(&mut bytes, &program).unwrap();
+        assert_eq!(
+            Program::from_bytes(&bytes).unwrap_err(),
+            ProgramDecodeError::Invalid(too_deep.to_string())
+        );
+    }
+
+    #[test]
+    fn serde_json() {
+        let program = Program::compile("x * 2").unwrap();
+        let json = serde_json::to_string(&program).unwrap();
+        let loaded: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.expression(), program.expression());
+    }
+
+    #[test]
+    fn invalid_bytes() {
+        let bytes = Program::compile("true").unwrap().to_bytes().unwrap();
+        assert_eq!(
+            Program::from_bytes(b"true").unwrap_err(),
+            ProgramDecodeError::NotAProgram
+        );
+        assert_eq!(
+            Program::from_bytes(&bytes[..5]).unwrap_err(),
+            ProgramDecodeError::NotAProgram
+        );
+
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            Program::from_bytes(&newer).unwrap_err(),
+            ProgramDecodeError::UnsupportedVersion {
+                found: FORMAT_VERSION + 1,
+                expected: FORMAT_VERSION,
+            }
+        );
+
+        assert!(matches!(
+            Program::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(ProgramDecodeError::Invalid(_))
+        ));
+    }
+}
@@ -2,7 +2,7 @@ use crate::common::value::Val;
 use crate::magic::{Function, FunctionRegistry, IntoFunction};
 use crate::objects::{TryIntoValue, Value};
 use crate::parser::Expression;
-use crate::{Env, ExecutionError};
+use crate::{Env, ExecutionError, Program};
 use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::sync::Arc;
@@ -19,14 +19,12 @@ use std::sync::Arc;
 /// resolve variables in the child's parents. You can have theoretically
 /// have an infinite number of child contexts that reference each-other.
 ///
-/// So why is this important? Well some CEL-macros such as the `.map` macro
-/// declare intermediate user-specified identifiers that should only be
-/// available within the macro, and should not override variables in the
-/// parent context. The `.map` macro can create a child context from the parent, add the
-/// intermediate identifier to the child context, and then evaluate the
-/// map expression.
+/// Macros such as `.map` declare intermediate user-specified identifiers too, which
+/// are only in scope within the macro and shadow variables of the context. The
+/// interpreter keeps those apart from the context, in a frame of their own for every
+/// iteration, so that they don't have to be copied into a child context.
 ///
-/// Intermediate variable stored in child context
+/// Intermediate variable stored in the frame of the iteration
 ///               ↓
 /// [1, 2, 3].map(x, x * 2) == [2, 4, 6]
 ///                  ↑
@@ -43,6 +41,7 @@ pub enum Context<'a> {
         parent: &'a Context<'a>,
         variables: BTreeMap<String, Box<dyn Val>>,
         resolver: Option<&'a dyn VariableResolver>,
+        program: Option<&'a Program>,
     },
 }
 
@@ -56,18 +55,8 @@ impl<'a> Context<'a> {
         S: Into<String>,
         V: TryIntoValue,
     {
-        match self {
-            Context::Root { variables, .. } => {
-                let value = value.try_into_value()?;
-                let value: Box<dyn Val> = value.try_into().unwrap();
-                variables.insert(name.into(), value);
-            }
-            Context::Child { variables, .. } => {
-                let value = value.try_into_value()?;
-                let value: Box<dyn Val> = value.try_into().unwrap();
-                variables.insert(name.into(), value);
-            }
-        }
+        let value = value.try_into_value()?;
+        self.add_variable_as_val(name, value.try_into().unwrap());
         Ok(())
     }
 
@@ -76,18 +65,34 @@ impl<'a> Context<'a> {
         S: Into<String>,
         V: Into<Value>,
     {
-        match self {
-            Context::Root { variables, .. } => {
-                let value = value.into();
-                let value: Box<dyn Val> = value.try_into().unwrap();
-                variables.insert(name.into(), value);
-            }
-            Context::Child { variables, .. } => {
-                let value = value.into();
-                let value: Box<dyn Val> = value.try_into().unwrap();
-                variables.insert(name.into(), value);
-            }
-        }
+        self.add_variable_as_val(name, value.into().try_into().unwrap());
+    }
+
+    /// Adds a JSON document as a variable, without converting it to a [`Value`] first.
+    ///
+    /// Arrays and objects are read as they are used, so an expression only pays for the part
+    /// of the document it actually reads. The document is shared, not copied: CEL values can't
+    /// borrow, so it is held through an [`Arc`] rather than a reference.
+    ///
+    /// # Example
+    /// ```
+    /// use cel::{Context, Program};
+    ///
+    /// let body = serde_json::json!({"items": [{"id": 1}, {"id": 2}]});
+    /// let mut context = Context::default();
+    /// context.add_json_variable("body", body);
+    ///
+    /// let program = Program::compile("body.items[1].id").unwrap();
+    /// assert_eq!(program.execute(&context).unwrap(), 2.into());
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn add_json_variable<S, J>(&mut self, name: S, json: J)
+    where
+        S: Into<String>,
+        J: Into<Arc<serde_json::Value>>,
+    {
+        let value = crate::common::types::CelJson::from_document(json.into());
+        self.add_variable_as_val(name, value);
     }
 
     pub(crate) fn add_variable_as_val<S>(&mut self, name: S, value: Box<dyn Val>)
@@ -115,51 +120,66 @@ impl<'a> Context<'a> {
         }
     }
 
+    /// Returns the variable `name`, or `None` if it isn't defined or the variable resolver
+    /// fails to resolve it.
     pub fn get_variable<S>(&'a self, name: S) -> Option<Cow<'a, dyn Val>>
     where
         S: AsRef<str>,
     {
-        let name = name.as_ref();
-        match self {
+        self.try_get_variable(name.as_ref()).ok().flatten()
+    }
+
+    /// Returns the variable `name`, or `None` if it isn't defined, failing if the variable
+    /// resolver fails to resolve it.
+    pub(crate) fn try_get_variable(
+        &'a self,
+        name: &str,
+    ) -> Result<Option<Cow<'a, dyn Val>>, ExecutionError> {
+        let (variables, resolver, parent) = match self {
             Context::Child {
                 variables,
                 parent,
                 resolver,
-            } => resolver
-                .and_then(|r| {
-                    r.resolve(name)
-                        .map(|v| Cow::<dyn Val>::Owned(v.try_into().unwrap()))
-                })
-                .or_else(|| {
-                    variables
-                        .get(name)
-                        .map(|b| Cow::<dyn Val>::Borrowed(b.as_ref()))
-                        .or_else(|| parent.get_variable(name))
-                }),
+                ..
+            } => (variables, resolver, Some(parent)),
             Context::Root {
                 variables,
                 resolver,
                 ..
-            } => resolver
-                .and_then(|r| {
-                    r.resolve(name)
-                        .map(|v| Cow::<dyn Val>::Owned(v.try_into().unwrap()))
-                })
-                .or_else(|| {
-                    variables
-                        .get(name)
-                        .map(|v| Cow::<dyn Val>::Borrowed(v.as_ref()))
-                }),
+            } => (variables, resolver, None),
+        };
+        if let Some(val) = resolver.map(|r| r.resolve_val(name)).transpose()?.flatten() {
+            return Ok(Some(val));
+        }
+        match variables.get(name) {
+            Some(val) => Ok(Some(Cow::Borrowed(val.as_ref()))),
+            None => parent.map_or(Ok(None), |parent| parent.try_get_variable(name)),
         }
     }
 
-    pub(crate) fn env(&self) -> &Env {
+    pub(crate) fn env(&self) -> &Arc<Env> {
         match self {
-            Context::Root { env, .. } => env.as_ref(),
+            Context::Root { env, .. } => env,
             Context::Child { parent, .. } => parent.env(),
         }
     }
 
+    /// Returns the precompiled pattern of the `matches` call expression `id`, from the
+    /// [`Program`] being executed. Patterns are precompiled within the default
+    /// [`RegexLimits`](crate::RegexLimits), so only overloads which have those use it, see
+    /// [`OverloadDecl::call_with_pattern`].
+    #[cfg(feature = "regex")]
+    pub(crate) fn precompiled_regex(&self, id: u64) -> Option<&regex::Regex> {
+        match self {
+            Context::Root { .. } => None,
+            Context::Child {
+                program: Some(program),
+                ..
+            } => program.precompiled_regex(id),
+            Context::Child { parent, .. } => parent.precompiled_regex(id),
+        }
+    }
+
     #[allow(dead_code)]
     pub(crate) fn get_function(&self, name: &str) -> Option<&Function> {
         match self {
@@ -190,6 +210,19 @@ impl<'a> Context<'a> {
             parent: self,
             variables: Default::default(),
             resolver: None,
+            program: None,
+        }
+    }
+
+    /// Creates the scope a [`Program`] is executed in, giving the interpreter access to what
+    /// was compiled along with it.
+    #[cfg(feature = "regex")]
+    pub(crate) fn new_program_scope<'p>(&'p self, program: &'p Program) -> Context<'p> {
+        Context::Child {
+            parent: self,
+            variables: Default::default(),
+            resolver: None,
+            program: Some(program),
         }
     }
 
@@ -234,9 +267,71 @@ impl Default for Context<'_> {
     }
 }
 
+/// The variables of a comprehension during one of its iterations, which shadow those of the
+/// [`Context`]. Values are borrowed from the range being iterated and the accumulator rather
+/// than copied, and each frame lives on the stack of the iteration, linked to the frame of the
+/// enclosing comprehension, if any.
+pub(crate) struct Frame<'a> {
+    parent: Option<&'a Frame<'a>>,
+    /// The iteration variable, unset while evaluating the result of the comprehension.
+    iter: Option<(&'a str, &'a dyn Val)>,
+    accu: (&'a str, &'a dyn Val),
+}
+
+/// A variable of a comprehension, addressed by how many frames up from the innermost one its
+/// comprehension is.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Slot {
+    pub(crate) up: usize,
+    pub(crate) var: SlotVar,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum SlotVar {
+    Iter,
+    Accu,
+}
+
+impl<'a> Frame<'a> {
+    pub(crate) fn new(
+        parent: Option<&'a Frame<'a>>,
+        iter: Option<(&'a str, &'a dyn Val)>,
+        accu: (&'a str, &'a dyn Val),
+    ) -> Self {
+        Frame { parent, iter, accu }
+    }
+
+    /// Looks up the variable `name` in this frame, and then in those of enclosing comprehensions.
+    pub(crate) fn get(&self, name: &str) -> Option<&'a dyn Val> {
+        let mut frame = self;
+        loop {
+            match frame.iter {
+                Some((iter, val)) if iter == name => return Some(val),
+                _ if frame.accu.0 == name => return Some(frame.accu.1),
+                _ => frame = frame.parent?,
+            }
+        }
+    }
+
+    /// Returns the variable in `slot`, which has to be one a plan assigned within this frame.
+    pub(crate) fn slot(&self, slot: Slot) -> &'a dyn Val {
+        let mut frame = self;
+        for _ in 0..slot.up {
+            frame = frame.parent.expect("slot of an enclosing comprehension");
+        }
+        match slot.var {
+            SlotVar::Iter => frame.iter.expect("slot of an iteration variable").1,
+            SlotVar::Accu => frame.accu.1,
+        }
+    }
+}
+
 /// VariableResolver implements a custom resolver for variables that is consulted before looking at
 /// variables added to the context. This allows dynamic variables, or avoiding HashMap lookup/creation.
 ///
+/// The interpreter calls [`VariableResolver::resolve_val`], which converts what
+/// [`VariableResolver::resolve`] returns by default. Override it as well to hand the interpreter
+/// a [`Val`] without converting it, possibly borrowed from the resolver.
 ///
 /// # Example
 /// ```
@@ -255,30 +350,85 @@ impl Default for Context<'_> {
 ///     }
 /// }
 /// ```
+///
+/// Resolving to a [`Val`] as well:
+/// ```
+/// use cel::common::types::CelString;
+/// use cel::common::value::Val;
+/// use cel::ExecutionError;
+/// use std::borrow::Cow;
+///
+/// struct Request {
+///     path: CelString,
+/// }
+///
+/// impl cel::context::VariableResolver for Request {
+///     fn resolve(&self, variable: &str) -> Option<cel::Value> {
+///         self.resolve_val(variable).ok()??.as_ref().try_into().ok()
+///     }
+///
+///     fn resolve_val(&self, variable: &str) -> Result<Option<Cow<'_, dyn Val>>, ExecutionError> {
+///         match variable {
+///             "path" => Ok(Some(Cow::Borrowed(&self.path))),
+///             _ => Ok(None),
+///         }
+///     }
+/// }
+/// ```
 pub trait VariableResolver: Send + Sync {
+    /// Resolves `variable`, or returns `None` to look it up in the context's variables.
     fn resolve(&self, variable: &str) -> Option<Value>;
+
+    /// Resolves `variable` to the [`Val`] the interpreter works with, or returns `None` to look
+    /// it up in the context's variables. Defaults to converting what
+    /// [`VariableResolver::resolve`] returns, failing the evaluation if the [`Value`] has no
+    /// [`Val`] representation.
+    fn resolve_val(&self, variable: &str) -> Result<Option<Cow<'_, dyn Val>>, ExecutionError> {
+        self.resolve(variable)
+            .map(|value| value.try_into().map(Cow::<dyn Val>::Owned))
+            .transpose()
+    }
 }
 
 impl<T: VariableResolver> VariableResolver for Box<T> {
     fn resolve(&self, variable: &str) -> Option<Value> {
         (**self).resolve(variable)
     }
+
+    fn resolve_val(&self, variable: &str) -> Result<Option<Cow<'_, dyn Val>>, ExecutionError> {
+        (**self).resolve_val(variable)
+    }
 }
 
 impl<T: VariableResolver> VariableResolver for Arc<T> {
     fn resolve(&self, variable: &str) -> Option<Value> {
         (**self).resolve(variable)
     }
+
+    fn resolve_val(&self, variable: &str) -> Result<Option<Cow<'_, dyn Val>>, ExecutionError> {
+        (**self).resolve_val(variable)
+    }
 }
 
 impl<T: VariableResolver> VariableResolver for &T {
     fn resolve(&self, variable: &str) -> Option<Value> {
         (**self).resolve(variable)
     }
+
+    fn resolve_val(&self, variable: &str) -> Result<Option<Cow<'_, dyn Val>>, ExecutionError> {
+        (**self).resolve_val(variable)
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use super::{Context, VariableResolver};
+    use crate::common::types::CelString;
+    use crate::common::value::Val;
+    use crate::{Backend, Env, ExecutionError, Program, Value};
+    use std::borrow::Cow;
+    use std::sync::Arc;
+
     // A helper function that requires T to implement some traits
     fn assert_send<T: Send>() {}
 
@@ -287,4 +437,76 @@ mod test {
         // This line will only compile if assertion passes
         assert_send::<super::Context>();
     }
+
+    #[test]
+    fn test_variable_resolvers() {
+        struct Vals(CelString);
+
+        impl VariableResolver for Vals {
+            fn resolve(&self, variable: &str) -> Option<Value> {
+                self.resolve_val(variable).ok()??.as_ref().try_into().ok()
+            }
+
+            fn resolve_val(
+                &self,
+                variable: &str,
+            ) -> Result<Option<Cow<'_, dyn Val>>, ExecutionError> {
+                Ok((variable == "name").then_some(Cow::Borrowed(&self.0)))
+            }
+        }
+
+        struct Values;
+
+        impl VariableResolver for Values {
+            fn resolve(&self, variable: &str) -> Option<Value> {
+                (variable == "name").then(|| "cel".into())
+            }
+        }
+
+        let program = Program::compile("name + '!' == 'cel!' && other == 1").unwrap();
+        let vals = Vals(CelString::from("cel"));
+        for resolver in [&vals as &dyn VariableResolver, &Values] {
+            let mut ctx = Context::default();
+            ctx.add_variable_from_value("other", 1);
+            ctx.set_variable_resolver(resolver);
+            assert_eq!(program.execute(&ctx), Ok(true.into()));
+        }
+        assert_eq!(vals.resolve("name"), Some("cel".into()));
+        assert_eq!(
+            Values
+                .resolve_val("name")
+                .unwrap()
+                .unwrap()
+                .downcast_ref::<CelString>(),
+            Some(&CelString::from("cel"))
+        );
+    }
+
+    #[test]
+    fn test_variable_resolver_failing_to_convert() {
+        struct Functions;
+
+        impl VariableResolver for Functions {
+            fn resolve(&self, variable: &str) -> Option<Value> {
+                (variable == "f").then(|| Value::Function(Arc::new("size".into()), None))
+            }
+        }
+
+        let env = Arc::new(Env::stdlib());
+        let mut ctx = Context::with_env(env.clone());
+        ctx.add_variable_from_value("f", 1);
+        ctx.set_variable_resolver(&Functions);
+        let compile = || Program::compile("f").unwrap();
+        for program in [
+            compile(),
+            compile().plan_with(&env, Backend::Tree),
+            compile().plan_with(&env, Backend::Bytecode),
+        ] {
+            // Rather than the variable of the context
+            assert!(matches!(
+                program.execute(&ctx),
+                Err(ExecutionError::UnsupportedTargetType { .. })
+            ));
+        }
+    }
 }
@@ -0,0 +1,756 @@
+// Like `ser`, this mirrors the Deserializer serde_json implements for its own Value
+// enum, see [serde_json](https://github.com/serde-rs/json/blob/master/src/value/de.rs).
+
+use crate::{
+    common::types::MapStorage,
+    objects::{Key, OptionalValue},
+    Value,
+};
+#[cfg(feature = "chrono")]
+use crate::{
+    objects::Map,
+    ser::{Duration, Timestamp},
+};
+use serde::de::{
+    self, value::StringDeserializer, DeserializeOwned, DeserializeSeed, Expected, IntoDeserializer,
+    Unexpected, Visitor,
+};
+use std::{fmt::Display, sync::Arc};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum DeserializationError {
+    SerdeError(String),
+}
+
+impl de::Error for DeserializationError {
+    fn custom<T>(msg: T) -> Self
+    where
+        T: Display,
+    {
+        DeserializationError::SerdeError(msg.to_string())
+    }
+}
+
+impl Display for DeserializationError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeserializationError::SerdeError(msg) => formatter.write_str(msg),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, DeserializationError>;
+
+/// Converts a [`Value`], such as the result of a [`Program`](crate::Program), to any type
+/// implementing [`serde::Deserialize`].
+///
+/// Maps deserialize to structs or maps, lists to sequences and optionals to [`Option`].
+/// Timestamps and durations deserialize to [`Timestamp`] and [`Duration`].
+///
+/// # Example
+///
+/// ```
+/// use cel::{from_value, Context, Program};
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Decision {
+///     allow: bool,
+///     reasons: Vec<String>,
+/// }
+///
+/// let program = Program::compile("{'allow': true, 'reasons': ['trusted']}").unwrap();
+/// let value = program.execute(&Context::default()).unwrap();
+/// let decision: Decision = from_value(value).unwrap();
+/// assert!(decision.allow);
+/// assert_eq!(decision.reasons, vec!["trusted"]);
+/// ```
+pub fn from_value<T>(value: Value) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    T::deserialize(value)
+}
+
+impl Value {
+    fn invalid_type<E: de::Error>(&self, exp: &dyn Expected) -> E {
+        de::Error::invalid_type(self.unexpected(), exp)
+    }
+
+    fn unexpected(&self) -> Unexpected<'_> {
+        match self {
+            Value::Null => Unexpected::Unit,
+            Value::Bool(b) => Unexpected::Bool(*b),
+            Value::Int(i) => Unexpected::Signed(*i),
+            Value::UInt(u) => Unexpected::Unsigned(*u),
+            Value::Float(f) => Unexpected::Float(*f),
+            Value::String(s) => Unexpected::Str(s),
+            Value::Bytes(b) => Unexpected::Bytes(b),
+            Value::List(_) => Unexpected::Seq,
+            Value::Map(_) => Unexpected::Map,
+            _ => Unexpected::Other(match self.type_of() {
+                crate::objects::ValueType::Duration => "duration",
+                crate::objects::ValueType::Timestamp => "timestamp",
+                crate::objects::ValueType::Function => "function",
+                _ => "opaque value",
+            }),
+        }
+    }
+}
+
+#[cfg(feature = "chrono")]
+fn duration_fields(d: chrono::Duration) -> Value {
+    let secs = d.num_seconds();
+    let nanos = (d - chrono::Duration::seconds(secs))
+        .num_nanoseconds()
+        .unwrap_or_default();
+    Value::Map(Map::from_iter([
+        (Duration::SECS_FIELD, Value::Int(secs)),
+        (Duration::NANOS_FIELD, Value::Int(nanos)),
+    ]))
+}
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = DeserializationError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Null => visitor.visit_unit(),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::Int(i) => visitor.visit_i64(i),
+            Value::UInt(u) => visitor.visit_u64(u),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::String(s) => visitor.visit_string(Arc::unwrap_or_clone(s)),
+            Value::Bytes(b) => visitor.visit_byte_buf(Arc::unwrap_or_clone(b)),
+            Value::List(l) => visit_list(Arc::unwrap_or_clone(l), visitor),
+            Value::Map(m) => visit_map(Arc::unwrap_or_clone(m.map), visitor),
+            #[cfg(feature = "chrono")]
+            Value::Timestamp(ts) => visitor.visit_string(ts.to_rfc3339()),
+            #[cfg(feature = "chrono")]
+            Value::Duration(d) => duration_fields(d).deserialize_any(visitor),
+            #[cfg(feature = "structs")]
+            Value::Struct(s) => {
+                let fields = s
+                    .field_values()
+                    .into_iter()
+                    .map(|(k, v)| {
+                        Value::try_from(v.as_ref())
+                            .map(|v| (Key::from(k), v))
+                            .map_err(de::Error::custom)
+                    })
+                    .collect::<Result<_>>()?;
+                visit_map(fields, visitor)
+            }
+            Value::Opaque(ref o) => match o.downcast_ref::<OptionalValue>() {
+                Some(opt) => match opt.value() {
+                    None => visitor.visit_none(),
+                    Some(v) => visitor.visit_some(v.clone()),
+                },
+                None => Err(self.invalid_type(&visitor)),
+            },
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Null => visitor.visit_none(),
+            Value::Opaque(ref o) if o.downcast_ref::<OptionalValue>().is_some() => {
+                self.deserialize_any(visitor)
+            }
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    #[cfg_attr(not(feature = "chrono"), allow(unused_variables))]
+    fn deserialize_newtype_struct<V>(self, name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        #[cfg(feature = "chrono")]
+        match (name, &self) {
+            (Timestamp::NAME, Value::Timestamp(ts)) => {
+                return visitor.visit_newtype_struct(Value::String(Arc::new(ts.to_rfc3339())));
+            }
+            (Duration::NAME, Value::Duration(d)) => {
+                return visitor.visit_newtype_struct(duration_fields(*d));
+            }
+            _ => {}
+        }
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let (variant, value) = match self {
+            Value::Map(m) => {
+                let mut iter = Arc::unwrap_or_clone(m.map).into_iter();
+                let (variant, value) = match (iter.next(), iter.next()) {
+                    (Some(entry), None) => entry,
+                    _ => {
+                        return Err(de::Error::invalid_value(
+                            Unexpected::Map,
+                            &"map with a single key",
+                        ))
+                    }
+                };
+                let Key::String(variant) = variant else {
+                    return Err(de::Error::invalid_type(
+                        Value::from(&variant).unexpected(),
+                        &"string variant",
+                    ));
+                };
+                (Arc::unwrap_or_clone(variant), Some(value))
+            }
+            Value::String(variant) => (Arc::unwrap_or_clone(variant), None),
+            other => return Err(other.invalid_type(&"string or map")),
+        };
+        visitor.visit_enum(EnumDeserializer { variant, value })
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::String(s) => visitor.visit_string(Arc::unwrap_or_clone(s)),
+            other => other.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// Reads a [`Value`] from any self-describing serde format, the inverse of
+/// the [`Serialize`](serde::Serialize) impl: units and `None` are `null`, sequences are lists and
+/// maps are maps. Unsigned integers are [`Value::Int`] when they fit and [`Value::UInt`]
+/// otherwise, and timestamps and durations come back as whatever the format wrote them as.
+///
+/// See [`tagged`](crate::tagged) for a representation which round-trips exactly.
+impl<'de> de::Deserialize<'de> for Value {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("any CEL value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> std::result::Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> std::result::Result<Value, E> {
+        Ok(Value::Int(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> std::result::Result<Value, E> {
+        Ok(i64::try_from(v).map_or(Value::UInt(v), Value::Int))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> std::result::Result<Value, E> {
+        Ok(Value::Float(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Value, E> {
+        Ok(Value::String(Arc::new(v.to_owned())))
+    }
+
+    fn visit_string<E>(self, v: String) -> std::result::Result<Value, E> {
+        Ok(Value::String(Arc::new(v)))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<Value, E> {
+        Ok(Value::Bytes(Arc::new(v.to_vec())))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> std::result::Result<Value, E> {
+        Ok(Value::Bytes(Arc::new(v)))
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> std::result::Result<Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        de::Deserialize::deserialize(deserializer)
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> std::result::Result<Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        de::Deserialize::deserialize(deserializer)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Value, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let mut list = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(value) = seq.next_element()? {
+            list.push(value);
+        }
+        Ok(Value::List(Arc::new(list)))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> std::result::Result<Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let mut entries = MapStorage::<Key, Value>::new();
+        while let Some((key, value)) = map.next_entry()? {
+            entries.insert(key, value);
+        }
+        Ok(entries.into())
+    }
+}
+
+impl<'de> de::Deserialize<'de> for Key {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Key, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        struct KeyVisitor;
+
+        impl Visitor<'_> for KeyVisitor {
+            type Value = Key;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a bool, an integer or a string")
+            }
+
+            fn visit_bool<E>(self, v: bool) -> std::result::Result<Key, E> {
+                Ok(Key::Bool(v))
+            }
+
+            fn visit_i64<E>(self, v: i64) -> std::result::Result<Key, E> {
+                Ok(Key::Int(v))
+            }
+
+            fn visit_u64<E>(self, v: u64) -> std::result::Result<Key, E> {
+                Ok(i64::try_from(v).map_or(Key::Uint(v), Key::Int))
+            }
+
+            fn visit_str<E>(self, v: &str) -> std::result::Result<Key, E> {
+                Ok(Key::String(Arc::new(v.to_owned())))
+            }
+
+            fn visit_string<E>(self, v: String) -> std::result::Result<Key, E> {
+                Ok(Key::String(Arc::new(v)))
+            }
+        }
+
+        deserializer.deserialize_any(KeyVisitor)
+    }
+}
+
+impl IntoDeserializer<'_, DeserializationError> for Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+fn visit_list<'de, V>(list: Vec<Value>, visitor: V) -> Result<V::Value>
+where
+    V: Visitor<'de>,
+{
+    let len = list.len();
+    let mut deserializer = SeqDeserializer {
+        iter: list.into_iter(),
+    };
+    let seq = visitor.visit_seq(&mut deserializer)?;
+    if deserializer.iter.len() == 0 {
+        Ok(seq)
+    } else {
+        Err(de::Error::invalid_length(len, &"fewer elements in list"))
+    }
+}
+
+fn visit_map<'de, V>(map: MapStorage<Key, Value>, visitor: V) -> Result<V::Value>
+where
+    V: Visitor<'de>,
+{
+    let len = map.len();
+    let mut deserializer = MapDeserializer {
+        iter: map.into_iter(),
+        value: None,
+    };
+    let map = visitor.visit_map(&mut deserializer)?;
+    if deserializer.iter.len() == 0 {
+        Ok(map)
+    } else {
+        Err(de::Error::invalid_length(len, &"fewer elements in map"))
+    }
+}
+
+struct SeqDeserializer {
+    iter: std::vec::IntoIter<Value>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqDeserializer {
+    type Error = DeserializationError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.iter.next() {
+            Some(value) => seed.deserialize(value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct MapDeserializer {
+    iter: <MapStorage<Key, Value> as IntoIterator>::IntoIter,
+    value: Option<Value>,
+}
+
+impl<'de> de::MapAccess<'de> for MapDeserializer {
+    type Error = DeserializationError;
+
+    fn next_key_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(KeyDeserializer(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<T>(&mut self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.value.take() {
+            Some(value) => seed.deserialize(value),
+            None => Err(de::Error::custom("value is missing")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+/// Deserializes map keys, which may also stand for struct fields or enum variants.
+struct KeyDeserializer(Key);
+
+impl<'de> de::Deserializer<'de> for KeyDeserializer {
+    type Error = DeserializationError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Key::Int(i) => visitor.visit_i64(i),
+            Key::Uint(u) => visitor.visit_u64(u),
+            Key::Bool(b) => visitor.visit_bool(b),
+            Key::String(s) => visitor.visit_string(Arc::unwrap_or_clone(s)),
+        }
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Value::from(self.0).deserialize_enum(name, variants, visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct EnumDeserializer {
+    variant: String,
+    value: Option<Value>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = DeserializationError;
+    type Variant = VariantDeserializer;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, VariantDeserializer)>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant: StringDeserializer<DeserializationError> = self.variant.into_deserializer();
+        let visitor = VariantDeserializer { value: self.value };
+        seed.deserialize(variant).map(|v| (v, visitor))
+    }
+}
+
+struct VariantDeserializer {
+    value: Option<Value>,
+}
+
+impl<'de> de::VariantAccess<'de> for VariantDeserializer {
+    type Error = DeserializationError;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.value {
+            None | Some(Value::Null) => Ok(()),
+            Some(value) => Err(value.invalid_type(&"unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.value {
+            Some(value) => seed.deserialize(value),
+            None => Err(de::Error::invalid_type(
+                Unexpected::UnitVariant,
+                &"newtype variant",
+            )),
+        }
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Some(Value::List(l)) => visit_list(Arc::unwrap_or_clone(l), visitor),
+            Some(other) => Err(other.invalid_type(&"tuple variant")),
+            None => Err(de::Error::invalid_type(
+                Unexpected::UnitVariant,
+                &"tuple variant",
+            )),
+        }
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Some(Value::Map(m)) => visit_map(Arc::unwrap_or_clone(m.map), visitor),
+            Some(other) => Err(other.invalid_type(&"struct variant")),
+            None => Err(de::Error::invalid_type(
+                Unexpected::UnitVariant,
+                &"struct variant",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_value, DeserializationError};
+    use crate::{to_value, Context, Program, Value};
+    #[cfg(feature = "chrono")]
+    use crate::{Duration, Timestamp};
+    use serde::{Deserialize, Serialize};
+    use std::collections::{BTreeMap, HashMap};
+
+    fn eval(script: &str) -> Value {
+        Program::compile(script)
+            .unwrap()
+            .execute(&Context::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_policy_result() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Decision {
+            allow: bool,
+            reasons: Vec<String>,
+            #[serde(default)]
+            ttl: Option<u32>,
+        }
+
+        let decision: Decision =
+            from_value(eval("{'allow': true, 'reasons': ['trusted', 'internal']}")).unwrap();
+        assert_eq!(
+            decision,
+            Decision {
+                allow: true,
+                reasons: vec!["trusted".to_owned(), "internal".to_owned()],
+                ttl: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_primitives() {
+        assert_eq!(from_value::<i32>(eval("-42")), Ok(-42));
+        assert_eq!(from_value::<u8>(eval("42u")), Ok(42));
+        assert_eq!(from_value::<f64>(eval("1.5")), Ok(1.5));
+        assert_eq!(from_value::<String>(eval("'cel'")), Ok("cel".to_owned()));
+        assert_eq!(
+            from_value::<serde_bytes::ByteBuf>(eval("b'abc'")).map(|b| b.into_vec()),
+            Ok(b"abc".to_vec())
+        );
+        assert_eq!(from_value::<()>(eval("null")), Ok(()));
+        assert!(from_value::<u8>(eval("-1")).is_err());
+    }
+
+    #[test]
+    fn test_keys() {
+        let map: BTreeMap<i64, bool> = from_value(eval("{1: true, 2: false}")).unwrap();
+        assert_eq!(map, BTreeMap::from([(1, true), (2, false)]));
+        let map: HashMap<bool, String> = from_value(eval("{true: 'yes'}")).unwrap();
+        assert_eq!(map, HashMap::from([(true, "yes".to_owned())]));
+    }
+
+    #[test]
+    fn test_optionals() {
+        assert_eq!(
+            from_value::<Option<i64>>(eval("optional.of(1)")),
+            Ok(Some(1))
+        );
+        assert_eq!(from_value::<Option<i64>>(eval("optional.none()")), Ok(None));
+        assert_eq!(from_value::<Option<i64>>(eval("null")), Ok(None));
+        assert_eq!(from_value::<Option<i64>>(eval("2")), Ok(Some(2)));
+    }
+
+    #[test]
+    fn test_enums() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        enum Action {
+            Deny,
+            Allow { scope: String },
+            Retry(u32),
+        }
+
+        assert_eq!(from_value(eval("'Deny'")), Ok(Action::Deny));
+        assert_eq!(
+            from_value(eval("{'Allow': {'scope': 'read'}}")),
+            Ok(Action::Allow {
+                scope: "read".to_owned()
+            })
+        );
+        assert_eq!(from_value(eval("{'Retry': 3}")), Ok(Action::Retry(3)));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Config {
+            name: String,
+            limits: HashMap<String, u64>,
+            tags: Vec<Option<String>>,
+        }
+
+        let config = Config {
+            name: "cel".to_owned(),
+            limits: HashMap::from([("rps".to_owned(), 100)]),
+            tags: vec![Some("a".to_owned()), None],
+        };
+        assert_eq!(from_value(to_value(&config).unwrap()), Ok(config));
+    }
+
+    #[test]
+    #[cfg(feature = "chrono")]
+    fn test_time() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Lease {
+            at: Timestamp,
+            ttl: Duration,
+        }
+
+        let lease: Lease = from_value(eval(
+            "{'at': timestamp('2025-01-01T00:00:00Z'), 'ttl': duration('1m30.5s')}",
+        ))
+        .unwrap();
+        assert_eq!(
+            lease.at,
+            chrono::DateTime::parse_from_rfc3339("2025-01-01T00:00:00Z")
+                .unwrap()
+                .into()
+        );
+        assert_eq!(lease.ttl, chrono::Duration::milliseconds(90_500).into());
+        let ttl: Duration = from_value(to_value(lease.ttl).unwrap()).unwrap();
+        assert_eq!(ttl, lease.ttl);
+    }
+
+    #[test]
+    fn test_errors() {
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct Decision {
+            allow: bool,
+        }
+
+        assert_eq!(
+            from_value::<Decision>(eval("{'allow': 'yes'}")).unwrap_err(),
+            DeserializationError::SerdeError(
+                "invalid type: string \"yes\", expected a boolean".to_owned()
+            )
+        );
+        assert_eq!(
+            from_value::<Decision>(eval("{}")).unwrap_err(),
+            DeserializationError::SerdeError("missing field `allow`".to_owned())
+        );
+    }
+}
@@ -0,0 +1,651 @@
+//! Diagnostics for [`ParseErrors`] and runtime [`LocatedError`]s: a stable error code, the
+//! spans of source the error concerns with a label each, and help text where there is any.
+//! Diagnostics render as text for terminals, with [`Display`], or as JSON for editors, with
+//! [`Diagnostic::to_json`].
+
+use crate::common::ast::{operators, Expr, IdedExpr, SourceInfo};
+use crate::objects::ValueType;
+use crate::{ExecutionError, LocatedError, ParseError, ParseErrorKind, ParseErrors, Value};
+use serde_json::json;
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// An error along with the parts of the source it concerns.
+///
+/// # Example
+/// ```
+/// # use cel::{Context, Program};
+/// let mut context = Context::default();
+/// context.add_variable_from_value("total", 7);
+/// context.add_variable_from_value("count", 0);
+/// let program = Program::compile("total / count").unwrap();
+/// let err = program.execute_with_location(&context).unwrap_err();
+/// let diagnostic = err.diagnostic();
+/// assert_eq!(diagnostic.code(), "division_by_zero");
+/// assert_eq!(
+///     diagnostic.to_string(),
+///     "error[division_by_zero]: Division by zero of Int(7)
+///  --> <input>:1:7
+///   |
+/// 1 | total / count
+///   | ----- ^ ----- right operand: int
+///   | |     |
+///   | |     division by zero
+///   | |
+///   | left operand: int
+///   |
+///   = help: guard the divisor, for example `y != 0 ? x / y : 0`"
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    code: &'static str,
+    message: String,
+    labels: Vec<Label>,
+    help: Option<String>,
+    source_info: Arc<SourceInfo>,
+}
+
+/// A span of the source, in bytes, with a message about it. The primary label of a
+/// [`Diagnostic`] marks where the error was raised, the others mark related expressions such
+/// as the operands of an operator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Range<usize>,
+    pub message: String,
+    pub primary: bool,
+}
+
+impl Diagnostic {
+    /// The stable code of the kind of error, such as `no_such_key`.
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The labelled spans of source, the primary one first.
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    pub fn help(&self) -> Option<&str> {
+        self.help.as_deref()
+    }
+
+    pub fn source_info(&self) -> &SourceInfo {
+        &self.source_info
+    }
+
+    /// The diagnostic as JSON, for editors and other tools. Lines and columns start at 1 and
+    /// account for the [source offset](crate::parser::Parser::source_offset), columns count
+    /// characters, and `start` and `end` are byte offsets into the expression's source.
+    ///
+    /// ```json
+    /// {
+    ///   "code": "no_such_key",
+    ///   "severity": "error",
+    ///   "message": "No such key: baz",
+    ///   "source": "policy.yaml",
+    ///   "labels": [{
+    ///     "primary": true, "message": "no such key", "start": 11, "end": 12,
+    ///     "line": 3, "column": 12, "end_line": 3, "end_column": 13
+    ///   }],
+    ///   "help": "use `has()` to test whether a key or field is present"
+    /// }
+    /// ```
+    pub fn to_json(&self) -> serde_json::Value {
+        let labels: Vec<_> = self
+            .labels
+            .iter()
+            .map(|label| {
+                let (line, column) = self.location(label.span.start);
+                let (end_line, end_column) = self.location(label.span.end);
+                json!({
+                    "primary": label.primary,
+                    "message": label.message,
+                    "start": label.span.start,
+                    "end": label.span.end,
+                    "line": line,
+                    "column": column,
+                    "end_line": end_line,
+                    "end_column": end_column,
+                })
+            })
+            .collect();
+        json!({
+            "code": self.code,
+            "severity": "error",
+            "message": self.message,
+            "source": self.source_info.name(),
+            "labels": labels,
+            "help": self.help,
+        })
+    }
+
+    /// The line and character column, both starting at 1, of the byte `offset` of the source
+    /// within the document it is embedded in.
+    fn location(&self, offset: usize) -> (isize, isize) {
+        let (line, column) = line_column(&self.source_info.source, offset);
+        self.source_info
+            .location((line as isize + 1, column as isize + 1))
+    }
+
+    /// The number within the document of `line` of the source, starting at 0.
+    fn line_number(&self, line: usize) -> isize {
+        self.source_info.location((line as isize + 1, 1)).0
+    }
+}
+
+/// Renders the diagnostic like the Rust compiler does: each labelled line of source followed by
+/// markers underlining the spans, `^` for the primary span and `-` for the others.
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "error[{}]: {}", self.code, self.message)?;
+        let Some(primary) = self.labels.iter().find(|label| label.primary) else {
+            return write!(f, "\n --> {}", self.source_info.name());
+        };
+
+        let source = &self.source_info.source;
+        let mut spans: Vec<_> = self
+            .labels
+            .iter()
+            .map(|label| {
+                let (line, start) = line_column(source, label.span.start);
+                let end = match line_column(source, label.span.end) {
+                    (end_line, end) if end_line == line => end,
+                    _ => source.lines().nth(line).unwrap_or_default().chars().count(),
+                };
+                Span {
+                    line,
+                    start,
+                    end: end.max(start + 1),
+                    label,
+                }
+            })
+            .collect();
+        spans.sort_by_key(|span| (span.line, span.start));
+
+        let last_line = spans.last().map_or(0, |span| span.line);
+        let width = self.line_number(last_line).to_string().len();
+        let (line, column) = self.location(primary.span.start);
+        let name = self.source_info.name();
+        write!(f, "\n{:width$}--> {name}:{line}:{column}", "")?;
+        write!(f, "\n{:width$} |", "")?;
+        for on_line in spans.chunk_by(|a, b| a.line == b.line) {
+            let line = on_line[0].line;
+            let text = source.lines().nth(line).unwrap_or_default();
+            write!(f, "\n{:>width$} | {text}", self.line_number(line))?;
+            write_markers(f, width, on_line)?;
+        }
+
+        if let Some(help) = &self.help {
+            write!(f, "\n{:width$} |", "")?;
+            write!(f, "\n{:width$} = help: {help}", "")?;
+        }
+        Ok(())
+    }
+}
+
+/// A label's span within a single line of source, in characters.
+struct Span<'a> {
+    line: usize,
+    start: usize,
+    end: usize,
+    label: &'a Label,
+}
+
+/// Writes the markers underlining the `spans` of one line of source, sorted by column. The last
+/// span's message follows its marker, the others hang below theirs.
+fn write_markers(f: &mut Formatter<'_>, width: usize, spans: &[Span]) -> fmt::Result {
+    write!(f, "\n{:width$} | ", "")?;
+    let mut column = 0;
+    for span in spans {
+        let start = span.start.max(column);
+        let end = span.end.max(start + 1);
+        let marker = if span.label.primary { "^" } else { "-" };
+        write!(
+            f,
+            "{:1$}{2}",
+            "",
+            start - column,
+            marker.repeat(end - start)
+        )?;
+        column = end;
+    }
+    let Some((last, hanging)) = spans.split_last() else {
+        return Ok(());
+    };
+    write!(f, " {}", last.label.message)?;
+
+    // Hang the messages of the other spans from the rightmost in, drawing a line down from each
+    // span still waiting for its message.
+    for (i, span) in hanging.iter().enumerate().rev() {
+        let mut lines = String::new();
+        for waiting in &hanging[..i] {
+            lines.push_str(&" ".repeat(waiting.start - lines.chars().count()));
+            lines.push('|');
+        }
+        lines.push_str(&" ".repeat(span.start - lines.chars().count()));
+        write!(f, "\n{:width$} | {lines}|", "")?;
+        write!(f, "\n{:width$} | {lines}{}", "", span.label.message)?;
+    }
+    Ok(())
+}
+
+/// The line, and the column in characters within it, both starting at 0, of the byte `offset`
+/// of `source`.
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let mut start = 0;
+    for (line, text) in source.split_inclusive('\n').enumerate() {
+        let end = start + text.len();
+        if offset < end || (offset == end && !text.ends_with('\n')) {
+            let column = text
+                .char_indices()
+                .take_while(|&(i, _)| start + i < offset)
+                .count();
+            return (line, column);
+        }
+        start = end;
+    }
+    (source.lines().count(), 0)
+}
+
+/// The byte offset of the position `(line, column)` of `source`, both starting at 1 and the
+/// column counting characters.
+fn offset_of(source: &str, (line, column): (isize, isize)) -> usize {
+    let mut start = 0;
+    for (i, text) in source.split_inclusive('\n').enumerate() {
+        if i as isize + 1 == line {
+            return text
+                .char_indices()
+                .nth((column - 1).max(0) as usize)
+                .map_or(start + text.len(), |(i, _)| start + i);
+        }
+        start += text.len();
+    }
+    source.len()
+}
+
+/// The span of the token the parser recorded for expression `id`.
+fn span_of(source_info: &SourceInfo, id: u64) -> Option<Range<usize>> {
+    source_info
+        .offset_for(id)
+        .map(|(start, stop)| start as usize..stop as usize + 1)
+}
+
+impl ParseErrors {
+    /// Each of the errors as a [`Diagnostic`].
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.errors.iter().map(ParseError::diagnostic).collect()
+    }
+}
+
+impl ParseError {
+    /// The stable code of the kind of error: `syntax_error`, `recursion_limit` or
+    /// `invalid_expression`.
+    pub fn code(&self) -> &'static str {
+        match self.kind {
+            ParseErrorKind::Syntax => "syntax_error",
+            ParseErrorKind::RecursionLimit => "recursion_limit",
+            ParseErrorKind::InvalidExpression => "invalid_expression",
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let source_info = self.source_info.clone().unwrap_or_default();
+        let span = match span_of(&source_info, self.expr_id).filter(|_| self.expr_id != 0) {
+            Some(span) => span,
+            None => {
+                let start = offset_of(&source_info.source, self.pos);
+                let end = source_info.source[start..]
+                    .chars()
+                    .next()
+                    .map_or(start, |c| start + c.len_utf8());
+                start..end
+            }
+        };
+        let code = self.code();
+        let label = match code {
+            "syntax_error" => "unexpected input",
+            "recursion_limit" => "nested too deeply",
+            _ => "invalid expression",
+        };
+        let help = (code == "recursion_limit").then(|| {
+            "raise the limit with `Parser::max_recursion_depth`, or split up the expression"
+                .to_string()
+        });
+        Diagnostic {
+            code,
+            message: self.msg.clone(),
+            labels: vec![Label {
+                span,
+                message: label.to_string(),
+                primary: true,
+            }],
+            help,
+            source_info,
+        }
+    }
+}
+
+impl LocatedError {
+    /// The error as a [`Diagnostic`], labelling the expression which raised it along with its
+    /// operands or arguments.
+    pub fn diagnostic(&self) -> Diagnostic {
+        let error = &self.error;
+        let mut labels: Vec<_> = span_of(&self.source_info, self.expr_id)
+            .map(|span| Label {
+                span,
+                message: primary_label(error).to_string(),
+                primary: true,
+            })
+            .into_iter()
+            .collect();
+        if concerns_operands(error) {
+            let types = operand_types(error).filter(|types| types.len() == self.operands.len());
+            for (i, (id, role)) in self.operands.iter().enumerate() {
+                let Some(span) = span_of(&self.source_info, *id) else {
+                    continue;
+                };
+                let message = match &types {
+                    Some(types) => format!("{role}: {}", types[i]),
+                    None => role.clone(),
+                };
+                labels.push(Label {
+                    span,
+                    message,
+                    primary: false,
+                });
+            }
+        }
+        Diagnostic {
+            code: error.code(),
+            message: error.to_string(),
+            labels,
+            help: help(error),
+            source_info: self.source_info.clone(),
+        }
+    }
+}
+
+/// The ids of the target and arguments of the call expression `id` within `expr`, each along
+/// with its role in the call.
+pub(crate) fn operands(expr: &IdedExpr, id: u64) -> Vec<(u64, String)> {
+    let Some(Expr::Call(call)) = find(expr, id).map(|expr| &expr.expr) else {
+        return Vec::new();
+    };
+    let target = call.target.as_deref().into_iter();
+    let ids: Vec<_> = target.chain(&call.args).map(|expr| expr.id).collect();
+    let roles: Vec<String> = match call.func_name.as_str() {
+        operators::INDEX | operators::OPT_INDEX => vec!["indexed value".into(), "index".into()],
+        operators::NEGATE | operators::LOGICAL_NOT => vec!["operand".into()],
+        name if name.starts_with('_') && ids.len() == 2 => {
+            vec!["left operand".into(), "right operand".into()]
+        }
+        _ => {
+            let target = call.target.iter().map(|_| "target".to_string());
+            let args = (1..=call.args.len()).map(|i| format!("argument {i}"));
+            target.chain(args).collect()
+        }
+    };
+    ids.into_iter().zip(roles).collect()
+}
+
+fn find(expr: &IdedExpr, id: u64) -> Option<&IdedExpr> {
+    expr.walk().map(|(expr, _)| expr).find(|expr| expr.id == id)
+}
+
+impl ExecutionError {
+    /// The stable code of the kind of error, such as `no_such_key` for
+    /// [`ExecutionError::NoSuchKey`].
+    pub fn code(&self) -> &'static str {
+        match self {
+            ExecutionError::InvalidArgumentCount { .. } => "invalid_argument_count",
+            ExecutionError::UnsupportedTargetType { .. } => "unsupported_target_type",
+            ExecutionError::NotSupportedAsMethod { .. } => "not_supported_as_method",
+            ExecutionError::UnsupportedKeyType(_) => "unsupported_key_type",
+            ExecutionError::UnexpectedType { .. } => "unexpected_type",
+            ExecutionError::NoSuchKey(_) => "no_such_key",
+            ExecutionError::NoSuchOverload => "no_such_overload",
+            ExecutionError::UndeclaredReference(_) => "undeclared_reference",
+            ExecutionError::MissingArgumentOrTarget => "missing_argument_or_target",
+            ExecutionError::ValuesNotComparable(..) => "values_not_comparable",
+            ExecutionError::UnsupportedUnaryOperator(..) => "unsupported_unary_operator",
+            ExecutionError::UnsupportedBinaryOperator(..) => "unsupported_binary_operator",
+            ExecutionError::UnsupportedMapIndex(_) => "unsupported_map_index",
+            ExecutionError::UnsupportedListIndex(_) => "unsupported_list_index",
+            ExecutionError::UnsupportedIndex(..) => "unsupported_index",
+            ExecutionError::UnsupportedFunctionCallIdentifierType(_) => {
+                "unsupported_function_call_identifier_type"
+            }
+            ExecutionError::UnsupportedFieldsConstruction(_) => "unsupported_fields_construction",
+            ExecutionError::FunctionError { .. } => "function_error",
+            ExecutionError::DivisionByZero(_) => "division_by_zero",
+            ExecutionError::RemainderByZero(_) => "remainder_by_zero",
+            ExecutionError::Overflow(..) => "overflow",
+            ExecutionError::LossyPromotion { .. } => "lossy_promotion",
+            ExecutionError::IndexOutOfBounds(_) => "index_out_of_bounds",
+            ExecutionError::InternalError(_) => "internal_error",
+            #[cfg(feature = "regex")]
+            ExecutionError::RegexLimitExceeded(_) => "regex_limit_exceeded",
+            ExecutionError::MaxDepthExceeded(_) => "max_depth_exceeded",
+        }
+    }
+}
+
+fn primary_label(error: &ExecutionError) -> &'static str {
+    match error {
+        ExecutionError::NoSuchKey(_) => "no such key",
+        ExecutionError::UndeclaredReference(_) => "not declared",
+        ExecutionError::NoSuchOverload => "no matching overload",
+        ExecutionError::UnsupportedUnaryOperator(..)
+        | ExecutionError::UnsupportedBinaryOperator(..) => "unsupported operand types",
+        ExecutionError::ValuesNotComparable(..) => "values can't be compared",
+        ExecutionError::DivisionByZero(_) => "division by zero",
+        ExecutionError::RemainderByZero(_) => "remainder by zero",
+        ExecutionError::Overflow(..) => "overflows",
+        ExecutionError::LossyPromotion { .. } => "inexact promotion",
+        ExecutionError::IndexOutOfBounds(_) => "index out of bounds",
+        ExecutionError::FunctionError { .. } => "function failed",
+        _ => "raised here",
+    }
+}
+
+/// Whether the operands of the failing call are worth labelling for `error`.
+fn concerns_operands(error: &ExecutionError) -> bool {
+    matches!(
+        error,
+        ExecutionError::NoSuchOverload
+            | ExecutionError::ValuesNotComparable(..)
+            | ExecutionError::UnsupportedUnaryOperator(..)
+            | ExecutionError::UnsupportedBinaryOperator(..)
+            | ExecutionError::UnsupportedIndex(..)
+            | ExecutionError::DivisionByZero(_)
+            | ExecutionError::RemainderByZero(_)
+            | ExecutionError::Overflow(..)
+            | ExecutionError::LossyPromotion { .. }
+    )
+}
+
+/// The types of the operands `error` was raised for, in the order they appear in the source.
+fn operand_types(error: &ExecutionError) -> Option<Vec<ValueType>> {
+    let types = match error {
+        ExecutionError::ValuesNotComparable(lhs, rhs)
+        | ExecutionError::UnsupportedBinaryOperator(_, lhs, rhs)
+        | ExecutionError::Overflow(_, lhs, rhs) => vec![lhs.type_of(), rhs.type_of()],
+        ExecutionError::UnsupportedUnaryOperator(_, value) => vec![value.type_of()],
+        ExecutionError::UnsupportedIndex(index, target) => vec![target.type_of(), index.type_of()],
+        // Only ints are divided by zero, doubles divide to infinity or NaN.
+        ExecutionError::DivisionByZero(lhs) | ExecutionError::RemainderByZero(lhs) => {
+            vec![lhs.type_of(), ValueType::Int]
+        }
+        _ => return None,
+    };
+    Some(types)
+}
+
+fn help(error: &ExecutionError) -> Option<String> {
+    let is_number =
+        |value: &Value| matches!(value, Value::Int(_) | Value::UInt(_) | Value::Float(_));
+    let help = match error {
+        ExecutionError::UndeclaredReference(name) => {
+            format!("add `{name}` to the `Context`, or check its spelling")
+        }
+        ExecutionError::NoSuchKey(_) => {
+            "use `has()` to test whether a key or field is present".to_string()
+        }
+        ExecutionError::NoSuchOverload => "convert the arguments to the types the function \
+            expects, for example with `int()`, `uint()`, `double()` or `string()`"
+            .to_string(),
+        ExecutionError::UnsupportedBinaryOperator(_, lhs, rhs)
+            if is_number(lhs) && is_number(rhs) =>
+        {
+            "convert an operand with `int()`, `uint()` or `double()`, or enable \
+            `Env::set_numeric_promotion`"
+                .to_string()
+        }
+        ExecutionError::LossyPromotion { .. } => {
+            "convert the operands explicitly with `int()`, `uint()` or `double()`".to_string()
+        }
+        ExecutionError::DivisionByZero(_) => {
+            "guard the divisor, for example `y != 0 ? x / y : 0`".to_string()
+        }
+        ExecutionError::RemainderByZero(_) => {
+            "guard the divisor, for example `y != 0 ? x % y : 0`".to_string()
+        }
+        ExecutionError::IndexOutOfBounds(_) => {
+            "use `size()` to check the length of the list first".to_string()
+        }
+        #[cfg(feature = "regex")]
+        ExecutionError::RegexLimitExceeded(_) => {
+            "simplify the pattern, or raise the limits with `Env::set_regex_limits`".to_string()
+        }
+        ExecutionError::MaxDepthExceeded(_) => {
+            "raise the limit with `Env::set_max_eval_depth`, or split up the expression".to_string()
+        }
+        _ => return None,
+    };
+    Some(help)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::Parser;
+    use crate::{Context, ParseErrorKind, Program};
+    use serde_json::json;
+
+    fn parser() -> Parser {
+        Parser::new()
+            .source_name("policy.yaml")
+            .source_offset(9, 12)
+    }
+
+    #[test]
+    fn parse_error() {
+        let errors = Program::compile_with(parser(), "a +\n  (b").unwrap_err();
+        let diagnostics = errors.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        assert_eq!(diagnostic.code(), "syntax_error");
+        assert_eq!(
+            diagnostic.to_string(),
+            format!(
+                "error[syntax_error]: {}\n  --> policy.yaml:11:5\n   |\n11 |   (b\n   |     ^ unexpected input",
+                diagnostic.message()
+            )
+        );
+    }
+
+    #[test]
+    fn recursion_limit() {
+        let source = "[".repeat(5) + &"]".repeat(5);
+        let errors =
+            Program::compile_with(Parser::new().max_recursion_depth(2), &source).unwrap_err();
+        let diagnostic = &errors.diagnostics()[0];
+        assert_eq!(errors.errors[0].kind, ParseErrorKind::RecursionLimit);
+        assert_eq!(diagnostic.code(), "recursion_limit");
+        assert!(diagnostic.help().unwrap().contains("max_recursion_depth"));
+    }
+
+    #[test]
+    fn invalid_expression() {
+        // Codes follow the kind of the error, whatever its message reads
+        let errors = Program::compile_with(parser(), "has('Recursion limit')").unwrap_err();
+        let diagnostic = &errors.diagnostics()[0];
+        assert_eq!(errors.errors[0].kind, ParseErrorKind::InvalidExpression);
+        assert_eq!(diagnostic.code(), "invalid_expression");
+        assert_eq!(diagnostic.help(), None);
+    }
+
+    #[test]
+    fn runtime_error() {
+        let program =
+            Program::compile_with(parser(), "{'foo': 1}.foo +\n  {'bar': 2}.baz").unwrap();
+        let err = program
+            .execute_with_location(&Context::default())
+            .unwrap_err();
+        let diagnostic = err.diagnostic();
+        assert_eq!(diagnostic.code(), "no_such_key");
+        assert_eq!(
+            diagnostic.to_string(),
+            "error[no_such_key]: No such key: baz
+  --> policy.yaml:11:13
+   |
+11 |   {'bar': 2}.baz
+   |             ^ no such key
+   |
+   = help: use `has()` to test whether a key or field is present"
+        );
+        assert_eq!(
+            diagnostic.to_json(),
+            json!({
+                "code": "no_such_key",
+                "severity": "error",
+                "message": "No such key: baz",
+                "source": "policy.yaml",
+                "labels": [{
+                    "primary": true,
+                    "message": "no such key",
+                    "start": 29,
+                    "end": 30,
+                    "line": 11,
+                    "column": 13,
+                    "end_line": 11,
+                    "end_column": 14,
+                }],
+                "help": "use `has()` to test whether a key or field is present",
+            })
+        );
+    }
+
+    #[test]
+    fn operands() {
+        let program = Program::compile_with(parser(), "'a' - 1").unwrap();
+        let err = program
+            .execute_with_location(&Context::default())
+            .unwrap_err();
+        let diagnostic = err.diagnostic();
+        let labels: Vec<_> = diagnostic
+            .labels()
+            .iter()
+            .map(|label| (label.span.clone(), label.message.as_str(), label.primary))
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                (4..5, "unsupported operand types", true),
+                (0..3, "left operand: string", false),
+                (6..7, "right operand: int", false),
+            ]
+        );
+        assert_eq!(
+            diagnostic.to_json()["labels"][0]["column"],
+            json!(17),
+            "the column offset applies to the first line"
+        );
+    }
+}
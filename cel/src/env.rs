@@ -1,11 +1,17 @@
+#[cfg(feature = "regex")]
+use crate::common::regex::RegexLimits;
+#[cfg(feature = "structs")]
+use crate::common::types::CelStruct;
 use crate::common::{
-    decls::FunctionDecl,
+    decls::{FunctionDecl, Op, OverloadDecl},
     functions::Function,
     types::{self, Type},
     value::Val,
 };
 #[cfg(feature = "structs")]
-use crate::{common::types::CelStruct, ExecutionError};
+use crate::ExecutionError;
+#[cfg(feature = "protobuf")]
+use prost_reflect::{DescriptorError, DescriptorPool, MessageDescriptor};
 use std::{
     borrow::Cow,
     collections::{
@@ -19,6 +25,33 @@ pub struct Env {
     functions: BTreeMap<String, FunctionDecl>,
     #[cfg(feature = "structs")]
     structs: BTreeMap<String, StructDef>,
+    #[cfg(feature = "regex")]
+    regex_limits: RegexLimits,
+    numeric_promotion: bool,
+    max_eval_depth: MaxEvalDepth,
+    #[cfg(feature = "protobuf")]
+    descriptors: Descriptors,
+}
+
+/// The limit of [`Env::set_max_eval_depth`], which by default there is none of.
+struct MaxEvalDepth(usize);
+
+impl Default for MaxEvalDepth {
+    fn default() -> Self {
+        MaxEvalDepth(usize::MAX)
+    }
+}
+
+/// The protobuf types known to an [`Env`]: the well-known types, and any type added to the
+/// global [`DescriptorPool`], unless replaced.
+#[cfg(feature = "protobuf")]
+struct Descriptors(DescriptorPool);
+
+#[cfg(feature = "protobuf")]
+impl Default for Descriptors {
+    fn default() -> Self {
+        Descriptors(DescriptorPool::global())
+    }
 }
 
 impl Env {
@@ -33,6 +66,9 @@ impl Env {
         types::string::stdlib(&mut env);
         types::uint::stdlib(&mut env);
 
+        #[cfg(feature = "regex")]
+        crate::common::regex::stdlib(&mut env);
+
         #[cfg(feature = "chrono")]
         {
             types::duration::stdlib(&mut env);
@@ -49,21 +85,13 @@ impl Env {
         args: Vec<types::Type>,
         op: Function,
     ) -> Result<(), ()> {
-        match self.functions.entry(name.to_owned()) {
-            Vacant(vacant_entry) => {
-                let mut value = FunctionDecl::new(name);
-                value.add_overload(id.to_string(), false, args, op)?;
-                vacant_entry.insert(value);
-                Ok(())
-            }
-            Occupied(occupied_entry) => {
-                occupied_entry
-                    .into_mut()
-                    .add_overload(id.to_string(), false, args, op)
-            }
-        }
+        self.add_op(name, id, false, args, Op::Function(op))
     }
 
+    /// The overload of function `name` which can be called with `args`, as a plain
+    /// [`Function`]. `None` if it compiles patterns, like `matches`, and this [`Env`] sets
+    /// limits other than the default ones with `set_regex_limits`, as the [`Function`]
+    /// couldn't enforce them.
     pub fn find_overload(&self, name: &str, args: &[Cow<dyn Val>]) -> Option<Function> {
         match self.functions.get(name) {
             None => None,
@@ -82,26 +110,146 @@ impl Env {
     ) -> Result<(), ()> {
         let mut args = args;
         args.insert(0, target);
+        self.add_op(name, id, true, args, Op::Function(op))
+    }
+
+    /// Like [`Env::find_overload`], for member functions, with the target as the first of
+    /// `args`.
+    pub fn find_member_overload(&self, name: &str, args: &[Cow<dyn Val>]) -> Option<Function> {
+        match self.functions.get(name) {
+            None => None,
+            Some(fn_decl) => fn_decl.find_overload(true, args),
+        }
+    }
+
+    /// Adds an overload of function `name`, with the target of a member function as the first
+    /// of its `args`.
+    pub(crate) fn add_op(
+        &mut self,
+        name: &str,
+        id: &str,
+        member_function: bool,
+        args: Vec<Type>,
+        op: Op,
+    ) -> Result<(), ()> {
         match self.functions.entry(name.to_owned()) {
             Vacant(vacant_entry) => {
                 let mut value = FunctionDecl::new(name);
-                value.add_overload(id.to_string(), true, args, op)?;
+                value.add_overload(id.to_string(), member_function, args, op)?;
                 vacant_entry.insert(value);
                 Ok(())
             }
             Occupied(occupied_entry) => {
                 occupied_entry
                     .into_mut()
-                    .add_overload(id.to_string(), true, args, op)
+                    .add_overload(id.to_string(), member_function, args, op)
             }
         }
     }
 
-    pub fn find_member_overload(&self, name: &str, args: &[Cow<dyn Val>]) -> Option<Function> {
-        match self.functions.get(name) {
-            None => None,
-            Some(fn_decl) => fn_decl.find_overload(true, args),
+    /// The overload of function `name` which the call with `args` dispatches to, with the
+    /// target of a member call as the first of them.
+    pub(crate) fn find_op(
+        &self,
+        name: &str,
+        member_function: bool,
+        args: &[Cow<dyn Val>],
+    ) -> Option<&OverloadDecl> {
+        self.functions
+            .get(name)
+            .and_then(|fn_decl| fn_decl.find(member_function, args))
+    }
+
+    /// The overloads of function `name` which could be called with `arity` arguments, counting
+    /// the target of a member function as one, for binding a call ahead of execution.
+    pub(crate) fn candidates(
+        &self,
+        name: &str,
+        member_function: bool,
+        arity: usize,
+    ) -> Vec<OverloadDecl> {
+        self.functions
+            .get(name)
+            .map(|fn_decl| {
+                fn_decl
+                    .candidates(member_function, arity)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Sets the limits enforced when compiling the patterns of `matches` and the `regex.*`
+    /// functions of this environment.
+    ///
+    /// # Example
+    /// ```
+    /// use cel::{Env, RegexLimits};
+    /// let mut env = Env::stdlib();
+    /// env.set_regex_limits(RegexLimits::default().max_pattern_length(256));
+    /// ```
+    #[cfg(feature = "regex")]
+    pub fn set_regex_limits(&mut self, limits: RegexLimits) {
+        for fn_decl in self.functions.values_mut() {
+            fn_decl.set_regex_limits(&limits);
         }
+        self.regex_limits = limits;
+    }
+
+    #[cfg(feature = "regex")]
+    pub fn regex_limits(&self) -> &RegexLimits {
+        &self.regex_limits
+    }
+
+    /// Enables promotion of mixed numeric operands of `+`, `-`, `*`, `/` and `%`, which are
+    /// otherwise an error. An int or uint paired with a double is promoted to a double, and a
+    /// uint paired with an int to an int. Operands that can't be promoted exactly, like a uint
+    /// above `i64::MAX` or an int beyond 2^53 paired with a double, are still an error.
+    ///
+    /// # Example
+    /// ```
+    /// use cel::{Context, Env, Program};
+    /// use std::sync::Arc;
+    /// let mut env = Env::stdlib();
+    /// env.set_numeric_promotion(true);
+    /// let mut ctx = Context::with_env(Arc::new(env));
+    /// ctx.add_variable_from_value("count", 3);
+    /// let program = Program::compile("count * 1.5").unwrap();
+    /// assert_eq!(program.execute(&ctx), Ok(4.5.into()));
+    /// ```
+    pub fn set_numeric_promotion(&mut self, enabled: bool) {
+        self.numeric_promotion = enabled;
+    }
+
+    pub fn numeric_promotion(&self) -> bool {
+        self.numeric_promotion
+    }
+
+    /// Limits how deeply nested the programs evaluated in this environment may be, failing
+    /// any nested deeper with [`ExecutionError::MaxDepthExceeded`] rather than overflowing the
+    /// stack of the thread. Long chains of operators, like `a + b + c`, nest as deep as they
+    /// are long, and an AST not parsed can be of any depth. Unlimited by default; a limit of
+    /// 100 is one even an unoptimized build evaluates within the usual 2 MiB stack.
+    ///
+    /// # Example
+    /// ```
+    /// use cel::{Context, Env, ExecutionError, Program};
+    /// use std::sync::Arc;
+    /// let mut env = Env::stdlib();
+    /// env.set_max_eval_depth(3);
+    /// let ctx = Context::with_env(Arc::new(env));
+    /// assert_eq!(Program::compile("1 + 2").unwrap().execute(&ctx), Ok(3.into()));
+    /// assert_eq!(
+    ///     Program::compile("[[[1]]]").unwrap().execute(&ctx),
+    ///     Err(ExecutionError::MaxDepthExceeded(3))
+    /// );
+    /// ```
+    pub fn set_max_eval_depth(&mut self, max: usize) {
+        self.max_eval_depth = MaxEvalDepth(max);
+    }
+
+    pub fn max_eval_depth(&self) -> usize {
+        self.max_eval_depth.0
     }
 
     #[cfg(feature = "structs")]
@@ -113,6 +261,61 @@ impl Env {
     pub(crate) fn find_struct(&self, name: &str) -> Option<&StructDef> {
         self.structs.get(name)
     }
+
+    /// Adds the message and enum types of an encoded `google.protobuf.FileDescriptorSet`,
+    /// as produced by `protoc --descriptor_set_out`, to this environment.
+    ///
+    /// # Example
+    /// ```
+    /// use cel::prost_reflect::prost::Message;
+    /// use cel::prost_reflect::prost_types::{FileDescriptorProto, FileDescriptorSet};
+    /// let set = FileDescriptorSet {
+    ///     file: vec![FileDescriptorProto {
+    ///         name: Some("empty.proto".to_owned()),
+    ///         ..Default::default()
+    ///     }],
+    /// };
+    /// let mut env = cel::Env::stdlib();
+    /// env.add_file_descriptor_set(set.encode_to_vec().as_slice()).unwrap();
+    /// ```
+    #[cfg(feature = "protobuf")]
+    pub fn add_file_descriptor_set<B>(&mut self, bytes: B) -> Result<(), DescriptorError>
+    where
+        B: prost_reflect::bytes::Buf,
+    {
+        self.descriptors.0.decode_file_descriptor_set(bytes)
+    }
+
+    /// Replaces the protobuf types known to this environment with the ones of `pool`.
+    #[cfg(feature = "protobuf")]
+    pub fn set_descriptor_pool(&mut self, pool: DescriptorPool) {
+        self.descriptors = Descriptors(pool);
+    }
+
+    #[cfg(feature = "protobuf")]
+    pub fn descriptor_pool(&self) -> &DescriptorPool {
+        &self.descriptors.0
+    }
+
+    #[cfg(feature = "protobuf")]
+    pub(crate) fn find_message(&self, name: &str) -> Option<MessageDescriptor> {
+        self.descriptors
+            .0
+            .get_message_by_name(name.strip_prefix('.').unwrap_or(name))
+    }
+
+    /// Returns the number of the enum value with the fully qualified `name`, such as
+    /// `google.protobuf.NullValue.NULL_VALUE`.
+    #[cfg(feature = "protobuf")]
+    pub(crate) fn find_enum_value(&self, name: &str) -> Option<i32> {
+        let name = name.strip_prefix('.').unwrap_or(name);
+        let (enum_name, value) = name.rsplit_once('.')?;
+        self.descriptors
+            .0
+            .get_enum_by_name(enum_name)?
+            .get_value_by_name(value)
+            .map(|value| value.number())
+    }
 }
 
 #[cfg(feature = "structs")]
@@ -194,4 +397,28 @@ mod tests {
     fn test_env_default() {
         let _: Arc<dyn Send + Sync> = Arc::new(Env::default());
     }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_find_overload_of_limited_function() {
+        use crate::common::types::CelString;
+        let mut env = Env::stdlib();
+        env.set_regex_limits(RegexLimits::default().max_pattern_length(1));
+        let args = || -> Vec<Cow<dyn Val>> {
+            vec![
+                Cow::<dyn Val>::Owned(Box::new(CelString::from("abc"))),
+                Cow::<dyn Val>::Owned(Box::new(CelString::from("^ab"))),
+            ]
+        };
+        // A plain function can't be bound to the limits of the env, so there is none
+        assert!(env.find_member_overload("matches", &args()).is_none());
+        assert!(Env::stdlib()
+            .find_member_overload("matches", &args())
+            .is_some());
+        let op = env.find_op("matches", true, &args()).unwrap();
+        assert!(matches!(
+            op.call(args()),
+            Err(crate::ExecutionError::RegexLimitExceeded(_))
+        ));
+    }
 }
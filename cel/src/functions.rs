@@ -15,6 +15,26 @@ type Result<T> = std::result::Result<T, ExecutionError>;
 /// It contains references to the target object (if the function is called as
 /// a method), the program context ([`Context`]) which gives functions access
 /// to variables, and the arguments to the function call.
+///
+/// The target and the arguments are the [`Val`]s the interpreter works with, so functions taking
+/// the context can use them without converting them to [`Value`]s first:
+/// ```
+/// # use cel::{Context, ExecutionError, FunctionContext, Program};
+/// use cel::common::types::{CelInt, CelString};
+/// use cel::common::value::Val;
+///
+/// fn len(ftx: &FunctionContext) -> Result<Box<dyn Val>, ExecutionError> {
+///     match ftx.this.as_deref().and_then(|this| this.downcast_ref::<CelString>()) {
+///         Some(s) => Ok(Box::new(CelInt::from(s.inner().chars().count() as i64))),
+///         None => Err(ftx.error("expected a string target")),
+///     }
+/// }
+///
+/// let mut context = Context::default();
+/// context.add_function("len", len);
+/// let program = Program::compile("'héllo'.len() == 5").unwrap();
+/// assert_eq!(program.execute(&context), Ok(true.into()));
+/// ```
 #[derive(Clone)]
 pub struct FunctionContext<'context, 'call: 'context> {
     pub name: &'call str,
@@ -280,10 +300,8 @@ pub fn matches(
     This(this): This<Arc<String>>,
     regex: Arc<String>,
 ) -> Result<bool> {
-    match regex::Regex::new(&regex) {
-        Ok(re) => Ok(re.is_match(&this)),
-        Err(err) => Err(ftx.error(format!("'{regex}' not a valid regex:\n{err}"))),
-    }
+    let re = crate::common::regex::compile(ftx.name, &regex, ftx.ptx.env().regex_limits())?;
+    Ok(re.is_match(&this))
 }
 
 use crate::common::value::Val;
@@ -547,6 +565,7 @@ mod tests {
             ("max multiple", "max(1, 2, 3) == 3"),
             ("max negative", "max(-1, 0) == 0"),
             ("max float", "max(-1.0, 0.0) == 0.0"),
+            ("max mixed", "max(1, 2.5, 2u) == 2.5"),
             ("max list", "max([1, 2, 3]) == 3"),
             ("max empty list", "max([]) == null"),
             ("max no args", "max() == null"),
@@ -569,6 +588,7 @@ mod tests {
             ("min multiple", "min(1, 2, 3) == 1"),
             ("min negative", "min(-1, 0) == -1"),
             ("min float", "min(-1.0, 0.0) == -1.0"),
+            ("min mixed", "min(1u, 0.5, 1) == 0.5"),
             (
                 "min float multiple",
                 "min(1.61803, 3.1415, 2.71828, 1.41421) == 1.41421",
@@ -588,6 +608,70 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_extractors_on_vals() {
+        use crate::common::types::CelString;
+        use crate::common::value::Val;
+        use crate::magic::This;
+        use crate::{ExecutionError, FunctionContext};
+        use std::sync::Arc;
+
+        fn repeat(This(this): This<Arc<String>>, times: i64) -> String {
+            this.repeat(times as usize)
+        }
+
+        fn or_empty(This(this): This<Option<Arc<String>>>) -> Arc<String> {
+            this.unwrap_or_default()
+        }
+
+        fn shout(ftx: &FunctionContext) -> super::Result<Box<dyn Val>> {
+            match ftx
+                .args
+                .first()
+                .and_then(|arg| arg.downcast_ref::<CelString>())
+            {
+                Some(s) => Ok(Box::new(CelString::from(s.inner().to_uppercase()))),
+                None => Err(ftx.error("expected a string")),
+            }
+        }
+
+        [
+            ("this target", "'ab'.repeat(3) == 'ababab'"),
+            ("this argument", "repeat('ab', 1) == 'ab'"),
+            (
+                "optional null",
+                "orEmpty(null) == '' && orEmpty('a') == 'a'",
+            ),
+            ("native result", "shout('hi') == 'HI'"),
+            ("bool result", "'a'.isEmpty() == false && ''.isEmpty()"),
+        ]
+        .iter()
+        .for_each(|a| {
+            let input: &(&str, &str) = a;
+            let mut context = Context::default();
+            context.add_function("repeat", repeat);
+            context.add_function("orEmpty", or_empty);
+            context.add_function("shout", shout);
+            context.add_function("isEmpty", |This(this): This<Arc<String>>| this.is_empty());
+            let r = test_script(input.1, Some(context));
+            assert_eq!(r, Ok(true.into()), "{}", input.0);
+        });
+
+        let mut context = Context::default();
+        context.add_function("repeat", repeat);
+        context.add_function("shout", shout);
+        assert!(matches!(
+            test_script("repeat(1, 2)", Some(context)),
+            Err(ExecutionError::UnexpectedType { .. })
+        ));
+        let mut context = Context::default();
+        context.add_function("shout", shout);
+        assert_eq!(
+            test_script("shout(1)", Some(context)),
+            Err(ExecutionError::function_error("shout", "expected a string"))
+        );
+    }
+
     #[test]
     fn test_starts_with() {
         [
@@ -808,6 +892,7 @@ mod tests {
     fn test_matches() {
         let tests = vec![
             ("string", "'foobar'.matches('^[a-zA-Z]*$') == true"),
+            ("global", "matches('foobar', '^[a-zA-Z]*$') == true"),
             (
                 "map",
                 "{'1': 'abc', '2': 'def', '3': 'ghi'}.all(key, key.matches('^[a-zA-Z]*$')) == false",
@@ -826,9 +911,11 @@ mod tests {
     #[cfg(feature = "regex")]
     #[test]
     fn test_matches_err() {
+        let mut ctx = Context::default();
+        ctx.add_variable_from_value("re", "(foo");
         assert_eq!(
             test_script(
-                "'foobar'.matches('(foo') == true", None),
+                "'foobar'.matches(re) == true", Some(ctx)),
             Err(
                 crate::ExecutionError::FunctionError {
                     function: "matches".to_string(),
@@ -838,6 +925,35 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_matches_precompiled() {
+        let program = crate::Program::compile(
+            "'foobar'.matches('^f') && matches('foobar', 'r$') && ['a'].all(x, x.matches('a'))",
+        )
+        .unwrap();
+        assert_eq!(program.regexes.len(), 3);
+        assert_eq!(program.execute(&Context::default()), Ok(true.into()));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_matches_literal_err() {
+        let err = crate::Program::compile("'foobar'.matches('(foo')").unwrap_err();
+        assert_eq!(err.errors.len(), 1);
+        assert_eq!(err.errors[0].pos, (1, 18));
+        assert_eq!(
+            err.to_string(),
+            "ERROR: <input>:1:18: '(foo' not a valid regex:
+regex parse error:
+    (foo
+    ^
+error: unclosed group
+| 'foobar'.matches('(foo')
+| .................^"
+        );
+    }
+
     #[test]
     fn test_string() {
         [
@@ -1,7 +1,9 @@
+use crate::objects::{Key, Map};
 use crate::Value;
 use base64::prelude::*;
 #[cfg(feature = "chrono")]
 use chrono::Duration;
+use std::sync::Arc;
 use thiserror::Error;
 
 #[derive(Debug, Clone, Error)]
@@ -20,6 +22,91 @@ pub enum ConvertToJsonError<'a> {
     DurationOverflow(&'a Duration),
 }
 
+/// What [`Value::json_with`] writes for `NaN` and infinite doubles, which JSON has no numbers for.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum NonFiniteFloats {
+    /// Writes `null`.
+    #[default]
+    Null,
+    /// Writes the strings `"NaN"`, `"Infinity"` and `"-Infinity"`, as the protobuf JSON mapping
+    /// does.
+    String,
+    /// Fails the conversion.
+    Error,
+}
+
+/// Controls how [`Value::json_with`] writes values JSON has no type for. The default is what
+/// [`Value::json`] does, see [`JsonOptions::protobuf`] for the protobuf JSON mapping.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct JsonOptions {
+    int64_as_string: bool,
+    #[cfg(feature = "chrono")]
+    duration_as_string: bool,
+    #[cfg(feature = "chrono")]
+    timestamp_format: Option<chrono::SecondsFormat>,
+    url_safe_base64: bool,
+    non_finite_floats: NonFiniteFloats,
+    sort_keys: bool,
+}
+
+impl JsonOptions {
+    /// The [protobuf JSON mapping](https://protobuf.dev/programming-guides/json/): 64-bit
+    /// integers and durations are strings, timestamps are in UTC with 0, 3, 6 or 9 fractional
+    /// digits, non-finite doubles are strings, and keys are sorted.
+    pub fn protobuf() -> Self {
+        let options = Self::default()
+            .int64_as_string(true)
+            .non_finite_floats(NonFiniteFloats::String)
+            .sort_keys(true);
+        #[cfg(feature = "chrono")]
+        let options = options
+            .duration_as_string(true)
+            .timestamp_format(chrono::SecondsFormat::AutoSi);
+        options
+    }
+
+    /// Writes `int`s and `uint`s as decimal strings, which JavaScript can read without losing
+    /// precision.
+    pub fn int64_as_string(mut self, enabled: bool) -> Self {
+        self.int64_as_string = enabled;
+        self
+    }
+
+    /// Writes durations as seconds with an `s` suffix and 0, 3, 6 or 9 fractional digits, e.g.
+    /// `"1.500s"` for one and a half seconds, instead of as a number of nanoseconds.
+    #[cfg(feature = "chrono")]
+    pub fn duration_as_string(mut self, enabled: bool) -> Self {
+        self.duration_as_string = enabled;
+        self
+    }
+
+    /// Writes timestamps in UTC, with as many fractional digits as `format` asks for, instead
+    /// of in their own offset with as many as they need.
+    #[cfg(feature = "chrono")]
+    pub fn timestamp_format(mut self, format: chrono::SecondsFormat) -> Self {
+        self.timestamp_format = Some(format);
+        self
+    }
+
+    /// Encodes bytes with the URL and filename safe base64 alphabet instead of the standard one.
+    pub fn url_safe_base64(mut self, enabled: bool) -> Self {
+        self.url_safe_base64 = enabled;
+        self
+    }
+
+    /// Sets what `NaN` and infinite doubles are written as.
+    pub fn non_finite_floats(mut self, non_finite: NonFiniteFloats) -> Self {
+        self.non_finite_floats = non_finite;
+        self
+    }
+
+    /// Orders the keys of objects, regardless of whether `serde_json` preserves insertion order.
+    pub fn sort_keys(mut self, enabled: bool) -> Self {
+        self.sort_keys = enabled;
+        self
+    }
+}
+
 impl Value {
     /// Converts a CEL value to a JSON value.
     ///
@@ -34,28 +121,72 @@ impl Value {
     /// assert_eq!(result, serde_json::Value::Null);
     /// ```
     pub fn json(&self) -> Result<serde_json::Value, ConvertToJsonError<'_>> {
+        self.json_with(&JsonOptions::default())
+    }
+
+    /// Converts a CEL value to a JSON value, writing the values JSON has no type for as
+    /// configured by `options`.
+    ///
+    /// # Example
+    /// ```
+    /// use cel::{Context, JsonOptions, Program};
+    ///
+    /// let program = Program::compile("{'id': 9007199254740993, 'ttl': duration('90s')}").unwrap();
+    /// let value = program.execute(&Context::default()).unwrap();
+    /// let result = value.json_with(&JsonOptions::protobuf()).unwrap();
+    ///
+    /// assert_eq!(result, serde_json::json!({"id": "9007199254740993", "ttl": "90s"}));
+    /// ```
+    pub fn json_with(
+        &self,
+        options: &JsonOptions,
+    ) -> Result<serde_json::Value, ConvertToJsonError<'_>> {
         Ok(match *self {
             Value::List(ref vec) => serde_json::Value::Array(
                 vec.iter()
-                    .map(|v| v.json())
+                    .map(|v| v.json_with(options))
                     .collect::<Result<Vec<_>, _>>()?,
             ),
             Value::Map(ref map) => {
-                let mut obj = serde_json::Map::new();
-                for (k, v) in map.map.iter() {
-                    obj.insert(k.to_string(), v.json()?);
+                let mut entries = map
+                    .map
+                    .iter()
+                    .map(|(k, v)| Ok((k.to_string(), v.json_with(options)?)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if options.sort_keys {
+                    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
                 }
-                serde_json::Value::Object(obj)
+                serde_json::Value::Object(entries.into_iter().collect())
             }
+            Value::Int(i) if options.int64_as_string => i.to_string().into(),
             Value::Int(i) => i.into(),
+            Value::UInt(u) if options.int64_as_string => u.to_string().into(),
             Value::UInt(u) => u.into(),
+            Value::Float(f) if !f.is_finite() => match options.non_finite_floats {
+                NonFiniteFloats::Null => serde_json::Value::Null,
+                NonFiniteFloats::String if f.is_nan() => "NaN".into(),
+                NonFiniteFloats::String if f > 0.0 => "Infinity".into(),
+                NonFiniteFloats::String => "-Infinity".into(),
+                NonFiniteFloats::Error => return Err(ConvertToJsonError::Value(self)),
+            },
             Value::Float(f) => f.into(),
             Value::String(ref s) => s.to_string().into(),
             Value::Bool(b) => b.into(),
-            Value::Bytes(ref b) => BASE64_STANDARD.encode(b.as_slice()).to_string().into(),
+            Value::Bytes(ref b) if options.url_safe_base64 => {
+                BASE64_URL_SAFE.encode(b.as_slice()).into()
+            }
+            Value::Bytes(ref b) => BASE64_STANDARD.encode(b.as_slice()).into(),
             Value::Null => serde_json::Value::Null,
             #[cfg(feature = "chrono")]
-            Value::Timestamp(ref dt) => dt.to_rfc3339().into(),
+            Value::Timestamp(ref dt) => match options.timestamp_format {
+                Some(format) => dt
+                    .with_timezone(&chrono::Utc)
+                    .to_rfc3339_opts(format, true)
+                    .into(),
+                None => dt.to_rfc3339().into(),
+            },
+            #[cfg(feature = "chrono")]
+            Value::Duration(ref v) if options.duration_as_string => format_duration(v).into(),
             #[cfg(feature = "chrono")]
             Value::Duration(ref v) => serde_json::Value::Number(serde_json::Number::from(
                 v.num_nanoseconds()
@@ -67,8 +198,150 @@ impl Value {
     }
 }
 
+/// Formats a duration as the protobuf JSON mapping does: seconds, with 0, 3, 6 or 9 fractional
+/// digits, and an `s` suffix.
+#[cfg(feature = "chrono")]
+fn format_duration(d: &Duration) -> String {
+    let sign = if *d < Duration::zero() { "-" } else { "" };
+    let secs = d.num_seconds().unsigned_abs();
+    let nanos = d.subsec_nanos().unsigned_abs();
+    if nanos == 0 {
+        format!("{sign}{secs}s")
+    } else if nanos % 1_000_000 == 0 {
+        format!("{sign}{secs}.{:03}s", nanos / 1_000_000)
+    } else if nanos % 1_000 == 0 {
+        format!("{sign}{secs}.{:06}s", nanos / 1_000)
+    } else {
+        format!("{sign}{secs}.{nanos:09}s")
+    }
+}
+
+/// Controls how [`Value::from_json_with`] reads strings which [`Value::json`] may have written
+/// for values JSON has no type for. By default, strings are always read as strings.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FromJsonOptions {
+    bytes: bool,
+    #[cfg(feature = "chrono")]
+    timestamps: bool,
+}
+
+impl FromJsonOptions {
+    /// Reads strings which are valid standard base64, as bytes are written, as bytes.
+    pub fn decode_bytes(mut self, enabled: bool) -> Self {
+        self.bytes = enabled;
+        self
+    }
+
+    /// Reads strings which are RFC 3339 date-times, as timestamps are written, as timestamps.
+    /// Takes precedence over [`decode_bytes`](Self::decode_bytes).
+    #[cfg(feature = "chrono")]
+    pub fn decode_timestamps(mut self, enabled: bool) -> Self {
+        self.timestamps = enabled;
+        self
+    }
+
+    fn string(&self, s: String) -> Value {
+        #[cfg(feature = "chrono")]
+        if self.timestamps {
+            if let Ok(ts) = chrono::DateTime::parse_from_rfc3339(&s) {
+                return Value::Timestamp(ts);
+            }
+        }
+        if self.bytes {
+            if let Ok(bytes) = BASE64_STANDARD.decode(&s) {
+                return Value::Bytes(Arc::new(bytes));
+            }
+        }
+        Value::String(Arc::new(s))
+    }
+}
+
+fn number(n: &serde_json::Number) -> Value {
+    if let Some(i) = n.as_i64() {
+        Value::Int(i)
+    } else if let Some(u) = n.as_u64() {
+        Value::UInt(u)
+    } else {
+        Value::Float(n.as_f64().unwrap_or(f64::NAN))
+    }
+}
+
+impl Value {
+    /// Converts a JSON value to a CEL value, the inverse of [`Value::json`].
+    ///
+    /// Integers are `int`s when they fit, `uint`s when only those fit, and `double`s otherwise;
+    /// objects are maps with string keys. Use [`Value::from_json_with`] to also read back bytes
+    /// and timestamps.
+    ///
+    /// # Example
+    /// ```
+    /// use cel::{Context, Program, Value};
+    ///
+    /// let mut context = Context::default();
+    /// let request = serde_json::json!({"path": "/admin", "size": 18446744073709551615u64});
+    /// context.add_variable_from_value("request", Value::from_json(request));
+    ///
+    /// let program = Program::compile("request.path.startsWith('/') && request.size > 0u").unwrap();
+    /// assert_eq!(program.execute(&context).unwrap(), true.into());
+    /// ```
+    pub fn from_json(json: serde_json::Value) -> Value {
+        Self::from_json_with(json, &FromJsonOptions::default())
+    }
+
+    /// Converts a JSON value to a CEL value like [`Value::from_json`], decoding strings as
+    /// configured by `options`.
+    pub fn from_json_with(json: serde_json::Value, options: &FromJsonOptions) -> Value {
+        match json {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Bool(b),
+            serde_json::Value::Number(n) => number(&n),
+            serde_json::Value::String(s) => options.string(s),
+            serde_json::Value::Array(vec) => Value::List(Arc::new(
+                vec.into_iter()
+                    .map(|v| Self::from_json_with(v, options))
+                    .collect(),
+            )),
+            serde_json::Value::Object(obj) => Value::Map(Map {
+                map: Arc::new(
+                    obj.into_iter()
+                        .map(|(k, v)| (Key::from(k), Self::from_json_with(v, options)))
+                        .collect(),
+                ),
+            }),
+        }
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(json: serde_json::Value) -> Self {
+        Value::from_json(json)
+    }
+}
+
+impl From<&serde_json::Value> for Value {
+    fn from(json: &serde_json::Value) -> Self {
+        match json {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Bool(*b),
+            serde_json::Value::Number(n) => number(n),
+            serde_json::Value::String(s) => Value::String(Arc::new(s.clone())),
+            serde_json::Value::Array(vec) => {
+                Value::List(Arc::new(vec.iter().map(Value::from).collect()))
+            }
+            serde_json::Value::Object(obj) => Value::Map(Map {
+                map: Arc::new(
+                    obj.iter()
+                        .map(|(k, v)| (Key::from(k.as_str()), Value::from(v)))
+                        .collect(),
+                ),
+            }),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::{FromJsonOptions, JsonOptions, NonFiniteFloats};
     use crate::objects::Map;
     use crate::Value as CelValue;
     #[cfg(feature = "chrono")]
@@ -109,4 +382,159 @@ mod tests {
             assert_eq!(value.json().unwrap(), *expected, "{value:?}={expected:?}");
         }
     }
+
+    #[test]
+    fn test_json_to_cel_value() {
+        let tests = vec![
+            (json!("hello"), CelValue::String("hello".to_string().into())),
+            (json!(-42), CelValue::Int(-42)),
+            (json!(u64::MAX), CelValue::UInt(u64::MAX)),
+            (json!(42.5), CelValue::Float(42.5)),
+            (json!(true), CelValue::Bool(true)),
+            (json!(null), CelValue::Null),
+            (
+                json!([true, null]),
+                CelValue::List(vec![CelValue::Bool(true), CelValue::Null].into()),
+            ),
+            (
+                json!({"hello": {"world": 1}}),
+                CelValue::Map(Map::from(HashMap::from([(
+                    "hello".to_string(),
+                    CelValue::Map(Map::from(HashMap::from([(
+                        "world".to_string(),
+                        CelValue::Int(1),
+                    )]))),
+                )]))),
+            ),
+        ];
+
+        for (json, expected) in tests.into_iter() {
+            assert_eq!(CelValue::from(&json), expected, "{json:?}");
+            assert_eq!(CelValue::from_json(json.clone()), expected, "{json:?}");
+            assert_eq!(expected.json().unwrap(), json);
+        }
+    }
+
+    #[test]
+    fn test_json_decoding_options() {
+        let json = json!(["aGVsbG8=", "2023-05-28T00:00:00+00:00", "hello"]);
+        assert_eq!(
+            CelValue::from_json(json.clone()),
+            CelValue::List(
+                vec![
+                    "aGVsbG8=".into(),
+                    "2023-05-28T00:00:00+00:00".into(),
+                    "hello".into()
+                ]
+                .into()
+            )
+        );
+
+        let options = FromJsonOptions::default().decode_bytes(true);
+        #[cfg(feature = "chrono")]
+        let options = options.decode_timestamps(true);
+        let value = CelValue::from_json_with(json.clone(), &options);
+        let CelValue::List(list) = &value else {
+            panic!("{value:?}");
+        };
+        assert_eq!(list[0], CelValue::Bytes(b"hello".to_vec().into()));
+        #[cfg(feature = "chrono")]
+        assert_eq!(
+            list[1],
+            CelValue::Timestamp(
+                chrono::DateTime::parse_from_rfc3339("2023-05-28T00:00:00Z").unwrap()
+            )
+        );
+        assert_eq!(list[2], "hello".into());
+        assert_eq!(value.json().unwrap(), json);
+    }
+
+    #[test]
+    fn test_json_options() {
+        let value = CelValue::List(
+            vec![
+                CelValue::Int(i64::MIN),
+                CelValue::UInt(u64::MAX),
+                CelValue::Bytes(vec![0xfb, 0xff].into()),
+                CelValue::Float(f64::NAN),
+                CelValue::Float(f64::NEG_INFINITY),
+            ]
+            .into(),
+        );
+        assert_eq!(
+            value.json().unwrap(),
+            json!([i64::MIN, u64::MAX, "+/8=", null, null])
+        );
+        let options = JsonOptions::default()
+            .int64_as_string(true)
+            .url_safe_base64(true)
+            .non_finite_floats(NonFiniteFloats::String);
+        assert_eq!(
+            value.json_with(&options).unwrap(),
+            json!([
+                "-9223372036854775808",
+                "18446744073709551615",
+                "-_8=",
+                "NaN",
+                "-Infinity"
+            ])
+        );
+        let options = options.non_finite_floats(NonFiniteFloats::Error);
+        assert!(value.json_with(&options).is_err());
+    }
+
+    #[test]
+    fn test_json_sorted_keys() {
+        let value = CelValue::Map(Map::from(HashMap::from([
+            ("b".to_string(), CelValue::Int(2)),
+            ("a".to_string(), CelValue::Int(1)),
+            ("c".to_string(), CelValue::Int(3)),
+        ])));
+        let json = value
+            .json_with(&JsonOptions::default().sort_keys(true))
+            .unwrap();
+        let keys: Vec<_> = json.as_object().unwrap().keys().cloned().collect();
+        assert_eq!(keys, vec!["a", "b", "c"]);
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_json_time_options() {
+        let ts = chrono::DateTime::parse_from_rfc3339("2023-05-28T02:00:00.120+02:00").unwrap();
+        let value = CelValue::List(
+            vec![
+                CelValue::Timestamp(ts),
+                CelValue::Duration(Duration::milliseconds(1500)),
+                CelValue::Duration(Duration::seconds(-3)),
+                CelValue::Duration(Duration::nanoseconds(-1_000_001_000)),
+                CelValue::Duration(Duration::nanoseconds(5)),
+            ]
+            .into(),
+        );
+        assert_eq!(
+            value.json().unwrap(),
+            json!([
+                "2023-05-28T02:00:00.120+02:00",
+                1_500_000_000,
+                -3_000_000_000i64,
+                -1_000_001_000,
+                5
+            ])
+        );
+        assert_eq!(
+            value.json_with(&JsonOptions::protobuf()).unwrap(),
+            json!([
+                "2023-05-28T00:00:00.120Z",
+                "1.500s",
+                "-3s",
+                "-1.000001s",
+                "0.000000005s"
+            ])
+        );
+        let options = JsonOptions::default().timestamp_format(chrono::SecondsFormat::Secs);
+        assert_eq!(
+            CelValue::Timestamp(ts).json_with(&options).unwrap(),
+            json!("2023-05-28T00:00:00Z")
+        );
+    }
 }
@@ -12,15 +12,18 @@ mod env;
 pub mod parser;
 
 pub use common::ast::IdedExpr;
-use common::ast::SelectExpr;
+use common::ast::{SelectExpr, SourceInfo};
 pub use context::Context;
 pub use functions::FunctionContext;
+use objects::Failure;
 pub use objects::{ResolveResult, Value};
 use parser::{Expression, ExpressionReferences, Parser};
-pub use parser::{ParseError, ParseErrors};
+pub use parser::{ParseError, ParseErrorKind, ParseErrors};
+pub use plan::Backend;
 pub mod functions;
 mod magic;
 pub mod objects;
+mod plan;
 mod resolvers;
 
 #[cfg(feature = "chrono")]
@@ -28,18 +31,38 @@ mod duration;
 #[cfg(feature = "chrono")]
 pub use ser::{Duration, Timestamp};
 
+#[cfg(feature = "regex")]
+pub use common::regex::RegexLimits;
 pub use env::Env;
 #[cfg(feature = "structs")]
 pub use env::StructDef;
 
+mod de;
+pub use de::from_value;
+pub use de::DeserializationError;
+
 mod ser;
 pub use ser::to_value;
 pub use ser::SerializationError;
+pub mod tagged;
+
+#[cfg(feature = "protobuf")]
+pub use prost_reflect;
+
+#[cfg(feature = "diagnostics")]
+mod diagnostic;
+#[cfg(feature = "diagnostics")]
+pub use diagnostic::{Diagnostic, Label};
+
+#[cfg(feature = "serde")]
+mod compiled;
+#[cfg(feature = "serde")]
+pub use compiled::{ProgramDecodeError, ProgramEncodeError};
 
 #[cfg(feature = "json")]
 mod json;
 #[cfg(feature = "json")]
-pub use json::ConvertToJsonError;
+pub use json::{ConvertToJsonError, FromJsonOptions, JsonOptions, NonFiniteFloats};
 
 use magic::FromContext;
 
@@ -116,10 +139,26 @@ pub enum ExecutionError {
     RemainderByZero(Value),
     #[error("Overflow from binary operator '{0}': {1:?}, {2:?}")]
     Overflow(&'static str, Value, Value),
+    /// Indicates that an operand of mixed numeric arithmetic has no exact representation in the
+    /// type it was promoted to, see [`Env::set_numeric_promotion`].
+    #[error("Cannot promote {value:?} to {to} exactly for binary operator '{operator}'")]
+    LossyPromotion {
+        operator: &'static str,
+        value: Value,
+        to: &'static str,
+    },
     #[error("Index out of bounds: {0:?}")]
     IndexOutOfBounds(Value),
     #[error("InternalError: {0:?}")]
     InternalError(String),
+    /// Indicates that a regular expression exceeded the [`RegexLimits`] of the [`Env`].
+    #[cfg(feature = "regex")]
+    #[error("Regex limit exceeded: {0}")]
+    RegexLimitExceeded(String),
+    /// Indicates that an expression was nested deeper than the [`Env`] evaluates, see
+    /// [`Env::set_max_eval_depth`].
+    #[error("Maximum evaluation depth of {0} exceeded")]
+    MaxDepthExceeded(usize),
 }
 
 impl ExecutionError {
@@ -162,21 +201,227 @@ impl ExecutionError {
     }
 }
 
+/// An [`ExecutionError`] along with the id of the expression which raised it, as returned by
+/// [`Program::execute_with_location`]. Displays like a [`ParseError`], pointing at the
+/// expression within the source.
+#[derive(Debug, Clone)]
+pub struct LocatedError {
+    pub error: ExecutionError,
+    pub expr_id: u64,
+    source_info: Arc<SourceInfo>,
+    /// The target and arguments of the expression, if it is a call, for labelling diagnostics.
+    #[cfg(feature = "diagnostics")]
+    operands: Vec<(u64, String)>,
+}
+
+impl LocatedError {
+    /// The line and column, both starting at 1, of the expression which raised the error. These
+    /// account for the [source offset](Parser::source_offset) the program was compiled with.
+    pub fn position(&self) -> Option<(isize, isize)> {
+        self.source_info
+            .pos_for(self.expr_id)
+            .map(|pos| self.source_info.location(pos))
+    }
+
+    pub fn source_info(&self) -> &SourceInfo {
+        &self.source_info
+    }
+}
+
+impl std::fmt::Display for LocatedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        parser::write_error(
+            f,
+            &self.error,
+            self.source_info.pos_for(self.expr_id),
+            Some(&self.source_info),
+        )
+    }
+}
+
+impl std::error::Error for LocatedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
 #[derive(Debug)]
 pub struct Program {
     expression: Expression,
+    source_info: Arc<SourceInfo>,
+    /// Patterns of `matches` calls given as string literals, compiled ahead of execution and
+    /// keyed by the id of the call expression.
+    #[cfg(feature = "regex")]
+    regexes: std::collections::HashMap<u64, regex::Regex>,
+    /// The expression planned against an [`Env`], see [`Program::plan`].
+    plan: Option<plan::Plan>,
+    /// How deeply nested the expression is, and the id of the first expression nested that
+    /// deep, which is blamed when it is nested deeper than the [`Env`] evaluates.
+    deepest: (usize, u64),
 }
 
 impl Program {
     pub fn compile(source: &str) -> Result<Program, ParseErrors> {
-        let parser = Parser::default();
-        parser
-            .parse(source)
-            .map(|expression| Program { expression })
+        Program::compile_with(Parser::default(), source)
+    }
+
+    /// Like [`Program::compile`], but parsing with the given `parser`, for instance to name the
+    /// source errors are reported against.
+    ///
+    /// # Example
+    /// ```
+    /// # use cel::{Context, Program};
+    /// # use cel::parser::Parser;
+    /// let parser = Parser::new().source_name("policy.yaml").source_offset(4, 10);
+    /// let program = Program::compile_with(parser, "size(missing)").unwrap();
+    /// let err = program.execute_with_location(&Context::default()).unwrap_err();
+    /// assert_eq!(err.position(), Some((5, 16)));
+    /// assert!(err.to_string().starts_with("ERROR: policy.yaml:5:16: "));
+    /// ```
+    pub fn compile_with(parser: Parser, source: &str) -> Result<Program, ParseErrors> {
+        let check_regex_literals = parser.check_regex_literals;
+        let (expression, source_info) = parser.parse_with_source_info(source)?;
+        Program::from_parsed(expression, source_info, check_regex_literals)
+    }
+
+    /// Completes compiling an `expression` already parsed from the source `source_info` has,
+    /// failing if `check_regex_literals` and one of its literal patterns isn't valid.
+    #[cfg_attr(not(feature = "regex"), allow(unused_variables))]
+    fn from_parsed(
+        expression: Expression,
+        source_info: Arc<SourceInfo>,
+        check_regex_literals: bool,
+    ) -> Result<Program, ParseErrors> {
+        let deepest = expression.deepest();
+        #[cfg(feature = "regex")]
+        {
+            let mut regexes = std::collections::HashMap::new();
+            let mut errors = Vec::new();
+            common::regex::precompile_literals(
+                &expression,
+                &source_info,
+                &mut regexes,
+                &mut errors,
+            );
+            if check_regex_literals && !errors.is_empty() {
+                return Err(ParseErrors { errors });
+            }
+            Ok(Program {
+                expression,
+                source_info,
+                regexes,
+                plan: None,
+                deepest,
+            })
+        }
+        #[cfg(not(feature = "regex"))]
+        Ok(Program {
+            expression,
+            source_info,
+            plan: None,
+            deepest,
+        })
+    }
+
+    /// Plans the program for repeated execution in contexts of `env`. Rather than being
+    /// looked up by name on every execution, operators and the overloads of `env` that calls
+    /// may dispatch to are resolved once, and literals are built once.
+    ///
+    /// The overloads are those `env` has when planning, so the plan is only used when executing
+    /// in a [`Context`] of that same `env`, as [`Context::with_env`] shares it. In a [`Context`]
+    /// of another [`Env`], the program is evaluated as if it weren't planned. Variables and the
+    /// functions added to the [`Context`] are still looked up by name on execution.
+    ///
+    /// # Example
+    /// ```
+    /// # use cel::{Context, Env, Program};
+    /// # use std::sync::Arc;
+    /// let env = Arc::new(Env::stdlib());
+    /// let program = Program::compile("size(name) > 3 && name.startsWith('A')")
+    ///     .unwrap()
+    ///     .plan(&env);
+    ///
+    /// let mut context = Context::with_env(env);
+    /// for (name, expected) in [("Alice", true), ("Bob", false)] {
+    ///     context.add_variable_from_value("name", name);
+    ///     assert_eq!(program.execute(&context), Ok(expected.into()));
+    /// }
+    /// ```
+    pub fn plan(self, env: &Arc<Env>) -> Program {
+        self.plan_with(env, Backend::Tree)
+    }
+
+    /// Like [`Program::plan`], but choosing the [`Backend`] which evaluates the plan.
+    ///
+    /// # Example
+    /// ```
+    /// # use cel::{Backend, Context, Env, Program};
+    /// # use std::sync::Arc;
+    /// let env = Arc::new(Env::stdlib());
+    /// let program = Program::compile("[1, 2, 3].map(x, x * 2).exists(x, x > 4) || missing")
+    ///     .unwrap()
+    ///     .plan_with(&env, Backend::Bytecode);
+    /// assert_eq!(program.execute(&Context::with_env(env)), Ok(true.into()));
+    /// ```
+    pub fn plan_with(mut self, env: &Arc<Env>, backend: Backend) -> Program {
+        self.plan = Some(plan::Plan::new(&self.expression, env, backend));
+        self
     }
 
     pub fn execute(&self, context: &Context) -> ResolveResult {
-        Value::resolve(&self.expression, context)
+        self.execute_located(context)
+            .map_err(|failure| *failure.error)
+    }
+
+    /// Executes the program, failing along with the expression which raised the error.
+    fn execute_located(&self, context: &Context) -> Result<Value, Failure> {
+        if let Some(plan) = self.plan.as_ref().filter(|plan| plan.is_for(context.env())) {
+            return plan.execute(context);
+        }
+        let (depth, deepest) = self.deepest;
+        let max = context.env().max_eval_depth();
+        if depth > max {
+            return Err(Failure::at(ExecutionError::MaxDepthExceeded(max), deepest));
+        }
+        #[cfg(feature = "regex")]
+        if !self.regexes.is_empty() {
+            return Value::resolve_shallow(&self.expression, &context.new_program_scope(self));
+        }
+        Value::resolve_shallow(&self.expression, context)
+    }
+
+    /// Like [`Program::execute`], but an error comes with the expression which raised it, and
+    /// displays with the line of source it is on.
+    ///
+    /// # Example
+    /// ```
+    /// # use cel::{Context, Program};
+    /// let program = Program::compile("{'foo': 1}.foo +\n  {'bar': 2}.baz").unwrap();
+    /// let err = program.execute_with_location(&Context::default()).unwrap_err();
+    /// assert_eq!(err.position(), Some((2, 13)));
+    /// assert_eq!(
+    ///     err.to_string(),
+    ///     "ERROR: <input>:2:13: No such key: baz\n|   {'bar': 2}.baz\n| ............^"
+    /// );
+    /// ```
+    pub fn execute_with_location(&self, context: &Context) -> Result<Value, LocatedError> {
+        self.execute_located(context).map_err(|failure| {
+            let expr_id = failure.at.unwrap_or(self.expression.id);
+            LocatedError {
+                error: *failure.error,
+                expr_id,
+                source_info: self.source_info.clone(),
+                #[cfg(feature = "diagnostics")]
+                operands: diagnostic::operands(&self.expression, expr_id),
+            }
+        })
+    }
+
+    /// Returns the pattern of the `matches` call expression `id`, if it was a string literal
+    /// compiled along with the program.
+    #[cfg(feature = "regex")]
+    pub(crate) fn precompiled_regex(&self, id: u64) -> Option<&regex::Regex> {
+        self.regexes.get(&id)
     }
 
     /// Returns the variables and functions referenced by the CEL program
@@ -210,11 +455,13 @@ impl TryFrom<&str> for Program {
 
 #[cfg(test)]
 mod tests {
+    use crate::common::ast::{operators, CallExpr, Expr, IdedExpr, LiteralValue};
     use crate::context::Context;
     use crate::objects::{ResolveResult, Value};
-    use crate::{ExecutionError, Program};
+    use crate::{Backend, Env, ExecutionError, Program};
     use std::collections::HashMap;
     use std::convert::TryInto;
+    use std::sync::Arc;
 
     /// Tests the provided script and returns the result. An optional context can be provided.
     pub(crate) fn test_script(script: &str, ctx: Option<Context>) -> ResolveResult {
@@ -305,4 +552,98 @@ mod tests {
             assert_eq!(res, error.into(), "{name}");
         }
     }
+
+    #[test]
+    fn test_execution_error_location() {
+        let tests = [
+            ("missing", (1, 1)),
+            ("1 + 2 == 3 && [1, 2].map(x, x / 0) == []", (1, 31)),
+            ("missing || true ? {'a': 1}.b : 0", (1, 27)),
+            ("[1, 2].exists(x, x == 'a' || x.b)", (1, 31)),
+            ("size(\n  'abc') + {}.size() + size(\n    1)", (2, 28)),
+        ];
+
+        for (script, position) in tests {
+            let program = Program::compile(script).unwrap();
+            let err = program
+                .execute_with_location(&Context::default())
+                .unwrap_err();
+            assert_eq!(
+                Err(err.error.clone()),
+                test_script(script, None),
+                "{script}"
+            );
+            assert_eq!(err.position(), Some(position), "{script}");
+        }
+
+        let program = Program::compile("1 +\n  missing").unwrap();
+        let err = program
+            .execute_with_location(&Context::default())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERROR: <input>:2:3: Undeclared reference to 'missing'\n|   missing\n| ..^"
+        );
+    }
+
+    #[test]
+    fn test_max_eval_depth() {
+        // Deeper than the stack of the thread evaluates natively.
+        let compile = || {
+            let mut expr = IdedExpr {
+                id: 1500,
+                expr: Expr::Literal(LiteralValue::Int(1.into())),
+            };
+            for id in (1..1500).rev() {
+                expr = IdedExpr {
+                    id,
+                    expr: Expr::Call(CallExpr {
+                        func_name: operators::NEGATE.to_string(),
+                        target: None,
+                        args: vec![expr],
+                    }),
+                };
+            }
+            Program::from_parsed(expr, Default::default(), true).unwrap()
+        };
+
+        let mut env = Env::stdlib();
+        env.set_max_eval_depth(100);
+        let env = Arc::new(env);
+        let ctx = Context::with_env(env.clone());
+        for program in [
+            compile(),
+            compile().plan_with(&env, Backend::Tree),
+            compile().plan_with(&env, Backend::Bytecode),
+        ] {
+            let err = program.execute_with_location(&ctx).unwrap_err();
+            assert_eq!(err.error, ExecutionError::MaxDepthExceeded(100));
+            assert_eq!(err.expr_id, 1500);
+        }
+        assert_eq!(
+            Value::resolve(compile().expression(), &ctx),
+            Err(ExecutionError::MaxDepthExceeded(100))
+        );
+
+        // A program fails as a whole, even where the expression nested too deep isn't reached.
+        let mut env = Env::stdlib();
+        env.set_max_eval_depth(4);
+        let env = Arc::new(env);
+        let ctx = Context::with_env(env.clone());
+        let program = || Program::compile("[1] == [1] || [[[1]]] == []").unwrap();
+        for program in [
+            program(),
+            program().plan_with(&env, Backend::Tree),
+            program().plan_with(&env, Backend::Bytecode),
+        ] {
+            assert_eq!(
+                program.execute(&ctx),
+                Err(ExecutionError::MaxDepthExceeded(4))
+            );
+        }
+
+        // Unlimited by default.
+        let program = Program::compile(&format!("1{}", " + 1".repeat(149))).unwrap();
+        assert_eq!(program.execute(&Context::default()), Ok(150.into()));
+    }
 }
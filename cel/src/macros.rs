@@ -1,7 +1,8 @@
 #[macro_export]
 macro_rules! impl_conversions {
-    // Capture pairs separated by commas, where each pair is separated by =>
-    ($($target_type:ty => $value_variant:path),* $(,)?) => {
+    // Capture pairs separated by commas, where each pair is separated by =>, optionally followed
+    // by the `Val` holding the type natively
+    ($($target_type:ty => $value_variant:path $(| $val_type:ty)?),* $(,)?) => {
         $(
             impl FromValue for $target_type {
                 fn from_value(expr: &Value) -> Result<Self, ExecutionError> {
@@ -29,6 +30,31 @@ macro_rules! impl_conversions {
                 }
             }
 
+            impl FromVal for $target_type {
+                fn from_val(val: &dyn Val) -> Result<Self, ExecutionError> {
+                    $(
+                        if let Some(v) = val.downcast_ref::<$val_type>() {
+                            return Ok(v.inner().to_owned().into());
+                        }
+                    )?
+                    Self::from_value(&val.try_into()?)
+                }
+            }
+
+            impl FromVal for Option<$target_type> {
+                fn from_val(val: &dyn Val) -> Result<Self, ExecutionError> {
+                    if val.downcast_ref::<CelNull>().is_some() {
+                        return Ok(None);
+                    }
+                    $(
+                        if let Some(v) = val.downcast_ref::<$val_type>() {
+                            return Ok(Some(v.inner().to_owned().into()));
+                        }
+                    )?
+                    Self::from_value(&val.try_into()?)
+                }
+            }
+
             impl From<$target_type> for Value {
                 fn from(value: $target_type) -> Self {
                     $value_variant(value)
@@ -39,12 +65,22 @@ macro_rules! impl_conversions {
                 fn into_resolve_result(self) -> ResolveResult {
                     Ok($value_variant(self))
                 }
+
+                $(
+                    fn into_val_result(self) -> Result<Cow<'static, dyn Val>, ExecutionError> {
+                        Ok(Cow::<dyn Val>::Owned(Box::new(<$val_type>::from(self))))
+                    }
+                )?
             }
 
             impl $crate::magic::IntoResolveResult for Result<$target_type, ExecutionError> {
                 fn into_resolve_result(self) -> ResolveResult {
                     self.map($value_variant)
                 }
+
+                fn into_val_result(self) -> Result<Cow<'static, dyn Val>, ExecutionError> {
+                    self.and_then(IntoResolveResult::into_val_result)
+                }
             }
 
             impl<'a, 'context, 'call> FromContext<'a, 'context, 'call> for $target_type {
@@ -52,7 +88,7 @@ macro_rules! impl_conversions {
                 where
                     Self: Sized,
                 {
-                    arg_value_from_context(ctx).and_then(|v| FromValue::from_value(&v))
+                    arg_from_context(ctx)
                 }
             }
         )*
@@ -74,7 +110,7 @@ macro_rules! impl_handler {
                         $(
                             let [<arg_ $t:lower>] = $t::from_context(_ftx)?;
                         )*
-                        self($([<arg_ $t:lower>],)*).into_resolve_result()
+                        self($([<arg_ $t:lower>],)*).into_val_result()
                     })
                 }
             }
@@ -90,7 +126,7 @@ macro_rules! impl_handler {
                         $(
                             let [<arg_ $t:lower>] = $t::from_context(_ftx)?;
                         )*
-                        self(_ftx, $([<arg_ $t:lower>],)*).into_resolve_result()
+                        self(_ftx, $([<arg_ $t:lower>],)*).into_val_result()
                     })
                 }
             }
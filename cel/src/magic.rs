@@ -1,25 +1,29 @@
+use crate::common::types::{CelBool, CelBytes, CelDouble, CelInt, CelNull, CelString, CelUInt};
+#[cfg(feature = "chrono")]
+use crate::common::types::{CelDuration, CelTimestamp};
+use crate::common::value::Val;
 use crate::macros::{impl_conversions, impl_handler};
 use crate::objects::Opaque;
-use crate::resolvers::{AllArguments, Argument};
 use crate::{ExecutionError, FunctionContext, ResolveResult, Value};
+use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::sync::Arc;
 
 impl_conversions!(
-    i64 => Value::Int,
-    u64 => Value::UInt,
-    f64 => Value::Float,
-    Arc<String> => Value::String,
-    Arc<Vec<u8>> => Value::Bytes,
-    bool => Value::Bool,
+    i64 => Value::Int | CelInt,
+    u64 => Value::UInt | CelUInt,
+    f64 => Value::Float | CelDouble,
+    Arc<String> => Value::String | CelString,
+    Arc<Vec<u8>> => Value::Bytes | CelBytes,
+    bool => Value::Bool | CelBool,
     Arc<Vec<Value>> => Value::List,
     Arc<dyn Opaque> => Value::Opaque
 );
 
 #[cfg(feature = "chrono")]
 impl_conversions!(
-    chrono::Duration => Value::Duration,
-    chrono::DateTime<chrono::FixedOffset> => Value::Timestamp,
+    chrono::Duration => Value::Duration | CelDuration,
+    chrono::DateTime<chrono::FixedOffset> => Value::Timestamp | CelTimestamp,
 );
 
 impl From<i32> for Value {
@@ -50,12 +54,21 @@ trait FromValue {
         Self: Sized;
 }
 
-impl FromValue for Value {
-    fn from_value(value: &Value) -> Result<Self, ExecutionError>
+/// Describes any type that can be extracted from a [`Val`], the runtime representation of
+/// values. Primitive types are read straight from the [`Val`] holding them, anything else is
+/// converted through [`Value`].
+trait FromVal {
+    fn from_val(val: &dyn Val) -> Result<Self, ExecutionError>
+    where
+        Self: Sized;
+}
+
+impl FromVal for Value {
+    fn from_val(val: &dyn Val) -> Result<Self, ExecutionError>
     where
         Self: Sized,
     {
-        Ok(value.clone())
+        val.try_into()
     }
 }
 
@@ -63,12 +76,26 @@ impl FromValue for Value {
 /// be registered to the CEL context must return a value that implements this trait.
 pub trait IntoResolveResult {
     fn into_resolve_result(self) -> ResolveResult;
+
+    /// Converts into the [`Val`] the interpreter continues with. Types with a native [`Val`]
+    /// representation are converted directly, the default goes through
+    /// [`IntoResolveResult::into_resolve_result`].
+    fn into_val_result(self) -> Result<Cow<'static, dyn Val>, ExecutionError>
+    where
+        Self: Sized,
+    {
+        Ok(Cow::Owned(self.into_resolve_result()?.try_into()?))
+    }
 }
 
 impl IntoResolveResult for String {
     fn into_resolve_result(self) -> ResolveResult {
         Ok(Value::String(Arc::new(self)))
     }
+
+    fn into_val_result(self) -> Result<Cow<'static, dyn Val>, ExecutionError> {
+        Ok(Cow::<dyn Val>::Owned(Box::new(CelString::from(self))))
+    }
 }
 
 impl IntoResolveResult for Result<Value, ExecutionError> {
@@ -77,6 +104,26 @@ impl IntoResolveResult for Result<Value, ExecutionError> {
     }
 }
 
+impl IntoResolveResult for Box<dyn Val> {
+    fn into_resolve_result(self) -> ResolveResult {
+        self.as_ref().try_into()
+    }
+
+    fn into_val_result(self) -> Result<Cow<'static, dyn Val>, ExecutionError> {
+        Ok(Cow::Owned(self))
+    }
+}
+
+impl IntoResolveResult for Result<Box<dyn Val>, ExecutionError> {
+    fn into_resolve_result(self) -> ResolveResult {
+        self.and_then(IntoResolveResult::into_resolve_result)
+    }
+
+    fn into_val_result(self) -> Result<Cow<'static, dyn Val>, ExecutionError> {
+        self.map(Cow::Owned)
+    }
+}
+
 /// Describes any type that can be converted from a [`FunctionContext`] into
 /// itself, for example CEL primitives implement this trait to allow them to
 /// be used as arguments to functions. This trait is core to the 'magic function
@@ -121,7 +168,7 @@ pub(crate) trait FromContext<'a, 'context, 'call> {
 ///
 /// # Type of `This`
 /// This also accepts a type `T` which determines the specific type
-/// that's extracted. Any type that supports [`FromValue`] can be used.
+/// that's extracted. Any type that supports `FromVal` can be used.
 /// In the previous example, the method `startsWith` is only ever called
 /// on a string, so we can use `This<Rc<String>>` to extract the string
 /// automatically prior to our method actually being called.
@@ -142,18 +189,17 @@ pub struct This<T>(pub T);
 
 impl<'a, 'context, 'call, T> FromContext<'a, 'context, 'call> for This<T>
 where
-    T: FromValue,
+    T: FromVal,
 {
     fn from_context(ctx: &'a mut FunctionContext<'context, 'call>) -> Result<Self, ExecutionError>
     where
         Self: Sized,
     {
         if let Some(ref this) = ctx.this {
-            Ok(This(T::from_value(&this.as_ref().try_into()?)?))
+            Ok(This(T::from_val(this.as_ref())?))
         } else {
-            let arg = arg_value_from_context(ctx)
-                .map_err(|_| ExecutionError::missing_argument_or_target())?;
-            Ok(This(T::from_value(&arg)?))
+            let arg = next_arg(ctx).map_err(|_| ExecutionError::missing_argument_or_target())?;
+            Ok(This(T::from_val(arg)?))
         }
     }
 }
@@ -232,10 +278,12 @@ impl<'a> FromContext<'a, '_, '_> for Arguments {
     where
         Self: Sized,
     {
-        match ctx.resolve(AllArguments)? {
-            Value::List(list) => Ok(Arguments(list.clone())),
-            _ => todo!(),
-        }
+        let args = ctx
+            .args
+            .iter()
+            .map(|arg| arg.as_ref().try_into())
+            .collect::<Result<_, _>>()?;
+        Ok(Arguments(Arc::new(args)))
     }
 }
 
@@ -244,21 +292,27 @@ impl<'a, 'context, 'call> FromContext<'a, 'context, 'call> for Value {
     where
         Self: Sized,
     {
-        arg_value_from_context(ctx)
+        arg_from_context(ctx)
     }
 }
 
-/// Returns the next argument specified by the context's `arg_idx` field as after resolving
-/// it. Calling this multiple times will increment the `arg_idx` which will return subsequent
-/// arguments every time.
-///
-/// Calling this function when there are no more arguments will result in a panic. Since this
-/// function is only ever called within the context of a controlled macro that calls it once
-/// for each argument, this should never happen.
-fn arg_value_from_context(ctx: &mut FunctionContext) -> Result<Value, ExecutionError> {
+/// Returns the next argument specified by the context's `arg_idx` field. Calling this multiple
+/// times will increment the `arg_idx` which will return subsequent arguments every time.
+fn next_arg<'a>(ctx: &'a mut FunctionContext) -> Result<&'a dyn Val, ExecutionError> {
     let idx = ctx.arg_idx;
     ctx.arg_idx += 1;
-    ctx.resolve(Argument(idx))
+    match ctx.args.get(idx) {
+        Some(arg) => Ok(arg.as_ref()),
+        None => Err(ExecutionError::invalid_argument_count(
+            idx + 1,
+            ctx.args.len(),
+        )),
+    }
+}
+
+/// Extracts the next argument of the context, see [`next_arg`].
+fn arg_from_context<T: FromVal>(ctx: &mut FunctionContext) -> Result<T, ExecutionError> {
+    next_arg(ctx).and_then(T::from_val)
 }
 
 pub struct WithFunctionContext;
@@ -298,7 +352,14 @@ impl FunctionRegistry {
     }
 }
 
-pub type Function = Box<dyn Fn(&mut FunctionContext) -> ResolveResult + Send + Sync>;
+/// A function added to the [`Context`](crate::Context), which returns its result as a [`Val`].
+pub type Function = Box<
+    dyn for<'context, 'call> Fn(
+            &mut FunctionContext<'context, 'call>,
+        ) -> Result<Cow<'context, dyn Val>, ExecutionError>
+        + Send
+        + Sync,
+>;
 
 pub trait IntoFunction<T> {
     fn into_function(self) -> Function;
@@ -1,9 +1,9 @@
-use crate::common::ast::{operators, EntryExpr, Expr};
-use crate::common::types::bool::Bool;
+use crate::common::ast::{CallExpr, ComprehensionExpr, EntryExpr, Expr, StructExpr};
+use crate::common::decls::OverloadDecl;
 use crate::common::types::*;
 use crate::common::value::Val;
-use crate::context::Context;
-use crate::ExecutionError::NoSuchOverload;
+use crate::context::{Context, Frame};
+use crate::plan::Op;
 use crate::{ExecutionError, Expression, FunctionContext};
 #[cfg(feature = "chrono")]
 use chrono::TimeZone;
@@ -11,7 +11,7 @@ use std::any::Any;
 use std::borrow::{Borrow, Cow};
 use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::convert::{Infallible, TryFrom, TryInto};
+use std::convert::{TryFrom, TryInto};
 use std::fmt::{Debug, Display, Formatter};
 use std::ops;
 use std::ops::Deref;
@@ -47,9 +47,21 @@ static MIN_TIMESTAMP: LazyLock<chrono::DateTime<chrono::FixedOffset>> = LazyLock
         .from_utc_datetime(&naive)
 });
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, Clone)]
 pub struct Map {
-    pub map: Arc<HashMap<Key, Value>>,
+    pub(crate) map: Arc<MapStorage<Key, Value>>,
+}
+
+impl PartialEq for Map {
+    /// Maps are equal if they have the same entries, where numeric keys match across int and
+    /// uint just like in lookups, so `{1: 'a'} == {1u: 'a'}`.
+    fn eq(&self, other: &Self) -> bool {
+        self.map.len() == other.map.len()
+            && self
+                .map
+                .iter()
+                .all(|(k, v)| other.get(k).is_some_and(|o| v == o))
+    }
 }
 
 impl PartialOrd for Map {
@@ -59,6 +71,32 @@ impl PartialOrd for Map {
 }
 
 impl Map {
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Iterates over the entries of the map, in the order they were inserted in with the
+    /// `preserve_order` feature and in an arbitrary order otherwise.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (&Key, &Value)> {
+        self.map.iter()
+    }
+
+    /// Iterates over the keys of the map, in the same order as [`Map::iter`].
+    pub fn keys(&self) -> impl ExactSizeIterator<Item = &Key> {
+        self.map.keys()
+    }
+
+    /// Iterates over the values of the map, in the same order as [`Map::iter`].
+    pub fn values(&self) -> impl ExactSizeIterator<Item = &Value> {
+        self.map.values()
+    }
+
     pub(crate) fn contains_key(&self, key: &(dyn AsKeyRef + '_)) -> bool {
         self.map.contains_key(key)
     }
@@ -286,12 +324,33 @@ impl<'a> TryFrom<&'a Value> for KeyRef<'a> {
 // Implement conversion from HashMap<K, V> into CelMap
 impl<K: Into<Key>, V: Into<Value>> From<HashMap<K, V>> for Map {
     fn from(map: HashMap<K, V>) -> Self {
-        let mut new_map = HashMap::with_capacity(map.len());
-        for (k, v) in map {
-            new_map.insert(k.into(), v.into());
-        }
+        map.into_iter().collect()
+    }
+}
+
+#[cfg(feature = "preserve_order")]
+impl<K: Into<Key>, V: Into<Value>> From<indexmap::IndexMap<K, V>> for Map {
+    fn from(map: indexmap::IndexMap<K, V>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl<K: Into<Key>, V: Into<Value>> From<MapStorage<K, V>> for Map {
+    fn from(map: MapStorage<K, V>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+/// Collects entries into a map which, with the `preserve_order` feature, iterates over them in
+/// the order they were collected in.
+impl<K: Into<Key>, V: Into<Value>> FromIterator<(K, V)> for Map {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
         Map {
-            map: Arc::new(new_map),
+            map: Arc::new(
+                iter.into_iter()
+                    .map(|(k, v)| (k.into(), v.into()))
+                    .collect(),
+            ),
         }
     }
 }
@@ -521,12 +580,6 @@ impl<T: serde::Serialize> TryIntoValue for T {
         crate::ser::to_value(self)
     }
 }
-impl TryIntoValue for Value {
-    type Error = Infallible;
-    fn try_into_value(self) -> Result<Value, Self::Error> {
-        Ok(self)
-    }
-}
 
 #[derive(Clone)]
 pub enum Value {
@@ -549,6 +602,8 @@ pub enum Value {
     Opaque(Arc<dyn Opaque>),
     #[cfg(feature = "structs")]
     Struct(Arc<CelStruct>),
+    #[cfg(feature = "protobuf")]
+    Message(Arc<prost_reflect::DynamicMessage>),
     Null,
 }
 
@@ -572,6 +627,8 @@ impl Debug for Value {
             Value::Null => write!(f, "Null"),
             #[cfg(feature = "structs")]
             Value::Struct(s) => write!(f, "{} {{}}", s.name()),
+            #[cfg(feature = "protobuf")]
+            Value::Message(m) => write!(f, "Message({:?})", m),
         }
     }
 }
@@ -593,6 +650,8 @@ pub enum ValueType {
     Null,
     #[cfg(feature = "structs")]
     Struct,
+    #[cfg(feature = "protobuf")]
+    Message,
 }
 
 impl Display for ValueType {
@@ -613,6 +672,8 @@ impl Display for ValueType {
             ValueType::Null => write!(f, "null"),
             #[cfg(feature = "structs")]
             ValueType::Struct => write!(f, "struct"),
+            #[cfg(feature = "protobuf")]
+            ValueType::Message => write!(f, "message"),
         }
     }
 }
@@ -637,6 +698,8 @@ impl Value {
             Value::Null => ValueType::Null,
             #[cfg(feature = "structs")]
             Value::Struct(_) => ValueType::Struct,
+            #[cfg(feature = "protobuf")]
+            Value::Message(_) => ValueType::Message,
         }
     }
 
@@ -688,22 +751,24 @@ impl PartialEq for Value {
             (Value::Duration(a), Value::Duration(b)) => a == b,
             #[cfg(feature = "chrono")]
             (Value::Timestamp(a), Value::Timestamp(b)) => a == b,
-            // Allow different numeric types to be compared without explicit casting.
-            (Value::Int(a), Value::UInt(b)) => a
-                .to_owned()
-                .try_into()
-                .map(|a: u64| a == *b)
-                .unwrap_or(false),
-            (Value::Int(a), Value::Float(b)) => (*a as f64) == *b,
-            (Value::UInt(a), Value::Int(b)) => a
-                .to_owned()
-                .try_into()
-                .map(|a: i64| a == *b)
-                .unwrap_or(false),
-            (Value::UInt(a), Value::Float(b)) => (*a as f64) == *b,
-            (Value::Float(a), Value::Int(b)) => *a == (*b as f64),
-            (Value::Float(a), Value::UInt(b)) => *a == (*b as f64),
+            // Numbers of different types are equal if they are the same number.
+            (Value::Int(a), Value::UInt(b)) => numeric::cmp_int_uint(*a, *b).is_eq(),
+            (Value::Int(a), Value::Float(b)) => {
+                numeric::cmp_int_double(*a, *b).is_some_and(Ordering::is_eq)
+            }
+            (Value::UInt(a), Value::Int(b)) => numeric::cmp_int_uint(*b, *a).is_eq(),
+            (Value::UInt(a), Value::Float(b)) => {
+                numeric::cmp_uint_double(*a, *b).is_some_and(Ordering::is_eq)
+            }
+            (Value::Float(a), Value::Int(b)) => {
+                numeric::cmp_int_double(*b, *a).is_some_and(Ordering::is_eq)
+            }
+            (Value::Float(a), Value::UInt(b)) => {
+                numeric::cmp_uint_double(*b, *a).is_some_and(Ordering::is_eq)
+            }
             (Value::Opaque(a), Value::Opaque(b)) => a.opaque_eq(b.deref()),
+            #[cfg(feature = "protobuf")]
+            (Value::Message(a), Value::Message(b)) => a == b,
             (_, _) => false,
         }
     }
@@ -725,24 +790,16 @@ impl PartialOrd for Value {
             #[cfg(feature = "chrono")]
             (Value::Timestamp(a), Value::Timestamp(b)) => Some(a.cmp(b)),
             // Allow different numeric types to be compared without explicit casting.
-            (Value::Int(a), Value::UInt(b)) => Some(
-                a.to_owned()
-                    .try_into()
-                    .map(|a: u64| a.cmp(b))
-                    // If the i64 doesn't fit into a u64 it must be less than 0.
-                    .unwrap_or(Ordering::Less),
-            ),
-            (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
-            (Value::UInt(a), Value::Int(b)) => Some(
-                a.to_owned()
-                    .try_into()
-                    .map(|a: i64| a.cmp(b))
-                    // If the u64 doesn't fit into a i64 it must be greater than i64::MAX.
-                    .unwrap_or(Ordering::Greater),
-            ),
-            (Value::UInt(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
-            (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
-            (Value::Float(a), Value::UInt(b)) => a.partial_cmp(&(*b as f64)),
+            (Value::Int(a), Value::UInt(b)) => Some(numeric::cmp_int_uint(*a, *b)),
+            (Value::Int(a), Value::Float(b)) => numeric::cmp_int_double(*a, *b),
+            (Value::UInt(a), Value::Int(b)) => Some(numeric::cmp_int_uint(*b, *a).reverse()),
+            (Value::UInt(a), Value::Float(b)) => numeric::cmp_uint_double(*a, *b),
+            (Value::Float(a), Value::Int(b)) => {
+                numeric::cmp_int_double(*b, *a).map(Ordering::reverse)
+            }
+            (Value::Float(a), Value::UInt(b)) => {
+                numeric::cmp_uint_double(*b, *a).map(Ordering::reverse)
+            }
             _ => None,
         }
     }
@@ -806,6 +863,14 @@ impl From<&::bytes::Bytes> for Value {
     }
 }
 
+#[cfg(feature = "protobuf")]
+// Convert a protobuf message to Value
+impl From<prost_reflect::DynamicMessage> for Value {
+    fn from(v: prost_reflect::DynamicMessage) -> Self {
+        Value::Message(Arc::new(v))
+    }
+}
+
 // Convert String to Value
 impl From<String> for Value {
     fn from(v: String) -> Self {
@@ -836,6 +901,19 @@ impl<K: Into<Key>, V: Into<Value>> From<HashMap<K, V>> for Value {
     }
 }
 
+impl<K: Into<Key>, V: Into<Value>> From<MapStorage<K, V>> for Value {
+    fn from(v: MapStorage<K, V>) -> Self {
+        Value::Map(v.into())
+    }
+}
+
+#[cfg(feature = "preserve_order")]
+impl<K: Into<Key>, V: Into<Value>> From<indexmap::IndexMap<K, V>> for Value {
+    fn from(v: indexmap::IndexMap<K, V>) -> Self {
+        Value::Map(v.into())
+    }
+}
+
 impl From<ExecutionError> for ResolveResult {
     fn from(value: ExecutionError) -> Self {
         Err(value)
@@ -877,6 +955,10 @@ impl TryFrom<&dyn Val> for Value {
                 Ok(Value::Timestamp(*ts))
             }
             Kind::List => {
+                #[cfg(feature = "json")]
+                if let Some(json) = v.downcast_ref::<CelJson>() {
+                    return Ok(json.to_value());
+                }
                 let list = v.downcast_ref::<CelList>().unwrap().inner();
                 Ok(Value::List(Arc::new(
                     list.iter()
@@ -885,6 +967,10 @@ impl TryFrom<&dyn Val> for Value {
                 )))
             }
             Kind::Map => {
+                #[cfg(feature = "json")]
+                if let Some(json) = v.downcast_ref::<CelJson>() {
+                    return Ok(json.to_value());
+                }
                 let map = v.downcast_ref::<CelMap>().unwrap().inner();
                 Ok(Value::Map(Map {
                     map: Arc::new(
@@ -913,6 +999,10 @@ impl TryFrom<&dyn Val> for Value {
                 }
             })),
             _ => {
+                #[cfg(feature = "protobuf")]
+                if let Some(m) = v.downcast_ref::<CelMessage>() {
+                    return Ok(Value::Message(m.inner().clone()));
+                }
                 #[cfg(feature = "structs")]
                 {
                     if let Some(v) = v.downcast_ref::<CelStruct>() {
@@ -962,7 +1052,8 @@ impl TryFrom<Value> for Box<dyn Val> {
                 Ok(Box::new(CelList::from(result?)))
             }
             Value::Map(map) => {
-                let result: Result<HashMap<CelMapKey, Box<dyn Val>>, ExecutionError> = (*map.map)
+                let result: Result<MapStorage<CelMapKey, Box<dyn Val>>, ExecutionError> = (*map
+                    .map)
                     .clone()
                     .into_iter()
                     .map(|(k, v)| v.clone().try_into().map(|v| (k.clone().into(), v)))
@@ -984,6 +1075,8 @@ impl TryFrom<Value> for Box<dyn Val> {
             Value::Struct(s) => Ok(Arc::try_unwrap(s)
                 .map(|s| Box::new(s) as Box<dyn Val>)
                 .unwrap_or_else(|arc| arc.clone_as_boxed())),
+            #[cfg(feature = "protobuf")]
+            Value::Message(m) => message::message_to_val(m),
             _ => Err(ExecutionError::UnsupportedTargetType { target: value }),
         }
     }
@@ -999,7 +1092,19 @@ impl Value {
     }
 
     pub fn resolve(expr: &Expression, ctx: &Context) -> ResolveResult {
-        Self::resolve_val(expr, ctx)?.as_ref().try_into()
+        Self::resolve_located(expr, ctx).map_err(|failure| *failure.error)
+    }
+
+    /// Like [`Value::resolve`], but failing along with the expression which raised the error.
+    pub(crate) fn resolve_located(expr: &Expression, ctx: &Context) -> Result<Value, Failure> {
+        check_depth(expr, ctx)?;
+        Self::resolve_shallow(expr, ctx)
+    }
+
+    /// Resolves `expr`, which is known to be nested no deeper than the [`Env`](crate::Env) of
+    /// `ctx` evaluates.
+    pub(crate) fn resolve_shallow(expr: &Expression, ctx: &Context) -> Result<Value, Failure> {
+        Ok(Self::resolve_in(expr, ctx, None)?.as_ref().try_into()?)
     }
 
     #[inline(always)]
@@ -1007,434 +1112,49 @@ impl Value {
         expr: &'a Expression,
         ctx: &'a Context<'a>,
     ) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+        check_depth(expr, ctx).map_err(|failure| *failure.error)?;
+        Self::resolve_in(expr, ctx, None).map_err(|failure| *failure.error)
+    }
+
+    /// Resolves `expr` within the `frame` of the comprehensions it is in, if any.
+    #[inline(always)]
+    fn resolve_in<'a>(
+        expr: &'a Expression,
+        ctx: &'a Context<'a>,
+        frame: Option<&'a Frame<'a>>,
+    ) -> Result<Cow<'a, dyn Val>, Failure> {
+        Self::resolve_expr(expr, ctx, frame).map_err(|failure| failure.blame(expr.id))
+    }
+
+    fn resolve_expr<'a>(
+        expr: &'a Expression,
+        ctx: &'a Context<'a>,
+        frame: Option<&'a Frame<'a>>,
+    ) -> Result<Cow<'a, dyn Val>, Failure> {
         match &expr.expr {
             Expr::Literal(literal) => Ok(literal.to_val()),
-            Expr::Call(call) => {
-                // START OF SPECIAL CASES FOR operators::...
-                if call.args.len() == 3 && call.func_name == operators::CONDITIONAL {
-                    let cond = Value::resolve_val(&call.args[0], ctx);
-                    return if try_bool(cond)? {
-                        Value::resolve_val(&call.args[1], ctx)
-                    } else {
-                        Value::resolve_val(&call.args[2], ctx)
-                    };
-                }
-                if call.args.len() == 2 {
-                    match call.func_name.as_str() {
-                        operators::LOGICAL_OR => {
-                            let left = try_bool(Value::resolve_val(&call.args[0], ctx));
-                            return if Ok(true) == left {
-                                Ok(Cow::<dyn Val>::Owned(Box::new(CelBool::from(true))))
-                            } else {
-                                let right = Value::resolve_val(&call.args[1], ctx)?
-                                    .downcast_ref::<CelBool>()
-                                    .map(|b| *b.inner());
-                                match (left, right) {
-                                    (Ok(false), Some(right)) => {
-                                        Ok(Cow::<dyn Val>::Owned(Box::new(CelBool::from(right))))
-                                    }
-                                    (Err(_), Some(true)) => {
-                                        Ok(Cow::<dyn Val>::Owned(Box::new(CelBool::from(true))))
-                                    }
-                                    (left, _) => Err(left.err().unwrap_or(NoSuchOverload)),
-                                }
-                            };
-                        }
-                        operators::LOGICAL_AND => {
-                            let left = try_bool(Value::resolve_val(&call.args[0], ctx));
-                            return if Ok(false) == left {
-                                Ok(Cow::<dyn Val>::Owned(Box::new(CelBool::from(false))))
-                            } else {
-                                let right = Value::resolve_val(&call.args[1], ctx)?
-                                    .downcast_ref::<CelBool>()
-                                    .map(|b| *b.inner());
-                                match (left, right) {
-                                    (Ok(true), Some(right)) => {
-                                        Ok(Cow::<dyn Val>::Owned(Box::new(CelBool::from(right))))
-                                    }
-                                    (Err(_), Some(false)) => {
-                                        Ok(Cow::<dyn Val>::Owned(Box::new(CelBool::from(false))))
-                                    }
-                                    (left, _) => Err(left.err().unwrap_or(NoSuchOverload)),
-                                }
-                            };
-                        }
-                        operators::EQUALS => {
-                            return Ok(bool(
-                                Value::resolve_val(&call.args[0], ctx)?
-                                    .eq(&Value::resolve_val(&call.args[1], ctx)?),
-                            ))
-                        }
-                        operators::NOT_EQUALS => {
-                            return Ok(bool(
-                                Value::resolve_val(&call.args[0], ctx)?
-                                    .ne(&Value::resolve_val(&call.args[1], ctx)?),
-                            ))
-                        }
-                        operators::INDEX | operators::OPT_INDEX => {
-                            let mut is_optional = call.func_name == operators::OPT_INDEX;
-                            let value = Value::resolve_val(&call.args[0], ctx)?;
-
-                            let value = if let Some(opt) = value.downcast_ref::<CelOptional>() {
-                                is_optional = true;
-                                match opt.inner() {
-                                    // todo try to keep this borrowed
-                                    Some(v) => Cow::Owned(v.clone_as_boxed()),
-                                    None => {
-                                        return Ok(Cow::<dyn Val>::Owned(Box::new(
-                                            CelOptional::none(),
-                                        )))
-                                    }
-                                }
-                            } else {
-                                value
-                            };
-
-                            let result = match value {
-                                Cow::Borrowed(val) => val
-                                    .as_indexer()
-                                    .ok_or(ExecutionError::NoSuchOverload)?
-                                    .get(Self::resolve_val(&call.args[1], ctx)?.as_ref()),
-                                Cow::Owned(val) => val
-                                    .into_indexer()
-                                    .ok_or(ExecutionError::NoSuchOverload)?
-                                    .steal(Self::resolve_val(&call.args[1], ctx)?.as_ref())
-                                    .map(Cow::Owned),
-                            };
-                            return if is_optional {
-                                Ok(match result {
-                                    Ok(val) => Cow::<dyn Val>::Owned(Box::new(CelOptional::from(
-                                        val.clone_as_boxed(),
-                                    ))),
-                                    Err(_) => Cow::<dyn Val>::Owned(Box::new(CelOptional::none())),
-                                })
-                            } else {
-                                result
-                            };
-                        }
-                        operators::OPT_SELECT => {
-                            let operand = Value::resolve_val(&call.args[0], ctx)?;
-                            let field_literal = Value::resolve_val(&call.args[1], ctx)?;
-                            let field = match field_literal.get_type().kind() {
-                                Kind::String => field_literal
-                                    .downcast_ref::<CelString>()
-                                    .expect("field must be string"),
-                                _ => {
-                                    return Err(ExecutionError::function_error(
-                                        "_?._",
-                                        "field must be string",
-                                    ))
-                                }
-                            };
-                            return Ok(Cow::<dyn Val>::Owned(Box::new(
-                                if let Some(opt) = operand.as_ref().downcast_ref::<CelOptional>() {
-                                    opt.map(|operand| {
-                                        operand
-                                            .as_indexer()
-                                            .map(|i| {
-                                                i.get(field)
-                                                    .map(|v| v.clone_as_boxed())
-                                                    .unwrap_or(CelOptional::none().clone_as_boxed())
-                                            })
-                                            .unwrap_or(CelOptional::none().clone_as_boxed())
-                                    })
-                                } else {
-                                    CelOptional::of(
-                                        operand
-                                            .as_indexer()
-                                            .ok_or(NoSuchOverload)?
-                                            .get(field)?
-                                            .clone_as_boxed(),
-                                    )
-                                },
-                            )));
-                        }
-                        // END OF SPECIAL CASES
-
-                        // all below is NOT special in the interpreter
-                        operators::ADD => {
-                            let lhs = Value::resolve_val(&call.args[0], ctx)?;
-                            let rhs = Value::resolve_val(&call.args[1], ctx)?;
-                            return Ok(Cow::Owned(
-                                lhs.as_ref()
-                                    .as_adder()
-                                    .ok_or_else(|| {
-                                        ExecutionError::UnsupportedBinaryOperator(
-                                            "add",
-                                            lhs.as_ref().try_into().unwrap_or(Value::Null),
-                                            rhs.as_ref().try_into().unwrap_or(Value::Null),
-                                        )
-                                    })?
-                                    .add(rhs.as_ref())?
-                                    .into_owned(),
-                            ));
-                        }
-                        operators::SUBSTRACT => {
-                            let lhs = Value::resolve_val(&call.args[0], ctx)?;
-                            let rhs = Value::resolve_val(&call.args[1], ctx)?;
-                            return Ok(Cow::Owned(
-                                lhs.as_subtractor()
-                                    .ok_or_else(|| {
-                                        ExecutionError::UnsupportedBinaryOperator(
-                                            "sub",
-                                            lhs.as_ref().try_into().unwrap_or(Value::Null),
-                                            rhs.as_ref().try_into().unwrap_or(Value::Null),
-                                        )
-                                    })?
-                                    .sub(rhs.as_ref())?
-                                    .into_owned(),
-                            ));
-                        }
-                        operators::DIVIDE => {
-                            let lhs = Value::resolve_val(&call.args[0], ctx)?;
-                            let rhs = Value::resolve_val(&call.args[1], ctx)?;
-                            return Ok(Cow::Owned(
-                                lhs.as_divider()
-                                    .ok_or_else(|| {
-                                        ExecutionError::UnsupportedBinaryOperator(
-                                            "div",
-                                            lhs.as_ref().try_into().unwrap_or(Value::Null),
-                                            rhs.as_ref().try_into().unwrap_or(Value::Null),
-                                        )
-                                    })?
-                                    .div(rhs.as_ref())?
-                                    .into_owned(),
-                            ));
-                        }
-                        operators::MULTIPLY => {
-                            let lhs = Value::resolve_val(&call.args[0], ctx)?;
-                            let rhs = Value::resolve_val(&call.args[1], ctx)?;
-                            return Ok(Cow::Owned(
-                                lhs.as_multiplier()
-                                    .ok_or_else(|| {
-                                        ExecutionError::UnsupportedBinaryOperator(
-                                            "mul",
-                                            lhs.as_ref().try_into().unwrap_or(Value::Null),
-                                            rhs.as_ref().try_into().unwrap_or(Value::Null),
-                                        )
-                                    })?
-                                    .mul(rhs.as_ref())?
-                                    .into_owned(),
-                            ));
-                        }
-                        operators::MODULO => {
-                            let lhs = Value::resolve_val(&call.args[0], ctx)?;
-                            let rhs = Value::resolve_val(&call.args[1], ctx)?;
-                            return Ok(Cow::Owned(
-                                lhs.as_modder()
-                                    .ok_or_else(|| {
-                                        ExecutionError::UnsupportedBinaryOperator(
-                                            "rem",
-                                            lhs.as_ref().try_into().unwrap_or(Value::Null),
-                                            rhs.as_ref().try_into().unwrap_or(Value::Null),
-                                        )
-                                    })?
-                                    .modulo(rhs.as_ref())?
-                                    .into_owned(),
-                            ));
-                        }
-                        operators::LESS => {
-                            let lhs = Value::resolve_val(&call.args[0], ctx)?;
-                            let rhs = Value::resolve_val(&call.args[1], ctx)?;
-                            return Ok(bool(
-                                lhs.as_comparer()
-                                    .ok_or(ExecutionError::NoSuchOverload)?
-                                    .compare(rhs.as_ref())?
-                                    == Ordering::Less,
-                            ));
-                        }
-                        operators::LESS_EQUALS => {
-                            let lhs = Value::resolve_val(&call.args[0], ctx)?;
-                            let rhs = Value::resolve_val(&call.args[1], ctx)?;
-                            return if lhs
-                                .as_comparer()
-                                .ok_or(ExecutionError::NoSuchOverload)?
-                                .compare(rhs.as_ref())?
-                                == Ordering::Greater
-                            {
-                                Ok(bool(false))
-                            } else {
-                                Ok(bool(true))
-                            };
-                        }
-                        operators::GREATER => {
-                            let lhs = Value::resolve_val(&call.args[0], ctx)?;
-                            let rhs = Value::resolve_val(&call.args[1], ctx)?;
-                            return Ok(bool(
-                                lhs.as_comparer()
-                                    .ok_or(ExecutionError::NoSuchOverload)?
-                                    .compare(rhs.as_ref())?
-                                    == Ordering::Greater,
-                            ));
-                        }
-                        operators::GREATER_EQUALS => {
-                            let lhs = Value::resolve_val(&call.args[0], ctx)?;
-                            let rhs = Value::resolve_val(&call.args[1], ctx)?;
-                            return if lhs
-                                .as_comparer()
-                                .ok_or(ExecutionError::NoSuchOverload)?
-                                .compare(rhs.as_ref())?
-                                == Ordering::Less
-                            {
-                                Ok(bool(false))
-                            } else {
-                                Ok(bool(true))
-                            };
-                        }
-                        operators::IN => {
-                            let lhs = Value::resolve_val(&call.args[0], ctx)?;
-                            let rhs = Value::resolve_val(&call.args[1], ctx)?;
-                            return if let Some(container) = rhs.as_container() {
-                                Ok(bool(container.contains(lhs.as_ref())?))
-                            } else {
-                                Err(ExecutionError::NoSuchOverload)
-                            };
-                        }
-                        _ => (),
-                    }
-                }
-                if call.args.len() == 1 {
-                    match call.func_name.as_str() {
-                        operators::LOGICAL_NOT => {
-                            let expr = Value::resolve_val(&call.args[0], ctx)?;
-                            return expr
-                                .downcast_ref::<CelBool>()
-                                .map(Bool::negate)
-                                .ok_or(ExecutionError::NoSuchOverload)
-                                .map(|b| bool(b.into_inner()));
-                        }
-                        operators::NEGATE => {
-                            let val = Value::resolve_val(&call.args[0], ctx)?;
-                            return Ok(Cow::<dyn Val>::Owned(
-                                val.as_negator()
-                                    .ok_or(ExecutionError::NoSuchOverload)?
-                                    .negate()?,
-                            ));
-                        }
-                        operators::NOT_STRICTLY_FALSE => {
-                            return Ok(bool(
-                                try_bool(Value::resolve_val(&call.args[0], ctx)).unwrap_or(true),
-                            ));
-                        }
-                        _ => (),
-                    }
-                }
-                match &call.target {
-                    None => {
-                        // TODO: Optimize for the 1 and 2 arg cases and avoid the Vec altogether
-                        let args: Result<Vec<Cow<dyn Val>>, ExecutionError> = call
-                            .args
-                            .iter()
-                            .map(|a| Value::resolve_val(a, ctx))
-                            .collect();
-                        let args = args?;
-                        if let Some(op) = ctx.env().find_overload(&call.func_name, &args) {
-                            return op(args);
-                        }
-                        let func = ctx.get_function(call.func_name.as_str()).ok_or_else(|| {
-                            ExecutionError::UndeclaredReference(call.func_name.clone().into())
-                        })?;
-                        let mut ctx = FunctionContext::new(&call.func_name, None, ctx, args);
-                        let v = (func)(&mut ctx)?;
-                        Ok(Cow::<dyn Val>::Owned(TryInto::<Box<dyn Val>>::try_into(v)?))
-                    }
-                    Some(target) => {
-                        let args: Result<Vec<Cow<dyn Val>>, ExecutionError> = call
-                            .args
-                            .iter()
-                            .map(|a| Value::resolve_val(a, ctx))
-                            .collect();
-                        let args = args?;
-                        let qualified_func = match &target.expr {
-                            Expr::Ident(prefix) => {
-                                let qualified_name = format!("{prefix}.{}", &call.func_name);
-                                if let Some(op) = ctx.env().find_overload(&qualified_name, &args) {
-                                    return op(args);
-                                }
-                                ctx.get_function(&qualified_name)
-                            }
-                            _ => None,
-                        };
-                        let (target, func, args) = match qualified_func {
-                            None => {
-                                let target = Value::resolve_val(target, ctx)?;
-                                let mut args = args;
-                                args.insert(0, target);
-                                if let Some(op) =
-                                    ctx.env().find_member_overload(&call.func_name, &args)
-                                {
-                                    return op(args);
-                                }
-                                let target = args.remove(0);
-                                let func =
-                                    ctx.get_function(call.func_name.as_str()).ok_or_else(|| {
-                                        ExecutionError::UndeclaredReference(
-                                            call.func_name.clone().into(),
-                                        )
-                                    })?;
-                                (Some(target), func, args)
-                            }
-                            Some(func) => (None, func, args),
-                        };
-                        let mut ctx = FunctionContext::new(&call.func_name, target, ctx, args);
-                        // todo fix this to _not_ use `Value`
-                        let v = (func)(&mut ctx)?;
-                        Ok(Cow::<dyn Val>::Owned(TryInto::<Box<dyn Val>>::try_into(v)?))
-                    }
+            Expr::Call(call) => Self::resolve_call(expr, call, ctx, frame),
+            Expr::Ident(name) => {
+                if let Some(val) = frame.and_then(|frame| frame.get(name)) {
+                    return Ok(Cow::Borrowed(val));
                 }
+                Ok(ctx.try_get_variable(name)?.ok_or_else(|| {
+                    ExecutionError::UndeclaredReference(Arc::new(name.to_string()))
+                })?)
             }
-            Expr::Ident(name) => Ok(ctx
-                .get_variable(name)
-                .ok_or_else(|| ExecutionError::UndeclaredReference(Arc::new(name.to_string())))?),
             Expr::Select(select) => {
-                let left = Value::resolve_val(select.operand.deref(), ctx)?;
-                let key: CelString = select.field.as_str().into();
-
-                if select.test {
-                    match left.get_type().kind() {
-                        Kind::Map => Ok(bool(
-                            left.as_container()
-                                .ok_or_else(|| {
-                                    ExecutionError::NoSuchKey(Arc::new(key.inner().to_string()))
-                                })?
-                                .contains(&key)?,
-                        )),
-                        #[cfg(feature = "structs")]
-                        Kind::Struct => {
-                            if let Some(indexer) = left.as_indexer() {
-                                Ok(bool(indexer.get(&key).is_ok()))
-                            } else {
-                                Ok(bool(false))
-                            }
-                        }
-                        _ => Ok(Cow::<dyn Val>::Owned(
-                            left.as_indexer()
-                                .ok_or_else(|| ExecutionError::NoSuchOverload)?
-                                .get(&key)?
-                                .into_owned(),
-                        )),
-                    }
-                } else {
-                    match left.get_type().kind() {
-                        Kind::Map => {
-                            // todo avoid cloning when not needed
-                            Ok(Cow::<dyn Val>::Owned(
-                                left.as_indexer()
-                                    .ok_or_else(|| {
-                                        ExecutionError::NoSuchKey(Arc::new(key.inner().to_string()))
-                                    })?
-                                    .get(&key)?
-                                    .into_owned(),
-                            ))
-                        }
-                        _ => Ok(Cow::<dyn Val>::Owned(
-                            left.as_indexer()
-                                .ok_or_else(|| ExecutionError::NoSuchOverload)?
-                                .get(&key)?
-                                .into_owned(),
-                        )),
+                let left = Self::resolve_in(select.operand.deref(), ctx, frame);
+                #[cfg(feature = "protobuf")]
+                if matches!(&left, Err(failure) if matches!(*failure.error, ExecutionError::UndeclaredReference(_)))
+                {
+                    if let Some(number) =
+                        qualified_name(expr).and_then(|name| ctx.env().find_enum_value(&name))
+                    {
+                        return Ok(Cow::<dyn Val>::Owned(Box::new(CelInt::from(number as i64))));
                     }
                 }
+                let key: CelString = select.field.as_str().into();
+                Ok(self::select(left?, &key, select.test)?)
             }
             Expr::List(list_expr) => {
                 let list = list_expr
@@ -1442,7 +1162,7 @@ impl Value {
                     .iter()
                     .enumerate()
                     .map(|(idx, element)| {
-                        Value::resolve_val(element, ctx).map(|value| {
+                        Self::resolve_in(element, ctx, frame).map(|value| {
                             if list_expr.optional_indices.contains(&idx) {
                                 if let Some(opt_val) = value.downcast_ref::<CelOptional>() {
                                     opt_val.inner().map(|v| v.clone_as_boxed())
@@ -1461,15 +1181,16 @@ impl Value {
                 Ok(Cow::<dyn Val>::Owned(Box::new(CelList::from(list))))
             }
             Expr::Map(map_expr) => {
-                let mut map = HashMap::with_capacity(map_expr.entries.len());
+                let mut map = MapStorage::with_capacity(map_expr.entries.len());
                 for entry in map_expr.entries.iter() {
                     let (k, v, is_optional) = match &entry.expr {
                         EntryExpr::StructField(_) => panic!("WAT?"),
                         EntryExpr::MapEntry(e) => (&e.key, &e.value, e.optional),
                     };
-                    let key: CelMapKey = Value::resolve_val(k, ctx)?.into_owned().try_into()?;
+                    let key: CelMapKey =
+                        Self::resolve_in(k, ctx, frame)?.into_owned().try_into()?;
                     // todo do not clone if not needed!
-                    let value = Value::resolve_val(v, ctx)?.into_owned();
+                    let value = Self::resolve_in(v, ctx, frame)?.into_owned();
 
                     if is_optional {
                         if let Some(opt_val) = value.downcast_ref::<CelOptional>() {
@@ -1487,77 +1208,366 @@ impl Value {
                 Ok(Cow::<dyn Val>::Owned(map))
             }
             Expr::Comprehension(comprehension) => {
-                let accu_init = Value::resolve_val(&comprehension.accu_init, ctx)?;
-                let iter = Value::resolve_val(&comprehension.iter_range, ctx)?;
-                let mut ctx = ctx.new_inner_scope();
-                ctx.add_variable_as_val(&comprehension.accu_var, accu_init.clone_as_boxed());
-
-                let mut items = iter
-                    .as_iterable()
-                    .ok_or(ExecutionError::NoSuchOverload)?
-                    .iter();
-                while let Some(item) = items.next() {
-                    if !try_bool(Value::resolve_val(&comprehension.loop_cond, &ctx))? {
-                        break;
-                    }
-                    ctx.add_variable_as_val(&comprehension.iter_var, item.clone_as_boxed());
-                    let accu = Value::resolve_val(&comprehension.loop_step, &ctx)?;
-                    ctx.add_variable_as_val(&comprehension.accu_var, accu.clone_as_boxed());
+                Self::resolve_comprehension(comprehension, ctx, frame)
+            }
+            Expr::Struct(strct) => Self::resolve_struct(strct, ctx, frame),
+            Expr::Unspecified => panic!("Can't evaluate Unspecified Expr"),
+        }
+    }
+
+    fn resolve_call<'a>(
+        expr: &'a Expression,
+        call: &'a CallExpr,
+        ctx: &'a Context<'a>,
+        frame: Option<&'a Frame<'a>>,
+    ) -> Result<Cow<'a, dyn Val>, Failure> {
+        if let Some(op) = Op::of(&call.func_name, call.args.len()) {
+            return op.apply(&call.args, ctx, |arg| Self::resolve_in(arg, ctx, frame));
+        }
+        match &call.target {
+            None => {
+                // TODO: Optimize for the 1 and 2 arg cases and avoid the Vec altogether
+                let args: Result<Vec<Cow<dyn Val>>, Failure> = call
+                    .args
+                    .iter()
+                    .map(|a| Self::resolve_in(a, ctx, frame))
+                    .collect();
+                let args = args?;
+                if let Some(op) = ctx.env().find_op(&call.func_name, false, &args) {
+                    return Ok(call_overload(expr.id, op, ctx, args)?);
                 }
-                Ok(Cow::<dyn Val>::Owned(
-                    Value::resolve_val(&comprehension.result, &ctx)?.into_owned(),
-                ))
+                let func = ctx.get_function(call.func_name.as_str()).ok_or_else(|| {
+                    ExecutionError::UndeclaredReference(call.func_name.clone().into())
+                })?;
+                Ok(call_function(&call.func_name, func, None, ctx, args)?)
             }
-            Expr::Struct(strct) => {
-                let name = strct.type_name.clone();
-                #[cfg(not(feature = "structs"))]
+            Some(target) => {
+                let args: Result<Vec<Cow<dyn Val>>, Failure> = call
+                    .args
+                    .iter()
+                    .map(|a| Self::resolve_in(a, ctx, frame))
+                    .collect();
+                let args = args?;
+                let qualified_func = match &target.expr {
+                    Expr::Ident(prefix) => {
+                        let qualified_name = format!("{prefix}.{}", &call.func_name);
+                        if let Some(op) = ctx.env().find_op(&qualified_name, false, &args) {
+                            return Ok(op.call(args)?);
+                        }
+                        ctx.get_function(&qualified_name)
+                    }
+                    _ => None,
+                };
+                let (target, func, args) = match qualified_func {
+                    None => {
+                        let target = Self::resolve_in(target, ctx, frame)?;
+                        let mut args = args;
+                        args.insert(0, target);
+                        if let Some(op) = ctx.env().find_op(&call.func_name, true, &args) {
+                            return Ok(call_overload(expr.id, op, ctx, args)?);
+                        }
+                        let target = args.remove(0);
+                        let func = ctx.get_function(call.func_name.as_str()).ok_or_else(|| {
+                            ExecutionError::UndeclaredReference(call.func_name.clone().into())
+                        })?;
+                        (Some(target), func, args)
+                    }
+                    Some(func) => (None, func, args),
+                };
+                Ok(call_function(&call.func_name, func, target, ctx, args)?)
+            }
+        }
+    }
+
+    fn resolve_comprehension<'a>(
+        comprehension: &'a ComprehensionExpr,
+        ctx: &'a Context<'a>,
+        frame: Option<&'a Frame<'a>>,
+    ) -> Result<Cow<'a, dyn Val>, Failure> {
+        let accu_init = Self::resolve_in(&comprehension.accu_init, ctx, frame)?;
+        let iter = Self::resolve_in(&comprehension.iter_range, ctx, frame)?;
+        let iter_var = comprehension.iter_var.as_str();
+        let accu_var = comprehension.accu_var.as_str();
+
+        let mut accu = accu_init;
+        let mut items = iter
+            .as_iterable()
+            .ok_or(ExecutionError::NoSuchOverload)?
+            .iter();
+        let appends = comprehension.appends();
+        while let Some(item) = items.next() {
+            let frame = Frame::new(frame, Some((iter_var, item)), (accu_var, accu.as_ref()));
+            if !try_bool(Self::resolve_in(
+                &comprehension.loop_cond,
+                ctx,
+                Some(&frame),
+            ))? {
+                break;
+            }
+            let Some((condition, element)) = appends else {
+                let step = Self::resolve_in(&comprehension.loop_step, ctx, Some(&frame))?;
+                accu = Cow::Owned(step.into_owned());
+                continue;
+            };
+            if let Some(condition) = condition {
+                let condition = Self::resolve_in(condition, ctx, Some(&frame))?;
+                if !try_bool(Ok(condition))
+                    .map_err(|error| Failure::at(error, comprehension.loop_step.id))?
                 {
-                    Err(ExecutionError::InternalError(format!(
-                        "Found struct {name}, feature not enabled!"
-                    )))
+                    continue;
                 }
-                #[cfg(feature = "structs")]
-                {
-                    let struct_def =
-                        ctx.env()
-                            .find_struct(&name)
-                            .ok_or(ExecutionError::UnexpectedType {
-                                got: name.to_owned(),
-                                want: "known struct".to_owned(),
-                            })?;
-                    let mut fields = std::collections::BTreeMap::new();
-                    for entry in &strct.entries {
-                        match &entry.expr {
-                            EntryExpr::StructField(expr) => {
-                                let f = expr.field.clone();
-                                fields.insert(f, Value::resolve_val(&expr.value, ctx)?);
-                            }
-                            EntryExpr::MapEntry(entry) => {
-                                return Err(ExecutionError::InternalError(format!(
-                                    "Expected struct_field_expr, got {entry:?}"
-                                )))
-                            }
+            }
+            let element = Self::resolve_in(element, ctx, Some(&frame))?.into_owned();
+            append(&mut accu, element)?;
+        }
+        if matches!(&comprehension.result.expr, Expr::Ident(name) if name == accu_var) {
+            return Ok(accu);
+        }
+        let frame = Frame::new(frame, None, (accu_var, accu.as_ref()));
+        Ok(Cow::<dyn Val>::Owned(
+            Self::resolve_in(&comprehension.result, ctx, Some(&frame))?.into_owned(),
+        ))
+    }
+
+    #[cfg_attr(not(feature = "structs"), allow(unused_variables))]
+    fn resolve_struct<'a>(
+        strct: &'a StructExpr,
+        ctx: &'a Context<'a>,
+        frame: Option<&'a Frame<'a>>,
+    ) -> Result<Cow<'a, dyn Val>, Failure> {
+        let name = strct.type_name.clone();
+        #[cfg(not(feature = "structs"))]
+        {
+            Err(
+                ExecutionError::InternalError(format!("Found struct {name}, feature not enabled!"))
+                    .into(),
+            )
+        }
+        #[cfg(feature = "structs")]
+        {
+            #[cfg(feature = "protobuf")]
+            if let Some(desc) = ctx.env().find_message(&name) {
+                let mut fields = Vec::with_capacity(strct.entries.len());
+                for entry in &strct.entries {
+                    match &entry.expr {
+                        EntryExpr::StructField(expr) => {
+                            let value = Self::resolve_in(&expr.value, ctx, frame)?;
+                            fields.push((expr.field.clone(), value));
+                        }
+                        EntryExpr::MapEntry(entry) => {
+                            return Err(ExecutionError::InternalError(format!(
+                                "Expected struct_field_expr, got {entry:?}"
+                            ))
+                            .into())
                         }
                     }
-                    let s = struct_def.new_struct(fields)?;
-                    Ok(Cow::<dyn Val>::Owned(Box::new(s)))
                 }
+                let msg = CelMessage::new_message(desc, fields)?;
+                return Ok(Cow::<dyn Val>::Owned(Box::new(msg)));
             }
-            Expr::Unspecified => panic!("Can't evaluate Unspecified Expr"),
+            let struct_def =
+                ctx.env()
+                    .find_struct(&name)
+                    .ok_or(ExecutionError::UnexpectedType {
+                        got: name.to_owned(),
+                        want: "known struct".to_owned(),
+                    })?;
+            let mut fields = std::collections::BTreeMap::new();
+            for entry in &strct.entries {
+                match &entry.expr {
+                    EntryExpr::StructField(expr) => {
+                        let f = expr.field.clone();
+                        fields.insert(f, Self::resolve_in(&expr.value, ctx, frame)?);
+                    }
+                    EntryExpr::MapEntry(entry) => {
+                        return Err(ExecutionError::InternalError(format!(
+                            "Expected struct_field_expr, got {entry:?}"
+                        ))
+                        .into())
+                    }
+                }
+            }
+            let s = struct_def.new_struct(fields)?;
+            Ok(Cow::<dyn Val>::Owned(Box::new(s)))
         }
     }
 }
 
-fn bool<'a>(boolean: bool) -> Cow<'a, dyn Val> {
-    Cow::<dyn Val>::Owned(Box::new(CelBool::from(boolean)))
+/// Returns the dotted name spelled by a chain of selections on an identifier, such as
+/// `google.protobuf.NullValue.NULL_VALUE`.
+#[cfg(feature = "protobuf")]
+pub(crate) fn qualified_name(mut expr: &Expression) -> Option<String> {
+    let mut fields: Vec<&String> = Vec::new();
+    loop {
+        match &expr.expr {
+            Expr::Ident(name) => {
+                let mut qualified = name.clone();
+                for field in fields.iter().rev() {
+                    qualified.push('.');
+                    qualified.push_str(field);
+                }
+                return Some(qualified);
+            }
+            Expr::Select(select) if !select.test => {
+                fields.push(&select.field);
+                expr = &select.operand;
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// An error raised evaluating an expression, along with the id of the innermost expression
+/// which raised it, once known.
+#[derive(Debug)]
+pub(crate) struct Failure {
+    // Boxed, so that evaluating nested expressions takes as little of the stack as it can.
+    pub(crate) error: Box<ExecutionError>,
+    pub(crate) at: Option<u64>,
+}
+
+impl Failure {
+    /// The `error` raised by the expression `id`.
+    pub(crate) fn at(error: ExecutionError, id: u64) -> Failure {
+        Failure {
+            error: Box::new(error),
+            at: Some(id),
+        }
+    }
+
+    /// Blames the expression `id` for the failure, unless an expression within it raised it.
+    pub(crate) fn blame(mut self, id: u64) -> Failure {
+        self.at.get_or_insert(id);
+        self
+    }
+}
+
+impl From<ExecutionError> for Failure {
+    fn from(error: ExecutionError) -> Self {
+        Failure {
+            error: Box::new(error),
+            at: None,
+        }
+    }
+}
+
+/// Fails for the first expression of `expr` nested deeper than the [`Env`](crate::Env) of `ctx`
+/// evaluates, if any.
+fn check_depth(expr: &Expression, ctx: &Context) -> Result<(), Failure> {
+    let max = ctx.env().max_eval_depth();
+    match expr.walk().find(|(_, depth)| *depth > max) {
+        Some((expr, _)) => Err(Failure::at(ExecutionError::MaxDepthExceeded(max), expr.id)),
+        None => Ok(()),
+    }
+}
+
+/// Selects `key` from `left`, or tests whether it has it.
+pub(crate) fn select<'a>(
+    left: Cow<'a, dyn Val>,
+    key: &CelString,
+    test: bool,
+) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+    if test {
+        match left.get_type().kind() {
+            Kind::Map => Ok(bool(
+                left.as_container()
+                    .ok_or_else(|| ExecutionError::NoSuchKey(Arc::new(key.inner().to_string())))?
+                    .contains(key)?,
+            )),
+            #[cfg(feature = "structs")]
+            Kind::Struct => {
+                #[cfg(feature = "protobuf")]
+                if let Some(msg) = left.downcast_ref::<CelMessage>() {
+                    return Ok(bool(msg.has_field(key.inner())?));
+                }
+                if let Some(indexer) = left.as_indexer() {
+                    Ok(bool(indexer.get(key).is_ok()))
+                } else {
+                    Ok(bool(false))
+                }
+            }
+            _ => Ok(Cow::<dyn Val>::Owned(
+                left.as_indexer()
+                    .ok_or_else(|| ExecutionError::NoSuchOverload)?
+                    .get(key)?
+                    .into_owned(),
+            )),
+        }
+    } else {
+        match left.get_type().kind() {
+            Kind::Map => {
+                // todo avoid cloning when not needed
+                Ok(Cow::<dyn Val>::Owned(
+                    left.as_indexer()
+                        .ok_or_else(|| {
+                            ExecutionError::NoSuchKey(Arc::new(key.inner().to_string()))
+                        })?
+                        .get(key)?
+                        .into_owned(),
+                ))
+            }
+            _ => Ok(Cow::<dyn Val>::Owned(
+                left.as_indexer()
+                    .ok_or_else(|| ExecutionError::NoSuchOverload)?
+                    .get(key)?
+                    .into_owned(),
+            )),
+        }
+    }
+}
+
+/// Calls `overload` of the Env for the call expression `id`, along with the pattern of the call
+/// compiled ahead of execution, if the [`Program`](crate::Program) compiled one.
+#[cfg_attr(not(feature = "regex"), allow(unused_variables))]
+fn call_overload<'a>(
+    id: u64,
+    overload: &OverloadDecl,
+    ctx: &Context,
+    args: Vec<Cow<'a, dyn Val>>,
+) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+    #[cfg(feature = "regex")]
+    if let Some(re) = ctx.precompiled_regex(id) {
+        return overload.call_with_pattern(re, args);
+    }
+    overload.call(args)
+}
+
+/// Calls `func`, a function added to the [`Context`] as `name`.
+pub(crate) fn call_function<'a>(
+    name: &'a str,
+    func: &crate::magic::Function,
+    this: Option<Cow<'a, dyn Val>>,
+    ctx: &'a Context<'a>,
+    args: Vec<Cow<'a, dyn Val>>,
+) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+    let mut ctx = FunctionContext::new(name, this, ctx, args);
+    (func)(&mut ctx)
+}
+
+/// Appends `element` to the list accumulated in `accu`, in place rather than copying the list
+/// as `accu + [element]` does.
+pub(crate) fn append(accu: &mut Cow<dyn Val>, element: Box<dyn Val>) -> Result<(), ExecutionError> {
+    let list = accu
+        .to_mut()
+        .downcast_mut::<CelList>()
+        .ok_or(ExecutionError::NoSuchOverload)?;
+    list.push(element);
+    Ok(())
 }
 
-fn try_bool(val: Result<Cow<dyn Val>, ExecutionError>) -> Result<bool, ExecutionError> {
+pub(crate) fn bool<'a>(boolean: bool) -> Cow<'a, dyn Val> {
+    Cow::Borrowed(CelBool::borrowed(boolean))
+}
+
+pub(crate) fn try_bool<E>(val: Result<Cow<dyn Val>, E>) -> Result<bool, E>
+where
+    E: From<ExecutionError>,
+{
     match val {
         Ok(val) => val
             .downcast_ref::<CelBool>()
             .map(|b| *b.inner())
-            .ok_or(ExecutionError::NoSuchOverload),
+            .ok_or_else(|| ExecutionError::NoSuchOverload.into()),
         Err(err) => Result::Err(err),
     }
 }
@@ -1814,6 +1824,23 @@ mod tests {
         let program = Program::compile("numbers[1u]").unwrap();
         let value = program.execute(&context).unwrap();
         assert_eq!(value, "one".into());
+
+        for script in [
+            "numbers[1] == 'one'",
+            "numbers[1.0] == 'one'",
+            "1 in numbers && 1.0 in numbers && !(1.5 in numbers)",
+            "{1: 'one'} == {1u: 'one'}",
+            "{1: 1.0} == {1u: 1}",
+        ] {
+            let program = Program::compile(script).unwrap();
+            assert_eq!(program.execute(&context), Ok(true.into()), "{script}");
+        }
+
+        let program = Program::compile("numbers[1.5]").unwrap();
+        assert_eq!(
+            program.execute(&context),
+            Err(ExecutionError::NoSuchKey(Arc::new("1.5".to_string())))
+        );
     }
 
     #[test]
@@ -1835,6 +1862,74 @@ mod tests {
             true.into(),
             "negative signed ints should be less than uints"
         );
+
+        for script in [
+            "1 == 1.0 && 1u == 1 && 1.0 == 1u && 1 < 2.5 && 2u > 1.5",
+            "1.0 in [1] && 1u in [1.0] && [1, 2u] == [1.0, 2.0]",
+            // Doubles aren't rounded to integers, nor integers to doubles.
+            "9223372036854775807 != 9223372036854775808.0",
+            "9223372036854775807 < 9223372036854775808.0",
+            "18446744073709551615u < 18446744073709551616.0",
+            "-9223372036854775808 == -9223372036854775808.0",
+            "9007199254740993 > 9007199254740992.0",
+            "!(double('NaN') in [double('NaN')])",
+        ] {
+            let program = Program::compile(script).unwrap();
+            assert_eq!(program.execute(&context), Ok(true.into()), "{script}");
+        }
+    }
+
+    #[test]
+    fn test_numeric_promotion() {
+        let script = "count * 1.5";
+        let mut ctx = Context::default();
+        ctx.add_variable_from_value("count", 3);
+        assert_eq!(
+            Program::compile(script).unwrap().execute(&ctx),
+            Err(ExecutionError::NoSuchOverload)
+        );
+
+        let mut env = crate::Env::stdlib();
+        env.set_numeric_promotion(true);
+        let ctx = Context::with_env(Arc::new(env));
+        for (script, expected) in [
+            ("3 * 1.5", Value::Float(4.5)),
+            ("1.5 + 2u", Value::Float(3.5)),
+            ("7u / 2.0", Value::Float(3.5)),
+            ("2 - 3u", Value::Int(-1)),
+            ("7u % 4", Value::Int(3)),
+            ("3 * 2", Value::Int(6)),
+            ("3u * 2u", Value::UInt(6)),
+        ] {
+            let program = Program::compile(script).unwrap();
+            assert_eq!(program.execute(&ctx), Ok(expected), "{script}");
+        }
+
+        for (script, operator, value, to) in [
+            (
+                "1 + 18446744073709551615u",
+                "add",
+                Value::UInt(u64::MAX),
+                "int",
+            ),
+            (
+                "9007199254740993 * 1.0",
+                "mul",
+                Value::Int(9007199254740993),
+                "double",
+            ),
+        ] {
+            let program = Program::compile(script).unwrap();
+            assert_eq!(
+                program.execute(&ctx),
+                Err(ExecutionError::LossyPromotion {
+                    operator,
+                    value,
+                    to,
+                }),
+                "{script}"
+            );
+        }
     }
 
     #[test]
@@ -1878,6 +1973,77 @@ mod tests {
         assert_eq!(program.execute(&context).unwrap(), Value::Bool(true));
     }
 
+    #[test]
+    fn test_comprehension_scopes() {
+        let mut context = Context::default();
+        context.add_variable("x", Value::Int(100)).unwrap();
+        for (script, expected) in [
+            ("[1, 2].map(x, x)[1] + x", Value::Int(102)),
+            (
+                "[1, 2].map(x, [10, 20].map(y, x * y)) == [[10, 20], [20, 40]]",
+                true.into(),
+            ),
+            (
+                "[1, 2].map(x, [10].map(x, x + 1)) == [[11], [11]]",
+                true.into(),
+            ),
+            (
+                "[1, 2].all(y, [3].exists(z, y < z && x == 100))",
+                true.into(),
+            ),
+            (
+                "[[1], [2, 3]].exists(l, l.exists_one(x, x == 3))",
+                true.into(),
+            ),
+        ] {
+            let program = Program::compile(script).unwrap();
+            assert_eq!(program.execute(&context), Ok(expected), "{script}");
+        }
+    }
+
+    #[test]
+    fn test_comprehension_iter_var_scope() {
+        use crate::common::ast::{ComprehensionExpr, Expr, IdedExpr};
+        use crate::parser::Parser;
+        use crate::plan::{Backend, Plan};
+
+        // Sums `[1, 2, 3]` into `sum` for as long as `loop_cond` holds, then evaluates `result`.
+        let comprehension = |loop_cond: &str, result: &str| {
+            let parse = |source| Parser::new().parse(source).unwrap();
+            IdedExpr {
+                id: 100,
+                expr: Expr::Comprehension(Box::new(ComprehensionExpr {
+                    iter_range: parse("[1, 2, 3]"),
+                    iter_var: "x".to_string(),
+                    iter_var2: None,
+                    accu_var: "sum".to_string(),
+                    accu_init: parse("0"),
+                    loop_cond: parse(loop_cond),
+                    loop_step: parse("sum + x"),
+                    result: parse(result),
+                })),
+            }
+        };
+        let mut context = Context::default();
+        context.add_variable("x", Value::Int(100)).unwrap();
+        for (expr, expected) in [
+            // The iteration variable is bound to the current item before `loop_cond` runs.
+            (comprehension("x < 3", "sum"), Value::Int(3)),
+            // It is out of scope in `result`, where `x` is the variable of the context.
+            (comprehension("true", "sum + x"), Value::Int(106)),
+        ] {
+            assert_eq!(Value::resolve(&expr, &context), Ok(expected.clone()));
+            for backend in [Backend::Tree, Backend::Bytecode] {
+                let plan = Plan::new(&expr, context.env(), backend);
+                assert_eq!(
+                    plan.execute(&context).map_err(|failure| *failure.error),
+                    Ok(expected.clone()),
+                    "{backend:?}"
+                );
+            }
+        }
+    }
+
     fn test_execution_error(program: &str, expected: ExecutionError) {
         let program = Program::compile(program).unwrap();
         let result = program.execute(&Context::default());
@@ -2084,6 +2250,44 @@ mod tests {
         assert!(result.is_err(), "Should error on missing map key");
     }
 
+    #[test]
+    fn test_map_storage_from_hash_map() {
+        // The same code builds a map whether or not `preserve_order` is enabled.
+        let map = crate::objects::Map {
+            map: Arc::new(HashMap::from([(Key::from("a"), Value::Int(1))]).into()),
+        };
+        let mut ctx = Context::default();
+        ctx.add_variable_from_value("m", Value::Map(map));
+        let program = Program::compile("m.a == 1 && size(m) == 1").unwrap();
+        assert_eq!(program.execute(&ctx), Ok(Value::Bool(true)));
+    }
+
+    #[cfg(feature = "preserve_order")]
+    #[test]
+    fn test_map_insertion_order() {
+        let program = Program::compile("{'z': 1, 'a': 2, 'm': 3, 5: 4, true: 5}").unwrap();
+        let Value::Map(map) = program.execute(&Context::default()).unwrap() else {
+            panic!("not a map");
+        };
+        let keys: Vec<_> = map.keys().map(ToString::to_string).collect();
+        assert_eq!(keys, vec!["z", "a", "m", "5", "true"]);
+
+        let mut ctx = Context::default();
+        ctx.add_variable_from_value(
+            "m",
+            Value::Map(crate::objects::Map::from_iter([
+                ("c", 1),
+                ("b", 2),
+                ("a", 3),
+            ])),
+        );
+        let program = Program::compile("m.map(k, k + string(m[k]))").unwrap();
+        assert_eq!(
+            program.execute(&ctx).unwrap(),
+            Value::List(Arc::new(vec!["c1".into(), "b2".into(), "a3".into()]))
+        );
+    }
+
     mod opaque {
         use crate::objects::{Map, Opaque, OpaqueVal, OptionalValue};
         use crate::parser::Parser;
@@ -2532,70 +2736,60 @@ mod tests {
                 .enable_optional_syntax(true)
                 .parse(r#"{"a": 1, "b": 2, ?"c": optional.of(3)}"#)
                 .expect("Must parse");
-            let mut expected_map = HashMap::new();
-            expected_map.insert("a".into(), Value::Int(1));
-            expected_map.insert("b".into(), Value::Int(2));
-            expected_map.insert("c".into(), Value::Int(3));
+            let mut expected_map = HashMap::<&str, Value>::new();
+            expected_map.insert("a", Value::Int(1));
+            expected_map.insert("b", Value::Int(2));
+            expected_map.insert("c", Value::Int(3));
             assert_eq!(
                 Value::resolve(&expr, &Context::default()),
-                Ok(Value::Map(Map {
-                    map: Arc::from(expected_map)
-                }))
+                Ok(Value::Map(Map::from(expected_map)))
             );
 
             let expr = Parser::default()
                 .enable_optional_syntax(true)
                 .parse(r#"{"a": 1, "b": 2, ?"c": optional.none()}"#)
                 .expect("Must parse");
-            let mut expected_map = HashMap::new();
-            expected_map.insert("a".into(), Value::Int(1));
-            expected_map.insert("b".into(), Value::Int(2));
+            let mut expected_map = HashMap::<&str, Value>::new();
+            expected_map.insert("a", Value::Int(1));
+            expected_map.insert("b", Value::Int(2));
             assert_eq!(
                 Value::resolve(&expr, &Context::default()),
-                Ok(Value::Map(Map {
-                    map: Arc::from(expected_map)
-                }))
+                Ok(Value::Map(Map::from(expected_map)))
             );
 
             let expr = Parser::default()
                 .enable_optional_syntax(true)
                 .parse(r#"{"a": 1, ?"b": optional.none(), ?"c": optional.of(3)}"#)
                 .expect("Must parse");
-            let mut expected_map = HashMap::new();
-            expected_map.insert("a".into(), Value::Int(1));
-            expected_map.insert("c".into(), Value::Int(3));
+            let mut expected_map = HashMap::<&str, Value>::new();
+            expected_map.insert("a", Value::Int(1));
+            expected_map.insert("c", Value::Int(3));
             assert_eq!(
                 Value::resolve(&expr, &Context::default()),
-                Ok(Value::Map(Map {
-                    map: Arc::from(expected_map)
-                }))
+                Ok(Value::Map(Map::from(expected_map)))
             );
 
             let expr = Parser::default()
                 .enable_optional_syntax(true)
                 .parse(r#"{"a": 1, ?"b": mymap[?"missing"]}"#)
                 .expect("Must parse");
-            let mut expected_map = HashMap::new();
-            expected_map.insert("a".into(), Value::Int(1));
+            let mut expected_map = HashMap::<&str, Value>::new();
+            expected_map.insert("a", Value::Int(1));
             assert_eq!(
                 Value::resolve(&expr, &map_ctx),
-                Ok(Value::Map(Map {
-                    map: Arc::from(expected_map)
-                }))
+                Ok(Value::Map(Map::from(expected_map)))
             );
 
             let expr = Parser::default()
                 .enable_optional_syntax(true)
                 .parse(r#"{"x": 10, ?"y": mymap[?"a"]}"#)
                 .expect("Must parse");
-            let mut expected_map = HashMap::new();
-            expected_map.insert("x".into(), Value::Int(10));
-            expected_map.insert("y".into(), Value::Int(1));
+            let mut expected_map = HashMap::<&str, Value>::new();
+            expected_map.insert("x", Value::Int(10));
+            expected_map.insert("y", Value::Int(1));
             assert_eq!(
                 Value::resolve(&expr, &map_ctx),
-                Ok(Value::Map(Map {
-                    map: Arc::from(expected_map)
-                }))
+                Ok(Value::Map(Map::from(expected_map)))
             );
 
             let expr = Parser::default()
@@ -2604,9 +2798,7 @@ mod tests {
                 .expect("Must parse");
             assert_eq!(
                 Value::resolve(&expr, &Context::default()),
-                Ok(Value::Map(Map {
-                    map: Arc::from(HashMap::new())
-                }))
+                Ok(Value::Map(Map::from(HashMap::<&str, Value>::new())))
             );
         }
     }
@@ -2746,9 +2938,9 @@ mod tests {
             );
 
             let mut context = Context::with_env(Arc::new(env));
-            context
-                .add_variable("my_var", Value::Struct(Arc::new(my_struct)))
-                .unwrap();
+            let my_struct = Value::Struct(Arc::new(my_struct));
+            assert!(context.add_variable("my_var", my_struct.clone()).is_err());
+            context.add_variable_from_value("my_var", my_struct);
 
             let program = Program::compile("has(my_var.name)").unwrap();
             let result = program.execute(&context).unwrap();
@@ -2816,9 +3008,7 @@ mod tests {
             );
 
             let mut context = Context::with_env(Arc::new(env));
-            context
-                .add_variable("my_var", Value::Struct(Arc::new(my_struct)))
-                .unwrap();
+            context.add_variable_from_value("my_var", Value::Struct(Arc::new(my_struct)));
 
             let program = Program::compile("my_var.name + ' ' + string(my_var.value)").unwrap();
             let result = program.execute(&context).unwrap();
@@ -0,0 +1,1069 @@
+//! A hand-written recursive descent parser for the grammar in `gen/CEL.g4`.
+//!
+//! It builds the same [`IdedExpr`] as visiting the tree of the generated parser does: ids are
+//! taken in the order the visitor takes them, for the same tokens, and macros are expanded by
+//! the same [`Parser`]. Syntax errors are reported like the generated parser reports them, but
+//! rather than recovering from them, parsing stops at the first one that can't be recovered
+//! from by skipping a single token.
+//!
+//! Reporting a single error is an intentional limitation: the errors the generated parser
+//! reports after the first one come from its error recovery guessing at the rest of the input,
+//! and matching them would mean reproducing that recovery token for token. The first error is
+//! always one the generated parser reports as well.
+
+use crate::common::ast::{
+    operators, EntryExpr, Expr, IdedEntryExpr, IdedExpr, ListExpr, LiteralValue, MapEntryExpr,
+    MapExpr, SelectExpr, StructExpr, StructFieldExpr,
+};
+use crate::parser::lexer::{escape, Lexer, Token, TokenKind};
+use crate::parser::{parse, ParseError, ParseErrorKind, Parser};
+use std::error::Error;
+
+/// The tokens an expression can start with.
+const EXPR_START: &[TokenKind] = &[
+    TokenKind::LBracket,
+    TokenKind::LBrace,
+    TokenKind::LParen,
+    TokenKind::Dot,
+    TokenKind::Minus,
+    TokenKind::Exclam,
+    TokenKind::True,
+    TokenKind::False,
+    TokenKind::Null,
+    TokenKind::NumFloat,
+    TokenKind::NumInt,
+    TokenKind::NumUint,
+    TokenKind::String,
+    TokenKind::Bytes,
+    TokenKind::Identifier,
+];
+
+/// The tokens that can follow the `[` of a list.
+const LIST_START: &[TokenKind] = &[
+    TokenKind::LBracket,
+    TokenKind::RBracket,
+    TokenKind::LBrace,
+    TokenKind::LParen,
+    TokenKind::Dot,
+    TokenKind::Comma,
+    TokenKind::Minus,
+    TokenKind::Exclam,
+    TokenKind::Question,
+    TokenKind::True,
+    TokenKind::False,
+    TokenKind::Null,
+    TokenKind::NumFloat,
+    TokenKind::NumInt,
+    TokenKind::NumUint,
+    TokenKind::String,
+    TokenKind::Bytes,
+    TokenKind::Identifier,
+];
+
+/// The tokens that can follow the `{` of a map.
+const MAP_START: &[TokenKind] = &[
+    TokenKind::LBracket,
+    TokenKind::LBrace,
+    TokenKind::RBrace,
+    TokenKind::LParen,
+    TokenKind::Dot,
+    TokenKind::Comma,
+    TokenKind::Minus,
+    TokenKind::Exclam,
+    TokenKind::Question,
+    TokenKind::True,
+    TokenKind::False,
+    TokenKind::Null,
+    TokenKind::NumFloat,
+    TokenKind::NumInt,
+    TokenKind::NumUint,
+    TokenKind::String,
+    TokenKind::Bytes,
+    TokenKind::Identifier,
+];
+
+/// The tokens that can follow the `{` of a message.
+const FIELDS_START: &[TokenKind] = &[
+    TokenKind::RBrace,
+    TokenKind::Comma,
+    TokenKind::Question,
+    TokenKind::Identifier,
+    TokenKind::EscIdentifier,
+];
+
+/// The tokens that can follow the `(` of a call.
+const ARGS_START: &[TokenKind] = &[
+    TokenKind::LBracket,
+    TokenKind::LBrace,
+    TokenKind::LParen,
+    TokenKind::RParen,
+    TokenKind::Dot,
+    TokenKind::Minus,
+    TokenKind::Exclam,
+    TokenKind::True,
+    TokenKind::False,
+    TokenKind::Null,
+    TokenKind::NumFloat,
+    TokenKind::NumInt,
+    TokenKind::NumUint,
+    TokenKind::String,
+    TokenKind::Bytes,
+    TokenKind::Identifier,
+];
+
+/// The tokens that can follow a complete expression.
+const EXPR_END: &[TokenKind] = &[
+    TokenKind::Eof,
+    TokenKind::Equals,
+    TokenKind::NotEquals,
+    TokenKind::In,
+    TokenKind::Less,
+    TokenKind::LessEquals,
+    TokenKind::GreaterEquals,
+    TokenKind::Greater,
+    TokenKind::LogicalAnd,
+    TokenKind::LogicalOr,
+    TokenKind::LBracket,
+    TokenKind::Dot,
+    TokenKind::Minus,
+    TokenKind::Question,
+    TokenKind::Plus,
+    TokenKind::Star,
+    TokenKind::Slash,
+    TokenKind::Percent,
+];
+
+const FIELD_NAME: &[TokenKind] = &[TokenKind::Identifier, TokenKind::EscIdentifier];
+
+/// Parses `source` for `parser`, reporting the errors it runs into to it.
+pub(super) fn parse(parser: &mut Parser, source: &str) -> Result<IdedExpr, ParseError> {
+    let mut lexer = Lexer::new(source);
+    let token = lexer.next_token();
+    let mut descent = Descent {
+        parser,
+        source,
+        lexer,
+        token,
+        next: None,
+        depth: 0,
+        errors: Vec::new(),
+    };
+    let expr = descent.start().unwrap_or_default();
+    // Read the rest of the source when parsing stopped early, for its recognition errors.
+    while descent.token.kind != TokenKind::Eof {
+        descent.bump();
+    }
+
+    let mut errors = descent.lexer.errors;
+    errors.append(&mut descent.errors);
+    errors.append(&mut descent.parser.errors);
+    descent.parser.errors = errors;
+    Ok(expr)
+}
+
+/// Parsing stopped at a syntax error, which has been reported.
+struct Stop;
+
+struct Descent<'a> {
+    parser: &'a mut Parser,
+    source: &'a str,
+    lexer: Lexer<'a>,
+    /// The current token.
+    token: Token,
+    /// The token following the current one, once it has been looked at.
+    next: Option<Token>,
+    /// The number of expressions being parsed, nested in one another.
+    depth: u16,
+    /// The syntax errors, which go before those the parser reports for the same position.
+    errors: Vec<ParseError>,
+}
+
+impl<'a> Descent<'a> {
+    fn start(&mut self) -> Result<IdedExpr, Stop> {
+        let expr = self.expr()?;
+        if self.token.kind != TokenKind::Eof {
+            if self.peek() != TokenKind::Eof {
+                return Err(self.mismatched(EXPR_END));
+            }
+            self.extraneous(&[TokenKind::Eof]);
+        }
+        Ok(expr)
+    }
+
+    fn expr(&mut self) -> Result<IdedExpr, Stop> {
+        self.depth += 1;
+        if self.depth > self.parser.max_recursion_depth || self.depth == u16::MAX {
+            let msg = format!(
+                "Recursion limit of {} exceeded",
+                self.parser.max_recursion_depth
+            );
+            self.errors.push(ParseError {
+                source: None,
+                pos: self.token.pos(),
+                msg,
+                expr_id: 0,
+                source_info: None,
+                kind: ParseErrorKind::RecursionLimit,
+            });
+            return Err(Stop);
+        }
+        let condition = self.conditional_or()?;
+        let expr = match self.eat(TokenKind::Question) {
+            None => condition,
+            Some(op) => {
+                let op_id = self.next_id(op);
+                let if_true = self.conditional_or()?;
+                self.expect(TokenKind::Colon)?;
+                let if_false = self.expr()?;
+                self.parser.global_call_or_macro(
+                    op_id,
+                    operators::CONDITIONAL.to_string(),
+                    vec![condition, if_true, if_false],
+                )
+            }
+        };
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn conditional_or(&mut self) -> Result<IdedExpr, Stop> {
+        self.logical(
+            TokenKind::LogicalOr,
+            operators::LOGICAL_OR,
+            Self::conditional_and,
+        )
+    }
+
+    fn conditional_and(&mut self) -> Result<IdedExpr, Stop> {
+        self.logical(
+            TokenKind::LogicalAnd,
+            operators::LOGICAL_AND,
+            Self::relation,
+        )
+    }
+
+    /// Terms joined by the logical operator `op`, which are balanced like the visitor of the
+    /// generated parser balances them.
+    fn logical(
+        &mut self,
+        op: TokenKind,
+        function: &str,
+        term: fn(&mut Self) -> Result<IdedExpr, Stop>,
+    ) -> Result<IdedExpr, Stop> {
+        let first = term(self)?;
+        if self.token.kind != op {
+            return Ok(first);
+        }
+        let mut logic = self.parser.new_logic_manager(function, first);
+        while let Some(op) = self.eat(op) {
+            let next = term(self)?;
+            let op_id = self.next_id(op);
+            logic.add_term(op_id, next);
+        }
+        Ok(logic.expr())
+    }
+
+    fn relation(&mut self) -> Result<IdedExpr, Stop> {
+        let mut lhs = self.addition()?;
+        while matches!(
+            self.token.kind,
+            TokenKind::Less
+                | TokenKind::LessEquals
+                | TokenKind::GreaterEquals
+                | TokenKind::Greater
+                | TokenKind::Equals
+                | TokenKind::NotEquals
+                | TokenKind::In
+        ) {
+            lhs = self.binary(lhs, Self::addition)?;
+        }
+        Ok(lhs)
+    }
+
+    fn addition(&mut self) -> Result<IdedExpr, Stop> {
+        let mut lhs = self.multiplication()?;
+        while matches!(self.token.kind, TokenKind::Plus | TokenKind::Minus) {
+            lhs = self.binary(lhs, Self::multiplication)?;
+        }
+        Ok(lhs)
+    }
+
+    fn multiplication(&mut self) -> Result<IdedExpr, Stop> {
+        let mut lhs = self.unary()?;
+        while matches!(
+            self.token.kind,
+            TokenKind::Star | TokenKind::Slash | TokenKind::Percent
+        ) {
+            lhs = self.binary(lhs, Self::unary)?;
+        }
+        Ok(lhs)
+    }
+
+    /// The binary operator at the current token applied to `lhs` and the operand `rhs` parses.
+    fn binary(
+        &mut self,
+        lhs: IdedExpr,
+        rhs: fn(&mut Self) -> Result<IdedExpr, Stop>,
+    ) -> Result<IdedExpr, Stop> {
+        let op = self.bump();
+        let op_id = self.next_id(op);
+        let rhs = rhs(self)?;
+        Ok(match operators::find_operator(self.text(op)) {
+            None => {
+                let msg = format!("Unknown `{}` operator!", self.text(op));
+                self.report_error(op, None, msg)
+            }
+            Some(function) => {
+                self.parser
+                    .global_call_or_macro(op_id, function.to_string(), vec![lhs, rhs])
+            }
+        })
+    }
+
+    fn unary(&mut self) -> Result<IdedExpr, Stop> {
+        // A minus followed by a number is the sign of a literal.
+        let sign = self.token.kind == TokenKind::Minus
+            && matches!(self.peek(), TokenKind::NumInt | TokenKind::NumFloat);
+        let (kind, function) = match self.token.kind {
+            TokenKind::Exclam => (TokenKind::Exclam, operators::LOGICAL_NOT),
+            TokenKind::Minus if !sign => (TokenKind::Minus, operators::NEGATE),
+            _ => return self.member(),
+        };
+        let op = self.bump();
+        let mut ops = 1;
+        while self.eat(kind).is_some() {
+            ops += 1;
+        }
+        if ops % 2 == 0 {
+            return self.member();
+        }
+        let op_id = self.next_id(op);
+        let operand = self.member()?;
+        Ok(self
+            .parser
+            .global_call_or_macro(op_id, function.to_string(), vec![operand]))
+    }
+
+    fn member(&mut self) -> Result<IdedExpr, Stop> {
+        let mut operand = self.primary()?;
+        loop {
+            operand = match self.token.kind {
+                TokenKind::Dot => self.select_or_call(operand)?,
+                TokenKind::LBracket => self.index(operand)?,
+                _ => return Ok(operand),
+            }
+        }
+    }
+
+    fn select_or_call(&mut self, operand: IdedExpr) -> Result<IdedExpr, Stop> {
+        let op = self.bump();
+        let opt = self.eat(TokenKind::Question);
+        let call = opt.is_none()
+            && self.token.kind == TokenKind::Identifier
+            && self.peek() == TokenKind::LParen;
+        match self.token.kind {
+            TokenKind::Identifier if call => {
+                let name = self.bump();
+                self.member_call(operand, name)
+            }
+            TokenKind::Identifier | TokenKind::EscIdentifier => {
+                let field = self.bump();
+                Ok(self.select(operand, op, field, opt.is_some()))
+            }
+            _ => Err(self.no_viable_alternative(op)),
+        }
+    }
+
+    fn select(&mut self, operand: IdedExpr, op: Token, field: Token, optional: bool) -> IdedExpr {
+        let field = self.text(field).to_string();
+        if !optional {
+            return self.next_expr(
+                op,
+                Expr::Select(SelectExpr {
+                    operand: Box::new(operand),
+                    field,
+                    test: false,
+                }),
+            );
+        }
+        if !self.parser.enable_optional_syntax {
+            return self.report_error(op, None, "unsupported syntax '.?'");
+        }
+        let field_literal = self.next_expr(op, Expr::Literal(LiteralValue::String(field.into())));
+        let op_id = self.next_id(op);
+        self.parser.global_call_or_macro(
+            op_id,
+            operators::OPT_SELECT.to_string(),
+            vec![operand, field_literal],
+        )
+    }
+
+    fn member_call(&mut self, operand: IdedExpr, name: Token) -> Result<IdedExpr, Stop> {
+        let open = self.bump();
+        let op_id = self.next_id(open);
+        let args = self.args()?;
+        let name = self.text(name).to_string();
+        Ok(self
+            .parser
+            .receiver_call_or_macro(op_id, name, operand, args))
+    }
+
+    fn index(&mut self, operand: IdedExpr) -> Result<IdedExpr, Stop> {
+        let op = self.bump();
+        let opt = self.eat(TokenKind::Question);
+        let op_id = self.next_id(op);
+        let index = self.expr()?;
+        self.expect_closing(TokenKind::RBracket)?;
+        let function = match opt {
+            None => operators::INDEX,
+            Some(_) if self.parser.enable_optional_syntax => operators::OPT_INDEX,
+            Some(_) => return Ok(self.report_error(op, None, "unsupported syntax '[?'")),
+        };
+        Ok(self
+            .parser
+            .global_call_or_macro(op_id, function.to_string(), vec![operand, index]))
+    }
+
+    fn primary(&mut self) -> Result<IdedExpr, Stop> {
+        match self.token.kind {
+            TokenKind::Dot | TokenKind::Identifier => self.identifier(),
+            TokenKind::LParen => {
+                self.bump();
+                let expr = self.expr()?;
+                self.expect_closing(TokenKind::RParen)?;
+                Ok(expr)
+            }
+            TokenKind::LBracket => self.list(),
+            TokenKind::LBrace => self.map(),
+            TokenKind::Minus => {
+                let sign = self.bump();
+                if !matches!(self.token.kind, TokenKind::NumInt | TokenKind::NumFloat) {
+                    return Err(self.no_viable_alternative(sign));
+                }
+                Ok(self.literal(Some(sign)))
+            }
+            TokenKind::NumInt
+            | TokenKind::NumUint
+            | TokenKind::NumFloat
+            | TokenKind::String
+            | TokenKind::Bytes
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Null => Ok(self.literal(None)),
+            _ => {
+                self.unexpected(EXPR_START)?;
+                self.primary()
+            }
+        }
+    }
+
+    /// An identifier, a call to a global function, or the name of a message along with its
+    /// fields. The grammar allows a message name to be qualified, so the identifier is followed
+    /// by as many of the segments of a qualified name as there are, and they turn out to be
+    /// field selections when the name isn't followed by the fields of a message.
+    fn identifier(&mut self) -> Result<IdedExpr, Stop> {
+        let leading_dot = self.eat(TokenKind::Dot);
+        if self.token.kind != TokenKind::Identifier {
+            let dot = leading_dot.expect("an identifier or a dot");
+            return Err(self.no_viable_alternative(dot));
+        }
+        let ident = self.bump();
+
+        if self.token.kind == TokenKind::LParen {
+            let open = self.bump();
+            let op_id = self.next_id(open);
+            let mut name = self.text(ident).to_string();
+            if leading_dot.is_some() {
+                name = format!(".{name}");
+            }
+            let args = self.args()?;
+            return Ok(self.parser.global_call_or_macro(op_id, name, args));
+        }
+
+        let mut segments = Vec::new();
+        while self.token.kind == TokenKind::Dot && self.peek() == TokenKind::Identifier {
+            let dot = self.bump();
+            segments.push((dot, self.bump()));
+        }
+        if self.token.kind == TokenKind::LBrace {
+            return self.message(leading_dot.is_some(), ident, &segments);
+        }
+
+        let call = if self.token.kind == TokenKind::LParen {
+            segments.pop()
+        } else {
+            None
+        };
+        let name = self.text(ident).to_string();
+        let mut expr = self.next_expr(ident, Expr::Ident(name));
+        for (dot, field) in segments {
+            expr = self.select(expr, dot, field, false);
+        }
+        match call {
+            None => Ok(expr),
+            Some((_, name)) => self.member_call(expr, name),
+        }
+    }
+
+    /// The arguments of a call, following its `(`.
+    fn args(&mut self) -> Result<Vec<IdedExpr>, Stop> {
+        if !ARGS_START.contains(&self.token.kind) {
+            self.unexpected(ARGS_START)?;
+        }
+        let mut args = Vec::new();
+        if self.token.kind != TokenKind::RParen {
+            args.push(self.expr()?);
+            while self.eat(TokenKind::Comma).is_some() {
+                args.push(self.expr()?);
+            }
+        }
+        self.expect_closing(TokenKind::RParen)?;
+        Ok(args)
+    }
+
+    fn list(&mut self) -> Result<IdedExpr, Stop> {
+        let open = self.bump();
+        let list_id = self.next_id(open);
+        let elements = self.entries(TokenKind::RBracket, LIST_START, Self::list_element)?;
+        let mut list = Vec::with_capacity(elements.len());
+        let mut optionals = Vec::new();
+        for (i, element) in elements.into_iter().enumerate() {
+            if let Some((element, optional)) = element {
+                if optional {
+                    optionals.push(i);
+                }
+                list.push(element);
+            }
+        }
+        Ok(IdedExpr {
+            id: list_id,
+            expr: Expr::List(ListExpr::new_with_optionals(list, optionals)),
+        })
+    }
+
+    fn list_element(&mut self) -> Result<Option<(IdedExpr, bool)>, Stop> {
+        let opt = self.eat(TokenKind::Question);
+        if let Some(opt) = opt {
+            if !self.parser.enable_optional_syntax {
+                self.report_error(opt, None, "unsupported syntax '?'");
+                self.expr()?;
+                return Ok(None);
+            }
+        }
+        Ok(Some((self.expr()?, opt.is_some())))
+    }
+
+    fn map(&mut self) -> Result<IdedExpr, Stop> {
+        let open = self.bump();
+        let struct_id = self.next_id(open);
+        let entries = self.entries(TokenKind::RBrace, MAP_START, Self::map_entry)?;
+        Ok(IdedExpr {
+            id: struct_id,
+            expr: Expr::Map(MapExpr {
+                entries: entries.into_iter().flatten().collect(),
+            }),
+        })
+    }
+
+    fn map_entry(&mut self) -> Result<Option<IdedEntryExpr>, Stop> {
+        // The entry is identified by its colon, but takes its id ahead of its key.
+        let id = self.parser.helper.reserve_id();
+        let opt = self.eat(TokenKind::Question);
+        let key = self.expr()?;
+        let colon = self.expect(TokenKind::Colon)?;
+        self.parser
+            .helper
+            .set_offsets(id, colon.start as u32, colon.stop() as u32);
+        if let Some(opt) = opt {
+            if !self.parser.enable_optional_syntax {
+                self.report_error(opt, None, "unsupported syntax '?'");
+                self.expr()?;
+                return Ok(None);
+            }
+        }
+        let value = self.expr()?;
+        Ok(Some(IdedEntryExpr {
+            id,
+            expr: EntryExpr::MapEntry(MapEntryExpr {
+                key,
+                value,
+                optional: opt.is_some(),
+            }),
+        }))
+    }
+
+    fn message(
+        &mut self,
+        leading_dot: bool,
+        ident: Token,
+        segments: &[(Token, Token)],
+    ) -> Result<IdedExpr, Stop> {
+        let mut type_name = String::new();
+        if leading_dot {
+            type_name.push('.');
+        }
+        type_name.push_str(self.text(ident));
+        for (_, segment) in segments {
+            type_name.push('.');
+            type_name.push_str(self.text(*segment));
+        }
+        let open = self.bump();
+        let op_id = self.next_id(open);
+        let entries = self.entries(TokenKind::RBrace, FIELDS_START, Self::field)?;
+        Ok(IdedExpr {
+            id: op_id,
+            expr: Expr::Struct(StructExpr {
+                type_name,
+                entries: entries.into_iter().flatten().collect(),
+            }),
+        })
+    }
+
+    fn field(&mut self) -> Result<Option<IdedEntryExpr>, Stop> {
+        let id = self.parser.helper.reserve_id();
+        let opt = self.eat(TokenKind::Question);
+        if !FIELD_NAME.contains(&self.token.kind) {
+            self.unexpected(FIELD_NAME)?;
+        }
+        let field = self.bump();
+        let colon = self.expect(TokenKind::Colon)?;
+        self.parser
+            .helper
+            .set_offsets(id, colon.start as u32, colon.stop() as u32);
+        let value = self.expr()?;
+        if let Some(opt) = opt {
+            if !self.parser.enable_optional_syntax {
+                self.report_error(opt, None, "unsupported syntax '?'");
+                return Ok(None);
+            }
+        }
+        Ok(Some(IdedEntryExpr {
+            id,
+            expr: EntryExpr::StructField(StructFieldExpr {
+                field: self.text(field).to_string(),
+                value,
+                optional: opt.is_some(),
+            }),
+        }))
+    }
+
+    /// The comma separated entries of a list, map or message, up to the `close` token ending
+    /// it, which may follow a trailing comma.
+    fn entries<T>(
+        &mut self,
+        close: TokenKind,
+        start: &[TokenKind],
+        entry: fn(&mut Self) -> Result<T, Stop>,
+    ) -> Result<Vec<T>, Stop> {
+        if !start.contains(&self.token.kind) {
+            self.unexpected(start)?;
+        }
+        let mut entries = Vec::new();
+        if self.token.kind != close && self.token.kind != TokenKind::Comma {
+            loop {
+                entries.push(entry(self)?);
+                if self.token.kind != TokenKind::Comma || self.peek() == close {
+                    break;
+                }
+                self.bump();
+            }
+        }
+        if self.token.kind != close && self.token.kind != TokenKind::Comma {
+            self.unexpected(&[close, TokenKind::Comma])?;
+        }
+        self.eat(TokenKind::Comma);
+        self.expect(close)?;
+        Ok(entries)
+    }
+
+    fn literal(&mut self, sign: Option<Token>) -> IdedExpr {
+        let token = self.bump();
+        let text = self.text(token);
+        let signed = || match sign {
+            None => text.to_string(),
+            Some(_) => format!("-{text}"),
+        };
+        let literal = match token.kind {
+            TokenKind::NumInt => {
+                let string = signed();
+                match if let Some(string) = string.strip_prefix("0x") {
+                    i64::from_str_radix(string, 16)
+                } else {
+                    string.parse::<i64>()
+                } {
+                    Ok(v) => LiteralValue::Int(v.into()),
+                    Err(e) => {
+                        return self.report_error(token, Some(Box::new(e)), "invalid int literal")
+                    }
+                }
+            }
+            TokenKind::NumUint => {
+                let string = &text[..text.len() - 1];
+                match if let Some(string) = string.strip_prefix("0x") {
+                    u64::from_str_radix(string, 16)
+                } else {
+                    string.parse::<u64>()
+                } {
+                    Ok(v) => LiteralValue::UInt(v.into()),
+                    Err(e) => {
+                        return self.report_error(token, Some(Box::new(e)), "invalid uint literal")
+                    }
+                }
+            }
+            TokenKind::NumFloat => match signed().parse::<f64>() {
+                Ok(d) if d.is_finite() => LiteralValue::Double(d.into()),
+                Err(e) => {
+                    return self.report_error(token, Some(Box::new(e)), "invalid double literal")
+                }
+                _ => return self.report_error(token, None, "invalid double literal"),
+            },
+            TokenKind::String => match parse::parse_string(text) {
+                Ok(string) => LiteralValue::String(string.into()),
+                Err(e) => {
+                    let msg = format!("invalid string literal: {e:?}");
+                    return self.report_error(token, None, msg);
+                }
+            },
+            TokenKind::Bytes => match parse::parse_bytes(&text[2..text.len() - 1]) {
+                Ok(bytes) => LiteralValue::Bytes(bytes.into()),
+                Err(e) => {
+                    let msg = format!("invalid bytes literal: {e:?}");
+                    return self.report_error(token, None, msg);
+                }
+            },
+            TokenKind::True => LiteralValue::Boolean(true.into()),
+            TokenKind::False => LiteralValue::Boolean(false.into()),
+            _ => LiteralValue::Null,
+        };
+        self.next_expr(token, Expr::Literal(literal))
+    }
+
+    fn text(&self, token: Token) -> &'a str {
+        &self.source[token.start..token.end]
+    }
+
+    fn next_id(&mut self, token: Token) -> u64 {
+        self.parser
+            .helper
+            .next_id_at(token.start as u32, token.stop() as u32)
+    }
+
+    fn next_expr(&mut self, token: Token, expr: Expr) -> IdedExpr {
+        IdedExpr {
+            id: self.next_id(token),
+            expr,
+        }
+    }
+
+    /// Moves on to the next token, returning the current one.
+    fn bump(&mut self) -> Token {
+        let next = match self.next.take() {
+            Some(next) => next,
+            None => self.lexer.next_token(),
+        };
+        std::mem::replace(&mut self.token, next)
+    }
+
+    /// The kind of the token following the current one.
+    fn peek(&mut self) -> TokenKind {
+        self.next
+            .get_or_insert_with(|| self.lexer.next_token())
+            .kind
+    }
+
+    fn eat(&mut self, kind: TokenKind) -> Option<Token> {
+        (self.token.kind == kind).then(|| self.bump())
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Result<Token, Stop> {
+        if self.token.kind != kind {
+            self.unexpected(&[kind])?;
+        }
+        Ok(self.bump())
+    }
+
+    /// Expects the token closing a parenthesized expression, an index or a call, which is
+    /// reported missing when the current token could follow it.
+    fn expect_closing(&mut self, kind: TokenKind) -> Result<Token, Stop> {
+        let token = self.token;
+        if token.kind != kind
+            && self.peek() != kind
+            && (EXPR_END.contains(&token.kind)
+                || matches!(
+                    token.kind,
+                    TokenKind::RParen
+                        | TokenKind::RBracket
+                        | TokenKind::RBrace
+                        | TokenKind::Comma
+                        | TokenKind::Colon
+                ))
+        {
+            let msg = format!("missing {} at {}", kind.name(), self.display(token));
+            self.syntax_error(token, msg);
+            return Err(Stop);
+        }
+        self.expect(kind)
+    }
+
+    /// Reports that the current token isn't one of `expected`. When the token following it is,
+    /// the current token is skipped, otherwise parsing stops.
+    fn unexpected(&mut self, expected: &[TokenKind]) -> Result<(), Stop> {
+        if self.token.kind != TokenKind::Eof && expected.contains(&self.peek()) {
+            self.extraneous(expected);
+            Ok(())
+        } else {
+            Err(self.mismatched(expected))
+        }
+    }
+
+    fn extraneous(&mut self, expected: &[TokenKind]) {
+        let token = self.bump();
+        let msg = format!(
+            "extraneous input {} expecting {}",
+            self.display(token),
+            expecting(expected)
+        );
+        self.syntax_error(token, msg);
+    }
+
+    fn mismatched(&mut self, expected: &[TokenKind]) -> Stop {
+        let token = self.token;
+        let msg = format!(
+            "mismatched input {} expecting {}",
+            self.display(token),
+            expecting(expected)
+        );
+        self.syntax_error(token, msg);
+        Stop
+    }
+
+    /// Reports that none of the alternatives of the grammar starting at `start` match up to the
+    /// current token.
+    fn no_viable_alternative(&mut self, start: Token) -> Stop {
+        let token = self.token;
+        let end = match token.kind {
+            TokenKind::Eof => start.end,
+            _ => token.end,
+        };
+        let msg = format!(
+            "no viable alternative at input '{}'",
+            escape(&self.source[start.start..end])
+        );
+        self.syntax_error(token, msg);
+        Stop
+    }
+
+    fn syntax_error(&mut self, token: Token, msg: String) {
+        self.errors.push(ParseError {
+            source: None,
+            pos: token.pos(),
+            msg: format!("Syntax error: {msg}"),
+            expr_id: 0,
+            source_info: None,
+            kind: ParseErrorKind::Syntax,
+        });
+    }
+
+    fn display(&self, token: Token) -> String {
+        match token.kind {
+            TokenKind::Eof => "'<EOF>'".to_string(),
+            _ => format!("'{}'", escape(self.text(token))),
+        }
+    }
+
+    /// Reports an error the visitor of the generated parser reports, for the expression at
+    /// `token`.
+    fn report_error(
+        &mut self,
+        token: Token,
+        source: Option<Box<dyn Error + Send + Sync + 'static>>,
+        msg: impl Into<String>,
+    ) -> IdedExpr {
+        let expr = self.next_expr(token, Expr::default());
+        self.parser.errors.push(ParseError {
+            source,
+            pos: token.pos(),
+            msg: msg.into(),
+            expr_id: expr.id,
+            source_info: None,
+            kind: ParseErrorKind::InvalidExpression,
+        });
+        expr
+    }
+}
+
+/// The tokens a syntax error expected.
+fn expecting(expected: &[TokenKind]) -> String {
+    match expected {
+        [kind] => kind.name().to_string(),
+        _ => {
+            let names: Vec<_> = expected.iter().map(|kind| kind.name()).collect();
+            format!("{{{}}}", names.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::ast::SourceInfo;
+    use crate::parser::ParseErrors;
+    use std::sync::Arc;
+
+    type Parsed = Result<(IdedExpr, Arc<SourceInfo>), ParseErrors>;
+
+    fn parser(source: &str) -> Parser {
+        Parser::new()
+            .enable_optional_syntax(!source.contains("@no_optional"))
+            .populate_macro_calls(true)
+    }
+
+    fn both(source: &str) -> (Parsed, Parsed) {
+        (
+            parser(source).parse_with(source, Parser::parse_antlr),
+            parser(source).parse_with(source, parse),
+        )
+    }
+
+    fn offsets(info: &SourceInfo) -> Vec<(u64, (u32, u32))> {
+        (0..1000)
+            .filter_map(|id| info.offset_for(id).map(|offsets| (id, offsets)))
+            .collect()
+    }
+
+    fn errors(errors: &ParseErrors) -> Vec<((isize, isize), String)> {
+        errors
+            .errors
+            .iter()
+            .map(|e| (e.pos, e.msg.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn same_as_generated_parser() {
+        let sources = [
+            "1 + 2 * 3 - 4 / 5 % 6",
+            "a < b == c != d >= e > f <= g in h",
+            "a || b && c || d && e || f",
+            "a ? (b ? c : d) : e ? f : g",
+            "!a && !!b && !!!c",
+            "-a + --b - ---c + -1 - --1 + - 2.5 + -1u",
+            "a.b.c.d(e).f[g][h].i()",
+            ".a.b(c) + .f(g) + .pkg.Msg{field: 1}",
+            "pkg.sub.Msg{a: 1, ?b: c, `c.d`: 2,} + Msg{} + Msg{,}",
+            "{'a': 1, ?'b': x, 2: [3, ?y, [], [,]]} + {} + {,}",
+            "[1, 2u, 3.5, -4, 0x10, 0x10u, 1e3, .5, 2.5e-3, true, false, null]",
+            r#"['a', "b", '''c''', """d
+e""", r'\n', R"\t", b'f', B"g", br'\x', '\x41é\101\'']"#,
+            "'éé' + x + '🦀' + y",
+            "a.?b.?c[?d][e]",
+            "has(a.b) && [1, 2].all(x, x > 0) && [1].exists(x, x == 1)",
+            "[1, 2].exists_one(x, x > 1) && [1].map(x, x * 2) == [1].filter(x, x > 0)",
+            "[1].map(x, x > 0, x + 1) + {'a': 1}.map(k, k)",
+            "[[1].all(x, [x].exists(y, y == x))].all(z, z)",
+            "a.b.c",
+            "a.`b c`",
+            "size(a) + int(b) + type(c)",
+            "// leading comment\n  a // trailing comment\n  + b",
+            "\ta\r\n\x0C+\n\n   b",
+            "0xFFFFFFFFFFFFFFFFF",
+            "0xFFFFFFFFFFFFFFFFFu",
+            "1.99e90000009",
+            "-0x10",
+            "'\\q'",
+            "has(m)",
+            "1.all(2, 3)",
+            "{",
+            "*@a | b",
+            "a | b",
+            "",
+            "\n",
+            "1 + ()",
+            "x(1,)",
+            ".",
+            "a.",
+            "@foo",
+            "a.b(",
+            "(1",
+            "[1",
+            "{1:2",
+            "f(1",
+            "a[1",
+            "Msg{",
+            "Msg{a",
+            "Msg{a:",
+            "Msg{a:1",
+            "a )",
+            "a b",
+            "a.1",
+            "`a`",
+            "a =b",
+            "\"abc",
+            "'''abc",
+            "b\"x",
+            "1.e5",
+            "a.?b && a[?b] @no_optional",
+            "[?a, ?b] @no_optional",
+            "Msg{?field: value} && {?'key': value} @no_optional",
+        ];
+        for source in sources {
+            match both(source) {
+                (Ok((generated, generated_info)), Ok((expr, info))) => {
+                    assert_eq!(expr, generated, "`{source}`");
+                    assert_eq!(offsets(&info), offsets(&generated_info), "`{source}`");
+                    assert_eq!(
+                        info.macro_calls().collect::<Vec<_>>(),
+                        generated_info.macro_calls().collect::<Vec<_>>(),
+                        "`{source}`"
+                    );
+                }
+                (Err(generated), Err(errs)) => {
+                    assert_eq!(errors(&errs), errors(&generated), "`{source}`");
+                }
+                (generated, parsed) => panic!("`{source}`: {generated:?} != {parsed:?}"),
+            }
+        }
+    }
+
+    /// Pins the intentional difference from the generated parser: only the first syntax error
+    /// is reported, and it is one of the errors the generated parser reports.
+    #[test]
+    fn stops_at_first_syntax_error() {
+        for source in [
+            "/", "a b c", "{1:2, 3", "a ? b", "!-x", "(1 2 3)", "[1 2 3]",
+        ] {
+            let (generated, parsed) = both(source);
+            let generated = generated.unwrap_err();
+            let parsed = parsed.unwrap_err();
+            assert_eq!(parsed.errors.len(), 1, "`{source}`: {parsed}");
+            assert!(
+                generated
+                    .errors
+                    .iter()
+                    .any(|e| e.pos == parsed.errors[0].pos),
+                "`{source}`: {parsed} not in {generated}"
+            );
+        }
+    }
+
+    #[test]
+    fn recursion_limit() {
+        let parse = |depth, source: &str| {
+            Parser::new()
+                .max_recursion_depth(depth)
+                .parse_with(source, parse)
+        };
+        assert!(parse(3, "[[[1]]]").is_ok());
+        let err = parse(2, "[[[1]]]").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERROR: <input>:1:4: Recursion limit of 3 exceeded\n| [[[1]]]\n| ...^"
+        );
+        assert_eq!(err.errors[0].kind, ParseErrorKind::RecursionLimit);
+
+        for source in ["a ? b : c ? d : e", "f(g(1), [2])"] {
+            assert!(parse(2, source).is_ok(), "`{source}`");
+            assert!(parse(1, source).is_err(), "`{source}`");
+        }
+    }
+}
@@ -0,0 +1,560 @@
+//! Splits CEL source into the tokens of the grammar in `gen/CEL.g4`, for the hand-written
+//! parser. Tokens are matched like the lexer generated from the grammar matches them: the
+//! longest token wins, and characters that don't start any token are reported and skipped.
+
+use crate::parser::{ParseError, ParseErrorKind};
+use std::borrow::Cow;
+
+/// The kinds of tokens, in the order of the token types of the grammar, which is the order
+/// the generated parser lists expected tokens in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum TokenKind {
+    Eof,
+    Equals,
+    NotEquals,
+    In,
+    Less,
+    LessEquals,
+    GreaterEquals,
+    Greater,
+    LogicalAnd,
+    LogicalOr,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    Dot,
+    Comma,
+    Minus,
+    Exclam,
+    Question,
+    Colon,
+    Plus,
+    Star,
+    Slash,
+    Percent,
+    True,
+    False,
+    Null,
+    NumFloat,
+    NumInt,
+    NumUint,
+    String,
+    Bytes,
+    Identifier,
+    EscIdentifier,
+}
+
+impl TokenKind {
+    /// The name of the kind of token in syntax errors.
+    pub(super) fn name(self) -> &'static str {
+        match self {
+            TokenKind::Eof => "<EOF>",
+            TokenKind::Equals => "'=='",
+            TokenKind::NotEquals => "'!='",
+            TokenKind::In => "'in'",
+            TokenKind::Less => "'<'",
+            TokenKind::LessEquals => "'<='",
+            TokenKind::GreaterEquals => "'>='",
+            TokenKind::Greater => "'>'",
+            TokenKind::LogicalAnd => "'&&'",
+            TokenKind::LogicalOr => "'||'",
+            TokenKind::LBracket => "'['",
+            TokenKind::RBracket => "']'",
+            TokenKind::LBrace => "'{'",
+            TokenKind::RBrace => "'}'",
+            TokenKind::LParen => "'('",
+            TokenKind::RParen => "')'",
+            TokenKind::Dot => "'.'",
+            TokenKind::Comma => "','",
+            TokenKind::Minus => "'-'",
+            TokenKind::Exclam => "'!'",
+            TokenKind::Question => "'?'",
+            TokenKind::Colon => "':'",
+            TokenKind::Plus => "'+'",
+            TokenKind::Star => "'*'",
+            TokenKind::Slash => "'/'",
+            TokenKind::Percent => "'%'",
+            TokenKind::True => "'true'",
+            TokenKind::False => "'false'",
+            TokenKind::Null => "'null'",
+            TokenKind::NumFloat => "NUM_FLOAT",
+            TokenKind::NumInt => "NUM_INT",
+            TokenKind::NumUint => "NUM_UINT",
+            TokenKind::String => "STRING",
+            TokenKind::Bytes => "BYTES",
+            TokenKind::Identifier => "IDENTIFIER",
+            TokenKind::EscIdentifier => "ESC_IDENTIFIER",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Token {
+    pub kind: TokenKind,
+    /// The byte offset of the token in the source.
+    pub start: usize,
+    /// The byte offset following the token.
+    pub end: usize,
+    pub line: isize,
+    /// The number of characters preceding the token on its line.
+    pub column: isize,
+}
+
+impl Token {
+    /// The offset of the last byte of the token, which the [`SourceInfo`] records along with
+    /// its start.
+    ///
+    /// [`SourceInfo`]: crate::common::ast::SourceInfo
+    pub(super) fn stop(&self) -> usize {
+        self.end.saturating_sub(1)
+    }
+
+    /// The position of the token in errors.
+    pub(super) fn pos(&self) -> (isize, isize) {
+        (self.line, self.column + 1)
+    }
+}
+
+pub(super) struct Lexer<'a> {
+    source: &'a str,
+    offset: usize,
+    line: isize,
+    column: isize,
+    /// The errors for the characters that were skipped because they don't start a token.
+    pub(super) errors: Vec<ParseError>,
+}
+
+impl<'a> Lexer<'a> {
+    pub(super) fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            offset: 0,
+            line: 1,
+            column: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// The next token, or [`TokenKind::Eof`] once all of the source has been read.
+    pub(super) fn next_token(&mut self) -> Token {
+        loop {
+            self.skip_hidden();
+            let start = self.offset;
+            let (line, column) = (self.line, self.column);
+            match self.scan(start) {
+                Ok((kind, end)) => {
+                    self.advance_to(end);
+                    return Token {
+                        kind,
+                        start,
+                        end,
+                        line,
+                        column,
+                    };
+                }
+                Err(failed) => {
+                    // Like the generated lexer, skip everything up to and including the
+                    // character the token failed to match at.
+                    let end = self.source[failed..]
+                        .chars()
+                        .next()
+                        .map_or(failed, |c| failed + c.len_utf8());
+                    self.errors.push(ParseError {
+                        source: None,
+                        pos: (line, column + 1),
+                        msg: format!(
+                            "Syntax error: token recognition error at: '{}'",
+                            &self.source[start..end]
+                        ),
+                        expr_id: 0,
+                        source_info: None,
+                        kind: ParseErrorKind::Syntax,
+                    });
+                    self.advance_to(end);
+                }
+            }
+        }
+    }
+
+    fn at(&self, offset: usize) -> Option<u8> {
+        self.source.as_bytes().get(offset).copied()
+    }
+
+    fn advance_to(&mut self, end: usize) {
+        for c in self.source[self.offset..end].chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.column = 0;
+            } else {
+                self.column += 1;
+            }
+        }
+        self.offset = end;
+    }
+
+    /// Skips whitespace and comments.
+    fn skip_hidden(&mut self) {
+        let mut end = self.offset;
+        loop {
+            match self.at(end) {
+                Some(b'\t' | b'\n' | b'\x0C' | b'\r' | b' ') => end += 1,
+                Some(b'/') if self.at(end + 1) == Some(b'/') => {
+                    end = self.source[end..]
+                        .find('\n')
+                        .map_or(self.source.len(), |newline| end + newline)
+                }
+                _ => break,
+            }
+        }
+        self.advance_to(end);
+    }
+
+    /// Matches the longest token at `start`, returning its kind and end, or the offset of the
+    /// character no token starting at `start` matches.
+    fn scan(&self, start: usize) -> Result<(TokenKind, usize), usize> {
+        let Some(c) = self.at(start) else {
+            return Ok((TokenKind::Eof, start));
+        };
+        let kind = match c {
+            b'[' => TokenKind::LBracket,
+            b']' => TokenKind::RBracket,
+            b'{' => TokenKind::LBrace,
+            b'}' => TokenKind::RBrace,
+            b'(' => TokenKind::LParen,
+            b')' => TokenKind::RParen,
+            b',' => TokenKind::Comma,
+            b'-' => TokenKind::Minus,
+            b'?' => TokenKind::Question,
+            b':' => TokenKind::Colon,
+            b'+' => TokenKind::Plus,
+            b'*' => TokenKind::Star,
+            b'/' => TokenKind::Slash,
+            b'%' => TokenKind::Percent,
+            b'=' | b'&' | b'|' => {
+                if self.at(start + 1) != Some(c) {
+                    return Err(start + 1);
+                }
+                let kind = match c {
+                    b'=' => TokenKind::Equals,
+                    b'&' => TokenKind::LogicalAnd,
+                    _ => TokenKind::LogicalOr,
+                };
+                return Ok((kind, start + 2));
+            }
+            b'!' | b'<' | b'>' => {
+                let equals = self.at(start + 1) == Some(b'=');
+                let kind = match (c, equals) {
+                    (b'!', false) => TokenKind::Exclam,
+                    (b'!', true) => TokenKind::NotEquals,
+                    (b'<', false) => TokenKind::Less,
+                    (b'<', true) => TokenKind::LessEquals,
+                    (_, false) => TokenKind::Greater,
+                    (_, true) => TokenKind::GreaterEquals,
+                };
+                return Ok((kind, start + 1 + usize::from(equals)));
+            }
+            b'.' if self.at(start + 1).is_some_and(|c| c.is_ascii_digit()) => {
+                return Ok(self.number(start))
+            }
+            b'.' => TokenKind::Dot,
+            b'0'..=b'9' => return Ok(self.number(start)),
+            b'"' | b'\'' => {
+                return match self.quoted(start, false) {
+                    Ok(end) | Err((_, Some(end))) => Ok((TokenKind::String, end)),
+                    Err((failed, None)) => Err(failed),
+                }
+            }
+            b'`' => return self.escaped_identifier(start),
+            b'r' | b'R' if self.is_quote(start + 1) => {
+                return Ok(self.prefixed(start, start + 1, true, TokenKind::String))
+            }
+            b'b' | b'B' if self.is_quote(start + 1) => {
+                return Ok(self.prefixed(start, start + 1, false, TokenKind::Bytes))
+            }
+            b'b' | b'B'
+                if matches!(self.at(start + 1), Some(b'r' | b'R')) && self.is_quote(start + 2) =>
+            {
+                return Ok(self.prefixed(start, start + 2, true, TokenKind::Bytes))
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' => return Ok(self.identifier(start)),
+            _ => return Err(start),
+        };
+        Ok((kind, start + 1))
+    }
+
+    fn is_quote(&self, offset: usize) -> bool {
+        matches!(self.at(offset), Some(b'"' | b'\''))
+    }
+
+    fn identifier(&self, start: usize) -> (TokenKind, usize) {
+        let end = self.source[start..]
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .map_or(self.source.len(), |len| start + len);
+        let kind = match &self.source[start..end] {
+            "in" => TokenKind::In,
+            "true" => TokenKind::True,
+            "false" => TokenKind::False,
+            "null" => TokenKind::Null,
+            _ => TokenKind::Identifier,
+        };
+        (kind, end)
+    }
+
+    fn escaped_identifier(&self, start: usize) -> Result<(TokenKind, usize), usize> {
+        let end = self.source[start + 1..]
+            .find(|c: char| !c.is_ascii_alphanumeric() && !"_.-/ ".contains(c))
+            .map_or(self.source.len(), |len| start + 1 + len);
+        if end > start + 1 && self.at(end) == Some(b'`') {
+            Ok((TokenKind::EscIdentifier, end + 1))
+        } else {
+            Err(end)
+        }
+    }
+
+    /// A string literal with the prefix from `start` to `quote`. If it doesn't match, its
+    /// prefix is an identifier.
+    fn prefixed(
+        &self,
+        start: usize,
+        quote: usize,
+        raw: bool,
+        kind: TokenKind,
+    ) -> (TokenKind, usize) {
+        match self.quoted(quote, raw) {
+            Ok(end) | Err((_, Some(end))) => (kind, end),
+            Err((_, None)) => self.identifier(start),
+        }
+    }
+
+    /// Matches the quoted part of a string literal starting at `start`, returning its end, or
+    /// the offset of the character it failed to match at along with the end of the empty
+    /// string literal it started with, if any.
+    fn quoted(&self, start: usize, raw: bool) -> Result<usize, (usize, Option<usize>)> {
+        let quote = self.source.as_bytes()[start];
+        if self.at(start + 1) == Some(quote) {
+            if self.at(start + 2) != Some(quote) {
+                return Ok(start + 2);
+            }
+            let closing = [quote; 3];
+            let mut offset = start + 3;
+            return loop {
+                match self.at(offset) {
+                    None => break Err((offset, Some(start + 2))),
+                    Some(_) if self.source.as_bytes()[offset..].starts_with(&closing) => {
+                        break Ok(offset + 3)
+                    }
+                    Some(b'\\') if !raw => match self.escape(offset) {
+                        Ok(end) => offset = end,
+                        Err(failed) => break Err((failed, Some(start + 2))),
+                    },
+                    Some(_) => offset += 1,
+                }
+            };
+        }
+        let mut offset = start + 1;
+        loop {
+            match self.at(offset) {
+                None | Some(b'\n' | b'\r') => return Err((offset, None)),
+                Some(c) if c == quote => return Ok(offset + 1),
+                Some(b'\\') if !raw => {
+                    offset = self.escape(offset).map_err(|failed| (failed, None))?
+                }
+                Some(_) => offset += 1,
+            }
+        }
+    }
+
+    /// Matches the escape sequence starting with the backslash at `start`.
+    fn escape(&self, start: usize) -> Result<usize, usize> {
+        let digits = |from: usize, count: usize, valid: fn(&u8) -> bool| {
+            (from..from + count)
+                .find(|&offset| !self.at(offset).as_ref().is_some_and(valid))
+                .map_or(Ok(from + count), Err)
+        };
+        let offset = start + 1;
+        match self.at(offset) {
+            Some(
+                b'a' | b'b' | b'f' | b'n' | b'r' | b't' | b'v' | b'"' | b'\'' | b'\\' | b'?' | b'`',
+            ) => Ok(offset + 1),
+            Some(b'0'..=b'3') => digits(offset + 1, 2, |c| (b'0'..=b'7').contains(c)),
+            Some(b'x' | b'X') => digits(offset + 1, 2, u8::is_ascii_hexdigit),
+            Some(b'u') => digits(offset + 1, 4, u8::is_ascii_hexdigit),
+            Some(b'U') => digits(offset + 1, 8, u8::is_ascii_hexdigit),
+            _ => Err(offset),
+        }
+    }
+
+    /// Matches a number at `start`, which is a digit or a dot followed by one.
+    fn number(&self, start: usize) -> (TokenKind, usize) {
+        let digits = |from: usize| {
+            self.source[from..]
+                .find(|c: char| !c.is_ascii_digit())
+                .map_or(self.source.len(), |len| from + len)
+        };
+        let float = |from: usize| (TokenKind::NumFloat, self.exponent(from).unwrap_or(from));
+        if self.at(start) == Some(b'.') {
+            return float(digits(start + 1));
+        }
+        if self.source[start..].starts_with("0x")
+            && self.at(start + 2).is_some_and(|c| c.is_ascii_hexdigit())
+        {
+            let end = self.source[start + 2..]
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .map_or(self.source.len(), |len| start + 2 + len);
+            return self.integer(end);
+        }
+        let end = digits(start);
+        match self.at(end) {
+            Some(b'.') if self.at(end + 1).is_some_and(|c| c.is_ascii_digit()) => {
+                float(digits(end + 1))
+            }
+            Some(b'e' | b'E') => match self.exponent(end) {
+                Some(end) => (TokenKind::NumFloat, end),
+                None => (TokenKind::NumInt, end),
+            },
+            _ => self.integer(end),
+        }
+    }
+
+    /// An integer ending at `end`, unless it is followed by the suffix of unsigned integers.
+    fn integer(&self, end: usize) -> (TokenKind, usize) {
+        match self.at(end) {
+            Some(b'u' | b'U') => (TokenKind::NumUint, end + 1),
+            _ => (TokenKind::NumInt, end),
+        }
+    }
+
+    /// The end of the exponent of a floating point number at `start`, if there is one.
+    fn exponent(&self, start: usize) -> Option<usize> {
+        if !matches!(self.at(start), Some(b'e' | b'E')) {
+            return None;
+        }
+        let mut offset = start + 1;
+        if matches!(self.at(offset), Some(b'+' | b'-')) {
+            offset += 1;
+        }
+        let len = self.source[offset..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.source.len() - offset);
+        (len > 0).then_some(offset + len)
+    }
+}
+
+/// Escapes the control characters of `text` in a syntax error.
+pub(super) fn escape(text: &str) -> Cow<'_, str> {
+    if !text.contains(['\n', '\r', '\t']) {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(
+        text.replace('\n', "\\n")
+            .replace('\r', "\\r")
+            .replace('\t', "\\t"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<(TokenKind, &str)> {
+        let mut lexer = Lexer::new(source);
+        let mut tokens = Vec::new();
+        loop {
+            let token = lexer.next_token();
+            if token.kind == TokenKind::Eof {
+                return tokens;
+            }
+            tokens.push((token.kind, &source[token.start..token.end]));
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        use TokenKind::*;
+        assert_eq!(
+            tokens("1 1u 0x1F 0x1Fu 1.5 .5e3 1e-2 1.5e 1.e5 0xg"),
+            [
+                (NumInt, "1"),
+                (NumUint, "1u"),
+                (NumInt, "0x1F"),
+                (NumUint, "0x1Fu"),
+                (NumFloat, "1.5"),
+                (NumFloat, ".5e3"),
+                (NumFloat, "1e-2"),
+                (NumFloat, "1.5"),
+                (Identifier, "e"),
+                (NumInt, "1"),
+                (Dot, "."),
+                (Identifier, "e5"),
+                (NumInt, "0"),
+                (Identifier, "xg"),
+            ]
+        );
+    }
+
+    #[test]
+    fn strings() {
+        use TokenKind::*;
+        assert_eq!(
+            tokens(r#"'a' "b\"" r'\' b"c" BR'd' rb'e' """f"g""" '''h'''"#),
+            [
+                (String, "'a'"),
+                (String, r#""b\"""#),
+                (String, r"r'\'"),
+                (Bytes, r#"b"c""#),
+                (Bytes, "BR'd'"),
+                (Identifier, "rb"),
+                (String, "'e'"),
+                (String, r#""""f"g""""#),
+                (String, "'''h'''"),
+            ]
+        );
+    }
+
+    #[test]
+    fn identifiers() {
+        use TokenKind::*;
+        assert_eq!(
+            tokens("in int true null_ `a.b-c/d e` // comment"),
+            [
+                (In, "in"),
+                (Identifier, "int"),
+                (True, "true"),
+                (Identifier, "null_"),
+                (EscIdentifier, "`a.b-c/d e`"),
+            ]
+        );
+    }
+
+    #[test]
+    fn recognition_errors() {
+        let mut lexer = Lexer::new("a | b @ 'c\nb\"d\n'''e");
+        let mut tokens = Vec::new();
+        loop {
+            let token = lexer.next_token();
+            if token.kind == TokenKind::Eof {
+                break;
+            }
+            tokens.push(&lexer.source[token.start..token.end]);
+        }
+        assert_eq!(tokens, ["a", "b", "b", "''"]);
+        let errors: Vec<_> = lexer
+            .errors
+            .iter()
+            .map(|e| (e.pos, e.msg.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                ((1, 3), "Syntax error: token recognition error at: '| '"),
+                ((1, 7), "Syntax error: token recognition error at: '@'"),
+                ((1, 9), "Syntax error: token recognition error at: ''c\n'"),
+                ((2, 2), "Syntax error: token recognition error at: '\"d\n'"),
+                ((3, 3), "Syntax error: token recognition error at: ''e'"),
+            ]
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use crate::common::ast::{
     operators, CallExpr, ComprehensionExpr, Expr, IdedExpr, ListExpr, LiteralValue,
 };
-use crate::parser::{MacroExprHelper, ParseError};
+use crate::parser::{MacroExprHelper, ParseError, ParseErrorKind};
 
 pub type MacroExpander = fn(
     helper: &mut MacroExprHelper,
@@ -53,6 +53,7 @@ fn has_macro_expander(
             msg: "invalid argument to has() macro".to_string(),
             expr_id: 0,
             source_info: None,
+            kind: ParseErrorKind::InvalidExpression,
         }),
     }
 }
@@ -334,6 +335,7 @@ fn extract_ident(expr: IdedExpr, helper: &mut MacroExprHelper) -> Result<String,
             msg: "argument must be a simple name".to_string(),
             expr_id: 0,
             source_info: None,
+            kind: ParseErrorKind::InvalidExpression,
         }),
     }
 }
@@ -6,6 +6,10 @@ pub mod references;
 
 pub use crate::common::ast::IdedExpr as Expression;
 
+#[cfg(feature = "handwritten_parser")]
+mod descent;
+#[cfg(feature = "handwritten_parser")]
+mod lexer;
 mod macros;
 mod parse;
 #[allow(non_snake_case)]
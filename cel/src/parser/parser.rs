@@ -76,33 +76,60 @@ pub struct ParseError {
     pub msg: String,
     pub expr_id: u64,
     pub source_info: Option<Arc<SourceInfo>>,
+    pub kind: ParseErrorKind,
+}
+
+/// The kind of a [`ParseError`], which its [`code`](ParseError::code) is derived from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ParseErrorKind {
+    /// The source isn't a CEL expression.
+    Syntax,
+    /// The expression nests deeper than [`Parser::max_recursion_depth`] allows.
+    RecursionLimit,
+    /// The expression is well-formed, but not valid, such as a macro called with arguments it
+    /// doesn't take.
+    InvalidExpression,
 }
 
 impl Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "ERROR: <input>:{}:{}: {}",
-            self.pos.0, self.pos.1, self.msg
-        )?;
-        if let Some(info) = &self.source_info {
-            if let Some(line) = info.snippet(self.pos.0 - 1) {
-                write!(f, "\n| {line}")?;
-                write!(f, "\n| {:.>width$}", "^", width = self.pos.1 as usize)?;
-            }
-        }
-        Ok(())
+        write_error(f, &self.msg, Some(self.pos), self.source_info.as_deref())
     }
 }
 
+/// Writes `msg` as an error at `pos` of the source, followed by the offending line of source
+/// and a caret pointing at the column, if `source_info` has them.
+pub(crate) fn write_error(
+    f: &mut std::fmt::Formatter<'_>,
+    msg: &dyn Display,
+    pos: Option<(isize, isize)>,
+    source_info: Option<&SourceInfo>,
+) -> std::fmt::Result {
+    let name = source_info.map_or("<input>", SourceInfo::name);
+    let Some(pos) = pos else {
+        return write!(f, "ERROR: {name}: {msg}");
+    };
+    let (line, column) = source_info.map_or(pos, |info| info.location(pos));
+    write!(f, "ERROR: {name}:{line}:{column}: {msg}")?;
+    if let Some(line) = source_info.and_then(|info| info.snippet(pos.0 - 1)) {
+        write!(f, "\n| {line}")?;
+        write!(f, "\n| {:.>width$}", "^", width = pos.1 as usize)?;
+    }
+    Ok(())
+}
+
 impl Error for ParseError {}
 
 pub struct Parser {
     ast: ast::Ast,
-    helper: ParserHelper,
-    errors: Vec<ParseError>,
-    max_recursion_depth: u16,
-    enable_optional_syntax: bool,
+    pub(super) helper: ParserHelper,
+    pub(super) errors: Vec<ParseError>,
+    pub(super) max_recursion_depth: u16,
+    pub(super) enable_optional_syntax: bool,
+    populate_macro_calls: bool,
+    #[cfg_attr(not(feature = "regex"), allow(dead_code))]
+    pub(crate) check_regex_literals: bool,
 }
 
 impl Parser {
@@ -115,6 +142,8 @@ impl Parser {
             errors: Vec::default(),
             max_recursion_depth: 96,
             enable_optional_syntax: false,
+            populate_macro_calls: false,
+            check_regex_literals: true,
         }
     }
 
@@ -128,7 +157,60 @@ impl Parser {
         self
     }
 
-    fn new_logic_manager(&self, func: &str, term: IdedExpr) -> LogicManager {
+    /// Names the source in errors, such as the path of the file the expression was read from,
+    /// rather than `<input>`.
+    pub fn source_name(mut self, name: impl Into<String>) -> Self {
+        self.helper.source_info.set_name(name.into());
+        self
+    }
+
+    /// Reports error positions relative to the document the expression is embedded in, such as
+    /// a YAML policy, given the number of `lines` preceding the expression and the number of
+    /// `columns` preceding it on its first line.
+    ///
+    /// # Example
+    /// ```
+    /// # use cel::parser::Parser;
+    /// let err = Parser::new()
+    ///     .source_name("policy.yaml")
+    ///     .source_offset(11, 8)
+    ///     .parse("1 +")
+    ///     .unwrap_err();
+    /// assert!(err.to_string().starts_with("ERROR: policy.yaml:12:12: "));
+    /// ```
+    pub fn source_offset(mut self, lines: u32, columns: u32) -> Self {
+        self.helper.source_info.set_location_offset(lines, columns);
+        self
+    }
+
+    /// Records the calls to macros, such as `all` or `has`, in the [`SourceInfo`] along with
+    /// the expressions they expanded to, as other CEL implementations do to unparse an
+    /// expression or exchange it with its macro calls.
+    pub fn populate_macro_calls(mut self, enable: bool) -> Self {
+        self.populate_macro_calls = enable;
+        self
+    }
+
+    /// Whether [`Program::compile_with`](crate::Program::compile_with) reports the literal
+    /// patterns of `matches` calls that aren't valid as errors, which it does by default.
+    /// Otherwise, such calls fail when executed.
+    ///
+    /// # Example
+    /// ```
+    /// # use cel::{Context, Program};
+    /// # use cel::parser::Parser;
+    /// assert!(Program::compile("false && 'abc'.matches('(')").is_err());
+    /// let parser = Parser::new().check_regex_literals(false);
+    /// let program = Program::compile_with(parser, "false && 'abc'.matches('(')").unwrap();
+    /// assert_eq!(program.execute(&Context::default()), Ok(false.into()));
+    /// ```
+    #[cfg(feature = "regex")]
+    pub fn check_regex_literals(mut self, enable: bool) -> Self {
+        self.check_regex_literals = enable;
+        self
+    }
+
+    pub(super) fn new_logic_manager(&self, func: &str, term: IdedExpr) -> LogicManager {
         LogicManager {
             function: func.to_string(),
             terms: vec![term],
@@ -136,7 +218,7 @@ impl Parser {
         }
     }
 
-    fn global_call_or_macro(
+    pub(super) fn global_call_or_macro(
         &mut self,
         id: u64,
         func_name: String,
@@ -152,19 +234,22 @@ impl Parser {
                 }),
             },
             Some(expander) => {
+                let call = self
+                    .populate_macro_calls
+                    .then(|| self.helper.macro_call(func_name, None, &args));
                 let mut helper = MacroExprHelper {
                     helper: &mut self.helper,
                     id,
                 };
                 match expander(&mut helper, None, args) {
-                    Ok(expr) => expr,
+                    Ok(expr) => self.add_macro_call(expr, call),
                     Err(err) => self.report_parse_error(None, err),
                 }
             }
         }
     }
 
-    fn receiver_call_or_macro(
+    pub(super) fn receiver_call_or_macro(
         &mut self,
         id: u64,
         func_name: String,
@@ -181,19 +266,85 @@ impl Parser {
                 }),
             },
             Some(expander) => {
+                let call = self
+                    .populate_macro_calls
+                    .then(|| self.helper.macro_call(func_name, Some(&target), &args));
                 let mut helper = MacroExprHelper {
                     helper: &mut self.helper,
                     id,
                 };
                 match expander(&mut helper, Some(target), args) {
-                    Ok(expr) => expr,
+                    Ok(expr) => self.add_macro_call(expr, call),
                     Err(err) => self.report_parse_error(None, err),
                 }
             }
         }
     }
 
-    pub fn parse(mut self, source: &str) -> Result<IdedExpr, ParseErrors> {
+    fn add_macro_call(&mut self, expr: IdedExpr, call: Option<IdedExpr>) -> IdedExpr {
+        if let Some(call) = call {
+            self.helper.source_info.add_macro_call(expr.id, call);
+        }
+        expr
+    }
+
+    /// Parses `source` into an expression, or returns the syntax errors found in it.
+    ///
+    /// With the `handwritten_parser` feature, only the first syntax error is returned, where the
+    /// generated parser recovers from it and reports the ones following it as well.
+    pub fn parse(self, source: &str) -> Result<IdedExpr, ParseErrors> {
+        self.parse_with_source_info(source).map(|(expr, _)| expr)
+    }
+
+    /// Parses `source`, also returning the [`SourceInfo`] mapping expression ids back to their
+    /// position in `source`.
+    pub(crate) fn parse_with_source_info(
+        self,
+        source: &str,
+    ) -> Result<(IdedExpr, Arc<SourceInfo>), ParseErrors> {
+        #[cfg(feature = "handwritten_parser")]
+        let parse = super::descent::parse;
+        #[cfg(not(feature = "handwritten_parser"))]
+        let parse = Parser::parse_antlr;
+        self.parse_with(source, parse)
+    }
+
+    /// Parses `source` with `parse`, which reports the errors it runs into to the parser.
+    pub(super) fn parse_with(
+        mut self,
+        source: &str,
+        parse: fn(&mut Parser, &str) -> Result<IdedExpr, ParseError>,
+    ) -> Result<(IdedExpr, Arc<SourceInfo>), ParseErrors> {
+        // todo! might want to avoid this cloning here...
+        self.helper.source_info.source = source.into();
+
+        let r = parse(&mut self, source);
+
+        let info = self.helper.source_info;
+        let source_info = Arc::new(info);
+
+        let mut errors = self.errors;
+        errors.sort_by_key(|a| a.pos);
+
+        if errors.is_empty() {
+            r.map(|expr| (expr, source_info))
+                .map_err(|e| ParseErrors { errors: vec![e] })
+        } else {
+            Err(ParseErrors {
+                errors: errors
+                    .into_iter()
+                    .map(|mut e: ParseError| {
+                        e.source_info = Some(source_info.clone());
+                        e
+                    })
+                    .collect(),
+            })
+        }
+    }
+
+    /// Parses `source` with the parser generated from the grammar.
+    #[cfg_attr(all(feature = "handwritten_parser", not(test)), allow(dead_code))]
+    pub(super) fn parse_antlr(&mut self, source: &str) -> Result<IdedExpr, ParseError> {
         let parse_errors = Rc::new(RefCell::new(Vec::<ParseError>::new()));
         let stream = InputStream::new(source);
         let mut lexer = gen::CELLexer::new(stream);
@@ -202,9 +353,6 @@ impl Parser {
             parse_errors: parse_errors.clone(),
         }));
 
-        // todo! might want to avoid this cloning here...
-        self.helper.source_info.source = source.into();
-
         let mut prsr = gen::CELParser::new(CommonTokenStream::new(lexer));
         prsr.remove_error_listeners();
         prsr.add_error_listener(Box::new(ParserErrorListener {
@@ -222,29 +370,14 @@ impl Parser {
                 msg: "UNKNOWN".to_string(),
                 expr_id: 0,
                 source_info: None,
+                kind: ParseErrorKind::Syntax,
             }),
         };
 
-        let info = self.helper.source_info;
-        let source_info = Arc::new(info);
-
         let mut errors = parse_errors.take();
-        errors.extend(self.errors);
-        errors.sort_by_key(|a| a.pos);
-
-        if errors.is_empty() {
-            r.map_err(|e| ParseErrors { errors: vec![e] })
-        } else {
-            Err(ParseErrors {
-                errors: errors
-                    .into_iter()
-                    .map(|mut e: ParseError| {
-                        e.source_info = Some(source_info.clone());
-                        e
-                    })
-                    .collect(),
-            })
-        }
+        errors.append(&mut self.errors);
+        self.errors = errors;
+        r
     }
 
     fn field_initializer_list(
@@ -372,6 +505,7 @@ impl Parser {
             msg: s.into(),
             expr_id: 0,
             source_info: None,
+            kind: ParseErrorKind::InvalidExpression,
         };
         self.report_parse_error(Some(token), error)
     }
@@ -419,6 +553,7 @@ impl<'a> ParseTreeListener<'a, CELParserContextType> for RecursionListener {
                 msg: format!("Recursion limit of {} exceeded", self.max),
                 expr_id: 0,
                 source_info: None,
+                kind: ParseErrorKind::RecursionLimit,
             })));
         }
         Ok(())
@@ -444,14 +579,22 @@ impl<'a, T: Recognizer<'a>> ErrorListener<'a, T> for ParserErrorListener {
         line: isize,
         column: isize,
         msg: &str,
-        _error: Option<&ANTLRError>,
+        error: Option<&ANTLRError>,
     ) {
+        // The recursion listener stops the parser with an error of its own
+        let kind = match error {
+            Some(ANTLRError::OtherError(err)) => err
+                .downcast_ref::<ParseError>()
+                .map_or(ParseErrorKind::Syntax, |err| err.kind),
+            _ => ParseErrorKind::Syntax,
+        };
         self.parse_errors.borrow_mut().push(ParseError {
             source: None,
             pos: (line, column + 1),
             msg: format!("Syntax error: {msg}"),
             expr_id: 0,
             source_info: None,
+            kind,
         })
     }
 }
@@ -690,7 +833,7 @@ impl gen::CELVisitorCompat<'_> for Parser {
             }
             Some(member) => {
                 if ctx.ops.len() % 2 == 0 {
-                    self.visit(member.as_ref());
+                    return self.visit(member.as_ref());
                 }
                 let op_id = self.helper.next_id(&ctx.ops[0]);
                 let target = self.visit(member.as_ref());
@@ -706,7 +849,7 @@ impl gen::CELVisitorCompat<'_> for Parser {
             }
             Some(member) => {
                 if ctx.ops.len() % 2 == 0 {
-                    self.visit(member.as_ref());
+                    return self.visit(member.as_ref());
                 }
                 let op_id = self.helper.next_id(&ctx.ops[0]);
                 let target = self.visit(member.as_ref());
@@ -1070,13 +1213,30 @@ impl Default for ParserHelper {
 
 impl ParserHelper {
     fn next_id(&mut self, token: &CommonToken) -> u64 {
+        self.next_id_at(token.start as u32, token.stop as u32)
+    }
+
+    /// The next id, for an expression spanning the bytes of the source from `start` to `stop`,
+    /// inclusive.
+    pub(super) fn next_id_at(&mut self, start: u32, stop: u32) -> u64 {
+        let id = self.reserve_id();
+        self.source_info.add_offset(id, start, stop);
+        id
+    }
+
+    /// The next id, for an expression whose offsets are only known once more of the source has
+    /// been parsed, and are then recorded with [`ParserHelper::set_offsets`].
+    pub(super) fn reserve_id(&mut self) -> u64 {
         let id = self.next_id;
-        self.source_info
-            .add_offset(id, token.start as u32, token.stop as u32);
         self.next_id += 1;
         id
     }
 
+    #[cfg_attr(not(feature = "handwritten_parser"), allow(dead_code))]
+    pub(super) fn set_offsets(&mut self, id: u64, start: u32, stop: u32) {
+        self.source_info.add_offset(id, start, stop);
+    }
+
     fn next_id_for_token(&mut self, token: Option<&CommonToken>) -> u64 {
         match token {
             None => 0,
@@ -1105,9 +1265,79 @@ impl ParserHelper {
             expr,
         }
     }
+
+    /// The call to a macro as [`SourceInfo::macro_call`] has it.
+    fn macro_call(
+        &self,
+        func_name: String,
+        target: Option<&IdedExpr>,
+        args: &[IdedExpr],
+    ) -> IdedExpr {
+        IdedExpr {
+            id: 0,
+            expr: Expr::Call(CallExpr {
+                func_name,
+                target: target.map(|target| Box::new(self.macro_call_arg(target))),
+                args: args.iter().map(|arg| self.macro_call_arg(arg)).collect(),
+            }),
+        }
+    }
+
+    /// A copy of the argument `expr` of a macro call, in which the macro calls `expr` contains
+    /// are left as just the ids of their expansions.
+    fn macro_call_arg(&self, expr: &IdedExpr) -> IdedExpr {
+        if self.source_info.macro_call(expr.id).is_some() {
+            return IdedExpr {
+                id: expr.id,
+                expr: Expr::Unspecified,
+            };
+        }
+        let arg = |expr: &IdedExpr| self.macro_call_arg(expr);
+        let entry = |entry: &IdedEntryExpr| IdedEntryExpr {
+            id: entry.id,
+            expr: match &entry.expr {
+                EntryExpr::StructField(field) => EntryExpr::StructField(StructFieldExpr {
+                    value: arg(&field.value),
+                    ..field.clone()
+                }),
+                EntryExpr::MapEntry(entry) => EntryExpr::MapEntry(MapEntryExpr {
+                    key: arg(&entry.key),
+                    value: arg(&entry.value),
+                    optional: entry.optional,
+                }),
+            },
+        };
+        let kind = match &expr.expr {
+            Expr::Call(call) => Expr::Call(CallExpr {
+                func_name: call.func_name.clone(),
+                target: call.target.as_deref().map(|target| Box::new(arg(target))),
+                args: call.args.iter().map(arg).collect(),
+            }),
+            Expr::List(list) => Expr::List(ListExpr::new_with_optionals(
+                list.elements.iter().map(arg).collect(),
+                list.optional_indices.clone(),
+            )),
+            Expr::Map(map) => Expr::Map(MapExpr {
+                entries: map.entries.iter().map(entry).collect(),
+            }),
+            Expr::Struct(s) => Expr::Struct(StructExpr {
+                type_name: s.type_name.clone(),
+                entries: s.entries.iter().map(entry).collect(),
+            }),
+            Expr::Select(select) => Expr::Select(SelectExpr {
+                operand: Box::new(arg(&select.operand)),
+                ..select.clone()
+            }),
+            kind => kind.clone(),
+        };
+        IdedExpr {
+            id: expr.id,
+            expr: kind,
+        }
+    }
 }
 
-struct LogicManager {
+pub(super) struct LogicManager {
     function: String,
     terms: Vec<IdedExpr>,
     ops: Vec<u64>,
@@ -1357,6 +1587,34 @@ mod tests {
                 i: "!a",
                 p: "!_(
     a^#2:*expr.Expr_IdentExpr#
+)^#1:*expr.Expr_CallExpr#",
+                e: "",
+                ..Default::default()
+            },
+            TestInfo {
+                i: "!!a",
+                p: "a^#1:*expr.Expr_IdentExpr#",
+                e: "",
+                ..Default::default()
+            },
+            TestInfo {
+                i: "!!!a",
+                p: "!_(
+    a^#2:*expr.Expr_IdentExpr#
+)^#1:*expr.Expr_CallExpr#",
+                e: "",
+                ..Default::default()
+            },
+            TestInfo {
+                i: "--a",
+                p: "a^#1:*expr.Expr_IdentExpr#",
+                e: "",
+                ..Default::default()
+            },
+            TestInfo {
+                i: "---a",
+                p: "-_(
+    a^#2:*expr.Expr_IdentExpr#
 )^#1:*expr.Expr_CallExpr#",
                 e: "",
                 ..Default::default()
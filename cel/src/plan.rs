@@ -0,0 +1,1152 @@
+//! Planning of [`Program`](crate::Program)s ahead of execution, see
+//! [`Program::plan`](crate::Program::plan).
+//!
+//! A [`Plan`] mirrors the AST, but with what the interpreter otherwise works out by name on
+//! every execution settled once: operators are [`Op`]s, calls carry the overloads of the
+//! [`Env`] they may dispatch to, literals and the keys of selections are built already, and
+//! the variables of comprehensions are slots of their frames. Other identifiers name variables
+//! of the [`Context`], which are only bound on execution, so they are still looked up by name.
+//!
+//! How a plan is evaluated depends on the [`Backend`] it is planned for: either by walking it,
+//! or by running the [`vm::Bytecode`] it compiles to.
+
+use crate::common::ast::{operators, EntryExpr, Expr, LiteralValue};
+use crate::common::decls::OverloadDecl;
+use crate::common::types::bool::Bool;
+use crate::common::types::{numeric, CelBool, CelList, CelMap, CelMapKey, CelOptional, CelString};
+#[cfg(feature = "protobuf")]
+use crate::common::types::{CelInt, CelMessage};
+use crate::common::types::{Kind, MapStorage};
+use crate::common::value::Val;
+use crate::context::{Context, Frame, Slot, SlotVar};
+use crate::objects::{self, bool, try_bool, Failure};
+use crate::parser::Expression;
+use crate::{Env, ExecutionError, Value};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+mod vm;
+
+/// How a planned [`Program`](crate::Program) is evaluated, see
+/// [`Program::plan_with`](crate::Program::plan_with).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Backend {
+    /// Evaluates the plan recursively, like the interpreter does the AST.
+    #[default]
+    Tree,
+    /// Compiles the plan to bytecode for a stack machine, which jumps over what the logical
+    /// operators and the conditional don't evaluate, and loops over comprehensions rather than
+    /// recursing into them. Results, errors and the expressions errors are located at are the
+    /// same as with the tree backend.
+    Bytecode,
+}
+
+/// The operators the interpreter implements itself, rather than dispatching to the overloads
+/// of a function.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Op {
+    Conditional,
+    LogicalOr,
+    LogicalAnd,
+    Equals,
+    NotEquals,
+    Index,
+    OptIndex,
+    OptSelect,
+    Add,
+    Subtract,
+    Divide,
+    Multiply,
+    Modulo,
+    Less,
+    LessEquals,
+    Greater,
+    GreaterEquals,
+    In,
+    LogicalNot,
+    Negate,
+    NotStrictlyFalse,
+}
+
+impl Op {
+    /// The operator a call to the function `name` with `arity` arguments is.
+    pub(crate) fn of(name: &str, arity: usize) -> Option<Op> {
+        let op = match (arity, name) {
+            (3, operators::CONDITIONAL) => Op::Conditional,
+            (2, operators::LOGICAL_OR) => Op::LogicalOr,
+            (2, operators::LOGICAL_AND) => Op::LogicalAnd,
+            (2, operators::EQUALS) => Op::Equals,
+            (2, operators::NOT_EQUALS) => Op::NotEquals,
+            (2, operators::INDEX) => Op::Index,
+            (2, operators::OPT_INDEX) => Op::OptIndex,
+            (2, operators::OPT_SELECT) => Op::OptSelect,
+            (2, operators::ADD) => Op::Add,
+            (2, operators::SUBSTRACT) => Op::Subtract,
+            (2, operators::DIVIDE) => Op::Divide,
+            (2, operators::MULTIPLY) => Op::Multiply,
+            (2, operators::MODULO) => Op::Modulo,
+            (2, operators::LESS) => Op::Less,
+            (2, operators::LESS_EQUALS) => Op::LessEquals,
+            (2, operators::GREATER) => Op::Greater,
+            (2, operators::GREATER_EQUALS) => Op::GreaterEquals,
+            (2, operators::IN) => Op::In,
+            (1, operators::LOGICAL_NOT) => Op::LogicalNot,
+            (1, operators::NEGATE) => Op::Negate,
+            (1, operators::NOT_STRICTLY_FALSE) => Op::NotStrictlyFalse,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// Applies the operator to `args`, which are only evaluated, with `eval`, as needed: the
+    /// logical operators short-circuit, and the conditional only evaluates one branch.
+    pub(crate) fn apply<'a, E, F>(
+        self,
+        args: &'a [E],
+        ctx: &'a Context<'a>,
+        eval: impl Fn(&'a E) -> Result<Cow<'a, dyn Val>, F>,
+    ) -> Result<Cow<'a, dyn Val>, F>
+    where
+        F: From<ExecutionError>,
+    {
+        match self {
+            Op::Conditional => {
+                if try_bool(eval(&args[0]))? {
+                    eval(&args[1])
+                } else {
+                    eval(&args[2])
+                }
+            }
+            Op::LogicalOr | Op::LogicalAnd => {
+                let left = try_bool(eval(&args[0]));
+                if self.short_circuits(&left) {
+                    return Ok(bool(self == Op::LogicalOr));
+                }
+                let right = eval(&args[1])?;
+                self.logical(left, right.as_ref()).map(bool)
+            }
+            Op::Index | Op::OptIndex => match self.index_target(eval(&args[0])?)? {
+                Some((value, is_optional)) => {
+                    Ok(Op::index(value, is_optional, eval(&args[1])?.as_ref())?)
+                }
+                None => Ok(Cow::<dyn Val>::Owned(Box::new(CelOptional::none()))),
+            },
+            Op::LogicalNot | Op::Negate => Ok(self.unary(eval(&args[0])?)?),
+            Op::NotStrictlyFalse => Ok(bool(try_bool(eval(&args[0])).unwrap_or(true))),
+            _ => Ok(self.binary(eval(&args[0])?, eval(&args[1])?, ctx)?),
+        }
+    }
+
+    /// Whether the logical operator is decided by its `left` operand alone.
+    pub(crate) fn short_circuits<E>(self, left: &Result<bool, E>) -> bool {
+        matches!(left, Ok(left) if *left == (self == Op::LogicalOr))
+    }
+
+    /// Combines the operands of a logical operator which didn't short-circuit, an error of the
+    /// `left` one only being recovered from if the `right` one decides the result.
+    pub(crate) fn logical<E>(self, left: Result<bool, E>, right: &dyn Val) -> Result<bool, E>
+    where
+        E: From<ExecutionError>,
+    {
+        let right = right.downcast_ref::<CelBool>().map(|b| *b.inner());
+        let decisive = self == Op::LogicalOr;
+        match (left, right) {
+            (Ok(left), Some(right)) if left != decisive => Ok(right),
+            (Err(_), Some(right)) if right == decisive => Ok(decisive),
+            (Err(err), _) => Err(err),
+            _ => Err(ExecutionError::NoSuchOverload.into()),
+        }
+    }
+
+    /// Prepares the operand of an index operator, unwrapping an optional, or `None` when it is
+    /// an empty optional, which the result is then too. The operand comes along with whether
+    /// the result is optional.
+    pub(crate) fn index_target<'a>(
+        self,
+        value: Cow<'a, dyn Val>,
+    ) -> Result<Option<IndexTarget<'a>>, ExecutionError> {
+        let (value, is_optional) = match value.downcast_ref::<CelOptional>() {
+            Some(opt) => match opt.inner() {
+                // todo try to keep this borrowed
+                Some(v) => (Cow::Owned(v.clone_as_boxed()), true),
+                None => return Ok(None),
+            },
+            None => (value, self == Op::OptIndex),
+        };
+        if value.as_indexer().is_none() {
+            return Err(ExecutionError::NoSuchOverload);
+        }
+        Ok(Some((value, is_optional)))
+    }
+
+    /// Indexes `value`, as prepared by [`Op::index_target`], with `index`.
+    pub(crate) fn index<'a>(
+        value: Cow<'a, dyn Val>,
+        is_optional: bool,
+        index: &dyn Val,
+    ) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+        let result = match value {
+            Cow::Borrowed(val) => val
+                .as_indexer()
+                .ok_or(ExecutionError::NoSuchOverload)?
+                .get(index),
+            Cow::Owned(val) => val
+                .into_indexer()
+                .ok_or(ExecutionError::NoSuchOverload)?
+                .steal(index)
+                .map(Cow::Owned),
+        };
+        if is_optional {
+            Ok(match result {
+                Ok(val) => Cow::<dyn Val>::Owned(Box::new(CelOptional::from(val.clone_as_boxed()))),
+                Err(_) => Cow::<dyn Val>::Owned(Box::new(CelOptional::none())),
+            })
+        } else {
+            result
+        }
+    }
+
+    /// Applies a unary operator to its evaluated operand.
+    pub(crate) fn unary<'a>(
+        self,
+        operand: Cow<'a, dyn Val>,
+    ) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+        match self {
+            Op::LogicalNot => operand
+                .downcast_ref::<CelBool>()
+                .map(Bool::negate)
+                .ok_or(ExecutionError::NoSuchOverload)
+                .map(|b| bool(b.into_inner())),
+            _ => Ok(Cow::<dyn Val>::Owned(
+                operand
+                    .as_negator()
+                    .ok_or(ExecutionError::NoSuchOverload)?
+                    .negate()?,
+            )),
+        }
+    }
+
+    /// Applies a binary operator which evaluates both its operands to them.
+    pub(crate) fn binary<'a>(
+        self,
+        lhs: Cow<'a, dyn Val>,
+        rhs: Cow<'a, dyn Val>,
+        ctx: &Context,
+    ) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+        match self {
+            Op::Equals => Ok(bool(lhs.eq(&rhs))),
+            Op::NotEquals => Ok(bool(lhs.ne(&rhs))),
+            Op::OptSelect => {
+                let field = match rhs.get_type().kind() {
+                    Kind::String => rhs
+                        .downcast_ref::<CelString>()
+                        .expect("field must be string"),
+                    _ => {
+                        return Err(ExecutionError::function_error(
+                            "_?._",
+                            "field must be string",
+                        ))
+                    }
+                };
+                Ok(Cow::<dyn Val>::Owned(Box::new(
+                    if let Some(opt) = lhs.as_ref().downcast_ref::<CelOptional>() {
+                        opt.map(|operand| {
+                            operand
+                                .as_indexer()
+                                .map(|i| {
+                                    i.get(field)
+                                        .map(|v| v.clone_as_boxed())
+                                        .unwrap_or(CelOptional::none().clone_as_boxed())
+                                })
+                                .unwrap_or(CelOptional::none().clone_as_boxed())
+                        })
+                    } else {
+                        CelOptional::of(
+                            lhs.as_indexer()
+                                .ok_or(ExecutionError::NoSuchOverload)?
+                                .get(field)?
+                                .clone_as_boxed(),
+                        )
+                    },
+                )))
+            }
+            Op::Add => self.arithmetic("add", lhs, rhs, ctx),
+            Op::Subtract => self.arithmetic("sub", lhs, rhs, ctx),
+            Op::Divide => self.arithmetic("div", lhs, rhs, ctx),
+            Op::Multiply => self.arithmetic("mul", lhs, rhs, ctx),
+            Op::Modulo => self.arithmetic("rem", lhs, rhs, ctx),
+            Op::Less | Op::LessEquals | Op::Greater | Op::GreaterEquals => {
+                let ordering = lhs
+                    .as_comparer()
+                    .ok_or(ExecutionError::NoSuchOverload)?
+                    .compare(rhs.as_ref())?;
+                Ok(bool(match self {
+                    Op::Less => ordering == Ordering::Less,
+                    Op::LessEquals => ordering != Ordering::Greater,
+                    Op::Greater => ordering == Ordering::Greater,
+                    _ => ordering != Ordering::Less,
+                }))
+            }
+            _ => match rhs.as_container() {
+                Some(container) => Ok(bool(container.contains(lhs.as_ref())?)),
+                None => Err(ExecutionError::NoSuchOverload),
+            },
+        }
+    }
+
+    /// Applies an arithmetic operator, named `operator` in errors, promoting mixed numbers to a
+    /// common type first if the [`Env`] enables it.
+    fn arithmetic<'a>(
+        self,
+        operator: &'static str,
+        lhs: Cow<'a, dyn Val>,
+        rhs: Cow<'a, dyn Val>,
+        ctx: &Context,
+    ) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+        let (lhs, rhs) = if ctx.env().numeric_promotion() {
+            numeric::promote(operator, lhs, rhs)?
+        } else {
+            (lhs, rhs)
+        };
+        let (l, r) = (lhs.as_ref(), rhs.as_ref());
+        let result = match self {
+            Op::Add => l.as_adder().map(|a| a.add(r).map(Cow::into_owned)),
+            Op::Subtract => l.as_subtractor().map(|s| s.sub(r).map(Cow::into_owned)),
+            Op::Divide => l.as_divider().map(|d| d.div(r).map(Cow::into_owned)),
+            Op::Multiply => l.as_multiplier().map(|m| m.mul(r).map(Cow::into_owned)),
+            _ => l.as_modder().map(|m| m.modulo(r).map(Cow::into_owned)),
+        };
+        let result = result.ok_or_else(|| {
+            ExecutionError::UnsupportedBinaryOperator(
+                operator,
+                l.try_into().unwrap_or(Value::Null),
+                r.try_into().unwrap_or(Value::Null),
+            )
+        })?;
+        Ok(Cow::Owned(result?))
+    }
+}
+
+/// The operand of an index operator, along with whether the result is optional.
+pub(crate) type IndexTarget<'a> = (Cow<'a, dyn Val>, bool);
+
+/// An expression planned ahead of execution against an [`Env`].
+pub(crate) struct Plan {
+    env: Arc<Env>,
+    root: Root,
+}
+
+enum Root {
+    Tree(Node),
+    Bytecode(vm::Bytecode),
+}
+
+impl Debug for Plan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Plan").finish_non_exhaustive()
+    }
+}
+
+impl Plan {
+    /// Plans `expression`, binding its calls to the overloads `env` has, along with the
+    /// literal patterns of the calls matching against one, for evaluation by `backend`. An
+    /// expression nested deeper than `env` evaluates is planned to fail as a whole.
+    pub(crate) fn new(expression: &Expression, env: &Arc<Env>, backend: Backend) -> Plan {
+        let mut planner = Planner {
+            env,
+            scopes: Vec::new(),
+        };
+        let max = env.max_eval_depth();
+        let root = match expression.deepest() {
+            (depth, deepest) if depth > max => Node {
+                id: deepest,
+                kind: NodeKind::Invalid(ExecutionError::MaxDepthExceeded(max)),
+            },
+            _ => planner.plan(expression),
+        };
+        let root = match backend {
+            Backend::Tree => Root::Tree(root),
+            Backend::Bytecode => Root::Bytecode(vm::Bytecode::compile(&root)),
+        };
+        Plan {
+            env: env.clone(),
+            root,
+        }
+    }
+
+    /// Whether the plan was planned against `env`, rather than another [`Env`], whose
+    /// overloads and limits it doesn't have.
+    pub(crate) fn is_for(&self, env: &Arc<Env>) -> bool {
+        Arc::ptr_eq(&self.env, env)
+    }
+
+    pub(crate) fn execute(&self, ctx: &Context) -> Result<Value, Failure> {
+        let value = match &self.root {
+            Root::Tree(root) => root.eval(ctx, None)?,
+            Root::Bytecode(bytecode) => bytecode.execute(ctx)?,
+        };
+        Ok(value.as_ref().try_into()?)
+    }
+}
+
+struct Node {
+    id: u64,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    Literal(Box<dyn Val>),
+    Ident(Arc<String>),
+    /// A variable of an enclosing comprehension.
+    Slot(Slot),
+    Select {
+        operand: Box<Node>,
+        field: CelString,
+        test: bool,
+        /// The number of the enum value the selection names, in case its operand is undeclared.
+        #[cfg(feature = "protobuf")]
+        enum_value: Option<i32>,
+    },
+    Op(Op, Vec<Node>),
+    Call(Box<Call>),
+    List(Vec<(Node, bool)>),
+    Map(Vec<(Node, Node, bool)>),
+    #[cfg_attr(not(feature = "structs"), allow(dead_code))]
+    Struct {
+        type_name: String,
+        fields: Vec<(String, Node)>,
+    },
+    Comprehension(Box<Comprehension>),
+    /// An expression which can't be evaluated, failing with the error whenever it is.
+    Invalid(ExecutionError),
+}
+
+struct Call {
+    name: Arc<String>,
+    target: Option<Node>,
+    args: Vec<Node>,
+    /// The overloads of the function the call may dispatch to, with the target of a member
+    /// call as the first argument.
+    overloads: Vec<OverloadDecl>,
+    /// The name and overloads of the function `target.name` names, for a call on an
+    /// identifier, which may be a namespace rather than a variable.
+    qualified: Option<(String, Vec<OverloadDecl>)>,
+}
+
+struct Comprehension {
+    iter_range: Node,
+    iter_var: Arc<String>,
+    accu_var: Arc<String>,
+    accu_init: Node,
+    loop_cond: Node,
+    loop_step: Step,
+    result: Node,
+    /// Whether the result is the accumulator as is, which is then returned without a copy.
+    result_is_accu: bool,
+}
+
+/// How a step of a [`Comprehension`] computes the accumulator of the next one.
+enum Step {
+    /// Evaluates the expression which is the next accumulator.
+    Eval(Node),
+    /// Appends the element to the list accumulated, if the condition holds, for the steps
+    /// [`ComprehensionExpr::appends`](crate::common::ast::ComprehensionExpr::appends)
+    /// recognizes, extending the list in place rather than copying it at every step.
+    Append {
+        /// The id of the step, which is blamed when the condition isn't a boolean.
+        id: u64,
+        condition: Option<Node>,
+        element: Node,
+    },
+}
+
+struct Planner<'e> {
+    env: &'e Env,
+    /// The iteration and accumulator variables of the comprehensions enclosing the expression
+    /// being planned, innermost last.
+    scopes: Vec<(Option<Arc<String>>, Arc<String>)>,
+}
+
+impl Planner<'_> {
+    fn ident(&mut self, name: &str) -> NodeKind {
+        for (up, (iter, accu)) in self.scopes.iter().rev().enumerate() {
+            let var = if iter.as_deref().is_some_and(|iter| iter == name) {
+                SlotVar::Iter
+            } else if accu.as_str() == name {
+                SlotVar::Accu
+            } else {
+                continue;
+            };
+            return NodeKind::Slot(Slot { up, var });
+        }
+        NodeKind::Ident(Arc::new(name.to_owned()))
+    }
+
+    fn plan(&mut self, expr: &Expression) -> Node {
+        let kind = match &expr.expr {
+            Expr::Literal(literal) => NodeKind::Literal(match literal {
+                LiteralValue::Null => Box::new(crate::common::types::CelNull),
+                literal => literal.to_val().into_owned(),
+            }),
+            Expr::Ident(name) => self.ident(name),
+            Expr::Select(select) => NodeKind::Select {
+                operand: Box::new(self.plan(&select.operand)),
+                field: select.field.as_str().into(),
+                test: select.test,
+                #[cfg(feature = "protobuf")]
+                enum_value: objects::qualified_name(expr)
+                    .and_then(|name| self.env.find_enum_value(&name)),
+            },
+            Expr::Call(call) => {
+                let args = call.args.iter().map(|arg| self.plan(arg)).collect();
+                if let Some(op) = Op::of(&call.func_name, call.args.len()) {
+                    NodeKind::Op(op, args)
+                } else {
+                    let arity = call.args.len() + usize::from(call.target.is_some());
+                    let qualified = match call.target.as_deref().map(|target| &target.expr) {
+                        Some(Expr::Ident(prefix)) => {
+                            let name = format!("{prefix}.{}", call.func_name);
+                            let overloads = self.env.candidates(&name, false, call.args.len());
+                            Some((name, overloads))
+                        }
+                        _ => None,
+                    };
+                    #[cfg_attr(not(feature = "regex"), allow(unused_mut))]
+                    let mut overloads =
+                        self.env
+                            .candidates(&call.func_name, call.target.is_some(), arity);
+                    #[cfg(feature = "regex")]
+                    if let Some(Expr::Literal(LiteralValue::String(pattern))) =
+                        call.args.last().map(|arg| &arg.expr)
+                    {
+                        overloads = overloads
+                            .iter()
+                            .map(|o| o.with_pattern(pattern.inner()))
+                            .collect();
+                    }
+                    NodeKind::Call(Box::new(Call {
+                        name: Arc::new(call.func_name.clone()),
+                        target: call.target.as_deref().map(|target| self.plan(target)),
+                        args,
+                        overloads,
+                        qualified,
+                    }))
+                }
+            }
+            Expr::List(list) => NodeKind::List(
+                list.elements
+                    .iter()
+                    .enumerate()
+                    .map(|(i, element)| (self.plan(element), list.optional_indices.contains(&i)))
+                    .collect(),
+            ),
+            Expr::Map(map) => {
+                let mut entries = Vec::with_capacity(map.entries.len());
+                for entry in &map.entries {
+                    match &entry.expr {
+                        EntryExpr::MapEntry(e) => {
+                            entries.push((self.plan(&e.key), self.plan(&e.value), e.optional))
+                        }
+                        EntryExpr::StructField(field) => {
+                            return self.invalid(expr, format!("Expected map_entry, got {field:?}"))
+                        }
+                    }
+                }
+                NodeKind::Map(entries)
+            }
+            Expr::Struct(strct) => {
+                let mut fields = Vec::with_capacity(strct.entries.len());
+                for entry in &strct.entries {
+                    match &entry.expr {
+                        EntryExpr::StructField(field) => {
+                            fields.push((field.field.clone(), self.plan(&field.value)))
+                        }
+                        EntryExpr::MapEntry(entry) => {
+                            return self.invalid(
+                                expr,
+                                format!("Expected struct_field_expr, got {entry:?}"),
+                            )
+                        }
+                    }
+                }
+                NodeKind::Struct {
+                    type_name: strct.type_name.clone(),
+                    fields,
+                }
+            }
+            Expr::Comprehension(comprehension) => {
+                let iter_range = self.plan(&comprehension.iter_range);
+                let accu_init = self.plan(&comprehension.accu_init);
+                let iter_var = Arc::new(comprehension.iter_var.clone());
+                let accu_var = Arc::new(comprehension.accu_var.clone());
+
+                self.scopes.push((Some(iter_var.clone()), accu_var.clone()));
+                let loop_cond = self.plan(&comprehension.loop_cond);
+                let loop_step = match comprehension.appends() {
+                    Some((condition, element)) => Step::Append {
+                        id: comprehension.loop_step.id,
+                        condition: condition.map(|condition| self.plan(condition)),
+                        element: self.plan(element),
+                    },
+                    None => Step::Eval(self.plan(&comprehension.loop_step)),
+                };
+                self.scopes.pop();
+
+                self.scopes.push((None, accu_var.clone()));
+                let result = self.plan(&comprehension.result);
+                self.scopes.pop();
+
+                let result_is_accu = matches!(
+                    result.kind,
+                    NodeKind::Slot(Slot {
+                        up: 0,
+                        var: SlotVar::Accu
+                    })
+                );
+                NodeKind::Comprehension(Box::new(Comprehension {
+                    iter_range,
+                    iter_var,
+                    accu_var,
+                    accu_init,
+                    loop_cond,
+                    loop_step,
+                    result,
+                    result_is_accu,
+                }))
+            }
+            Expr::Unspecified => {
+                return self.invalid(expr, "Can't evaluate Unspecified Expr".into())
+            }
+        };
+        Node { id: expr.id, kind }
+    }
+
+    fn invalid(&self, expr: &Expression, message: String) -> Node {
+        Node {
+            id: expr.id,
+            kind: NodeKind::Invalid(ExecutionError::InternalError(message)),
+        }
+    }
+}
+
+impl Node {
+    /// Evaluates the node within the `frame` of the comprehensions it is in, if any.
+    #[inline(always)]
+    fn eval<'a>(
+        &'a self,
+        ctx: &'a Context<'a>,
+        frame: Option<&'a Frame<'a>>,
+    ) -> Result<Cow<'a, dyn Val>, Failure> {
+        self.eval_kind(ctx, frame)
+            .map_err(|failure| failure.blame(self.id))
+    }
+
+    fn eval_kind<'a>(
+        &'a self,
+        ctx: &'a Context<'a>,
+        frame: Option<&'a Frame<'a>>,
+    ) -> Result<Cow<'a, dyn Val>, Failure> {
+        match &self.kind {
+            NodeKind::Literal(val) => Ok(Cow::Borrowed(val.as_ref())),
+            NodeKind::Slot(slot) => Ok(Cow::Borrowed(
+                frame.expect("slot within a comprehension").slot(*slot),
+            )),
+            NodeKind::Ident(name) => Ok(ctx
+                .try_get_variable(name)?
+                .ok_or_else(|| ExecutionError::UndeclaredReference(name.clone()))?),
+            NodeKind::Select {
+                operand,
+                field,
+                test,
+                #[cfg(feature = "protobuf")]
+                enum_value,
+            } => {
+                let left = operand.eval(ctx, frame);
+                #[cfg(feature = "protobuf")]
+                if let (Err(failure), Some(number)) = (&left, enum_value) {
+                    if matches!(*failure.error, ExecutionError::UndeclaredReference(_)) {
+                        return Ok(Cow::<dyn Val>::Owned(Box::new(CelInt::from(
+                            *number as i64,
+                        ))));
+                    }
+                }
+                Ok(objects::select(left?, field, *test)?)
+            }
+            NodeKind::Op(op, args) => op.apply(args, ctx, |arg| arg.eval(ctx, frame)),
+            NodeKind::Call(call) => call.eval(ctx, frame),
+            NodeKind::List(elements) => {
+                let mut list = Vec::with_capacity(elements.len());
+                for (element, optional) in elements {
+                    let value = element.eval(ctx, frame)?;
+                    match value.downcast_ref::<CelOptional>() {
+                        Some(opt) if *optional => {
+                            list.extend(opt.inner().map(|v| v.clone_as_boxed()))
+                        }
+                        _ => list.push(value.into_owned()),
+                    }
+                }
+                Ok(Cow::<dyn Val>::Owned(Box::new(CelList::from(list))))
+            }
+            NodeKind::Map(entries) => {
+                let mut map = MapStorage::with_capacity(entries.len());
+                for (key, value, optional) in entries {
+                    let key: CelMapKey = key.eval(ctx, frame)?.into_owned().try_into()?;
+                    let value = value.eval(ctx, frame)?.into_owned();
+                    match value.downcast_ref::<CelOptional>() {
+                        Some(opt) if *optional => {
+                            if let Some(inner) = opt.inner() {
+                                map.insert(key, inner.clone_as_boxed());
+                            }
+                        }
+                        _ => {
+                            map.insert(key, value);
+                        }
+                    }
+                }
+                let map: Box<CelMap> = CelMap::from(map).into();
+                Ok(Cow::<dyn Val>::Owned(map))
+            }
+            #[cfg(not(feature = "structs"))]
+            NodeKind::Struct { type_name, .. } => Err(ExecutionError::InternalError(format!(
+                "Found struct {type_name}, feature not enabled!"
+            ))
+            .into()),
+            #[cfg(feature = "structs")]
+            NodeKind::Struct { type_name, fields } => {
+                #[cfg(feature = "protobuf")]
+                if let Some(desc) = ctx.env().find_message(type_name) {
+                    let mut values = Vec::with_capacity(fields.len());
+                    for (field, value) in fields {
+                        values.push((field.clone(), value.eval(ctx, frame)?));
+                    }
+                    let msg = CelMessage::new_message(desc, values)?;
+                    return Ok(Cow::<dyn Val>::Owned(Box::new(msg)));
+                }
+                let struct_def =
+                    ctx.env()
+                        .find_struct(type_name)
+                        .ok_or(ExecutionError::UnexpectedType {
+                            got: type_name.to_owned(),
+                            want: "known struct".to_owned(),
+                        })?;
+                let mut values = std::collections::BTreeMap::new();
+                for (field, value) in fields {
+                    values.insert(field.clone(), value.eval(ctx, frame)?);
+                }
+                let s = struct_def.new_struct(values)?;
+                Ok(Cow::<dyn Val>::Owned(Box::new(s)))
+            }
+            NodeKind::Comprehension(comprehension) => comprehension.eval(ctx, frame),
+            NodeKind::Invalid(err) => Err(err.clone().into()),
+        }
+    }
+}
+
+impl Call {
+    fn eval<'a>(
+        &'a self,
+        ctx: &'a Context<'a>,
+        frame: Option<&'a Frame<'a>>,
+    ) -> Result<Cow<'a, dyn Val>, Failure> {
+        let mut args = Vec::with_capacity(self.args.len() + 1);
+        for arg in &self.args {
+            args.push(arg.eval(ctx, frame)?);
+        }
+        let Some(target) = &self.target else {
+            if let Some(overload) = self.overloads.iter().find(|o| o.accepts(&args)) {
+                return Ok(overload.call(args)?);
+            }
+            let func = ctx
+                .get_function(&self.name)
+                .ok_or_else(|| ExecutionError::UndeclaredReference(self.name.clone()))?;
+            return Ok(objects::call_function(&self.name, func, None, ctx, args)?);
+        };
+        if let Some((name, overloads)) = &self.qualified {
+            if let Some(overload) = overloads.iter().find(|o| o.accepts(&args)) {
+                return Ok(overload.call(args)?);
+            }
+            if let Some(func) = ctx.get_function(name) {
+                return Ok(objects::call_function(&self.name, func, None, ctx, args)?);
+            }
+        }
+        args.insert(0, target.eval(ctx, frame)?);
+        if let Some(overload) = self.overloads.iter().find(|o| o.accepts(&args)) {
+            return Ok(overload.call(args)?);
+        }
+        let target = args.remove(0);
+        let func = ctx
+            .get_function(&self.name)
+            .ok_or_else(|| ExecutionError::UndeclaredReference(self.name.clone()))?;
+        Ok(objects::call_function(
+            &self.name,
+            func,
+            Some(target),
+            ctx,
+            args,
+        )?)
+    }
+}
+
+impl Comprehension {
+    fn eval<'a>(
+        &'a self,
+        ctx: &'a Context<'a>,
+        frame: Option<&'a Frame<'a>>,
+    ) -> Result<Cow<'a, dyn Val>, Failure> {
+        let accu_init = self.accu_init.eval(ctx, frame)?;
+        let iter = self.iter_range.eval(ctx, frame)?;
+        let (iter_var, accu_var) = (self.iter_var.as_str(), self.accu_var.as_str());
+
+        let mut accu = accu_init;
+        let mut items = iter
+            .as_iterable()
+            .ok_or(ExecutionError::NoSuchOverload)?
+            .iter();
+        while let Some(item) = items.next() {
+            let frame = Frame::new(frame, Some((iter_var, item)), (accu_var, accu.as_ref()));
+            if !try_bool(self.loop_cond.eval(ctx, Some(&frame)))? {
+                break;
+            }
+            match &self.loop_step {
+                Step::Eval(step) => {
+                    let step = step.eval(ctx, Some(&frame))?;
+                    accu = Cow::Owned(step.into_owned());
+                }
+                Step::Append {
+                    id,
+                    condition,
+                    element,
+                } => {
+                    if let Some(condition) = condition {
+                        let condition = condition.eval(ctx, Some(&frame))?;
+                        if !try_bool(Ok(condition)).map_err(|error| Failure::at(error, *id))? {
+                            continue;
+                        }
+                    }
+                    let element = element.eval(ctx, Some(&frame))?.into_owned();
+                    objects::append(&mut accu, element)?;
+                }
+            }
+        }
+        if self.result_is_accu {
+            return Ok(accu);
+        }
+        let frame = Frame::new(frame, None, (accu_var, accu.as_ref()));
+        Ok(Cow::<dyn Val>::Owned(
+            self.result.eval(ctx, Some(&frame))?.into_owned(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backend, Node, NodeKind, Plan, Root, Step};
+    use crate::common::types::{self, CelInt};
+    use crate::common::value::Val;
+    use crate::context::{Slot, SlotVar};
+    use crate::parser::Parser;
+    use crate::{Context, Env, ExecutionError, Program, Value};
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn context<'a>(env: Arc<Env>) -> Context<'a> {
+        let mut ctx = Context::with_env(env);
+        ctx.add_variable_from_value("foo", HashMap::from([("bar", 1i64)]));
+        ctx.add_variable_from_value("list", vec![1i64, 2, 3]);
+        ctx.add_variable_from_value("name", "Alice");
+        ctx.add_function("twice", |x: i64| x * 2);
+        ctx.add_function("ns.twice", |x: i64| x * 2);
+        ctx
+    }
+
+    #[test]
+    fn same_as_interpreter() {
+        let env = Arc::new(Env::stdlib());
+        let ctx = context(env.clone());
+        for source in [
+            "1 + 2 * 3 - 4 / 2 % 3",
+            "-(1) + --2",
+            "1 < 2 && 2 <= 2 && 3 > 2 && 3 >= 4 || !false",
+            "foo.bar == 1 && foo.bar != 2",
+            "has(foo.bar) && !has(foo.baz)",
+            "foo.baz",
+            "missing || true",
+            "missing && false",
+            "true ? name : missing",
+            "list[1] + list[?5].orValue(7)",
+            "optional.of(foo).?bar.orValue(0) + foo.?baz.orValue(5)",
+            "{'a': 1}[?'b']",
+            "[1, ?optional.none(), ?optional.of(3)]",
+            "{'a': 1, ?'b': optional.none(), ?'c': optional.of(2)}",
+            "2 in list && 'bar' in foo",
+            "list.map(x, x * 2).filter(x, x > 2).all(x, x % 2 == 0)",
+            "list.exists_one(x, x == 2) && list.exists(x, x == 3)",
+            "list.map(x, list.map(list, list * x)).map(x, x.filter(y, y > 2))",
+            "list.all(x, list.exists(y, x * y == 6 || y.missing()))",
+            "foo.all(bar, foo[bar] == 1) && foo.map(k, k + name)[0].size()",
+            "size(name) + name.size() + size(list)",
+            "name.startsWith('A') && name.matches('^A.*e$')",
+            "name.matches(name + '[')",
+            "twice(21) + list.size()",
+            "ns.twice(2) + 1.twice()",
+            "missing(1)",
+            "1.missing()",
+            "int('42') + int(1u) + 1u",
+            "1 + 1u",
+            "9223372036854775807 + 1",
+            "[1] + [2] == [1, 2] && 'a' + 'b' == 'ab'",
+            "null == null",
+            "b'abc'[0]",
+            "missing || false",
+            "1 || true",
+            "1 || false",
+            "false || 1",
+            "missing && other",
+            "(missing || list.missing()) && true",
+            "(missing || 1) + 1",
+            "true ? missing : 1",
+            "1 ? 2 : 3",
+            "list.exists(x, x / 0 == 1 || x == 3)",
+            "list.all(x, x == 1 || missing)",
+            "list.exists(x, 'a' / x)",
+            "list.map(x, x / (x - 2))",
+            "list.map(x, x > 1, x * 10) + list.filter(x, x != 2)",
+            "list.map(x, x != 1, x / (x - 2))",
+            "list.filter(x, x)",
+            "list.map(x, x - 1, x)",
+            "list.filter(x, x == 2 || missing)",
+            "1.all(x, true)",
+            "list.filter(x, x > 1).map(x, [x, x * x]).map(x, x[1] - x[0])",
+            "[[1, 2], [3]].map(x, x.map(y, y + 1).size()).exists_one(x, x == 1)",
+            "list.map(x, list.filter(y, y < x).size())",
+            "{'a': 1, 'b': 2}.map(k, k).size() + {'a': [1]}.a.map(x, x)[0]",
+            "{'a': 1}.exists(k, {'b': 2}.all(j, j == k))",
+            "optional.none()[missing]",
+            "1[missing]",
+            "[1][missing]",
+            "{missing: 1}",
+            "{[1]: missing}",
+            "{'a': 1, 'a': missing}",
+            "list[?3].orValue(list[?1].value())",
+            "missing.matches('a')",
+            "matches(1, 'a')",
+            "1.matches('a')",
+            "ns.missing(1)",
+        ] {
+            let compile = || {
+                let parser = Parser::new().enable_optional_syntax(true);
+                Program::compile_with(parser, source).unwrap()
+            };
+            let expected = compile().execute_with_location(&ctx);
+            for backend in [Backend::Tree, Backend::Bytecode] {
+                let planned = compile().plan_with(&env, backend);
+                let actual = planned.execute_with_location(&ctx);
+                match (actual, &expected) {
+                    (Ok(actual), Ok(expected)) => {
+                        assert_eq!(&actual, expected, "`{source}` {backend:?}")
+                    }
+                    (Err(actual), Err(expected)) => {
+                        assert_eq!(actual.error, expected.error, "`{source}` {backend:?}");
+                        assert_eq!(actual.expr_id, expected.expr_id, "`{source}` {backend:?}");
+                    }
+                    (actual, expected) => {
+                        panic!("`{source}` {backend:?}: {actual:?} != {expected:?}")
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn comprehension_variables_are_slots() {
+        fn slots(node: &Node, found: &mut Vec<Slot>) {
+            match &node.kind {
+                NodeKind::Slot(slot) => found.push(*slot),
+                NodeKind::Op(_, args) => args.iter().for_each(|arg| slots(arg, found)),
+                NodeKind::List(elements) => elements.iter().for_each(|(e, _)| slots(e, found)),
+                NodeKind::Comprehension(c) => {
+                    for node in [&c.iter_range, &c.accu_init, &c.loop_cond] {
+                        slots(node, found);
+                    }
+                    match &c.loop_step {
+                        Step::Eval(step) => slots(step, found),
+                        Step::Append {
+                            condition, element, ..
+                        } => condition
+                            .iter()
+                            .chain([element])
+                            .for_each(|n| slots(n, found)),
+                    }
+                    slots(&c.result, found);
+                }
+                _ => {}
+            }
+        }
+
+        let program = Program::compile("[1].map(x, [2].map(y, x + y))").unwrap();
+        let plan = Plan::new(
+            program.expression(),
+            &Arc::new(Env::stdlib()),
+            Backend::Tree,
+        );
+        let Root::Tree(root) = &plan.root else {
+            unreachable!("planned for the tree backend")
+        };
+        let mut found = Vec::new();
+        slots(root, &mut found);
+        let (iter, accu) = (SlotVar::Iter, SlotVar::Accu);
+        assert_eq!(
+            found,
+            [
+                // The element the inner map appends, `x + y`, and its result.
+                Slot { up: 1, var: iter },
+                Slot { up: 0, var: iter },
+                Slot { up: 0, var: accu },
+                // The result of the outer map.
+                Slot { up: 0, var: accu },
+            ]
+        );
+    }
+
+    #[test]
+    fn binds_overloads_of_env() {
+        fn triple<'a>(args: Vec<Cow<'a, dyn Val>>) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+            let x = args[0].downcast_ref::<CelInt>().unwrap();
+            Ok(Cow::<dyn Val>::Owned(Box::new(CelInt::from(x.inner() * 3))))
+        }
+
+        let mut env = Env::stdlib();
+        env.add_overload("triple", "triple_int", vec![types::INT_TYPE], triple)
+            .unwrap();
+        env.add_member_overload("triple", "int_triple", types::INT_TYPE, vec![], triple)
+            .unwrap();
+
+        let env = Arc::new(env);
+        let program = Program::compile("triple(2) + 3.triple()").unwrap();
+        let ctx = Context::default();
+        assert_eq!(
+            program.execute(&ctx),
+            Err(ExecutionError::undeclared_reference("triple"))
+        );
+        let program = program.plan(&env);
+        assert_eq!(
+            program.execute(&Context::with_env(env.clone())),
+            Ok(Value::Int(15))
+        );
+        // The plan isn't used in a context of another env, which has other overloads
+        assert_eq!(
+            program.execute(&ctx),
+            Err(ExecutionError::undeclared_reference("triple"))
+        );
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn matches_evaluates_its_operand_once() {
+        use crate::context::VariableResolver;
+        use crate::extractors::This;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        struct Counting(AtomicUsize);
+        impl VariableResolver for Counting {
+            fn resolve(&self, variable: &str) -> Option<Value> {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                (variable == "x").then_some(Value::Int(1))
+            }
+        }
+
+        let env = Arc::new(Env::stdlib());
+        let resolver = Counting(AtomicUsize::new(0));
+        let mut ctx = Context::with_env(env.clone());
+        ctx.set_variable_resolver(&resolver);
+        ctx.add_function("matches", |This(x): This<i64>, _: Arc<String>| x == 1);
+        let compile = || Program::compile("x.matches('a')").unwrap();
+        for program in [
+            compile(),
+            compile().plan_with(&env, Backend::Tree),
+            compile().plan_with(&env, Backend::Bytecode),
+        ] {
+            resolver.0.store(0, Ordering::Relaxed);
+            assert_eq!(program.execute(&ctx), Ok(true.into()));
+            assert_eq!(resolver.0.load(Ordering::Relaxed), 1);
+        }
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn precompiled_patterns_only_replace_the_builtin_matches() {
+        fn always<'a>(_: Vec<Cow<'a, dyn Val>>) -> Result<Cow<'a, dyn Val>, ExecutionError> {
+            Ok(Cow::<dyn Val>::Owned(Box::new(types::CelBool::from(true))))
+        }
+
+        let mut env = Env::default();
+        env.add_member_overload(
+            "matches",
+            "matches_always",
+            types::STRING_TYPE,
+            vec![types::STRING_TYPE],
+            always,
+        )
+        .unwrap();
+        let env = Arc::new(env);
+        let ctx = Context::with_env(env.clone());
+        let compile = || Program::compile("'b'.matches('a')").unwrap();
+        for program in [
+            compile(),
+            compile().plan_with(&env, Backend::Tree),
+            compile().plan_with(&env, Backend::Bytecode),
+        ] {
+            assert_eq!(program.execute(&ctx), Ok(true.into()));
+        }
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn precompiled_patterns_are_within_the_limits_of_env() {
+        let compile = || Program::compile("'abc'.matches('^ab')").unwrap();
+        for max in [2, 3] {
+            let mut env = Env::stdlib();
+            env.set_regex_limits(crate::RegexLimits::default().max_pattern_length(max));
+            let env = Arc::new(env);
+            let ctx = Context::with_env(env.clone());
+            for program in [
+                compile(),
+                compile().plan_with(&env, Backend::Tree),
+                compile().plan_with(&env, Backend::Bytecode),
+            ] {
+                let result = program.execute(&ctx);
+                if max < 3 {
+                    assert!(
+                        matches!(result, Err(ExecutionError::RegexLimitExceeded(_))),
+                        "{result:?}"
+                    );
+                } else {
+                    assert_eq!(result, Ok(true.into()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn overloads_by_argument_types() {
+        let env = Arc::new(Env::stdlib());
+        let ctx = Context::with_env(env.clone());
+        let program = Program::compile("[int(1.5), int('2'), int(3u)]")
+            .unwrap()
+            .plan(&env);
+        assert_eq!(
+            program.execute(&ctx),
+            Ok(vec![Value::Int(1), Value::Int(2), Value::Int(3)].into())
+        );
+
+        let program = Program::compile("int(true)").unwrap().plan(&env);
+        assert_eq!(
+            program.execute(&ctx),
+            Err(ExecutionError::undeclared_reference("int"))
+        );
+    }
+}
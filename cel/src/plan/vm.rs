@@ -0,0 +1,1001 @@
+//! Execution of a [`Plan`](super::Plan) compiled to bytecode, see
+//! [`Backend::Bytecode`](super::Backend::Bytecode).
+//!
+//! The stack machine keeps the operands of the instructions on a stack of its own, and the
+//! state of the comprehensions being evaluated in a stack of loops, so that evaluating an
+//! expression doesn't recurse. The logical operators, the conditional and the index operator
+//! skip over the code of what they don't evaluate.
+//!
+//! Failures unwind to the innermost [`Instr::Try`], which guards the operands the interpreter
+//! recovers from the failure of, i.e. those of `||`, `&&` and `@not_strictly_false`. Each
+//! instruction comes with the id of the expression it evaluates, which is blamed when it fails.
+
+use super::{Call, Comprehension, Node, NodeKind, Op, Step};
+use crate::common::decls::OverloadDecl;
+use crate::common::traits;
+#[cfg(feature = "protobuf")]
+use crate::common::types::CelInt;
+use crate::common::types::{CelList, CelMap, CelMapKey, CelOptional, CelString, MapStorage};
+use crate::common::value::Val;
+use crate::context::{Context, Slot, SlotVar};
+use crate::objects::{self, bool, try_bool, Failure};
+use crate::ExecutionError;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::sync::Arc;
+
+/// A [`Plan`](super::Plan) compiled to instructions for the stack machine.
+pub(crate) struct Bytecode {
+    code: Vec<Instr>,
+    /// The id of the expression each instruction of `code` evaluates.
+    ids: Vec<u64>,
+    literals: Vec<Box<dyn Val>>,
+    names: Vec<Arc<String>>,
+    fields: Vec<CelString>,
+    calls: Vec<CallSite>,
+    /// Which elements of a list, or entries of a map, are optional.
+    optionals: Vec<Vec<bool>>,
+    #[cfg(feature = "structs")]
+    structs: Vec<(String, Vec<String>)>,
+    errors: Vec<ExecutionError>,
+}
+
+/// An instruction of the stack machine. Those jumping name the index of the instruction to
+/// jump to, and the others refer to the tables of [`Bytecode`] by index.
+#[derive(Clone, Copy, Debug)]
+enum Instr {
+    Literal(usize),
+    Ident(usize),
+    Slot(Slot),
+    /// Moves the accumulator of a comprehension onto the stack, for the only use of it in the
+    /// expression being evaluated.
+    TakeSlot(Slot),
+    Select {
+        field: usize,
+        test: bool,
+    },
+    /// Selects from the operand, or falls back to an enum value if it is undeclared.
+    #[cfg(feature = "protobuf")]
+    SelectOrEnum {
+        field: usize,
+        test: bool,
+        number: i32,
+    },
+    /// Guards the instructions up to the matching [`Instr::EndTry`]: a failure among them
+    /// unwinds the stacks, pushes the failure and jumps to the target.
+    Try(usize),
+    EndTry,
+    Jump(usize),
+    /// Jumps unless the condition of a conditional is true.
+    Branch(usize),
+    /// Turns the left operand of a logical operator into a boolean, jumping with the result
+    /// if it decides the operator.
+    Left(Op, usize),
+    Logical(Op),
+    NotStrictlyFalse,
+    /// Prepares the operand of an index operator, jumping with an empty optional if the
+    /// result is one.
+    IndexTarget(Op, usize),
+    Index,
+    Unary(Op),
+    Binary(Op),
+    Call(usize),
+    /// Calls the function the target of a member call names along with the function, if any,
+    /// jumping with the result.
+    QualifiedCall {
+        call: usize,
+        done: usize,
+    },
+    MemberCall(usize),
+    List(usize),
+    MapKey,
+    Map(usize),
+    /// Checks the type of a struct is known, before its fields are evaluated.
+    #[cfg(feature = "structs")]
+    StructType(usize),
+    #[cfg(feature = "structs")]
+    Struct(usize),
+    Fail(usize),
+    /// Starts a comprehension over the range on top of the stack, with the initial value of
+    /// the accumulator below it.
+    LoopStart,
+    /// Moves to the next item of the range, jumping once there is none.
+    LoopNext(usize),
+    /// Jumps out of the loop unless the condition is true.
+    LoopCond(usize),
+    LoopStep,
+    /// Appends the element on top of the stack to the list accumulated, in place, see
+    /// [`Step::Append`].
+    LoopAppend,
+    /// Ends the iteration, leaving the accumulator in scope for the result.
+    LoopEnd,
+    LoopExit,
+}
+
+/// A call to a function which isn't an operator, see [`Call`].
+struct CallSite {
+    name: Arc<String>,
+    arity: usize,
+    overloads: Vec<OverloadDecl>,
+    qualified: Option<(String, Vec<OverloadDecl>)>,
+}
+
+impl Bytecode {
+    pub(super) fn compile(root: &Node) -> Bytecode {
+        let mut compiler = Compiler {
+            bytecode: Bytecode {
+                code: Vec::new(),
+                ids: Vec::new(),
+                literals: Vec::new(),
+                names: Vec::new(),
+                fields: Vec::new(),
+                calls: Vec::new(),
+                optionals: Vec::new(),
+                #[cfg(feature = "structs")]
+                structs: Vec::new(),
+                errors: Vec::new(),
+            },
+            takes: Vec::new(),
+        };
+        compiler.compile(root);
+        compiler.bytecode
+    }
+
+    pub(crate) fn execute<'a>(&'a self, ctx: &'a Context<'a>) -> Result<Cow<'a, dyn Val>, Failure> {
+        let (stack, handlers) = SPARE_STACKS.take();
+        let mut machine = Machine {
+            stack: recycle(stack),
+            loops: Vec::new(),
+            handlers,
+        };
+        let result = machine.run(self, ctx);
+        let Machine {
+            mut stack,
+            mut handlers,
+            ..
+        } = machine;
+        stack.clear();
+        handlers.clear();
+        SPARE_STACKS.set((recycle(stack), handlers));
+        result
+    }
+}
+
+thread_local! {
+    /// The stacks of the last machine run on this thread, for the next one to reuse rather
+    /// than allocate its own.
+    static SPARE_STACKS: Cell<(Vec<Entry<'static>>, Vec<Handler>)> = const {
+        Cell::new((Vec::new(), Vec::new()))
+    };
+}
+
+/// Hands the allocation of an empty `stack` over to one for entries of another lifetime,
+/// which collecting in place reuses.
+fn recycle<'a, 'b>(stack: Vec<Entry<'a>>) -> Vec<Entry<'b>> {
+    stack
+        .into_iter()
+        .map(|_| unreachable!("the stack is empty"))
+        .collect()
+}
+
+struct Compiler {
+    bytecode: Bytecode,
+    /// Whether the expressions being compiled may take the accumulator of each comprehension
+    /// they are in, innermost last.
+    takes: Vec<bool>,
+}
+
+impl Compiler {
+    fn emit(&mut self, id: u64, instr: Instr) -> usize {
+        self.bytecode.code.push(instr);
+        self.bytecode.ids.push(id);
+        self.bytecode.code.len() - 1
+    }
+
+    /// Points the jump of the instruction `at` to the next instruction to be emitted.
+    fn patch(&mut self, at: usize) {
+        let target = self.bytecode.code.len();
+        match &mut self.bytecode.code[at] {
+            Instr::Try(to)
+            | Instr::Jump(to)
+            | Instr::Branch(to)
+            | Instr::Left(_, to)
+            | Instr::IndexTarget(_, to)
+            | Instr::LoopNext(to)
+            | Instr::LoopCond(to)
+            | Instr::QualifiedCall { done: to, .. } => *to = target,
+            instr => unreachable!("{instr:?} doesn't jump"),
+        }
+    }
+
+    /// Compiles the operand of an operator which recovers from its failure.
+    fn guarded(&mut self, node: &Node) {
+        let at = self.emit(node.id, Instr::Try(0));
+        self.compile(node);
+        self.emit(node.id, Instr::EndTry);
+        self.patch(at);
+    }
+
+    fn compile(&mut self, node: &Node) {
+        let id = node.id;
+        match &node.kind {
+            NodeKind::Literal(val) => {
+                self.bytecode.literals.push(val.clone_as_boxed());
+                self.emit(id, Instr::Literal(self.bytecode.literals.len() - 1));
+            }
+            NodeKind::Ident(name) => {
+                self.bytecode.names.push(name.clone());
+                self.emit(id, Instr::Ident(self.bytecode.names.len() - 1));
+            }
+            NodeKind::Slot(slot) => {
+                let takes = slot.var == SlotVar::Accu && self.takes[self.takes.len() - 1 - slot.up];
+                let instr = if takes {
+                    Instr::TakeSlot(*slot)
+                } else {
+                    Instr::Slot(*slot)
+                };
+                self.emit(id, instr);
+            }
+            NodeKind::Select {
+                operand,
+                field,
+                test,
+                #[cfg(feature = "protobuf")]
+                enum_value,
+            } => {
+                self.bytecode.fields.push(field.clone());
+                let field = self.bytecode.fields.len() - 1;
+                let test = *test;
+                #[cfg(feature = "protobuf")]
+                if let Some(number) = *enum_value {
+                    self.guarded(operand);
+                    self.emit(
+                        id,
+                        Instr::SelectOrEnum {
+                            field,
+                            test,
+                            number,
+                        },
+                    );
+                    return;
+                }
+                self.compile(operand);
+                self.emit(id, Instr::Select { field, test });
+            }
+            NodeKind::Op(op, args) => self.op(id, *op, args),
+            NodeKind::Call(call) => self.call(id, call),
+            NodeKind::List(elements) => {
+                for (element, _) in elements {
+                    self.compile(element);
+                }
+                let optionals = elements.iter().map(|(_, optional)| *optional).collect();
+                self.bytecode.optionals.push(optionals);
+                self.emit(id, Instr::List(self.bytecode.optionals.len() - 1));
+            }
+            NodeKind::Map(entries) => {
+                for (key, value, _) in entries {
+                    self.compile(key);
+                    self.emit(id, Instr::MapKey);
+                    self.compile(value);
+                }
+                let optionals = entries.iter().map(|(_, _, optional)| *optional).collect();
+                self.bytecode.optionals.push(optionals);
+                self.emit(id, Instr::Map(self.bytecode.optionals.len() - 1));
+            }
+            #[cfg(not(feature = "structs"))]
+            NodeKind::Struct { type_name, .. } => {
+                self.bytecode
+                    .errors
+                    .push(ExecutionError::InternalError(format!(
+                        "Found struct {type_name}, feature not enabled!"
+                    )));
+                self.emit(id, Instr::Fail(self.bytecode.errors.len() - 1));
+            }
+            #[cfg(feature = "structs")]
+            NodeKind::Struct { type_name, fields } => {
+                let names = fields.iter().map(|(name, _)| name.clone()).collect();
+                self.bytecode.structs.push((type_name.clone(), names));
+                let index = self.bytecode.structs.len() - 1;
+                self.emit(id, Instr::StructType(index));
+                for (_, value) in fields {
+                    self.compile(value);
+                }
+                self.emit(id, Instr::Struct(index));
+            }
+            NodeKind::Comprehension(comprehension) => self.comprehension(id, comprehension),
+            NodeKind::Invalid(err) => {
+                self.bytecode.errors.push(err.clone());
+                self.emit(id, Instr::Fail(self.bytecode.errors.len() - 1));
+            }
+        }
+    }
+
+    fn op(&mut self, id: u64, op: Op, args: &[Node]) {
+        match op {
+            Op::Conditional => {
+                self.compile(&args[0]);
+                let branch = self.emit(id, Instr::Branch(0));
+                self.compile(&args[1]);
+                let jump = self.emit(id, Instr::Jump(0));
+                self.patch(branch);
+                self.compile(&args[2]);
+                self.patch(jump);
+            }
+            Op::LogicalOr | Op::LogicalAnd => {
+                self.guarded(&args[0]);
+                let left = self.emit(id, Instr::Left(op, 0));
+                self.compile(&args[1]);
+                self.emit(id, Instr::Logical(op));
+                self.patch(left);
+            }
+            Op::NotStrictlyFalse => {
+                self.guarded(&args[0]);
+                self.emit(id, Instr::NotStrictlyFalse);
+            }
+            Op::Index | Op::OptIndex => {
+                self.compile(&args[0]);
+                let target = self.emit(id, Instr::IndexTarget(op, 0));
+                self.compile(&args[1]);
+                self.emit(id, Instr::Index);
+                self.patch(target);
+            }
+            Op::LogicalNot | Op::Negate => {
+                self.compile(&args[0]);
+                self.emit(id, Instr::Unary(op));
+            }
+            _ => {
+                self.compile(&args[0]);
+                self.compile(&args[1]);
+                self.emit(id, Instr::Binary(op));
+            }
+        }
+    }
+
+    fn call(&mut self, id: u64, call: &Call) {
+        self.bytecode.calls.push(CallSite {
+            name: call.name.clone(),
+            arity: call.args.len(),
+            overloads: call.overloads.clone(),
+            qualified: call.qualified.clone(),
+        });
+        let site = self.bytecode.calls.len() - 1;
+        let mut done = Vec::new();
+        for arg in &call.args {
+            self.compile(arg);
+        }
+        match &call.target {
+            None => {
+                self.emit(id, Instr::Call(site));
+            }
+            Some(target) => {
+                if call.qualified.is_some() {
+                    done.push(self.emit(
+                        id,
+                        Instr::QualifiedCall {
+                            call: site,
+                            done: 0,
+                        },
+                    ));
+                }
+                self.compile(target);
+                self.emit(id, Instr::MemberCall(site));
+            }
+        }
+        for at in done {
+            self.patch(at);
+        }
+    }
+
+    fn comprehension(&mut self, id: u64, comprehension: &Comprehension) {
+        self.compile(&comprehension.accu_init);
+        self.compile(&comprehension.iter_range);
+        self.emit(id, Instr::LoopStart);
+        let next = self.emit(id, Instr::LoopNext(0));
+
+        self.takes.push(false);
+        self.compile(&comprehension.loop_cond);
+        self.takes.pop();
+        let cond = self.emit(id, Instr::LoopCond(0));
+
+        match &comprehension.loop_step {
+            Step::Eval(step) => {
+                self.takes.push(accu_loads(step, 0) <= 1);
+                self.compile(step);
+                self.takes.pop();
+                self.emit(id, Instr::LoopStep);
+            }
+            Step::Append {
+                id: step,
+                condition,
+                element,
+            } => {
+                self.takes.push(false);
+                let branch = condition.as_ref().map(|condition| {
+                    self.compile(condition);
+                    self.emit(*step, Instr::Branch(0))
+                });
+                self.compile(element);
+                self.emit(*step, Instr::LoopAppend);
+                self.takes.pop();
+                if let Some(branch) = branch {
+                    self.patch(branch);
+                }
+            }
+        }
+        self.emit(id, Instr::Jump(next));
+
+        self.patch(next);
+        self.patch(cond);
+        self.emit(id, Instr::LoopEnd);
+        if comprehension.result_is_accu {
+            let accu = Slot {
+                up: 0,
+                var: SlotVar::Accu,
+            };
+            self.emit(id, Instr::TakeSlot(accu));
+        } else {
+            self.takes.push(accu_loads(&comprehension.result, 0) <= 1);
+            self.compile(&comprehension.result);
+            self.takes.pop();
+        }
+        self.emit(id, Instr::LoopExit);
+    }
+}
+
+/// How many times evaluating `node` may load the accumulator of the comprehension `up` levels
+/// out, where more than once is all the same.
+fn accu_loads(node: &Node, up: usize) -> usize {
+    let loads = |nodes: &mut dyn Iterator<Item = &Node>| nodes.map(|n| accu_loads(n, up)).sum();
+    match &node.kind {
+        NodeKind::Slot(slot) => usize::from(slot.up == up && slot.var == SlotVar::Accu),
+        NodeKind::Select { operand, .. } => accu_loads(operand, up),
+        NodeKind::Op(Op::Conditional, args) => {
+            accu_loads(&args[0], up) + accu_loads(&args[1], up).max(accu_loads(&args[2], up))
+        }
+        NodeKind::Op(_, args) => loads(&mut args.iter()),
+        NodeKind::Call(call) => loads(&mut call.target.iter().chain(&call.args)),
+        NodeKind::List(elements) => loads(&mut elements.iter().map(|(e, _)| e)),
+        NodeKind::Map(entries) => loads(&mut entries.iter().flat_map(|(k, v, _)| [k, v])),
+        NodeKind::Struct { fields, .. } => loads(&mut fields.iter().map(|(_, v)| v)),
+        NodeKind::Comprehension(c) => {
+            let step = match &c.loop_step {
+                Step::Eval(step) => accu_loads(step, up + 1),
+                Step::Append {
+                    condition, element, ..
+                } => condition
+                    .iter()
+                    .chain([element])
+                    .map(|node| accu_loads(node, up + 1))
+                    .sum(),
+            };
+            let body = accu_loads(&c.loop_cond, up + 1) + step;
+            accu_loads(&c.iter_range, up)
+                + accu_loads(&c.accu_init, up)
+                + body * 2
+                + accu_loads(&c.result, up + 1)
+        }
+        NodeKind::Literal(_) | NodeKind::Ident(_) | NodeKind::Invalid(_) => 0,
+    }
+}
+
+enum Entry<'a> {
+    Val(Cow<'a, dyn Val>),
+    /// A failure recovered from, so far.
+    Failed(Failure),
+    Bool(bool),
+    Key(CelMapKey),
+}
+
+/// The state of a comprehension being evaluated.
+struct Loop<'a> {
+    items: Items<'a>,
+    item: Option<Cow<'a, dyn Val>>,
+    accu: Option<Cow<'a, dyn Val>>,
+}
+
+enum Items<'a> {
+    Borrowed(Box<dyn traits::Iterator<'a> + 'a>),
+    /// The items of a range the expression computed, moved out of it.
+    Owned(std::vec::IntoIter<Box<dyn Val>>),
+}
+
+/// Where to resume evaluation after a failure, and the depths of the stacks to unwind to.
+struct Handler {
+    target: usize,
+    stack: usize,
+    loops: usize,
+}
+
+struct Machine<'a> {
+    stack: Vec<Entry<'a>>,
+    loops: Vec<Loop<'a>>,
+    handlers: Vec<Handler>,
+}
+
+impl<'a> Machine<'a> {
+    fn run(
+        &mut self,
+        code: &'a Bytecode,
+        ctx: &'a Context<'a>,
+    ) -> Result<Cow<'a, dyn Val>, Failure> {
+        let mut pc = 0;
+        while let Some(instr) = code.code.get(pc) {
+            let at = pc;
+            pc += 1;
+            if let Err(mut failure) = self.step(code, ctx, *instr, &mut pc) {
+                failure.at.get_or_insert(code.ids[at]);
+                let Some(handler) = self.handlers.pop() else {
+                    return Err(failure);
+                };
+                self.stack.truncate(handler.stack);
+                self.loops.truncate(handler.loops);
+                self.stack.push(Entry::Failed(failure));
+                pc = handler.target;
+            }
+        }
+        Ok(self.pop_val())
+    }
+
+    fn step(
+        &mut self,
+        code: &'a Bytecode,
+        ctx: &'a Context<'a>,
+        instr: Instr,
+        pc: &mut usize,
+    ) -> Result<(), Failure> {
+        let val = match instr {
+            Instr::Literal(i) => Cow::Borrowed(code.literals[i].as_ref()),
+            Instr::Ident(i) => {
+                let name = &code.names[i];
+                ctx.try_get_variable(name)?
+                    .ok_or_else(|| ExecutionError::UndeclaredReference(name.clone()))?
+            }
+            Instr::Slot(slot) => match self.slot(slot) {
+                Cow::Borrowed(val) => Cow::Borrowed(*val),
+                Cow::Owned(val) => Cow::Owned(val.clone_as_boxed()),
+            },
+            Instr::TakeSlot(slot) => {
+                let frame = self.frame(slot.up);
+                let var = match slot.var {
+                    SlotVar::Iter => &mut frame.item,
+                    SlotVar::Accu => &mut frame.accu,
+                };
+                var.take().expect("variable taken only once")
+            }
+            Instr::Select { field, test } => {
+                let operand = self.pop_val();
+                objects::select(operand, &code.fields[field], test)?
+            }
+            #[cfg(feature = "protobuf")]
+            Instr::SelectOrEnum {
+                field,
+                test,
+                number,
+            } => match self.pop() {
+                Entry::Failed(failure)
+                    if matches!(*failure.error, ExecutionError::UndeclaredReference(_)) =>
+                {
+                    Cow::<dyn Val>::Owned(Box::new(CelInt::from(number as i64)))
+                }
+                Entry::Failed(failure) => return Err(failure),
+                Entry::Val(operand) => objects::select(operand, &code.fields[field], test)?,
+                _ => unreachable!("operand is a value"),
+            },
+            Instr::Try(target) => {
+                self.handlers.push(Handler {
+                    target,
+                    stack: self.stack.len(),
+                    loops: self.loops.len(),
+                });
+                return Ok(());
+            }
+            Instr::EndTry => {
+                self.handlers.pop();
+                return Ok(());
+            }
+            Instr::Jump(target) => {
+                *pc = target;
+                return Ok(());
+            }
+            Instr::Branch(target) => {
+                if !try_bool::<Failure>(Ok(self.pop_val()))? {
+                    *pc = target;
+                }
+                return Ok(());
+            }
+            Instr::Left(op, target) => {
+                let left = match self.pop() {
+                    Entry::Val(val) => try_bool(Ok(val)),
+                    Entry::Failed(failure) => Err(failure),
+                    _ => unreachable!("operand is a value"),
+                };
+                if op.short_circuits(&left) {
+                    *pc = target;
+                    bool(op == Op::LogicalOr)
+                } else {
+                    self.stack.push(match left {
+                        Ok(left) => Entry::Bool(left),
+                        Err(failure) => Entry::Failed(failure),
+                    });
+                    return Ok(());
+                }
+            }
+            Instr::Logical(op) => {
+                let right = self.pop_val();
+                let left = match self.pop() {
+                    Entry::Bool(left) => Ok(left),
+                    Entry::Failed(failure) => Err(failure),
+                    _ => unreachable!("left operand is decided"),
+                };
+                bool(op.logical(left, right.as_ref())?)
+            }
+            Instr::NotStrictlyFalse => bool(match self.pop() {
+                Entry::Val(val) => try_bool::<ExecutionError>(Ok(val)).unwrap_or(true),
+                _ => true,
+            }),
+            Instr::IndexTarget(op, target) => match op.index_target(self.pop_val())? {
+                Some((val, is_optional)) => {
+                    self.stack.push(Entry::Val(val));
+                    self.stack.push(Entry::Bool(is_optional));
+                    return Ok(());
+                }
+                None => {
+                    *pc = target;
+                    Cow::<dyn Val>::Owned(Box::new(CelOptional::none()))
+                }
+            },
+            Instr::Index => {
+                let index = self.pop_val();
+                let Entry::Bool(is_optional) = self.pop() else {
+                    unreachable!("index target is prepared")
+                };
+                let val = self.pop_val();
+                Op::index(val, is_optional, index.as_ref())?
+            }
+            Instr::Unary(op) => op.unary(self.pop_val())?,
+            Instr::Binary(op) => {
+                let rhs = self.pop_val();
+                let lhs = self.pop_val();
+                op.binary(lhs, rhs, ctx)?
+            }
+            Instr::Call(call) => {
+                let site = &code.calls[call];
+                let args = self.pop_vals(site.arity);
+                if let Some(overload) = site.overloads.iter().find(|o| o.accepts(&args)) {
+                    overload.call(args)?
+                } else {
+                    let func = ctx
+                        .get_function(&site.name)
+                        .ok_or_else(|| ExecutionError::UndeclaredReference(site.name.clone()))?;
+                    objects::call_function(&site.name, func, None, ctx, args)?
+                }
+            }
+            Instr::QualifiedCall { call, done } => {
+                let site = &code.calls[call];
+                let (name, overloads) = site.qualified.as_ref().expect("qualified call");
+                let args = self.pop_vals(site.arity);
+                if let Some(overload) = overloads.iter().find(|o| o.accepts(&args)) {
+                    *pc = done;
+                    overload.call(args)?
+                } else if let Some(func) = ctx.get_function(name) {
+                    *pc = done;
+                    objects::call_function(&site.name, func, None, ctx, args)?
+                } else {
+                    self.stack.extend(args.into_iter().map(Entry::Val));
+                    return Ok(());
+                }
+            }
+            Instr::MemberCall(call) => {
+                let site = &code.calls[call];
+                let target = self.pop_val();
+                let mut args = self.pop_vals(site.arity);
+                args.insert(0, target);
+                if let Some(overload) = site.overloads.iter().find(|o| o.accepts(&args)) {
+                    overload.call(args)?
+                } else {
+                    let target = args.remove(0);
+                    let func = ctx
+                        .get_function(&site.name)
+                        .ok_or_else(|| ExecutionError::UndeclaredReference(site.name.clone()))?;
+                    objects::call_function(&site.name, func, Some(target), ctx, args)?
+                }
+            }
+            Instr::List(i) => {
+                let optionals = &code.optionals[i];
+                let mut list = Vec::with_capacity(optionals.len());
+                for (value, optional) in self.pop_vals(optionals.len()).into_iter().zip(optionals) {
+                    match value.downcast_ref::<CelOptional>() {
+                        Some(opt) if *optional => {
+                            list.extend(opt.inner().map(|v| v.clone_as_boxed()))
+                        }
+                        _ => list.push(value.into_owned()),
+                    }
+                }
+                Cow::<dyn Val>::Owned(Box::new(CelList::from(list)))
+            }
+            Instr::MapKey => {
+                let key: CelMapKey = self.pop_val().into_owned().try_into()?;
+                self.stack.push(Entry::Key(key));
+                return Ok(());
+            }
+            Instr::Map(i) => {
+                let optionals = &code.optionals[i];
+                let mut map = MapStorage::with_capacity(optionals.len());
+                let start = self.stack.len() - 2 * optionals.len();
+                let mut entries = self.stack.drain(start..);
+                for optional in optionals {
+                    let (Some(Entry::Key(key)), Some(Entry::Val(value))) =
+                        (entries.next(), entries.next())
+                    else {
+                        unreachable!("map entries are evaluated")
+                    };
+                    let value = value.into_owned();
+                    match value.downcast_ref::<CelOptional>() {
+                        Some(opt) if *optional => {
+                            if let Some(inner) = opt.inner() {
+                                map.insert(key, inner.clone_as_boxed());
+                            }
+                        }
+                        _ => {
+                            map.insert(key, value);
+                        }
+                    }
+                }
+                let map: Box<CelMap> = CelMap::from(map).into();
+                Cow::<dyn Val>::Owned(map)
+            }
+            #[cfg(feature = "structs")]
+            Instr::StructType(i) => {
+                let type_name = &code.structs[i].0;
+                #[cfg(feature = "protobuf")]
+                if ctx.env().find_message(type_name).is_some() {
+                    return Ok(());
+                }
+                if ctx.env().find_struct(type_name).is_none() {
+                    return Err(ExecutionError::UnexpectedType {
+                        got: type_name.to_owned(),
+                        want: "known struct".to_owned(),
+                    }
+                    .into());
+                }
+                return Ok(());
+            }
+            #[cfg(feature = "structs")]
+            Instr::Struct(i) => {
+                let (type_name, names) = &code.structs[i];
+                let values = names.iter().cloned().zip(self.pop_vals(names.len()));
+                #[cfg(feature = "protobuf")]
+                if let Some(desc) = ctx.env().find_message(type_name) {
+                    let msg =
+                        crate::common::types::CelMessage::new_message(desc, values.collect())?;
+                    self.stack
+                        .push(Entry::Val(Cow::<dyn Val>::Owned(Box::new(msg))));
+                    return Ok(());
+                }
+                let struct_def = ctx
+                    .env()
+                    .find_struct(type_name)
+                    .expect("struct type checked");
+                let s = struct_def.new_struct(values.collect())?;
+                Cow::<dyn Val>::Owned(Box::new(s))
+            }
+            Instr::Fail(i) => return Err(code.errors[i].clone().into()),
+            Instr::LoopStart => {
+                let range = self.pop_val();
+                let accu = self.pop_val();
+                let items = match range {
+                    Cow::Borrowed(range) => Items::Borrowed(
+                        range
+                            .as_iterable()
+                            .ok_or(ExecutionError::NoSuchOverload)?
+                            .iter(),
+                    ),
+                    Cow::Owned(range) => {
+                        let items = match Vec::<Box<dyn Val>>::try_from(range) {
+                            Ok(items) => items,
+                            Err(range) => {
+                                let mut items = Vec::new();
+                                let mut iter = range
+                                    .as_iterable()
+                                    .ok_or(ExecutionError::NoSuchOverload)?
+                                    .iter();
+                                while let Some(item) = iter.next() {
+                                    items.push(item.clone_as_boxed());
+                                }
+                                items
+                            }
+                        };
+                        Items::Owned(items.into_iter())
+                    }
+                };
+                self.loops.push(Loop {
+                    items,
+                    item: None,
+                    accu: Some(accu),
+                });
+                return Ok(());
+            }
+            Instr::LoopNext(target) => {
+                let frame = self.frame(0);
+                frame.item = match &mut frame.items {
+                    Items::Borrowed(items) => items.next().map(Cow::Borrowed),
+                    Items::Owned(items) => items.next().map(Cow::Owned),
+                };
+                if frame.item.is_none() {
+                    *pc = target;
+                }
+                return Ok(());
+            }
+            Instr::LoopCond(target) => {
+                if !try_bool::<Failure>(Ok(self.pop_val()))? {
+                    *pc = target;
+                }
+                return Ok(());
+            }
+            Instr::LoopStep => {
+                let accu = self.pop_val();
+                self.frame(0).accu = Some(accu);
+                return Ok(());
+            }
+            Instr::LoopAppend => {
+                let element = self.pop_val().into_owned();
+                let accu = self.frame(0).accu.as_mut().expect("accumulator in scope");
+                objects::append(accu, element)?;
+                return Ok(());
+            }
+            Instr::LoopEnd => {
+                self.frame(0).item = None;
+                return Ok(());
+            }
+            Instr::LoopExit => {
+                self.loops.pop();
+                return Ok(());
+            }
+        };
+        self.stack.push(Entry::Val(val));
+        Ok(())
+    }
+
+    fn frame(&mut self, up: usize) -> &mut Loop<'a> {
+        let depth = self.loops.len() - 1 - up;
+        &mut self.loops[depth]
+    }
+
+    fn slot(&mut self, slot: Slot) -> &Cow<'a, dyn Val> {
+        let frame = self.frame(slot.up);
+        let var = match slot.var {
+            SlotVar::Iter => &frame.item,
+            SlotVar::Accu => &frame.accu,
+        };
+        var.as_ref().expect("variable in scope")
+    }
+
+    fn pop(&mut self) -> Entry<'a> {
+        self.stack.pop().expect("operand on the stack")
+    }
+
+    fn pop_val(&mut self) -> Cow<'a, dyn Val> {
+        match self.pop() {
+            Entry::Val(val) => val,
+            _ => unreachable!("operand is a value"),
+        }
+    }
+
+    fn pop_vals(&mut self, n: usize) -> Vec<Cow<'a, dyn Val>> {
+        let start = self.stack.len() - n;
+        self.stack
+            .drain(start..)
+            .map(|entry| match entry {
+                Entry::Val(val) => val,
+                _ => unreachable!("operand is a value"),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bytecode, Instr};
+    use crate::common::types::CelInt;
+    use crate::context::{Slot, SlotVar};
+    use crate::plan::{Backend, Comprehension, Node, NodeKind, Op, Plan, Root, Step};
+    use crate::{Context, Env, Program, Value};
+    use std::sync::Arc;
+
+    fn bytecode(source: &str) -> Bytecode {
+        let program = Program::compile(source).unwrap();
+        let plan = Plan::new(
+            program.expression(),
+            &Arc::new(Env::stdlib()),
+            Backend::Bytecode,
+        );
+        match plan.root {
+            Root::Bytecode(bytecode) => bytecode,
+            Root::Tree(_) => unreachable!("planned for the bytecode backend"),
+        }
+    }
+
+    /// The accumulator loads, and whether they take it, in order.
+    fn accu_loads(source: &str) -> Vec<bool> {
+        bytecode(source)
+            .code
+            .iter()
+            .filter_map(|instr| match instr {
+                Instr::Slot(slot) if slot.var == SlotVar::Accu => Some(false),
+                Instr::TakeSlot(slot) if slot.var == SlotVar::Accu => Some(true),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn takes_accumulator_used_once() {
+        // The condition of `exists` reads the accumulator, its step uses it once, and the
+        // result is the accumulator.
+        assert_eq!(accu_loads("[1].exists(x, x > 0)"), [false, true, true]);
+        // Only one of the branches of `exists_one`'s step is evaluated.
+        assert_eq!(accu_loads("[1].exists_one(x, x > 0)"), [true, true, true]);
+    }
+
+    #[test]
+    fn counts_accumulator_loads() {
+        fn node(kind: NodeKind) -> Node {
+            Node { id: 0, kind }
+        }
+        fn slot(up: usize, var: SlotVar) -> Node {
+            node(NodeKind::Slot(Slot { up, var }))
+        }
+        fn literal() -> Node {
+            node(NodeKind::Literal(Box::new(CelInt::from(1))))
+        }
+        let accu = || slot(0, SlotVar::Accu);
+
+        let sum = node(NodeKind::Op(Op::Add, vec![accu(), accu()]));
+        assert_eq!(super::accu_loads(&sum, 0), 2);
+        let conditional = node(NodeKind::Op(
+            Op::Conditional,
+            vec![slot(0, SlotVar::Iter), accu(), accu()],
+        ));
+        assert_eq!(super::accu_loads(&conditional, 0), 1);
+
+        // Within a comprehension, the loop may load the accumulator of the outer one many
+        // times, but the result only once.
+        let comprehension = |loop_step, result| {
+            node(NodeKind::Comprehension(Box::new(Comprehension {
+                iter_range: literal(),
+                iter_var: Arc::new("x".into()),
+                accu_var: Arc::new("@result".into()),
+                accu_init: literal(),
+                loop_cond: literal(),
+                loop_step: Step::Eval(loop_step),
+                result,
+                result_is_accu: false,
+            })))
+        };
+        let in_loop = comprehension(slot(1, SlotVar::Accu), literal());
+        assert_eq!(super::accu_loads(&in_loop, 0), 2);
+        let in_result = comprehension(literal(), slot(1, SlotVar::Accu));
+        assert_eq!(super::accu_loads(&in_result, 0), 1);
+    }
+
+    #[test]
+    fn iterates_ranges_computed() {
+        let env = Arc::new(Env::stdlib());
+        let ctx = Context::with_env(env.clone());
+        let program = Program::compile("[3, 1, 2].filter(x, x > 1).map(x, {'a': x}).map(m, m.a)")
+            .unwrap()
+            .plan_with(&env, Backend::Bytecode);
+        assert_eq!(
+            program.execute(&ctx),
+            Ok(vec![Value::Int(3), Value::Int(2)].into())
+        );
+
+        let program = Program::compile("{'a': 1, 'b': 2}.filter(k, k != 'a')")
+            .unwrap()
+            .plan_with(&env, Backend::Bytecode);
+        assert_eq!(
+            program.execute(&ctx),
+            Ok(vec![Value::String("b".to_string().into())].into())
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use crate::parser::Expression;
-use crate::{ExecutionError, FunctionContext, ResolveResult, Value};
+use crate::{FunctionContext, ResolveResult, Value};
 
 /// Resolver knows how to resolve a [`Value`] from a [`FunctionContext`].
 /// At their core, resolvers are responsible for taking Expressions and
@@ -7,11 +7,10 @@ use crate::{ExecutionError, FunctionContext, ResolveResult, Value};
 /// some of the complexity surrounding how the expression is obtained in
 /// the first place.
 ///
-/// For example, the [`Argument`] resolver takes an index and resolves the
-/// corresponding argument from the [`FunctionContext`]. Resolver makes it
-/// easy to (1) get the expression for a specific argument index, (2)
-/// return an error if the argument is missing, and (3) resolve the expression
-/// into a value.
+/// For example, an [`Expression`] resolves against the variables of the
+/// [`FunctionContext`]'s context. The arguments of a function are already
+/// resolved, they are available as [`Val`](crate::common::value::Val)s in
+/// [`FunctionContext::args`].
 pub trait Resolver {
     fn resolve(&self, ctx: &FunctionContext) -> ResolveResult;
 }
@@ -21,41 +20,3 @@ impl Resolver for Expression {
         Value::resolve(self, ctx.ptx)
     }
 }
-
-/// Argument is a [`Resolver`] that resolves to the nth argument.
-///
-/// # Example
-/// ```skip
-/// let arg0 = ftx.resolve(Argument(0))?;
-/// ```
-pub(crate) struct Argument(pub usize);
-
-impl Resolver for Argument {
-    fn resolve(&self, ctx: &FunctionContext) -> ResolveResult {
-        let index = self.0;
-        let arg = ctx
-            .args
-            .get(index)
-            .ok_or_else(|| ExecutionError::invalid_argument_count(index + 1, ctx.args.len()))?;
-        arg.as_ref().try_into()
-    }
-}
-
-/// A resolver for all arguments passed to a function. Each argument will be
-/// resolved and then returned as a [`Value::List`]
-///
-/// # Example
-/// ```skip
-/// let args = ftx.resolve(AllArguments)?;
-/// ```
-pub(crate) struct AllArguments;
-
-impl Resolver for AllArguments {
-    fn resolve(&self, ctx: &FunctionContext) -> ResolveResult {
-        let mut args = Vec::with_capacity(ctx.args.len());
-        for arg in ctx.args.iter() {
-            args.push(arg.as_ref().try_into()?);
-        }
-        Ok(Value::List(args.into()))
-    }
-}
@@ -3,7 +3,11 @@
 // from [serde_json](https://github.com/serde-rs/json/blob/master/src/value/ser.rs),
 // also mentioned in the [serde documentation](https://serde.rs/).
 
-use crate::{objects::Key, Value};
+use crate::{
+    common::types::MapStorage,
+    objects::{Key, OptionalValue},
+    Value,
+};
 use serde::{
     ser::{self, Impossible},
     Serialize,
@@ -14,6 +18,8 @@ use thiserror::Error;
 #[cfg(feature = "chrono")]
 use chrono::FixedOffset;
 #[cfg(feature = "chrono")]
+use serde::de;
+#[cfg(feature = "chrono")]
 use serde::ser::SerializeStruct;
 
 pub struct Serializer;
@@ -58,10 +64,10 @@ impl Duration {
     // Since serde can't natively represent durations, we serialize a special
     // newtype to indicate we want to rebuild the duration in the result, while
     // remaining compatible with most other Serializer implementations.
-    const NAME: &str = "$__cel_private_Duration";
+    pub(crate) const NAME: &str = "$__cel_private_Duration";
     const STRUCT_NAME: &str = "Duration";
-    const SECS_FIELD: &str = "secs";
-    const NANOS_FIELD: &str = "nanos";
+    pub(crate) const SECS_FIELD: &str = "secs";
+    pub(crate) const NANOS_FIELD: &str = "nanos";
 }
 
 #[cfg(feature = "chrono")]
@@ -106,6 +112,81 @@ impl ser::Serialize for Duration {
     }
 }
 
+#[cfg(feature = "chrono")]
+impl<'de> de::Deserialize<'de> for Duration {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        // The inverse of the `DurationProxy` above: a struct of seconds and nanoseconds
+        // wrapped in the marker newtype struct.
+        struct DurationVisitor;
+        impl<'de> de::Visitor<'de> for DurationVisitor {
+            type Value = Duration;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a duration")
+            }
+
+            fn visit_newtype_struct<D>(
+                self,
+                deserializer: D,
+            ) -> std::result::Result<Self::Value, D::Error>
+            where
+                D: de::Deserializer<'de>,
+            {
+                deserializer.deserialize_struct(
+                    Duration::STRUCT_NAME,
+                    &[Duration::SECS_FIELD, Duration::NANOS_FIELD],
+                    self,
+                )
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error>
+            where
+                A: de::SeqAccess<'de>,
+            {
+                let secs: i64 = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let nanos: i64 = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                duration(secs, nanos)
+            }
+
+            fn visit_map<A>(self, mut map: A) -> std::result::Result<Self::Value, A::Error>
+            where
+                A: de::MapAccess<'de>,
+            {
+                let (mut secs, mut nanos) = (None, None);
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        Duration::SECS_FIELD => secs = Some(map.next_value::<i64>()?),
+                        Duration::NANOS_FIELD => nanos = Some(map.next_value::<i64>()?),
+                        _ => {
+                            map.next_value::<de::IgnoredAny>()?;
+                        }
+                    }
+                }
+                duration(
+                    secs.ok_or_else(|| de::Error::missing_field(Duration::SECS_FIELD))?,
+                    nanos.ok_or_else(|| de::Error::missing_field(Duration::NANOS_FIELD))?,
+                )
+            }
+        }
+
+        fn duration<E: de::Error>(secs: i64, nanos: i64) -> std::result::Result<Duration, E> {
+            chrono::Duration::try_seconds(secs)
+                .and_then(|d| d.checked_add(&chrono::Duration::nanoseconds(nanos)))
+                .map(Duration)
+                .ok_or_else(|| de::Error::custom("duration out of range"))
+        }
+
+        deserializer.deserialize_newtype_struct(Self::NAME, DurationVisitor)
+    }
+}
+
 /// A wrapper Timestamp type which allows conversion to [Value::Timestamp] for
 /// types using automatic conversion with [serde::Serialize].
 ///
@@ -147,7 +228,7 @@ impl Timestamp {
     // Since serde can't natively represent timestamps, we serialize a special
     // newtype to indicate we want to rebuild the timestamp in the result,
     // while remaining compatible with most other Serializer implementations.
-    const NAME: &str = "$__cel_private_Timestamp";
+    pub(crate) const NAME: &str = "$__cel_private_Timestamp";
 }
 
 #[cfg(feature = "chrono")]
@@ -174,6 +255,43 @@ impl ser::Serialize for Timestamp {
     }
 }
 
+#[cfg(feature = "chrono")]
+impl<'de> de::Deserialize<'de> for Timestamp {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        struct TimestampVisitor;
+        impl<'de> de::Visitor<'de> for TimestampVisitor {
+            type Value = Timestamp;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("an RFC 3339 timestamp")
+            }
+
+            fn visit_newtype_struct<D>(
+                self,
+                deserializer: D,
+            ) -> std::result::Result<Self::Value, D::Error>
+            where
+                D: de::Deserializer<'de>,
+            {
+                de::Deserialize::deserialize(deserializer).map(Timestamp)
+            }
+
+            fn visit_str<E>(self, v: &str) -> std::result::Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                v.parse::<chrono::DateTime<FixedOffset>>()
+                    .map(Timestamp)
+                    .map_err(de::Error::custom)
+            }
+        }
+        deserializer.deserialize_newtype_struct(Self::NAME, TimestampVisitor)
+    }
+}
+
 #[derive(Error, Debug, PartialEq, Clone)]
 pub enum SerializationError {
     InvalidKey(String),
@@ -200,6 +318,88 @@ impl Display for SerializationError {
 
 pub type Result<T> = std::result::Result<T, SerializationError>;
 
+/// The name of the newtype struct an optional [`Value`] is written as, so that [`to_value`]
+/// reads it back as an optional rather than as the value it holds.
+const OPTIONAL_NAME: &str = "$__cel_private_Optional";
+
+/// The name of the newtype struct structs and opaque values are written as. Other formats
+/// write what it wraps, but [`to_value`] can't turn it back into the value it came from.
+const UNCONVERTIBLE_NAME: &str = "$__cel_private_Unconvertible";
+
+/// Writes a [`Value`] to any serde format: `null` is a unit, lists are sequences, maps are
+/// maps, and timestamps and durations are written as [`Timestamp`] and [`Duration`] are.
+/// Optional values are options, structs are maps of their fields, while other opaque values
+/// are written as their [`Opaque::json`](crate::objects::Opaque::json) representation, when
+/// they have one.
+///
+/// [`to_value`] reads back everything but structs and opaque values exactly, which is how a
+/// [`Value`] added with [`Context::add_variable`](crate::Context::add_variable) gets there.
+/// Add those with [`Context::add_variable_from_value`](crate::Context::add_variable_from_value)
+/// instead.
+///
+/// See [`tagged`](crate::tagged) for a representation which keeps the type of every value.
+///
+/// # Example
+/// ```
+/// use cel::Value;
+///
+/// let value = Value::from(vec![Value::Int(1), Value::Null]);
+/// let json = serde_json::to_string(&value).unwrap();
+/// assert_eq!(json, "[1,null]");
+/// ```
+impl Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        match self {
+            Value::Null => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Int(i) => serializer.serialize_i64(*i),
+            Value::UInt(u) => serializer.serialize_u64(*u),
+            Value::Float(f) => serializer.serialize_f64(*f),
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Bytes(b) => serializer.serialize_bytes(b),
+            Value::List(l) => serializer.collect_seq(l.iter()),
+            Value::Map(m) => serializer.collect_map(m.map.iter()),
+            #[cfg(feature = "chrono")]
+            Value::Timestamp(ts) => Timestamp(*ts).serialize(serializer),
+            #[cfg(feature = "chrono")]
+            Value::Duration(d) => Duration(*d).serialize(serializer),
+            #[cfg(feature = "structs")]
+            Value::Struct(s) => {
+                let fields = s
+                    .field_values()
+                    .into_iter()
+                    .map(|(k, v)| Value::try_from(v.as_ref()).map(|v| (k, v)))
+                    .collect::<std::result::Result<std::collections::BTreeMap<_, _>, _>>()
+                    .map_err(ser::Error::custom)?;
+                serializer.serialize_newtype_struct(UNCONVERTIBLE_NAME, &fields)
+            }
+            Value::Opaque(o) => match o.downcast_ref::<OptionalValue>() {
+                Some(opt) => serializer.serialize_newtype_struct(OPTIONAL_NAME, &opt.value()),
+                #[cfg(feature = "json")]
+                None => match o.json() {
+                    Some(json) => serializer.serialize_newtype_struct(UNCONVERTIBLE_NAME, &json),
+                    None => Err(ser::Error::custom(format!(
+                        "opaque value of type '{}' can't be serialized",
+                        o.runtime_type_name()
+                    ))),
+                },
+                #[cfg(not(feature = "json"))]
+                None => Err(ser::Error::custom(format!(
+                    "opaque value of type '{}' can't be serialized",
+                    o.runtime_type_name()
+                ))),
+            },
+            other => Err(ser::Error::custom(format!(
+                "value of type '{}' can't be serialized",
+                other.type_of()
+            ))),
+        }
+    }
+}
+
 pub fn to_value<T>(value: T) -> Result<Value>
 where
     T: Serialize,
@@ -312,6 +512,12 @@ impl ser::Serializer for Serializer {
             Duration::NAME => value.serialize(TimeSerializer::Duration),
             #[cfg(feature = "chrono")]
             Timestamp::NAME => value.serialize(TimeSerializer::Timestamp),
+            OPTIONAL_NAME => value.serialize(OptionalSerializer),
+            UNCONVERTIBLE_NAME => Err(SerializationError::SerdeError(
+                "structs and opaque values can't be converted back from serde, add them with \
+                 `Context::add_variable_from_value` instead"
+                    .to_string(),
+            )),
             _ => value.serialize(self),
         }
     }
@@ -362,7 +568,7 @@ impl ser::Serializer for Serializer {
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
         Ok(SerializeMap {
-            map: HashMap::new(),
+            map: MapStorage::new(),
             next_key: None,
         })
     }
@@ -380,7 +586,7 @@ impl ser::Serializer for Serializer {
     ) -> Result<Self::SerializeStructVariant> {
         Ok(SerializeStructVariant {
             name: String::from(variant),
-            map: HashMap::new(),
+            map: MapStorage::new(),
         })
     }
 }
@@ -395,13 +601,13 @@ pub struct SerializeTupleVariant {
 }
 
 pub struct SerializeMap {
-    map: HashMap<Key, Value>,
+    map: MapStorage<Key, Value>,
     next_key: Option<Key>,
 }
 
 pub struct SerializeStructVariant {
     name: String,
-    map: HashMap<Key, Value>,
+    map: MapStorage<Key, Value>,
 }
 
 #[cfg(feature = "chrono")]
@@ -971,6 +1177,174 @@ impl ser::Serializer for TimeSerializer {
     }
 }
 
+/// Reads back the option an optional [`Value`] is written as, keeping `optional.none()` apart
+/// from `optional.of(null)`.
+struct OptionalSerializer;
+
+impl ser::Serializer for OptionalSerializer {
+    type Ok = Value;
+    type Error = SerializationError;
+
+    // Only options are written under the optional marker.
+    type SerializeSeq = Impossible<Value, SerializationError>;
+    type SerializeTuple = Impossible<Value, SerializationError>;
+    type SerializeTupleStruct = Impossible<Value, SerializationError>;
+    type SerializeTupleVariant = Impossible<Value, SerializationError>;
+    type SerializeMap = Impossible<Value, SerializationError>;
+    type SerializeStruct = Impossible<Value, SerializationError>;
+    type SerializeStructVariant = Impossible<Value, SerializationError>;
+
+    fn serialize_none(self) -> Result<Value> {
+        Ok(Value::Opaque(Arc::new(OptionalValue::none())))
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<Value>
+    where
+        T: ?Sized + Serialize,
+    {
+        Ok(Value::Opaque(Arc::new(OptionalValue::of(
+            value.serialize(Serializer)?,
+        ))))
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_char(self, _v: char) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_unit(self) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Value> {
+        unreachable!()
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, _value: &T) -> Result<Value>
+    where
+        T: ?Sized + Serialize,
+    {
+        unreachable!()
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Value>
+    where
+        T: ?Sized + Serialize,
+    {
+        unreachable!()
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        unreachable!()
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        unreachable!()
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        unreachable!()
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        unreachable!()
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        unreachable!()
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        unreachable!()
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        unreachable!()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[cfg(feature = "chrono")]
@@ -1372,4 +1746,64 @@ mod tests {
         let actual = serde_json::to_string(&tests).unwrap();
         assert_eq!(actual, expect);
     }
+
+    #[test]
+    fn test_serialize_value() {
+        let value = Program::compile(
+            "{'list': [null, true, -1, 1u, 1.5, b'\\x01'], 'opt': optional.of('x'), 'none': optional.none()}",
+        )
+        .unwrap()
+        .execute(&Context::default())
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(&value).unwrap(),
+            serde_json::json!({
+                "list": [null, true, -1, 1, 1.5, [1]],
+                "opt": "x",
+                "none": null,
+            })
+        );
+        let back: Value = serde_json::from_value(serde_json::to_value(&value).unwrap()).unwrap();
+        assert_eq!(
+            back,
+            Program::compile("{'list': [null, true, -1, 1, 1.5, [1]], 'opt': 'x', 'none': null}")
+                .unwrap()
+                .execute(&Context::default())
+                .unwrap()
+        );
+        let function = Value::Function(Arc::new("size".into()), None);
+        assert!(serde_json::to_value(&function).is_err());
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_serialize_time_values() {
+        let value = Program::compile("[timestamp('2023-05-28T00:00:00Z'), duration('1.5s')]")
+            .unwrap()
+            .execute(&Context::default())
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&value).unwrap(),
+            serde_json::json!(["2023-05-28T00:00:00Z", {"secs": 1, "nanos": 500000000}])
+        );
+    }
+
+    #[test]
+    fn test_add_value_variable() {
+        let mut context = Context::default();
+        context.add_variable("n", Value::UInt(1)).unwrap();
+        context
+            .add_variable("b", Value::Bytes(Arc::new(vec![1])))
+            .unwrap();
+        let optionals = Program::compile("[optional.none(), optional.of(null)]")
+            .unwrap()
+            .execute(&context)
+            .unwrap();
+        context.add_variable("o", optionals).unwrap();
+        let program = Program::compile(
+            "n == 1u && b == b'\\x01' && !o[0].hasValue() && o[1].hasValue() && o[1].value() == null",
+        )
+        .unwrap();
+        assert_eq!(program.execute(&context), Ok(true.into()));
+    }
 }
@@ -0,0 +1,499 @@
+//! A lossless serde representation of [`Value`].
+//!
+//! The [`Serialize`] and [`Deserialize`] impls of [`Value`] map values onto the
+//! data model every format understands, which can't tell an `int` from a `uint`, or a timestamp
+//! from a string. In the tagged representation, every value is written as a variant of an enum named
+//! after its CEL type instead, so that it reads back exactly as it was written, in
+//! self-describing formats and in compact ones like bincode alike:
+//!
+//! | CEL type    | variant                                          |
+//! |-------------|--------------------------------------------------|
+//! | `null_type` | `null`, a unit variant                           |
+//! | `bool`      | `bool(bool)`                                     |
+//! | `int`       | `int(i64)`                                       |
+//! | `uint`      | `uint(u64)`                                      |
+//! | `double`    | `double(f64)`                                    |
+//! | `string`    | `string(String)`                                 |
+//! | `bytes`     | `bytes(bytes)`                                   |
+//! | `list`      | `list(seq)` of tagged values                     |
+//! | `map`       | `map(seq)` of (tagged key, tagged value) pairs   |
+//! | `timestamp` | `timestamp(String)`, in RFC 3339                 |
+//! | `duration`  | `duration((i64, i32))`, as seconds and nanos     |
+//! | `optional`  | `optional(Option)` of a tagged value             |
+//!
+//! Structs, messages, functions and other opaque values have no tagged representation.
+//!
+//! Use [`Tagged`] to wrap a value, or this module with `#[serde(with = "cel::tagged")]`:
+//!
+//! ```
+//! use cel::Value;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct CacheEntry {
+//!     #[serde(with = "cel::tagged")]
+//!     result: Value,
+//! }
+//!
+//! let entry = CacheEntry { result: Value::UInt(42) };
+//! let json = serde_json::to_string(&entry).unwrap();
+//! assert_eq!(json, r#"{"result":{"uint":42}}"#);
+//! let entry: CacheEntry = serde_json::from_str(&json).unwrap();
+//! assert_eq!(entry.result, Value::UInt(42));
+//! ```
+
+use crate::common::types::MapStorage;
+use crate::objects::{Key, OptionalValue};
+use crate::Value;
+use serde::de::{self, EnumAccess, VariantAccess, Visitor};
+use serde::ser::{self, SerializeSeq};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::sync::Arc;
+
+const NAME: &str = "Value";
+
+const VARIANTS: &[&str] = &[
+    "null",
+    "bool",
+    "int",
+    "uint",
+    "double",
+    "string",
+    "bytes",
+    "list",
+    "map",
+    "timestamp",
+    "duration",
+    "optional",
+];
+
+/// A [`Value`] which (de)serializes in the tagged representation, see the [module](self) docs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tagged(pub Value);
+
+impl From<Value> for Tagged {
+    fn from(value: Value) -> Self {
+        Tagged(value)
+    }
+}
+
+impl From<Tagged> for Value {
+    fn from(tagged: Tagged) -> Self {
+        tagged.0
+    }
+}
+
+/// Serializes `value` in the tagged representation, for `#[serde(with = "cel::tagged")]`.
+pub fn serialize<S>(value: &Value, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    TaggedRef(value).serialize(serializer)
+}
+
+/// Deserializes a value in the tagged representation, for `#[serde(with = "cel::tagged")]`.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Value, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Tagged::deserialize(deserializer).map(|tagged| tagged.0)
+}
+
+impl Serialize for Tagged {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        TaggedRef(&self.0).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Tagged {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer
+            .deserialize_enum(NAME, VARIANTS, TaggedVisitor)
+            .map(Tagged)
+    }
+}
+
+struct TaggedRef<'a>(&'a Value);
+
+impl Serialize for TaggedRef<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.0 {
+            Value::Null => serializer.serialize_unit_variant(NAME, 0, VARIANTS[0]),
+            Value::Bool(b) => serializer.serialize_newtype_variant(NAME, 1, VARIANTS[1], b),
+            Value::Int(i) => serializer.serialize_newtype_variant(NAME, 2, VARIANTS[2], i),
+            Value::UInt(u) => serializer.serialize_newtype_variant(NAME, 3, VARIANTS[3], u),
+            Value::Float(f) => serializer.serialize_newtype_variant(NAME, 4, VARIANTS[4], f),
+            Value::String(s) => {
+                serializer.serialize_newtype_variant(NAME, 5, VARIANTS[5], s.as_str())
+            }
+            Value::Bytes(b) => {
+                serializer.serialize_newtype_variant(NAME, 6, VARIANTS[6], &Bytes(b))
+            }
+            Value::List(l) => serializer.serialize_newtype_variant(NAME, 7, VARIANTS[7], &List(l)),
+            Value::Map(m) => {
+                serializer.serialize_newtype_variant(NAME, 8, VARIANTS[8], &Entries(&m.map))
+            }
+            #[cfg(feature = "chrono")]
+            Value::Timestamp(ts) => serializer.serialize_newtype_variant(
+                NAME,
+                9,
+                VARIANTS[9],
+                &ts.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true),
+            ),
+            #[cfg(feature = "chrono")]
+            Value::Duration(d) => {
+                let secs = d.num_seconds();
+                let nanos = d.subsec_nanos();
+                serializer.serialize_newtype_variant(NAME, 10, VARIANTS[10], &(secs, nanos))
+            }
+            Value::Opaque(o) => match o.downcast_ref::<OptionalValue>() {
+                Some(opt) => serializer.serialize_newtype_variant(
+                    NAME,
+                    11,
+                    VARIANTS[11],
+                    &opt.value().map(TaggedRef),
+                ),
+                None => Err(ser::Error::custom(format!(
+                    "opaque value of type '{}' has no tagged representation",
+                    o.runtime_type_name()
+                ))),
+            },
+            other => Err(ser::Error::custom(format!(
+                "value of type '{}' has no tagged representation",
+                other.type_of()
+            ))),
+        }
+    }
+}
+
+struct Bytes<'a>(&'a [u8]);
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+struct List<'a>(&'a [Value]);
+
+impl Serialize for List<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.0.iter().map(TaggedRef))
+    }
+}
+
+struct Entries<'a>(&'a MapStorage<Key, Value>);
+
+impl Serialize for Entries<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+        for (k, v) in self.0 {
+            seq.serialize_element(&(TaggedKey(k), TaggedRef(v)))?;
+        }
+        seq.end()
+    }
+}
+
+/// Map keys are tagged with the variant of the value they stand for.
+struct TaggedKey<'a>(&'a Key);
+
+impl Serialize for TaggedKey<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.0 {
+            Key::Bool(b) => serializer.serialize_newtype_variant(NAME, 1, VARIANTS[1], b),
+            Key::Int(i) => serializer.serialize_newtype_variant(NAME, 2, VARIANTS[2], i),
+            Key::Uint(u) => serializer.serialize_newtype_variant(NAME, 3, VARIANTS[3], u),
+            Key::String(s) => {
+                serializer.serialize_newtype_variant(NAME, 5, VARIANTS[5], s.as_str())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Variant {
+    Null,
+    Bool,
+    Int,
+    UInt,
+    Double,
+    String,
+    Bytes,
+    List,
+    Map,
+    Timestamp,
+    Duration,
+    Optional,
+}
+
+impl Variant {
+    const ALL: [Variant; 12] = [
+        Variant::Null,
+        Variant::Bool,
+        Variant::Int,
+        Variant::UInt,
+        Variant::Double,
+        Variant::String,
+        Variant::Bytes,
+        Variant::List,
+        Variant::Map,
+        Variant::Timestamp,
+        Variant::Duration,
+        Variant::Optional,
+    ];
+}
+
+impl<'de> Deserialize<'de> for Variant {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct VariantVisitor;
+
+        impl Visitor<'_> for VariantVisitor {
+            type Value = Variant;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a CEL type name")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Variant, E> {
+                usize::try_from(v)
+                    .ok()
+                    .and_then(|i| Variant::ALL.get(i).copied())
+                    .ok_or_else(|| {
+                        de::Error::invalid_value(de::Unexpected::Unsigned(v), &"a variant index")
+                    })
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Variant, E> {
+                VARIANTS
+                    .iter()
+                    .position(|name| *name == v)
+                    .map(|i| Variant::ALL[i])
+                    .ok_or_else(|| de::Error::unknown_variant(v, VARIANTS))
+            }
+        }
+
+        deserializer.deserialize_identifier(VariantVisitor)
+    }
+}
+
+struct TaggedVisitor;
+
+impl<'de> Visitor<'de> for TaggedVisitor {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a tagged CEL value")
+    }
+
+    fn visit_enum<A>(self, data: A) -> Result<Value, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        let (variant, access) = data.variant::<Variant>()?;
+        Ok(match variant {
+            Variant::Null => {
+                access.unit_variant()?;
+                Value::Null
+            }
+            Variant::Bool => Value::Bool(access.newtype_variant()?),
+            Variant::Int => Value::Int(access.newtype_variant()?),
+            Variant::UInt => Value::UInt(access.newtype_variant()?),
+            Variant::Double => Value::Float(access.newtype_variant()?),
+            Variant::String => Value::String(Arc::new(access.newtype_variant()?)),
+            Variant::Bytes => Value::Bytes(Arc::new(access.newtype_variant::<ByteBuf>()?.0)),
+            Variant::List => {
+                let list: Vec<Tagged> = access.newtype_variant()?;
+                Value::List(Arc::new(list.into_iter().map(|t| t.0).collect()))
+            }
+            Variant::Map => {
+                let entries: Vec<(Tagged, Tagged)> = access.newtype_variant()?;
+                let mut map = MapStorage::with_capacity(entries.len());
+                for (k, v) in entries {
+                    let key = match k.0 {
+                        Value::Bool(b) => Key::Bool(b),
+                        Value::Int(i) => Key::Int(i),
+                        Value::UInt(u) => Key::Uint(u),
+                        Value::String(s) => Key::String(s),
+                        other => {
+                            return Err(de::Error::custom(format!(
+                                "value of type '{}' can't be a map key",
+                                other.type_of()
+                            )))
+                        }
+                    };
+                    map.insert(key, v.0);
+                }
+                map.into()
+            }
+            #[cfg(feature = "chrono")]
+            Variant::Timestamp => {
+                let ts: String = access.newtype_variant()?;
+                Value::Timestamp(
+                    chrono::DateTime::parse_from_rfc3339(&ts).map_err(de::Error::custom)?,
+                )
+            }
+            #[cfg(feature = "chrono")]
+            Variant::Duration => {
+                let (secs, nanos): (i64, i32) = access.newtype_variant()?;
+                Value::Duration(
+                    chrono::Duration::seconds(secs) + chrono::Duration::nanoseconds(nanos.into()),
+                )
+            }
+            #[cfg(not(feature = "chrono"))]
+            Variant::Timestamp | Variant::Duration => {
+                return Err(de::Error::custom(
+                    "timestamps and durations require the `chrono` feature",
+                ))
+            }
+            Variant::Optional => {
+                let value: Option<Tagged> = access.newtype_variant()?;
+                Value::Opaque(Arc::new(match value {
+                    Some(tagged) => OptionalValue::of(tagged.0),
+                    None => OptionalValue::none(),
+                }))
+            }
+        })
+    }
+}
+
+struct ByteBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ByteBufVisitor;
+
+        impl<'de> Visitor<'de> for ByteBufVisitor {
+            type Value = ByteBuf;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("bytes")
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v))
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<ByteBuf, A::Error>
+            where
+                A: de::SeqAccess<'de>,
+            {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+                while let Some(b) = seq.next_element()? {
+                    bytes.push(b);
+                }
+                Ok(ByteBuf(bytes))
+            }
+        }
+
+        deserializer.deserialize_byte_buf(ByteBufVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tagged;
+    use crate::objects::OptionalValue;
+    use crate::{Context, Program, Value};
+    use std::sync::Arc;
+
+    fn eval(script: &str) -> Value {
+        Program::compile(script)
+            .unwrap()
+            .execute(&Context::default())
+            .unwrap()
+    }
+
+    fn values() -> Vec<Value> {
+        let mut values = vec![
+            Value::Null,
+            eval("true"),
+            eval("-1"),
+            eval("1u"),
+            eval("1.0"),
+            eval("'cel'"),
+            eval("b'\\xff\\x00'"),
+            eval("[1, 1u, 1.0, [null]]"),
+            eval("{1: 'int', 1u: 'uint', true: 'bool', 'key': {'nested': b'x'}}"),
+            Value::Opaque(Arc::new(OptionalValue::none())),
+            Value::Opaque(Arc::new(OptionalValue::of(eval("[2u]")))),
+        ];
+        #[cfg(feature = "chrono")]
+        values.extend([
+            eval("timestamp('2023-05-28T00:00:00.123456789+02:00')"),
+            eval("duration('-1.5s')"),
+            eval("duration('3h2m1s4ns')"),
+        ]);
+        values
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        for value in values() {
+            let json = serde_json::to_string(&Tagged(value.clone())).unwrap();
+            let Tagged(back) = serde_json::from_str(&json).unwrap();
+            assert_eq!(back, value, "{json}");
+        }
+        assert_eq!(
+            serde_json::to_string(&Tagged(eval("[1, 1u, 1.0]"))).unwrap(),
+            r#"{"list":[{"int":1},{"uint":1},{"double":1.0}]}"#
+        );
+    }
+
+    #[test]
+    fn test_bincode_round_trip() {
+        for value in values() {
+            let bytes = bincode::serialize(&Tagged(value.clone())).unwrap();
+            let Tagged(back) = bincode::deserialize(&bytes).unwrap();
+            assert_eq!(back, value);
+        }
+    }
+
+    #[test]
+    fn test_unrepresentable() {
+        let err = serde_json::to_string(&Tagged(Value::Function(Arc::new("size".into()), None)))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "value of type 'function' has no tagged representation"
+        );
+        let err =
+            serde_json::from_str::<Tagged>(r#"{"map":[[{"double":1.0},"null"]]}"#).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("value of type 'float' can't be a map key"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! Counts the allocations of executions, which needs the allocator of the whole test binary.
+
+use cel::{Backend, Context, Env, Program};
+use std::sync::{Arc, Mutex, PoisonError};
+
+#[global_allocator]
+static ALLOC: dhat::Alloc = dhat::Alloc;
+
+/// Only one profiler runs at a time, and it counts the allocations of every thread.
+static PROFILING: Mutex<()> = Mutex::new(());
+
+fn programs(env: &Arc<Env>, source: &str) -> [(&'static str, Program); 3] {
+    [
+        ("interpreter", Program::compile(source).unwrap()),
+        (
+            "tree",
+            Program::compile(source)
+                .unwrap()
+                .plan_with(env, Backend::Tree),
+        ),
+        (
+            "bytecode",
+            Program::compile(source)
+                .unwrap()
+                .plan_with(env, Backend::Bytecode),
+        ),
+    ]
+}
+
+/// The number of allocations executing `program` makes.
+fn allocations(program: &Program, ctx: &Context) -> u64 {
+    let before = dhat::HeapStats::get().total_blocks;
+    program.execute(ctx).unwrap();
+    dhat::HeapStats::get().total_blocks - before
+}
+
+#[test]
+fn predicates_on_scalars_dont_allocate() {
+    let _profiling = PROFILING.lock().unwrap_or_else(PoisonError::into_inner);
+    let _profiler = dhat::Profiler::builder().testing().build();
+    let env = Arc::new(Env::stdlib());
+    let mut ctx = Context::with_env(env.clone());
+    ctx.add_variable_from_value("a", 2);
+    ctx.add_variable_from_value("b", "x");
+
+    for source in [
+        "a > 1 && b == 'x'",
+        "!(a < 1) || b != 'x' ? null == null : false",
+    ] {
+        for (backend, program) in programs(&env, source) {
+            assert_eq!(program.execute(&ctx), Ok(true.into()), "`{source}`");
+            let allocated = allocations(&program, &ctx);
+            assert_eq!(allocated, 0, "`{source}` with the {backend} backend");
+        }
+    }
+}
+
+#[test]
+fn comprehensions_accumulate_lists_linearly() {
+    let _profiling = PROFILING.lock().unwrap_or_else(PoisonError::into_inner);
+    let _profiler = dhat::Profiler::builder().testing().build();
+    let env = Arc::new(Env::stdlib());
+    let context = |len: i64| {
+        let mut ctx = Context::with_env(env.clone());
+        ctx.add_variable_from_value("list", (0..len).collect::<Vec<_>>());
+        ctx
+    };
+    let (short, long) = (context(1_000), context(2_000));
+
+    for source in ["list.map(x, x * 2)", "list.filter(x, x % 2 == 0)"] {
+        for (backend, program) in programs(&env, source) {
+            // Copying the accumulator at every step makes twice the items take four times
+            // the allocations.
+            let (short, long) = (allocations(&program, &short), allocations(&program, &long));
+            assert!(
+                long < short * 5 / 2,
+                "`{source}` with the {backend} backend: {short} then {long} allocations"
+            );
+        }
+    }
+}
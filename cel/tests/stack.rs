@@ -0,0 +1,148 @@
+//! Checks that programs as deep as the limits for untrusted ones allow load and evaluate on a
+//! thread with the usual 2 MiB stack. CI also runs these unoptimized, as crates depending on this one are
+//! built by default, which takes much more of the stack.
+
+use cel::parser::Parser;
+use cel::{Backend, Context, Env, ExecutionError, Program};
+use std::sync::Arc;
+
+/// The stack size of the threads Rust spawns, unless told otherwise.
+const STACK_SIZE: usize = 2 << 20;
+
+/// The evaluation depth [`Env::set_max_eval_depth`] suggests for a 2 MiB stack.
+const MAX_EVAL_DEPTH: usize = 100;
+
+/// An [`Env`] evaluating as deep as [`MAX_EVAL_DEPTH`].
+fn env() -> Arc<Env> {
+    let mut env = Env::stdlib();
+    env.set_max_eval_depth(MAX_EVAL_DEPTH);
+    Arc::new(env)
+}
+
+fn on_thread<T: Send + 'static>(stack_size: usize, f: impl FnOnce() -> T + Send + 'static) -> T {
+    std::thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+/// Compiles `source` on a thread with a larger stack, as unoptimized, the generated parser
+/// takes much more of it than evaluating does.
+fn compile(source: String) -> Program {
+    on_thread(64 << 20, move || {
+        let parser = Parser::new().max_recursion_depth(u16::MAX);
+        Program::compile_with(parser, &source).unwrap()
+    })
+}
+
+/// Sources of expressions of various kinds, nesting deeper as `n` grows.
+fn shapes(n: usize) -> Vec<String> {
+    let nest = |open: &str, inner: &str, close: &str| {
+        format!("{}{inner}{}", open.repeat(n), close.repeat(n))
+    };
+    vec![
+        nest("-(", "1", ")"),
+        nest("[", "1", "]"),
+        nest("{'a': ", "1", "}.a"),
+        nest("true ? (", "1", ") : 0"),
+        nest("int(", "1", ")"),
+        nest("f(", "1", ")"),
+        nest("[1].all(x, ", "true", ")"),
+        nest("[1].map(x, ", "x", ")"),
+        format!("1{}", " + 1".repeat(n)),
+    ]
+}
+
+#[test]
+fn evaluates_up_to_the_max_depth() {
+    on_thread(STACK_SIZE, || {
+        let env = env();
+        let mut ctx = Context::with_env(env.clone());
+        ctx.add_function("f", |x: i64| x);
+        for backend in [None, Some(Backend::Tree), Some(Backend::Bytecode)] {
+            for shape in 0..shapes(1).len() {
+                for n in 1.. {
+                    let source = shapes(n).swap_remove(shape);
+                    let mut program = compile(source.clone());
+                    if let Some(backend) = backend {
+                        program = program.plan_with(&env, backend);
+                    }
+                    match program.execute(&ctx) {
+                        Ok(_) => {}
+                        Err(ExecutionError::MaxDepthExceeded(_)) => break,
+                        Err(err) => panic!("{source}: {err}"),
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(feature = "expr_proto")]
+mod proto {
+    use super::{env, on_thread, MAX_EVAL_DEPTH, STACK_SIZE};
+    use cel::common::ast::operators;
+    use cel::common::ast::proto::constant::ConstantKind;
+    use cel::common::ast::proto::{expr, Constant, Expr, FromProtoError, ParsedExpr};
+    use cel::{Context, ExecutionError, Program};
+
+    /// `-(-(...(1)))`, `depth` deep.
+    fn negations(depth: i64) -> ParsedExpr {
+        let mut expr = Expr {
+            id: depth,
+            expr_kind: Some(expr::ExprKind::ConstExpr(Constant {
+                constant_kind: Some(ConstantKind::Int64Value(1)),
+            })),
+        };
+        for id in (1..depth).rev() {
+            expr = Expr {
+                id,
+                expr_kind: Some(expr::ExprKind::CallExpr(expr::Call {
+                    function: operators::NEGATE.to_string(),
+                    target: None,
+                    args: vec![expr],
+                })),
+            };
+        }
+        ParsedExpr {
+            expr: Some(expr),
+            source_info: None,
+        }
+    }
+
+    #[test]
+    fn loads_up_to_the_max_depth() {
+        on_thread(STACK_SIZE, || {
+            let program = Program::from_parsed_expr(&negations(200), None).unwrap();
+            assert_eq!(
+                program.execute(&Context::with_env(env())),
+                Err(ExecutionError::MaxDepthExceeded(MAX_EVAL_DEPTH))
+            );
+            assert_eq!(
+                Program::from_parsed_expr(&negations(201), None).unwrap_err(),
+                FromProtoError::TooDeep { id: 201, max: 200 }
+            );
+            assert_eq!(
+                Program::from_parsed_expr(&negations(5000), None).unwrap_err(),
+                FromProtoError::TooDeep { id: 201, max: 200 }
+            );
+        });
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn loads_bytes_up_to_the_max_depth() {
+    let bytes = compile(format!("1{}", " + 1".repeat(199)))
+        .to_bytes()
+        .unwrap();
+    on_thread(STACK_SIZE, move || {
+        let program = Program::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            program.execute(&Context::with_env(env())),
+            Err(ExecutionError::MaxDepthExceeded(MAX_EVAL_DEPTH))
+        );
+    });
+}
@@ -63,7 +63,7 @@ fn arbitrary_value(
                 for _ in 0..length {
                     map.insert(arbitrary_key(u)?, arbitrary_value(u, depth)?);
                 }
-                Value::Map(Map { map: Arc::new(map) })
+                Value::Map(Map::from(map))
             }
             2u64 => Value::Int(arbitrary::Arbitrary::arbitrary(u)?),
             3u64 => Value::UInt(arbitrary::Arbitrary::arbitrary(u)?),